@@ -0,0 +1,187 @@
+//! `BTree` against `std::collections::BTreeMap` at several fanouts. Run with
+//! `cargo bench --features test-util`, and compare runs with criterion's `--save-baseline` and
+//! `--baseline`.
+
+use std::collections::BTreeMap;
+use std::hint::black_box;
+
+use btree::btree::BTree;
+use btree::oracle::Op;
+use btree::slot::Slot;
+use btree::workload::{Distribution, Mix, Workload};
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+
+const KEYS: u64 = 100_000;
+const FANOUTS: [usize; 4] = [8, 16, 64, 128];
+
+fn keys(distribution: Distribution) -> Vec<u64> {
+    let workload = Workload {
+        seed: 1,
+        keys: KEYS,
+        distribution,
+        mix: Mix {
+            insert: 1,
+            get: 0,
+            delete: 0,
+            range: 0,
+        },
+        ..Default::default()
+    };
+
+    workload
+        .ops()
+        .take(KEYS as usize)
+        .map(|op| match op {
+            Op::Insert(k, _) => k,
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
+fn loaded(max: usize) -> BTree<u64, u64> {
+    BTree::bulk_load(max, (0..KEYS).map(|k| (k, k)))
+}
+
+fn insert(c: &mut Criterion, name: &str, distribution: Distribution) {
+    let keys = keys(distribution);
+    let mut group = c.benchmark_group(name);
+    group.throughput(Throughput::Elements(keys.len() as u64));
+
+    group.bench_function("std", |b| {
+        b.iter(|| {
+            let mut map = BTreeMap::new();
+            for &k in &keys {
+                map.insert(k, k);
+            }
+            map
+        })
+    });
+    for max in FANOUTS {
+        group.bench_with_input(BenchmarkId::new("btree", max), &max, |b, &max| {
+            b.iter(|| {
+                let mut tree = BTree::new(max);
+                for &k in &keys {
+                    tree.insert(Slot::new_leaf(k, k));
+                }
+                tree
+            })
+        });
+    }
+
+    group.finish();
+}
+
+fn insert_random(c: &mut Criterion) {
+    insert(c, "insert_random", Distribution::Uniform);
+}
+
+fn insert_sequential(c: &mut Criterion) {
+    insert(c, "insert_sequential", Distribution::Sequential);
+}
+
+fn get(c: &mut Criterion) {
+    let keys = keys(Distribution::Uniform);
+    let mut group = c.benchmark_group("get");
+    group.throughput(Throughput::Elements(keys.len() as u64));
+
+    let map = (0..KEYS).map(|k| (k, k)).collect::<BTreeMap<_, _>>();
+    group.bench_function("std", |b| b.iter(|| keys.iter().filter_map(|k| map.get(k)).count()));
+    for max in FANOUTS {
+        let tree = loaded(max);
+        group.bench_with_input(BenchmarkId::new("btree", max), &tree, |b, tree| {
+            b.iter(|| keys.iter().filter_map(|k| tree.get(*k)).count())
+        });
+    }
+
+    group.finish();
+}
+
+fn range(c: &mut Criterion) {
+    const LEN: u64 = 1000;
+    let starts = keys(Distribution::Uniform)
+        .into_iter()
+        .take(100)
+        .map(|k| k.min(KEYS - LEN))
+        .collect::<Vec<_>>();
+    let mut group = c.benchmark_group("range");
+    group.throughput(Throughput::Elements(starts.len() as u64 * LEN));
+
+    let map = (0..KEYS).map(|k| (k, k)).collect::<BTreeMap<_, _>>();
+    group.bench_function("std", |b| {
+        b.iter(|| {
+            let sum = |&s: &u64| map.range(s..s + LEN).map(|e| *e.1).sum::<u64>();
+            starts.iter().map(sum).sum::<u64>()
+        })
+    });
+    for max in FANOUTS {
+        let tree = loaded(max);
+        group.bench_with_input(BenchmarkId::new("btree", max), &tree, |b, tree| {
+            b.iter(|| {
+                let sum = |&s: &u64| tree.range(s..s + LEN).map(|e| e.1).sum::<u64>();
+                starts.iter().map(sum).sum::<u64>()
+            })
+        });
+    }
+
+    group.finish();
+}
+
+/// YCSB-style: mostly reads of skewed keys, with some updates, deletes and short scans.
+fn mixed(c: &mut Criterion) {
+    let workload = Workload {
+        seed: 2,
+        keys: KEYS,
+        distribution: Distribution::Zipfian(0.99),
+        mix: Mix {
+            insert: 20,
+            get: 70,
+            delete: 5,
+            range: 5,
+        },
+        scan_len: 50,
+    };
+    let ops = workload.take(100_000);
+    let mut group = c.benchmark_group("mixed");
+    group.throughput(Throughput::Elements(ops.len() as u64));
+
+    let map = (0..KEYS).map(|k| (k, k)).collect::<BTreeMap<_, _>>();
+    group.bench_function("std", |b| {
+        b.iter_batched_ref(
+            || map.clone(),
+            |map| {
+                for op in &ops {
+                    match *op {
+                        Op::Insert(k, v) => _ = map.insert(k, v),
+                        Op::Get(k) => _ = black_box(map.get(&k)),
+                        Op::Delete(k) => _ = map.remove(&k),
+                        Op::Range(s, e) => _ = black_box(map.range((s, e)).count()),
+                    }
+                }
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    for max in FANOUTS {
+        group.bench_with_input(BenchmarkId::new("btree", max), &max, |b, &max| {
+            b.iter_batched_ref(
+                || loaded(max),
+                |tree| {
+                    for op in &ops {
+                        match *op {
+                            Op::Insert(k, v) => tree.insert(Slot::new_leaf(k, v)),
+                            Op::Get(k) => _ = black_box(tree.get(k)),
+                            Op::Delete(k) => _ = tree.delete(k),
+                            Op::Range(s, e) => _ = black_box(tree.range((s, e)).count()),
+                        }
+                    }
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, get, insert_random, insert_sequential, range, mixed);
+criterion_main!(benches);
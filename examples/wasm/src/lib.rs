@@ -0,0 +1,50 @@
+use btree::btree::BTree;
+use btree::bytes::Bytes;
+use btree::slot::{Either, Slot};
+use wasm_bindgen::prelude::*;
+
+type Key = Bytes<64>;
+
+fn key(s: &str) -> Result<Key, JsError> {
+    Bytes::new(s.as_bytes()).ok_or_else(|| JsError::new("keys are at most 64 bytes"))
+}
+
+/// An index from string keys to row numbers, for JavaScript.
+#[wasm_bindgen]
+pub struct Index(BTree<Key, u32>);
+
+#[wasm_bindgen]
+impl Index {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Index {
+        Index(BTree::new(32))
+    }
+
+    pub fn insert(&mut self, key: &str, row: u32) -> Result<(), JsError> {
+        self.0.insert(Slot::new_leaf(self::key(key)?, row));
+        Ok(())
+    }
+
+    pub fn get(&self, key: &str) -> Result<Option<u32>, JsError> {
+        Ok(self.0.get(self::key(key)?).map(|slot| match slot.1 {
+            Either::Left(row) => row,
+            Either::Right(_) => unreachable!(),
+        }))
+    }
+
+    pub fn delete(&mut self, key: &str) -> Result<bool, JsError> {
+        Ok(self.0.delete(self::key(key)?))
+    }
+
+    /// The rows of the keys from `start` up to but not including `end`, in key order.
+    pub fn range(&self, start: &str, end: &str) -> Result<Vec<u32>, JsError> {
+        let range = self::key(start)?..self::key(end)?;
+        Ok(self.0.range(range).map(|(_, row)| row).collect())
+    }
+}
+
+impl Default for Index {
+    fn default() -> Self {
+        Self::new()
+    }
+}
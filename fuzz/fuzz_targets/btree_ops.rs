@@ -0,0 +1,73 @@
+//! Applies a sequence of operations to a `BTree` and a `BTreeMap`, which must agree after each one,
+//! and validates the tree at the end. Keys are narrow so operations hit the same keys and leaves
+//! often, and node sizes are small so there are many splits and separators to get wrong.
+#![no_main]
+
+use std::collections::BTreeMap;
+
+use arbitrary::Arbitrary;
+use btree::btree::BTree;
+use btree::get_left;
+use btree::slot::{Either, Slot};
+use libfuzzer_sys::fuzz_target;
+
+#[derive(Arbitrary, Debug)]
+enum Op {
+    Insert(u8, u32),
+    Get(u8),
+    Delete(u8),
+    Range(u8, u8),
+    DeleteWhere(u8),
+}
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    max: u8,
+    ops: Vec<Op>,
+}
+
+/// Separators are exclusive upper bounds made with `Increment::next()`, which overflows for the
+/// greatest key, so it can't be stored.
+fn key(k: u8) -> u8 {
+    k.min(u8::MAX - 1)
+}
+
+fuzz_target!(|input: Input| {
+    let mut tree = BTree::new(4 + input.max as usize % 13);
+    let mut want = BTreeMap::new();
+
+    for op in input.ops {
+        match op {
+            Op::Insert(k, v) => {
+                let k = key(k);
+                tree.insert(Slot::new_leaf(k, v));
+                want.insert(k, v);
+            }
+            Op::Get(k) => {
+                let have = tree.get(k).map(|s| get_left!(s));
+                assert_eq!(have, want.get(&k).copied(), "get {k}");
+            }
+            Op::Delete(k) => {
+                assert_eq!(tree.delete(k), want.remove(&k).is_some(), "delete {k}");
+            }
+            Op::Range(a, b) => {
+                let (a, b) = (a.min(b), a.max(b));
+                let have = tree.range(a..b).collect::<Vec<_>>();
+                let want = want.range(a..b).map(|(k, v)| (*k, *v)).collect::<Vec<_>>();
+                assert_eq!(have, want, "range {a}..{b}");
+            }
+            Op::DeleteWhere(m) => {
+                let m = m.max(1);
+                let n = tree.delete_where(|k, _| k % m == 0);
+                let before = want.len();
+                want.retain(|k, _| k % m != 0);
+                assert_eq!(n, before - want.len(), "delete where k % {m} == 0");
+            }
+        }
+    }
+
+    let have = tree.iter().collect::<Vec<_>>();
+    let want = want.into_iter().collect::<Vec<_>>();
+    assert_eq!(have, want);
+    tree.validate().unwrap();
+});
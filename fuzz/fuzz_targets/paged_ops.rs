@@ -0,0 +1,67 @@
+//! Applies a sequence of operations to an in-memory `PagedBTree` and a `BTreeMap`, which must
+//! agree after each one, and validates the tree at the end. A small pool makes pages get evicted
+//! and read back throughout.
+#![no_main]
+
+use std::collections::BTreeMap;
+
+use arbitrary::Arbitrary;
+use btree::buffer::Capacity;
+use btree::paged::PagedBTree;
+use libfuzzer_sys::fuzz_target;
+
+#[derive(Arbitrary, Debug)]
+enum Op {
+    Insert(u8, u64),
+    Get(u8),
+    Delete(u8),
+    Range(u8, u8),
+    MergeUnderfull(u8),
+}
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    max: u8,
+    ops: Vec<Op>,
+}
+
+/// Splits make separators with `Increment::next()`, which overflows for the greatest key, so it
+/// can't be stored.
+fn key(k: u8) -> u8 {
+    k.min(u8::MAX - 1)
+}
+
+fuzz_target!(|input: Input| {
+    let max = 4 + input.max as usize % 13;
+    let mut tree = PagedBTree::create_in_memory(max, Capacity::Pages(8)).unwrap();
+    let mut want = BTreeMap::new();
+
+    for op in input.ops {
+        match op {
+            Op::Insert(k, v) => {
+                let k = key(k);
+                let have = tree.insert(k, v).unwrap();
+                assert_eq!(have, want.insert(k, v), "insert {k}");
+            }
+            Op::Get(k) => {
+                assert_eq!(tree.get(k).unwrap(), want.get(&k).copied(), "get {k}");
+            }
+            Op::Delete(k) => {
+                assert_eq!(tree.delete(k).unwrap(), want.remove(&k), "delete {k}");
+            }
+            Op::Range(a, b) => {
+                let (a, b) = (a.min(b), a.max(b));
+                let want = want.range(a..=b).map(|(k, v)| (*k, *v)).collect::<Vec<_>>();
+                assert_eq!(tree.range(a..=b).unwrap(), want, "range {a}..={b}");
+            }
+            Op::MergeUnderfull(limit) => {
+                tree.merge_underfull(limit as usize).unwrap();
+            }
+        }
+    }
+
+    let want = want.into_iter().collect::<Vec<_>>();
+    assert_eq!(tree.iter().unwrap(), want);
+    assert_eq!(tree.len(), want.len());
+    tree.validate().unwrap();
+});
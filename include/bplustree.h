@@ -0,0 +1,80 @@
+#ifndef BPLUSTREE_H
+#define BPLUSTREE_H
+
+/* Generated by cbindgen from src/ffi.rs, don't edit by hand. */
+
+#include <stddef.h>
+#include <stdint.h>
+
+#define BPLUSTREE_KEY_SIZE 64
+
+#define BPLUSTREE_VALUE_SIZE 256
+
+typedef struct BPlusTree BPlusTree;
+
+// Called by `bplustree_scan()` with every entry in the range, returns non-zero to stop.
+typedef int (*BPlusTreeScan)(void *ctx,
+                             const uint8_t *key,
+                             size_t key_len,
+                             const uint8_t *value,
+                             size_t value_len);
+
+// Creates an empty tree, free it with `bplustree_free()`. Returns null if `max` is less than 4.
+struct BPlusTree *bplustree_new(size_t max);
+
+// Inserts or replaces the value of `key`. Returns 0.
+//
+// # Safety
+//
+// `tree` is from `bplustree_new()`, `key` and `value` point to `key_len` and `value_len` bytes.
+int bplustree_insert(struct BPlusTree *tree,
+                     const uint8_t *key,
+                     size_t key_len,
+                     const uint8_t *value,
+                     size_t value_len);
+
+// Copies the value of `key` to `out`, up to `out_cap` bytes, and sets `out_len` to its length.
+// Returns 1 if `key` was found, 0 if not.
+//
+// # Safety
+//
+// `tree` is from `bplustree_new()`, `key` points to `key_len` bytes, `out` to `out_cap` bytes and
+// `out_len` to a `size_t`.
+int bplustree_get(const struct BPlusTree *tree,
+                  const uint8_t *key,
+                  size_t key_len,
+                  uint8_t *out,
+                  size_t out_cap,
+                  size_t *out_len);
+
+// Returns 1 if `key` was deleted, 0 if it wasn't in the tree.
+//
+// # Safety
+//
+// `tree` is from `bplustree_new()` and `key` points to `key_len` bytes.
+int bplustree_delete(struct BPlusTree *tree, const uint8_t *key, size_t key_len);
+
+// Calls `scan` with every entry from `start` up to but not including `end`, in order. A null
+// `start` or `end` leaves that end of the range open. The pointers passed to `scan` are valid
+// until it returns. Returns the number of entries `scan` was called with.
+//
+// # Safety
+//
+// `tree` is from `bplustree_new()`, `start` and `end` point to `start_len` and `end_len` bytes or
+// are null, and `scan` doesn't change the tree.
+int bplustree_scan(const struct BPlusTree *tree,
+                   const uint8_t *start,
+                   size_t start_len,
+                   const uint8_t *end,
+                   size_t end_len,
+                   BPlusTreeScan scan,
+                   void *ctx);
+
+// Frees a tree from `bplustree_new()`, null is ignored.
+//
+// # Safety
+//
+// `tree` isn't used after.
+void bplustree_free(struct BPlusTree *tree);
+
+#endif  /* BPLUSTREE_H */
@@ -0,0 +1,208 @@
+use std::fmt::Debug;
+use std::io;
+use std::ops::RangeBounds;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+use tokio::task;
+
+use crate::btree::Increment;
+use crate::page::Encode;
+use crate::paged::{Options, PagedBTree};
+use crate::wal::Wal;
+
+/// A `PagedBTree` for async code, as a wrapper running the blocking tree on tokio's blocking
+/// threads.
+///
+/// The I/O itself isn't async: pool misses, write backs and log syncs block, with any backend,
+/// on the blocking thread running the operation instead of on the runtime's workers. Each
+/// operation waiting on the disk holds one of those threads, so the blocking pool's size bounds
+/// how many wait at once. Reads share the tree, writes take it exclusively. Clones refer to the
+/// same tree.
+pub struct AsyncPagedBTree<K, V> {
+    tree: Arc<RwLock<PagedBTree<K, V>>>,
+    wal: Option<Arc<Wal>>,
+}
+
+impl<K, V> Clone for AsyncPagedBTree<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            tree: self.tree.clone(),
+            wal: self.wal.clone(),
+        }
+    }
+}
+
+impl<K, V> AsyncPagedBTree<K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment + Encode + Send + Sync + 'static,
+    V: Clone + Copy + Debug + Encode + Send + Sync + 'static,
+{
+    pub fn new(mut tree: PagedBTree<K, V>) -> Self {
+        let wal = tree.defer_sync(true);
+        Self {
+            tree: Arc::new(RwLock::new(tree)),
+            wal,
+        }
+    }
+
+    pub async fn create_with_options<P: Into<PathBuf>>(
+        path: P,
+        max: usize,
+        options: Options,
+    ) -> io::Result<Self> {
+        let path = path.into();
+        let tree = blocking(move || PagedBTree::create_with_options(path, max, options)).await?;
+        Ok(Self::new(tree))
+    }
+
+    pub async fn open_with_options<P: Into<PathBuf>>(
+        path: P,
+        options: Options,
+    ) -> io::Result<Self> {
+        let path = path.into();
+        let tree = blocking(move || PagedBTree::open_with_options(path, options)).await?;
+        Ok(Self::new(tree))
+    }
+
+    /// Runs `f` with shared access to the tree on a blocking thread.
+    pub async fn read<F, T>(&self, f: F) -> io::Result<T>
+    where
+        F: FnOnce(&PagedBTree<K, V>) -> io::Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let tree = self.tree.clone();
+        blocking(move || f(&tree.read().unwrap())).await
+    }
+
+    /// Runs `f` with exclusive access to the tree on a blocking thread. Under `SyncPolicy::Group`
+    /// the commits of writers running together are synced at once, after the tree is released,
+    /// each writer waiting for the sync on its blocking thread.
+    pub async fn write<F, T>(&self, f: F) -> io::Result<T>
+    where
+        F: FnOnce(&mut PagedBTree<K, V>) -> io::Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let tree = self.tree.clone();
+        let wal = self.wal.clone();
+        blocking(move || {
+            let out = f(&mut tree.write().unwrap())?;
+            match wal {
+                Some(wal) => wal.wait_durable(wal.committed()).map(|_| out),
+                None => Ok(out),
+            }
+        })
+        .await
+    }
+
+    pub async fn get(&self, key: K) -> io::Result<Option<V>> {
+        self.read(move |tree| tree.get(key)).await
+    }
+
+    pub async fn insert(&self, key: K, value: V) -> io::Result<Option<V>> {
+        self.write(move |tree| tree.insert(key, value)).await
+    }
+
+    pub async fn delete(&self, key: K) -> io::Result<Option<V>> {
+        self.write(move |tree| tree.delete(key)).await
+    }
+
+    pub async fn range<R>(&self, range: R) -> io::Result<Vec<(K, V)>>
+    where
+        R: RangeBounds<K> + Send + 'static,
+    {
+        self.read(move |tree| tree.range(range)).await
+    }
+
+    /// Waits behind any write in progress, without blocking the runtime.
+    pub async fn len(&self) -> usize {
+        // Only fails if the closure panicked
+        self.read(|tree| Ok(tree.len())).await.unwrap()
+    }
+
+    pub async fn is_empty(&self) -> bool {
+        self.len().await == 0
+    }
+
+    pub async fn sync(&self) -> io::Result<()> {
+        self.read(|tree| tree.sync()).await
+    }
+
+    pub async fn checkpoint(&self) -> io::Result<()> {
+        self.read(|tree| tree.checkpoint()).await
+    }
+}
+
+async fn blocking<F, T>(f: F) -> io::Result<T>
+where
+    F: FnOnce() -> io::Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    match task::spawn_blocking(f).await {
+        Ok(res) => res,
+        Err(e) => Err(io::Error::other(e)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::runtime::Builder;
+
+    use crate::paged::Options;
+    use crate::wal::SyncPolicy;
+
+    use super::AsyncPagedBTree;
+
+    #[test]
+    fn test_async_paged_btree() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree");
+        let options = Options {
+            wal: Some(SyncPolicy::PerCommit),
+            ..Default::default()
+        };
+
+        let rt = Builder::new_current_thread().build().unwrap();
+        rt.block_on(async {
+            let tree = AsyncPagedBTree::<u32, u64>::create_with_options(&path, 8, options)
+                .await
+                .unwrap();
+
+            let tasks = (0..4u32)
+                .map(|t| {
+                    let tree = tree.clone();
+                    tokio::spawn(async move {
+                        for k in (t..200).step_by(4) {
+                            tree.insert(k, k as u64).await.unwrap();
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+            for task in tasks {
+                task.await.unwrap();
+            }
+
+            assert!(tree.len().await == 200);
+            assert!(tree.get(42).await.unwrap() == Some(42));
+            assert!(tree.delete(42).await.unwrap() == Some(42));
+
+            let want = (40..50)
+                .filter(|k| *k != 42)
+                .map(|k| (k, k as u64))
+                .collect::<Vec<_>>();
+            let have = tree.range(40..50).await.unwrap();
+            assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+            tree.sync().await.unwrap();
+        });
+        drop(rt);
+
+        let rt = Builder::new_current_thread().build().unwrap();
+        rt.block_on(async {
+            let tree = AsyncPagedBTree::<u32, u64>::open_with_options(&path, options)
+                .await
+                .unwrap();
+            assert!(tree.len().await == 199);
+            assert!(tree.get(42).await.unwrap().is_none());
+        });
+    }
+}
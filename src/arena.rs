@@ -0,0 +1,321 @@
+//! A process-wide allocator for programs built around very large in-memory trees, on Linux with
+//! the `hugepages` feature. It isn't an option of a tree: a `BTree` allocates its nodes and their
+//! slots from the global allocator, so installing an `Arena` as the `#[global_allocator]` puts
+//! them on 2MiB huge pages, which cuts the TLB misses of trees much larger than the CPU caches, and
+//! can bind them to one NUMA node so a tree used from one socket isn't read across the
+//! interconnect:
+//!
+//! ```text
+//! #[global_allocator]
+//! static ARENA: Arena = Arena::new(Placement { huge_pages: true, numa_node: Some(0) });
+//! ```
+//!
+//! Allocations of up to `MAX_BLOCK` bytes, which covers nodes of any practical fanout, are carved
+//! out of `CHUNK_SIZE` chunks by size class and reused once freed, chunks are never returned to
+//! the OS. Larger ones go to the system allocator.
+//!
+//! Being global, it serves every allocation of the process, every tree's and everything else's,
+//! with the same `Placement`. Trees can't opt in or out one at a time, or be placed on different
+//! NUMA nodes, and `ArenaStats` are totals for the process rather than for any one tree.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::ptr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+pub const CHUNK_SIZE: usize = 2 << 20;
+/// The largest allocation served from chunks.
+pub const MAX_BLOCK: usize = 64 << 10;
+const MIN_BLOCK: usize = 16;
+const CLASSES: usize = (MAX_BLOCK / MIN_BLOCK).trailing_zeros() as usize + 1;
+/// The NUMA nodes an `Arena` can bind to and count chunks on.
+pub const MAX_NUMA_NODES: usize = 64;
+
+const MPOL_BIND: libc::c_long = 2;
+const MPOL_F_NODE: libc::c_ulong = 1;
+const MPOL_F_ADDR: libc::c_ulong = 2;
+
+/// Where an `Arena` maps its chunks.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct Placement {
+    /// Maps chunks from the kernel's reserved huge pages, or asks for transparent huge pages
+    /// when none are reserved.
+    pub huge_pages: bool,
+    /// Binds chunks to this NUMA node. Unbound chunks are placed by the kernel on first touch.
+    pub numa_node: Option<u32>,
+}
+
+/// What an `Arena` has allocated, for the whole process.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct ArenaStats {
+    pub chunks: u64,
+    /// Chunks mapped from reserved huge pages.
+    pub huge_chunks: u64,
+    /// Chunks that couldn't be bound to `Placement::numa_node`.
+    pub unbound: u64,
+    /// Bytes allocated and not yet freed, rounded up to their size class.
+    pub in_use: u64,
+    /// Bytes of chunks on each NUMA node, by where their first page landed. Empty if the kernel
+    /// doesn't say.
+    pub nodes: Vec<u64>,
+}
+
+// The blocks of one size class. Free blocks hold the address of the next free block.
+struct Class {
+    free: usize,
+    // The unused part of the chunk the class is carving up
+    bump: usize,
+    end: usize,
+}
+
+/// A global allocator that serves the process's small allocations from huge-page chunks, see the
+/// module docs.
+pub struct Arena {
+    placement: Placement,
+    classes: [Mutex<Class>; CLASSES],
+    chunks: AtomicU64,
+    huge_chunks: AtomicU64,
+    unbound: AtomicU64,
+    in_use: AtomicU64,
+    nodes: [AtomicU64; MAX_NUMA_NODES],
+}
+
+/// The size class of `layout`, `None` if it is too big for a chunk. Blocks of a class are
+/// aligned to their size, chunks to theirs.
+fn class(layout: Layout) -> Option<usize> {
+    let size = layout
+        .size()
+        .max(layout.align())
+        .max(MIN_BLOCK)
+        .next_power_of_two();
+    (size <= MAX_BLOCK).then(|| (size / MIN_BLOCK).trailing_zeros() as usize)
+}
+
+impl Arena {
+    pub const fn new(placement: Placement) -> Self {
+        Self {
+            placement,
+            classes: [const {
+                Mutex::new(Class {
+                    free: 0,
+                    bump: 0,
+                    end: 0,
+                })
+            }; CLASSES],
+            chunks: AtomicU64::new(0),
+            huge_chunks: AtomicU64::new(0),
+            unbound: AtomicU64::new(0),
+            in_use: AtomicU64::new(0),
+            nodes: [const { AtomicU64::new(0) }; MAX_NUMA_NODES],
+        }
+    }
+
+    pub fn stats(&self) -> ArenaStats {
+        let mut nodes = self
+            .nodes
+            .iter()
+            .map(|n| n.load(Ordering::Relaxed))
+            .collect::<Vec<_>>();
+        while nodes.last() == Some(&0) {
+            nodes.pop();
+        }
+
+        ArenaStats {
+            chunks: self.chunks.load(Ordering::Relaxed),
+            huge_chunks: self.huge_chunks.load(Ordering::Relaxed),
+            unbound: self.unbound.load(Ordering::Relaxed),
+            in_use: self.in_use.load(Ordering::Relaxed),
+            nodes,
+        }
+    }
+
+    // Never panics, a panic in the allocator aborts
+    fn lock(&self, class: usize) -> MutexGuard<'_, Class> {
+        self.classes[class]
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Maps a chunk aligned to its size, placed as `placement` says. Doesn't allocate.
+    unsafe fn map_chunk(&self) -> Option<usize> {
+        let prot = libc::PROT_READ | libc::PROT_WRITE;
+        let flags = libc::MAP_PRIVATE | libc::MAP_ANONYMOUS;
+
+        let mut chunk = libc::MAP_FAILED;
+        if self.placement.huge_pages {
+            // Huge pages are aligned to their size
+            chunk = libc::mmap(ptr::null_mut(), CHUNK_SIZE, prot, flags | libc::MAP_HUGETLB, -1, 0);
+        }
+        let huge = chunk != libc::MAP_FAILED;
+
+        if !huge {
+            // Map twice the size and trim it to an aligned chunk
+            let raw = libc::mmap(ptr::null_mut(), 2 * CHUNK_SIZE, prot, flags, -1, 0);
+            if raw == libc::MAP_FAILED {
+                return None;
+            }
+            let raw = raw as usize;
+            let start = raw.next_multiple_of(CHUNK_SIZE);
+            if start > raw {
+                libc::munmap(raw as *mut _, start - raw);
+            }
+            let tail = raw + 2 * CHUNK_SIZE - (start + CHUNK_SIZE);
+            if tail > 0 {
+                libc::munmap((start + CHUNK_SIZE) as *mut _, tail);
+            }
+
+            chunk = start as *mut _;
+            if self.placement.huge_pages {
+                libc::madvise(chunk, CHUNK_SIZE, libc::MADV_HUGEPAGE);
+            }
+        }
+
+        // Bound before the first touch places it
+        if let Some(node) = self.placement.numa_node {
+            let bound = (node as usize) < MAX_NUMA_NODES && {
+                let mask: u64 = 1 << node;
+                let maxnode = MAX_NUMA_NODES as libc::c_ulong + 1;
+                let len = CHUNK_SIZE as libc::c_ulong;
+                let flags: libc::c_ulong = 0;
+                libc::syscall(libc::SYS_mbind, chunk, len, MPOL_BIND, &mask, maxnode, flags) == 0
+            };
+            if !bound {
+                self.unbound.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        chunk.cast::<u8>().write_volatile(0);
+        let mut node: libc::c_int = -1;
+        let flags = MPOL_F_NODE | MPOL_F_ADDR;
+        let (none, maxnode) = (ptr::null_mut::<libc::c_ulong>(), 0 as libc::c_ulong);
+        if libc::syscall(libc::SYS_get_mempolicy, &mut node, none, maxnode, chunk, flags) == 0
+            && (0..MAX_NUMA_NODES as libc::c_int).contains(&node)
+        {
+            self.nodes[node as usize].fetch_add(CHUNK_SIZE as u64, Ordering::Relaxed);
+        }
+
+        self.chunks.fetch_add(1, Ordering::Relaxed);
+        self.huge_chunks.fetch_add(huge as u64, Ordering::Relaxed);
+        Some(chunk as usize)
+    }
+}
+
+unsafe impl GlobalAlloc for Arena {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let Some(class) = class(layout) else {
+            return System.alloc(layout);
+        };
+        let size = MIN_BLOCK << class;
+
+        let mut c = self.lock(class);
+        let block = if c.free != 0 {
+            let block = c.free;
+            c.free = *(block as *const usize);
+            block
+        } else {
+            if c.bump == c.end {
+                match self.map_chunk() {
+                    Some(chunk) => (c.bump, c.end) = (chunk, chunk + CHUNK_SIZE),
+                    None => return ptr::null_mut(),
+                }
+            }
+            c.bump += size;
+            c.bump - size
+        };
+
+        self.in_use.fetch_add(size as u64, Ordering::Relaxed);
+        block as *mut u8
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let Some(class) = class(layout) else {
+            return System.dealloc(ptr, layout);
+        };
+
+        let mut c = self.lock(class);
+        *(ptr as *mut usize) = c.free;
+        c.free = ptr as usize;
+        self.in_use
+            .fetch_sub((MIN_BLOCK << class) as u64, Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new = Layout::from_size_align_unchecked(new_size, layout.align());
+        match (class(layout), class(new)) {
+            // The block has room
+            (Some(a), Some(b)) if a == b => ptr,
+            (None, None) => System.realloc(ptr, layout, new_size),
+            _ => {
+                let moved = self.alloc(new);
+                if !moved.is_null() {
+                    ptr::copy_nonoverlapping(ptr, moved, layout.size().min(new_size));
+                    self.dealloc(ptr, layout);
+                }
+                moved
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::alloc::{GlobalAlloc, Layout};
+    use std::slice;
+
+    use super::{Arena, Placement, CHUNK_SIZE, MAX_BLOCK};
+
+    #[test]
+    fn test_arena() {
+        let arena = Arena::new(Placement {
+            huge_pages: true,
+            numa_node: Some(0),
+        });
+        let layouts = [(24, 8), (100, 8), (1500, 8), (4096, 4096), (MAX_BLOCK, 8)]
+            .map(|(size, align)| Layout::from_size_align(size, align).unwrap());
+
+        let mut blocks = Vec::new();
+        for i in 0..2000 {
+            let layout = layouts[i % layouts.len()];
+            let block = unsafe { arena.alloc(layout) };
+            assert!(!block.is_null() && (block as usize).is_multiple_of(layout.align()));
+            unsafe { block.write_bytes(i as u8, layout.size()) };
+            blocks.push((block, layout, i as u8));
+        }
+        // No two blocks overlap
+        for &(block, layout, byte) in &blocks {
+            let have = unsafe { slice::from_raw_parts(block, layout.size()) };
+            assert!(have.iter().all(|b| *b == byte));
+        }
+
+        let stats = arena.stats();
+        let want = blocks
+            .iter()
+            .map(|(_, l, _)| l.size().next_power_of_two() as u64);
+        assert!(stats.in_use == want.sum::<u64>(), "{:?}", stats);
+        assert!(stats.chunks >= layouts.len() as u64, "{:?}", stats);
+        assert!(stats.nodes.iter().sum::<u64>() <= stats.chunks * CHUNK_SIZE as u64);
+
+        // Growing within a class keeps the block
+        let (block, layout, _) = blocks[1];
+        let grown = unsafe { arena.realloc(block, layout, 120) };
+        assert!(grown == block);
+        blocks[1].1 = Layout::from_size_align(120, 8).unwrap();
+
+        for &(block, layout, _) in &blocks {
+            unsafe { arena.dealloc(block, layout) };
+        }
+        assert!(arena.stats().in_use == 0);
+        let again = unsafe { arena.alloc(layouts[2]) };
+        assert!(blocks.iter().any(|(block, ..)| *block == again));
+        assert!(arena.stats().chunks == stats.chunks);
+
+        // Too big for a chunk
+        let layout = Layout::from_size_align(MAX_BLOCK + 1, 8).unwrap();
+        let big = unsafe { arena.alloc(layout) };
+        assert!(!big.is_null() && arena.stats().in_use == 2048);
+        unsafe {
+            arena.dealloc(big, layout);
+            arena.dealloc(again, layouts[2]);
+        }
+    }
+}
@@ -0,0 +1,180 @@
+use std::fmt::Debug;
+#[cfg(any(feature = "fs", feature = "parquet"))]
+use std::io;
+use std::mem;
+use std::ops::RangeBounds;
+use std::sync::Arc;
+
+use arrow_array::types::{
+    Int16Type, Int32Type, Int64Type, Int8Type, UInt16Type, UInt32Type, UInt64Type, UInt8Type,
+};
+use arrow_array::{ArrayRef, ArrowPrimitiveType, PrimitiveArray, RecordBatch};
+use arrow_schema::{Field, Schema, SchemaRef};
+
+use crate::btree::{BTree, Increment};
+#[cfg(feature = "fs")]
+use crate::page::Encode;
+#[cfg(feature = "fs")]
+use crate::paged::PagedBTree;
+
+/// Keys and values that are stored in an Arrow column of a primitive type.
+pub trait ArrowColumn: Copy {
+    type Type: ArrowPrimitiveType<Native = Self>;
+}
+
+macro_rules! impl_arrow_column {
+    ($( $t:ty => $a:ty ),*) => {
+        $(
+        impl ArrowColumn for $t {
+            type Type = $a;
+        }
+        )*
+    };
+}
+
+impl_arrow_column!(
+    i8 => Int8Type, i16 => Int16Type, i32 => Int32Type, i64 => Int64Type,
+    u8 => UInt8Type, u16 => UInt16Type, u32 => UInt32Type, u64 => UInt64Type
+);
+
+/// A non-nullable `key` column and a non-nullable `value` column.
+pub fn schema<K: ArrowColumn, V: ArrowColumn>() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("key", K::Type::DATA_TYPE, false),
+        Field::new("value", V::Type::DATA_TYPE, false),
+    ]))
+}
+
+/// Collects `entries` into record batches of up to `rows` rows each, see `schema()`.
+pub fn record_batches<K, V, I>(entries: I, rows: usize) -> Vec<RecordBatch>
+where
+    K: ArrowColumn,
+    V: ArrowColumn,
+    I: IntoIterator<Item = (K, V)>,
+{
+    assert!(rows > 0, "record batches of 0 rows");
+
+    let schema = schema::<K, V>();
+    let batch = |keys: Vec<K>, values: Vec<V>| {
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(PrimitiveArray::<K::Type>::from_iter_values(keys)),
+            Arc::new(PrimitiveArray::<V::Type>::from_iter_values(values)),
+        ];
+        RecordBatch::try_new(schema.clone(), columns).expect("columns match the schema")
+    };
+
+    let mut batches = Vec::new();
+    let (mut keys, mut values) = (Vec::with_capacity(rows), Vec::with_capacity(rows));
+    for (k, v) in entries {
+        keys.push(k);
+        values.push(v);
+        if keys.len() == rows {
+            let k = mem::replace(&mut keys, Vec::with_capacity(rows));
+            let v = mem::replace(&mut values, Vec::with_capacity(rows));
+            batches.push(batch(k, v));
+        }
+    }
+    if !keys.is_empty() {
+        batches.push(batch(keys, values));
+    }
+
+    batches
+}
+
+/// Writes `batches` from `record_batches()` to `writer` as a Parquet file.
+#[cfg(feature = "parquet")]
+pub fn write_parquet<K, V, W>(writer: W, batches: &[RecordBatch]) -> io::Result<()>
+where
+    K: ArrowColumn,
+    V: ArrowColumn,
+    W: io::Write + Send,
+{
+    use parquet::arrow::ArrowWriter;
+
+    let mut writer =
+        ArrowWriter::try_new(writer, schema::<K, V>(), None).map_err(io::Error::other)?;
+    for batch in batches {
+        writer.write(batch).map_err(io::Error::other)?;
+    }
+    writer.close().map_err(io::Error::other)?;
+
+    Ok(())
+}
+
+impl<K, V> BTree<K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment + ArrowColumn,
+    V: Clone + Copy + Debug + Eq + ArrowColumn,
+{
+    /// Scans `range` into record batches of up to `rows` rows each.
+    pub fn range_batches<R: RangeBounds<K>>(&self, range: R, rows: usize) -> Vec<RecordBatch> {
+        record_batches(self.range(range), rows)
+    }
+}
+
+#[cfg(feature = "fs")]
+impl<K, V> PagedBTree<K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment + Encode + ArrowColumn,
+    V: Clone + Copy + Debug + Encode + ArrowColumn,
+{
+    /// Scans `range` into record batches of up to `rows` rows each.
+    pub fn range_batches<R: RangeBounds<K>>(
+        &self,
+        range: R,
+        rows: usize,
+    ) -> io::Result<Vec<RecordBatch>> {
+        Ok(record_batches(self.range(range)?, rows))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::{UInt32Type, UInt64Type};
+
+    use crate::btree::BTree;
+
+    #[test]
+    fn test_range_batches() {
+        let tree = BTree::bulk_load(8, (0..1000u32).map(|k| (k, k as u64 * 3)));
+        let batches = tree.range_batches(100..350, 100);
+        let rows = batches.iter().map(|b| b.num_rows()).collect::<Vec<_>>();
+        assert!(rows == [100, 100, 50], "Have: {:?}", rows);
+
+        let keys = batches[2].column(0).as_primitive::<UInt32Type>();
+        let values = batches[2].column(1).as_primitive::<UInt64Type>();
+        assert!(keys.value(0) == 300 && values.value(49) == 1047);
+        assert!(batches[0].schema().field(1).name() == "value");
+
+        assert!(tree.range_batches(2000.., 100).is_empty());
+
+        #[cfg(feature = "fs")]
+        {
+            use crate::buffer::Capacity;
+            use crate::paged::PagedBTree;
+
+            let mut paged = PagedBTree::create_in_memory(16, Capacity::Pages(16)).unwrap();
+            for k in 0..1000u32 {
+                paged.insert(k, k as u64 * 3).unwrap();
+            }
+            let have = paged.range_batches(100..350, 100).unwrap();
+            assert!(batches == have);
+        }
+
+        #[cfg(feature = "parquet")]
+        {
+            use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+            let file = tempfile::tempfile().unwrap();
+            super::write_parquet::<u32, u64, _>(&file, &batches).unwrap();
+            let reader = ParquetRecordBatchReaderBuilder::try_new(file)
+                .unwrap()
+                .build()
+                .unwrap();
+            let have = reader.map(|batch| batch.unwrap()).collect::<Vec<_>>();
+            let have = have.iter().map(|b| b.num_rows()).sum::<usize>();
+            assert!(have == 250, "Have: {have}");
+        }
+    }
+}
@@ -0,0 +1,239 @@
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+
+use crate::compress::Packed;
+use crate::disk;
+use crate::page::{self, PageId, PAGE_SIZE};
+use crate::paged::wal_path;
+use crate::store::PageStore;
+use crate::wal::Lsn;
+
+const MAGIC: &[u8; 8] = b"BPTINCR\0";
+/// Magic, the LSNs the backup is from and up to, and whether its pages are compressed.
+const HEADER: usize = 32;
+/// Page ID, length and a CRC32C of the page as it is stored.
+const ENTRY_HEADER: usize = 16;
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Copies every page of `disk` to a new file at `path`, as they are stored. The copy is a tree
+/// file of its own, opened with the same key as the original.
+pub(crate) fn full(disk: &dyn PageStore, path: &Path) -> io::Result<()> {
+    let file = File::create(path)?;
+
+    let header = disk.raw_header()?;
+    let packed = !header.is_empty();
+    file.write_all_at(&header, 0)?;
+
+    let mut end = header.len() as u64;
+    for id in (0..disk.pages()).map(PageId) {
+        let raw = match disk.read_raw(id)? {
+            Some(raw) => raw,
+            None => continue,
+        };
+
+        match packed {
+            true => {
+                file.write_all_at(&raw, end)?;
+                end += raw.len() as u64;
+            }
+            false => file.write_all_at(&raw, id.0 * PAGE_SIZE as u64)?,
+        }
+    }
+
+    file.sync_all()
+}
+
+/// Copies the pages of `disk` changed after `since` to a new incremental backup at `path`, which
+/// brings a backup taken at `since` up to `upto`.
+pub(crate) fn incremental(
+    disk: &dyn PageStore,
+    path: &Path,
+    since: Lsn,
+    upto: Lsn,
+) -> io::Result<()> {
+    let mut out = Vec::with_capacity(HEADER);
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&since.to_le_bytes());
+    out.extend_from_slice(&upto.to_le_bytes());
+    out.push(!disk.raw_header()?.is_empty() as u8);
+    out.resize(HEADER, 0);
+
+    let mut buf = [0; PAGE_SIZE];
+    for id in (0..disk.pages()).map(PageId) {
+        disk.read_page(id, &mut buf)?;
+        if page::lsn(&buf) <= since {
+            continue;
+        }
+        let raw = match disk.read_raw(id)? {
+            Some(raw) => raw,
+            None => continue,
+        };
+
+        out.extend_from_slice(&id.0.to_le_bytes());
+        out.extend_from_slice(&(raw.len() as u32).to_le_bytes());
+        out.extend_from_slice(&crc32c::crc32c(&raw).to_le_bytes());
+        out.extend_from_slice(&raw);
+    }
+
+    let file = File::create(path)?;
+    file.write_all_at(&out, 0)?;
+    file.sync_all()
+}
+
+/// Restores a tree to `to` from the full backup at `full` and the incremental backups taken after
+/// it, oldest first. Each incremental backup must be taken since the LSN returned by the backup
+/// before it, or an earlier one. Any tree and log at `to` are replaced, a crash leaves either the
+/// old tree or the restored one.
+pub fn restore<P: AsRef<Path>, Q: AsRef<Path>>(
+    full: P,
+    incrementals: &[Q],
+    to: P,
+) -> io::Result<()> {
+    let to = to.as_ref();
+    let mut tmp = to.as_os_str().to_owned();
+    tmp.push("-restore");
+    let tmp = PathBuf::from(tmp);
+
+    fs::copy(full, &tmp)?;
+    let file = OpenOptions::new().read(true).write(true).open(&tmp)?;
+    let packed = Packed::is_packed(&file)?;
+    let mut end = file.metadata()?.len();
+
+    let mut last: Option<Lsn> = None;
+    for path in incrementals {
+        let path = path.as_ref();
+        let buf = fs::read(path)?;
+        if buf.len() < HEADER || &buf[..8] != MAGIC {
+            return Err(invalid(format!("{} isn't an incremental backup", path.display())));
+        }
+
+        let since = u64::from_le_bytes(buf[8..16].try_into().unwrap());
+        let upto = u64::from_le_bytes(buf[16..24].try_into().unwrap());
+        if (buf[24] == 1) != packed {
+            return Err(invalid(format!(
+                "{} is of a tree stored differently from the full backup",
+                path.display()
+            )));
+        }
+        match last {
+            Some(last) if since > last => {
+                return Err(invalid(format!(
+                    "{} starts at LSN {since}, missing the changes after LSN {last}",
+                    path.display()
+                )))
+            }
+            Some(last) if upto < last => {
+                return Err(invalid(format!(
+                    "{} is up to LSN {upto}, older than the backup before it",
+                    path.display()
+                )))
+            }
+            _ => {}
+        }
+
+        let mut at = HEADER;
+        while at < buf.len() {
+            let corrupt = || invalid(format!("{} is corrupt", path.display()));
+            if at + ENTRY_HEADER > buf.len() {
+                return Err(corrupt());
+            }
+
+            let id = u64::from_le_bytes(buf[at..at + 8].try_into().unwrap());
+            let len = u32::from_le_bytes(buf[at + 8..at + 12].try_into().unwrap()) as usize;
+            let crc = u32::from_le_bytes(buf[at + 12..at + 16].try_into().unwrap());
+            let raw = buf
+                .get(at + ENTRY_HEADER..at + ENTRY_HEADER + len)
+                .filter(|raw| crc32c::crc32c(raw) == crc)
+                .ok_or_else(corrupt)?;
+
+            // The latest record of a page wins in a compressed file
+            match packed {
+                true => {
+                    file.write_all_at(raw, end)?;
+                    end += len as u64;
+                }
+                false => file.write_all_at(raw, id * PAGE_SIZE as u64)?,
+            }
+            at += ENTRY_HEADER + len;
+        }
+
+        last = Some(upto);
+    }
+
+    file.sync_all()?;
+    fs::rename(&tmp, to)?;
+    disk::sync_dir(to)?;
+    match fs::remove_file(wal_path(to)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use crate::compress::Codec;
+    use crate::crypt::Key;
+    use crate::paged::{Options, PagedBTree};
+    use crate::wal::SyncPolicy;
+
+    use super::restore;
+
+    #[test]
+    fn test_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree");
+        let to = dir.path().join("restored");
+        let [full, first, second] = ["full", "first", "second"].map(|name| dir.path().join(name));
+
+        let plain = Options {
+            wal: Some(SyncPolicy::PerCommit),
+            ..Default::default()
+        };
+        let encrypted = Options {
+            codec: Codec::Lz4,
+            key: Some(Key([7; 32])),
+            ..plain
+        };
+
+        for options in [plain, encrypted] {
+            let mut tree = PagedBTree::<u32, u64>::create_with_options(&path, 32, options).unwrap();
+            for k in 0..5000 {
+                tree.insert(k, k as u64).unwrap();
+            }
+            let lsn = tree.backup_to(&full).unwrap();
+
+            for k in 5000..5100 {
+                tree.insert(k, k as u64).unwrap();
+            }
+            let lsn = tree.backup_since(&first, lsn).unwrap();
+            let size = |path| fs::metadata(path).unwrap().len();
+            assert!(size(&first) * 4 < size(&full), "{} {}", size(&first), size(&full));
+
+            for k in (0..5100).step_by(7) {
+                tree.delete(k).unwrap();
+            }
+            tree.insert(1, 100).unwrap();
+            tree.backup_since(&second, lsn).unwrap();
+            let want = tree.iter().unwrap();
+            tree.insert(9000, 0).unwrap();
+            drop(tree);
+
+            // Out of order
+            assert!(restore(&full, &[&second, &first], &to).is_err());
+            assert!(restore(&full, &[&full], &to).is_err());
+
+            restore(&full, &[&first, &second], &to).unwrap();
+            let tree = PagedBTree::<u32, u64>::open_with_options(&to, options).unwrap();
+            let have = tree.iter().unwrap();
+            assert!(tree.len() == want.len(), "Have: {}", tree.len());
+            assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+        }
+    }
+}
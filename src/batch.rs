@@ -0,0 +1,175 @@
+//! Puts and deletes collected to be applied at once, with `BTree::write_batch()` or
+//! `PagedBTree::write_batch()`, in key order. A `BTree` applies all of a batch or, if a trigger
+//! vetoes a write, none of it. A `PagedBTree` with a log commits a batch as one operation, which
+//! a crash leaves whole or drops, see `PagedBTree::write_batch()` for when it doesn't.
+
+use std::collections::BTreeMap;
+
+/// Puts and deletes keyed by their key, the last one for a key replaces the ones before it.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct WriteBatch<K, V> {
+    ops: BTreeMap<K, Option<V>>,
+}
+
+impl<K, V> Default for WriteBatch<K, V> {
+    fn default() -> Self {
+        Self {
+            ops: BTreeMap::new(),
+        }
+    }
+}
+
+impl<K: Ord, V> WriteBatch<K, V> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn put(&mut self, key: K, value: V) {
+        self.ops.insert(key, Some(value));
+    }
+
+    /// Deletes `key`, which needn't be in the tree.
+    pub fn delete(&mut self, key: K) {
+        self.ops.insert(key, None);
+    }
+
+    /// The keys written, each once.
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.ops.clear();
+    }
+
+    /// The writes in key order, `None` for a delete.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, Option<&V>)> {
+        self.ops.iter().map(|(k, v)| (k, v.as_ref()))
+    }
+
+    pub(crate) fn into_ops(self) -> Vec<(K, Option<V>)> {
+        self.ops.into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use rand::seq::SliceRandom;
+    use rand::{thread_rng, Rng};
+
+    use crate::btree::BTree;
+    use crate::slot::Slot;
+    use crate::trigger::Vetoed;
+
+    use super::WriteBatch;
+
+    #[test]
+    fn test_write_batch() {
+        let mut tree = BTree::new(8);
+        let mut want = BTreeMap::new();
+        let mut keys = (0..4000u32).collect::<Vec<_>>();
+        keys.shuffle(&mut thread_rng());
+        for k in &keys[..2000] {
+            tree.insert(Slot::new_leaf(*k, *k));
+            want.insert(*k, *k);
+        }
+
+        for _ in 0..50 {
+            let mut batch = WriteBatch::new();
+            for _ in 0..200 {
+                let k = thread_rng().gen_range(0..4500u32);
+                match thread_rng().gen_bool(0.7) {
+                    true => batch.put(k, k + 1),
+                    false => batch.delete(k),
+                }
+            }
+            for (k, v) in batch.iter() {
+                match v {
+                    Some(v) => want.insert(*k, *v),
+                    None => want.remove(k),
+                };
+            }
+
+            tree.write_batch(batch).unwrap();
+            tree.validate().unwrap();
+            assert!(tree.len() == want.len(), "Want: {}\nHave: {}", want.len(), tree.len());
+        }
+        let have = tree.iter().collect::<Vec<_>>();
+        let want = want.into_iter().collect::<Vec<_>>();
+        assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+
+        // A veto of any write stops all of them
+        tree.before_write(|event| match event.key() {
+            13 => Err(Vetoed("13".into())),
+            _ => Ok(()),
+        });
+        let mut batch = WriteBatch::new();
+        batch.put(1, 0);
+        batch.delete(2);
+        batch.put(13, 0);
+        let have = tree.write_batch(batch.clone());
+        assert!(have == Err(Vetoed("13".into())), "Have: {:?}", have);
+        assert!(tree.iter().collect::<Vec<_>>() == want);
+
+        batch.delete(13);
+        batch.put(13, 0);
+        batch.delete(13);
+        batch.put(4999, 1);
+        assert!(batch.len() == 4);
+        tree.clear_triggers();
+        tree.write_batch(batch).unwrap();
+        assert!(tree.get(1).is_some_and(|s| *s.value() == 0));
+        assert!(tree.get(2).is_none() && tree.get(13).is_none());
+        assert!(tree.get(4999).is_some_and(|s| *s.value() == 1));
+        tree.validate().unwrap();
+
+        #[cfg(feature = "fs")]
+        {
+            use crate::buffer::Capacity;
+            use crate::fault::FaultInjector;
+            use crate::paged::{Options, PagedBTree};
+            use crate::wal::SyncPolicy;
+
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("tree");
+            let options = Options {
+                capacity: Capacity::Pages(256),
+                wal: Some(SyncPolicy::PerCommit),
+                ..Default::default()
+            };
+            let mut tree = PagedBTree::create_with_options(&path, 8, options).unwrap();
+            let mut batch = WriteBatch::new();
+            for k in 0..500u32 {
+                batch.put(k, k as u64);
+            }
+            tree.write_batch(batch).unwrap();
+
+            // The log is written once, at the end of the batch, and a crash tearing that write
+            // loses the whole batch
+            let faults = FaultInjector::new();
+            tree.set_faults(Some(faults.clone()));
+            let mut batch = WriteBatch::new();
+            for k in 0..500u32 {
+                match k % 2 {
+                    0 => batch.delete(k),
+                    _ => batch.put(k + 500, 0),
+                }
+            }
+            faults.crash_after(1, true);
+            assert!(tree.write_batch(batch).is_err() && faults.crashed());
+            std::mem::forget(tree);
+
+            faults.reset();
+            let tree = PagedBTree::<u32, u64>::open(&path).unwrap();
+            let have = tree.iter().unwrap();
+            let want = (0..500u32).map(|k| (k, k as u64)).collect::<Vec<_>>();
+            assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+        }
+    }
+}
@@ -0,0 +1,444 @@
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::ops::{Bound, RangeBounds};
+
+/// Settings for a `BeTree`.
+///
+/// Each internal node has room for `node_size` pivots and buffered messages, of which
+/// `node_size^epsilon` go to pivots and the rest to the buffer. An `epsilon` of 1 is a plain
+/// B+Tree that passes every write straight down to its leaf, lower ones trade fanout, and so read
+/// depth, for larger buffers that amortize each flush over more writes.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Options {
+    /// The most entries in a leaf, and pivots plus messages in an internal node.
+    pub node_size: usize,
+    /// Between 0 and 1.
+    pub epsilon: f64,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            node_size: 64,
+            epsilon: 0.5,
+        }
+    }
+}
+
+impl Options {
+    /// The most children of an internal node, at least 2.
+    pub fn fanout(&self) -> usize {
+        ((self.node_size as f64).powf(self.epsilon).round() as usize).clamp(2, self.node_size)
+    }
+
+    /// The most messages an internal node buffers before flushing some to a child.
+    pub fn buffer(&self) -> usize {
+        self.node_size.saturating_sub(self.fanout())
+    }
+}
+
+/// The sizes from `Options`, so flushing everything can drop the buffer size alone.
+#[derive(Clone, Copy)]
+struct Sizes {
+    leaf: usize,
+    fanout: usize,
+    buffer: usize,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+enum Message<V> {
+    Put(V),
+    Delete,
+}
+
+enum Node<K, V> {
+    Leaf(Vec<(K, V)>),
+    Internal {
+        // `children[i]` holds the keys from `pivots[i - 1]` up to but not including `pivots[i]`
+        pivots: Vec<K>,
+        children: Vec<Node<K, V>>,
+        // Writes to the subtree not applied below yet, newer than any below
+        buffer: BTreeMap<K, Message<V>>,
+    },
+}
+
+/// A write-optimized tree whose internal nodes buffer inserts and deletes as messages.
+///
+/// A write only touches the root's buffer until it fills, then the messages for the child with
+/// the most of them are moved down in one go, so each node written on the way to a leaf carries a
+/// batch of writes rather than one. Reads check the buffers on their path on top of the leaves,
+/// newest first.
+pub struct BeTree<K, V> {
+    root: Node<K, V>,
+    options: Options,
+    sizes: Sizes,
+}
+
+impl<K, V> BeTree<K, V>
+where
+    K: Clone + Copy + Debug + Ord,
+    V: Clone + Copy + Debug,
+{
+    pub fn new(options: Options) -> Self {
+        assert!(options.node_size >= 2, "nodes must hold at least two entries");
+        assert!((0.0..=1.0).contains(&options.epsilon), "epsilon must be within 0..=1");
+
+        Self {
+            root: Node::Leaf(Vec::new()),
+            options,
+            sizes: Sizes {
+                leaf: options.node_size,
+                fanout: options.fanout(),
+                buffer: options.buffer(),
+            },
+        }
+    }
+
+    pub fn options(&self) -> Options {
+        self.options
+    }
+
+    pub fn insert(&mut self, key: K, value: V) {
+        self.send(key, Message::Put(value));
+    }
+
+    /// Deletes are blind, the key is only looked for once the message reaches its leaf.
+    pub fn delete(&mut self, key: K) {
+        self.send(key, Message::Delete);
+    }
+
+    fn send(&mut self, key: K, message: Message<V>) {
+        let split = self.root.apply(vec![(key, message)], self.sizes);
+        self.grow(split, self.sizes);
+    }
+
+    /// Pushes every buffered message down to the leaves.
+    pub fn flush(&mut self) {
+        let sizes = Sizes {
+            buffer: 0,
+            ..self.sizes
+        };
+        let split = self.root.flush(sizes);
+        self.grow(split, sizes);
+    }
+
+    /// Puts the root and the pieces split off it under a new root, until it has few enough.
+    fn grow(&mut self, mut split: Vec<(K, Node<K, V>)>, sizes: Sizes) {
+        while !split.is_empty() {
+            let left = std::mem::replace(&mut self.root, Node::Leaf(Vec::new()));
+            let (pivots, rest): (Vec<_>, Vec<_>) = split.into_iter().unzip();
+            let mut children = vec![left];
+            children.extend(rest);
+            self.root = Node::Internal {
+                pivots,
+                children,
+                buffer: BTreeMap::new(),
+            };
+            split = self.root.split(sizes);
+        }
+    }
+
+    pub fn get(&self, key: K) -> Option<V> {
+        let mut node = &self.root;
+        loop {
+            match node {
+                Node::Leaf(entries) => {
+                    let i = entries.binary_search_by(|e| e.0.cmp(&key)).ok()?;
+                    return Some(entries[i].1);
+                }
+                Node::Internal {
+                    pivots,
+                    children,
+                    buffer,
+                } => match buffer.get(&key) {
+                    Some(Message::Put(v)) => return Some(*v),
+                    Some(Message::Delete) => return None,
+                    None => node = &children[pivots.partition_point(|p| *p <= key)],
+                },
+            }
+        }
+    }
+
+    /// Returns the entries with keys in `range`, in order.
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> Vec<(K, V)> {
+        let mut out = BTreeMap::new();
+        self.root.collect(&range, &mut out);
+        out.into_iter().collect()
+    }
+
+    pub fn iter(&self) -> Vec<(K, V)> {
+        self.range(..)
+    }
+
+    /// Returns the number of messages waiting in buffers.
+    pub fn pending(&self) -> usize {
+        self.root.pending()
+    }
+
+    pub fn height(&self) -> usize {
+        let mut height = 1;
+        let mut node = &self.root;
+        while let Node::Internal { children, .. } = node {
+            height += 1;
+            node = &children[0];
+        }
+
+        height
+    }
+}
+
+impl<K, V> Node<K, V>
+where
+    K: Clone + Copy + Debug + Ord,
+    V: Clone + Copy + Debug,
+{
+    /// Applies `messages`, in key order, to the node. Returns the pieces split off its end, each
+    /// after the pivot it starts at, if it grew too large.
+    fn apply(&mut self, messages: Vec<(K, Message<V>)>, sizes: Sizes) -> Vec<(K, Self)> {
+        match self {
+            Node::Leaf(entries) => {
+                for (key, message) in messages {
+                    match (entries.binary_search_by(|e| e.0.cmp(&key)), message) {
+                        (Ok(i), Message::Put(v)) => entries[i].1 = v,
+                        (Err(i), Message::Put(v)) => entries.insert(i, (key, v)),
+                        (Ok(i), Message::Delete) => {
+                            entries.remove(i);
+                        }
+                        (Err(_), Message::Delete) => {}
+                    }
+                }
+
+                self.split(sizes)
+            }
+            Node::Internal { buffer, .. } => {
+                buffer.extend(messages);
+                self.flush(sizes)
+            }
+        }
+    }
+
+    /// Moves messages down to the child with the most of them until the buffer is within its
+    /// size. Returns the pieces split off like `apply`.
+    fn flush(&mut self, sizes: Sizes) -> Vec<(K, Self)> {
+        let (pivots, children, buffer) = match self {
+            Node::Leaf(_) => return Vec::new(),
+            Node::Internal {
+                pivots,
+                children,
+                buffer,
+            } => (pivots, children, buffer),
+        };
+
+        let adopt = |pivots: &mut Vec<K>, children: &mut Vec<Self>, i, split: Vec<(K, Self)>| {
+            for (j, (pivot, child)) in split.into_iter().enumerate() {
+                pivots.insert(i + j, pivot);
+                children.insert(i + j + 1, child);
+            }
+        };
+
+        while buffer.len() > sizes.buffer {
+            let mut counts = vec![0; children.len()];
+            for key in buffer.keys() {
+                counts[pivots.partition_point(|p| p <= key)] += 1;
+            }
+            let i = (0..counts.len()).max_by_key(|i| counts[*i]).unwrap();
+
+            let lo = match i {
+                0 => Bound::Unbounded,
+                i => Bound::Included(pivots[i - 1]),
+            };
+            let hi = match pivots.get(i) {
+                Some(p) => Bound::Excluded(*p),
+                None => Bound::Unbounded,
+            };
+            let keys = buffer.range((lo, hi)).map(|e| *e.0).collect::<Vec<_>>();
+            let messages = keys
+                .into_iter()
+                .map(|k| (k, buffer.remove(&k).unwrap()))
+                .collect();
+
+            let split = children[i].apply(messages, sizes);
+            adopt(pivots, children, i, split);
+        }
+
+        // Without room for messages, the children must not keep any either
+        if sizes.buffer == 0 {
+            let mut i = 0;
+            while i < children.len() {
+                let split = children[i].flush(sizes);
+                adopt(pivots, children, i, split);
+                i += 1;
+            }
+        }
+
+        self.split(sizes)
+    }
+
+    /// Splits the node into even pieces within the sizes, returning every piece but the first.
+    fn split(&mut self, sizes: Sizes) -> Vec<(K, Self)> {
+        let mut out = Vec::new();
+        match self {
+            Node::Leaf(entries) => {
+                if entries.len() <= sizes.leaf {
+                    return out;
+                }
+
+                let size = entries.len().div_ceil(entries.len().div_ceil(sizes.leaf));
+                let mut rest = entries.split_off(size);
+                while !rest.is_empty() {
+                    let tail = rest.split_off(size.min(rest.len()));
+                    out.push((rest[0].0, Node::Leaf(rest)));
+                    rest = tail;
+                }
+            }
+            Node::Internal {
+                pivots,
+                children,
+                buffer,
+            } => {
+                if children.len() <= sizes.fanout {
+                    return out;
+                }
+
+                let size = children
+                    .len()
+                    .div_ceil(children.len().div_ceil(sizes.fanout));
+                let mut rest = children.split_off(size);
+                let mut rest_pivots = pivots.split_off(size - 1);
+                let mut rest_buffer = buffer.split_off(&rest_pivots[0]);
+                while !rest.is_empty() {
+                    let pivot = rest_pivots.remove(0);
+                    let tail = rest.split_off(size.min(rest.len()));
+                    let tail_pivots = rest_pivots.split_off(rest.len() - 1);
+                    let tail_buffer = match tail_pivots.first() {
+                        Some(p) => rest_buffer.split_off(p),
+                        None => BTreeMap::new(),
+                    };
+                    out.push((
+                        pivot,
+                        Node::Internal {
+                            pivots: rest_pivots,
+                            children: rest,
+                            buffer: rest_buffer,
+                        },
+                    ));
+                    (rest, rest_pivots, rest_buffer) = (tail, tail_pivots, tail_buffer);
+                }
+            }
+        }
+
+        out
+    }
+
+    fn collect<R: RangeBounds<K>>(&self, range: &R, out: &mut BTreeMap<K, V>) {
+        match self {
+            Node::Leaf(entries) => {
+                out.extend(entries.iter().filter(|e| range.contains(&e.0)).copied());
+            }
+            Node::Internal {
+                pivots,
+                children,
+                buffer,
+            } => {
+                for (i, child) in children.iter().enumerate() {
+                    let below_start = match (i, range.start_bound()) {
+                        (i, Bound::Included(s) | Bound::Excluded(s)) if i < pivots.len() => {
+                            pivots[i] <= *s
+                        }
+                        _ => false,
+                    };
+                    let past_end = match (i, range.end_bound()) {
+                        (0, _) => false,
+                        (i, Bound::Included(e)) => pivots[i - 1] > *e,
+                        (i, Bound::Excluded(e)) => pivots[i - 1] >= *e,
+                        (_, Bound::Unbounded) => false,
+                    };
+                    if past_end {
+                        break;
+                    }
+                    if !below_start {
+                        child.collect(range, out);
+                    }
+                }
+
+                for (key, message) in buffer.range((range.start_bound(), range.end_bound())) {
+                    match message {
+                        Message::Put(v) => out.insert(*key, *v),
+                        Message::Delete => out.remove(key),
+                    };
+                }
+            }
+        }
+    }
+
+    fn pending(&self) -> usize {
+        match self {
+            Node::Leaf(_) => 0,
+            Node::Internal {
+                children, buffer, ..
+            } => buffer.len() + children.iter().map(Node::pending).sum::<usize>(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use rand::{seq::SliceRandom, thread_rng};
+
+    use super::{BeTree, Options};
+
+    #[test]
+    fn test_betree() {
+        let options = Options {
+            node_size: 16,
+            epsilon: 0.5,
+        };
+        assert!(options.fanout() == 4 && options.buffer() == 12);
+
+        let mut keys = (0..2000u32).collect::<Vec<_>>();
+        keys.shuffle(&mut thread_rng());
+
+        let mut tree = BeTree::new(options);
+        let mut want = BTreeMap::new();
+        for (i, k) in keys.iter().enumerate() {
+            tree.insert(*k, i);
+            want.insert(*k, i);
+            if i % 3 == 0 {
+                let k = keys[i / 2];
+                tree.delete(k);
+                want.remove(&k);
+            }
+        }
+        assert!(tree.pending() > 0);
+
+        for k in [0, 1, 999, 1999, 2000] {
+            let have = tree.get(k);
+            assert!(have == want.get(&k).copied(), "Want: {:?}\nHave: {:?}", want.get(&k), have);
+        }
+        let have = tree.range(500..1500);
+        let want_range = want
+            .range(500..1500)
+            .map(|(k, v)| (*k, *v))
+            .collect::<Vec<_>>();
+        assert!(want_range == have, "Want: {:?}\nHave: {:?}", want_range, have);
+
+        tree.flush();
+        assert!(tree.pending() == 0);
+        let want = want.into_iter().collect::<Vec<_>>();
+        let have = tree.iter();
+        assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+
+        // With epsilon 1 every write goes straight to its leaf
+        let mut tree = BeTree::new(Options {
+            node_size: 16,
+            epsilon: 1.0,
+        });
+        for k in keys {
+            tree.insert(k, k);
+        }
+        assert!(tree.pending() == 0, "Have: {}", tree.pending());
+        assert!(tree.iter().len() == 2000);
+    }
+}
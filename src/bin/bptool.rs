@@ -0,0 +1,238 @@
+//! Inspects a tree file: a `PagedBTree`, a snapshot from `BTree::save()` or a CSV from
+//! `BTree::export_csv()`. Commands are read from stdin, one per line, run `help` for a list.
+//!
+//!     bptool [--types K:V] [--max N] <path>
+//!
+//! Keys and values are any of `u32`, `u64`, `i32` and `i64`, `u64` by default.
+
+use std::env;
+use std::fmt::{Debug, Display};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+
+use btree::btree::{BTree, Increment, DEFAULT_MAX};
+use btree::csv::CsvImport;
+use btree::page::Encode;
+use btree::paged::PagedBTree;
+use btree::slot::{Either, Slot};
+
+const SNAPSHOT_MAGIC: &[u8; 8] = b"BPTSNAP\0";
+
+const HELP: &str = "\
+get <key>
+range [<from>] [<to>]   keys from <from> up to, excluding, <to>
+insert <key> <value>
+delete <key>
+stats
+validate
+dot [<path>]            to stdout without a path
+quit";
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+enum Tree<K, V> {
+    Memory(BTree<K, V>),
+    Paged(PagedBTree<K, V>),
+}
+
+impl<K, V> Tree<K, V>
+where
+    K: Clone + Copy + Debug + Display + Ord + Increment + Encode + FromStr,
+    V: Clone + Copy + Debug + Display + Eq + Encode + FromStr,
+{
+    fn load(path: &Path, max: usize) -> io::Result<Self> {
+        let mut magic = [0; 8];
+        let snapshot = File::open(path)?.read_exact(&mut magic).is_ok() && &magic == SNAPSHOT_MAGIC;
+
+        if snapshot {
+            Ok(Self::Memory(BTree::load(BufReader::new(File::open(path)?))?))
+        } else if path.extension().is_some_and(|ext| ext == "csv") {
+            let reader = BufReader::new(File::open(path)?);
+            let tree = BTree::import_csv(max, reader, CsvImport::Insert, |rows| {
+                eprintln!("{rows} rows");
+            })?;
+            Ok(Self::Memory(tree))
+        } else {
+            Ok(Self::Paged(PagedBTree::open(path)?))
+        }
+    }
+
+    fn run(&mut self, line: &str, out: &mut impl Write) -> io::Result<bool> {
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        let parse = |i: usize| -> io::Result<K> {
+            let word = words.get(i).ok_or_else(|| invalid("missing key".into()))?;
+            word.parse()
+                .map_err(|_| invalid(format!("bad key {word:?}")))
+        };
+        let bound = |i: usize| words.get(i).map(|_| parse(i)).transpose();
+
+        match words.as_slice() {
+            [] => {}
+            ["get", _] => {
+                let value = match self {
+                    Self::Memory(tree) => tree.get(parse(1)?).map(|slot| match slot.1 {
+                        Either::Left(v) => v,
+                        Either::Right(_) => unreachable!(),
+                    }),
+                    Self::Paged(tree) => tree.get(parse(1)?)?,
+                };
+                match value {
+                    Some(value) => writeln!(out, "{value}")?,
+                    None => writeln!(out, "not found")?,
+                }
+            }
+            ["range", ..] if words.len() <= 3 => {
+                let range = (bound(1)?, bound(2)?);
+                let range = (
+                    range.0.map_or(Bound::Unbounded, Bound::Included),
+                    range.1.map_or(Bound::Unbounded, Bound::Excluded),
+                );
+                let entries = match self {
+                    Self::Memory(tree) => tree.range(range).collect(),
+                    Self::Paged(tree) => tree.range(range)?,
+                };
+                for (k, v) in entries {
+                    writeln!(out, "{k} {v}")?;
+                }
+            }
+            ["insert", _, value] => {
+                let key = parse(1)?;
+                let value = value
+                    .parse()
+                    .map_err(|_| invalid(format!("bad value {value:?}")))?;
+                match self {
+                    Self::Memory(tree) => tree.insert(Slot::new_leaf(key, value)),
+                    Self::Paged(tree) => _ = tree.insert(key, value)?,
+                }
+            }
+            ["delete", _] => {
+                let deleted = match self {
+                    Self::Memory(tree) => tree.delete(parse(1)?),
+                    Self::Paged(tree) => tree.delete(parse(1)?)?.is_some(),
+                };
+                if !deleted {
+                    writeln!(out, "not found")?;
+                }
+            }
+            ["stats"] => match self {
+                Self::Memory(tree) => {
+                    writeln!(out, "entries: {}", tree.iter().count())?;
+                    writeln!(out, "max: {}", tree.max())?;
+                }
+                Self::Paged(tree) => {
+                    let space = tree.space();
+                    writeln!(out, "entries: {}", tree.len())?;
+                    writeln!(out, "file bytes: {}", space.file_bytes)?;
+                    writeln!(out, "pages: {} ({} free)", space.pages, space.free_pages)?;
+                    writeln!(out, "live bytes: {}", space.live_bytes)?;
+                }
+            },
+            ["validate"] => {
+                match self {
+                    Self::Memory(tree) => tree.validate().map_err(invalid)?,
+                    Self::Paged(tree) => tree.validate()?,
+                }
+                writeln!(out, "ok")?;
+            }
+            ["dot"] => self.dot(&mut *out)?,
+            ["dot", path] => self.dot(File::create(path)?)?,
+            ["help"] => writeln!(out, "{HELP}")?,
+            ["quit"] | ["exit"] => return Ok(false),
+            _ => return Err(invalid(format!("bad command {line:?}, try help"))),
+        }
+
+        Ok(true)
+    }
+
+    fn dot<W: Write>(&self, writer: W) -> io::Result<()> {
+        match self {
+            Self::Memory(tree) => tree.to_dot(writer),
+            Self::Paged(tree) => tree.to_dot(writer),
+        }
+    }
+}
+
+fn repl<K, V>(path: &Path, max: usize) -> io::Result<()>
+where
+    K: Clone + Copy + Debug + Display + Ord + Increment + Encode + FromStr,
+    V: Clone + Copy + Debug + Display + Eq + Encode + FromStr,
+{
+    let mut tree = Tree::<K, V>::load(path, max)?;
+    let stdin = io::stdin().lock();
+    let mut stdout = io::stdout().lock();
+
+    for line in stdin.lines() {
+        match tree.run(&line?, &mut stdout) {
+            Ok(true) => {}
+            Ok(false) => break,
+            // Keep going, the tree is still usable
+            Err(e) if e.kind() == io::ErrorKind::InvalidInput => eprintln!("{e}"),
+            Err(e) => return Err(e),
+        }
+        stdout.flush()?;
+    }
+
+    Ok(())
+}
+
+fn run(path: &Path, max: usize, key: &str, value: &str) -> io::Result<()> {
+    macro_rules! types {
+        ($k:ty; $( $v:ident ),*) => {
+            match value {
+                $( stringify!($v) => repl::<$k, $v>(path, max), )*
+                _ => Err(invalid(format!("unsupported value type {value:?}"))),
+            }
+        };
+    }
+
+    match key {
+        "u32" => types!(u32; u32, u64, i32, i64),
+        "u64" => types!(u64; u32, u64, i32, i64),
+        "i32" => types!(i32; u32, u64, i32, i64),
+        "i64" => types!(i64; u32, u64, i32, i64),
+        _ => Err(invalid(format!("unsupported key type {key:?}"))),
+    }
+}
+
+fn main() {
+    let usage = "usage: bptool [--types K:V] [--max N] <path>";
+    let mut args = env::args().skip(1);
+    let (mut types, mut max, mut path) = (String::from("u64:u64"), DEFAULT_MAX, None);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--types" => types = args.next().unwrap_or_default(),
+            "--max" => match args.next().and_then(|max| max.parse().ok()) {
+                Some(n) => max = n,
+                None => {
+                    eprintln!("{usage}");
+                    process::exit(2);
+                }
+            },
+            "-h" | "--help" => {
+                println!("{usage}\n\ncommands:\n{HELP}");
+                return;
+            }
+            _ if path.is_none() => path = Some(PathBuf::from(arg)),
+            _ => {
+                eprintln!("{usage}");
+                process::exit(2);
+            }
+        }
+    }
+
+    let (Some(path), Some((key, value))) = (path, types.split_once(':')) else {
+        eprintln!("{usage}");
+        process::exit(2);
+    };
+    if let Err(e) = run(&path, max, key, value) {
+        eprintln!("bptool: {}: {e}", path.display());
+        process::exit(1);
+    }
+}
@@ -1,14 +1,51 @@
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{BuildHasher, Hash};
+use std::ops::{Bound, Deref, RangeBounds};
 use std::ptr;
+use std::sync::Arc;
 
-use crate::get_right;
-use crate::node::Node;
+use crate::batch::WriteBatch;
+use crate::iter::{Leaves, Range};
+use crate::metrics::{self, Counter, Op};
+use crate::node::{Heat, Node};
+use crate::observe::{NodeRef, Observer};
+#[cfg(feature = "shadow")]
+use crate::shadow::Shadow;
+use crate::sizing::Sizing;
 use crate::slot::{Either, Slot};
+use crate::trace;
+use crate::trigger::{Triggers, Vetoed};
+use crate::watch::{Event, Watcher};
+use crate::{get_left, get_right};
 
+/// The fanout of trees built without one, by `From` and deserializing.
+pub const DEFAULT_MAX: usize = 64;
+
+/// Combines an operand with the value of a key, if it has one, into its new value.
+pub type MergeOperator<K, V> = Box<dyn Fn(K, Option<V>, V) -> V + Send + Sync>;
+
+/// Values are moved through inserts and splits rather than copied, so they can own heap memory.
+/// Reads hand out clones, which an `Arc` makes cheap, or borrow with `get_ref()`.
 pub struct BTree<K, V> {
     root: *mut Node<K, V>,
     max: usize,
+    merge: Option<MergeOperator<K, V>>,
+    // Empty unless something subscribed, so writes only look up the old value for events then
+    pub(crate) watchers: Vec<Watcher<K, V>>,
+    observer: Option<Arc<dyn Observer<K>>>,
+    pub(crate) triggers: Option<Box<Triggers<K, V>>>,
+    // The key after the last one `push()` assigned
+    pushed: Option<K>,
+    pub(crate) sizing: Option<Sizing>,
+    #[cfg(feature = "shadow")]
+    pub(crate) shadow: Shadow<K, V>,
 }
 
+// The tree uniquely owns its nodes and only reads them through `&self`. Watchers hold senders of
+// keys and values, which are only `Sync` for `Send` ones
+unsafe impl<K: Send, V: Send> Send for BTree<K, V> {}
+unsafe impl<K: Send + Sync, V: Send + Sync> Sync for BTree<K, V> {}
+
 pub trait Increment {
     const MAX: Self;
 
@@ -39,26 +76,206 @@ impl_increment!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
 use std::fmt::Debug;
 impl<K, V> BTree<K, V>
 where
-    K: Clone + Copy + Debug + Ord + Copy + Increment,
-    V: Clone + Copy + Debug + Eq,
+    K: Clone + Copy + Debug + Ord + Increment,
+    V: Clone + Debug + Eq,
 {
     pub fn new(max: usize) -> Self {
         Self {
             root: ptr::null_mut(),
             max,
+            merge: None,
+            watchers: Vec::new(),
+            observer: None,
+            triggers: None,
+            pushed: None,
+            sizing: None,
+            #[cfg(feature = "shadow")]
+            shadow: Shadow::new(),
+        }
+    }
+
+    /// Builds a tree from entries in ascending key order, filling each node as far as `insert()`
+    /// would before splitting it. Panics unless the keys are ascending.
+    pub fn bulk_load<I: IntoIterator<Item = (K, V)>>(max: usize, entries: I) -> Self {
+        let fill = (max / 2).max(2);
+
+        let mut level: Vec<*mut Node<K, V>> = Vec::new();
+        let mut last: Option<K> = None;
+        #[cfg(feature = "shadow")]
+        let mut shadow = Shadow::new();
+        for (k, v) in entries {
+            if let Some(last) = last {
+                assert!(k > last, "{:?} loaded after {:?}", k, last);
+            }
+            last = Some(k);
+            #[cfg(feature = "shadow")]
+            shadow.insert(k, v.clone());
+
+            let full = match level.last() {
+                Some(leaf) => unsafe { (**leaf).values.len() == fill },
+                None => true,
+            };
+            if full {
+                let leaf = Box::into_raw(Box::new(Node::new_leaf(max)));
+                if let Some(prev) = level.last() {
+                    unsafe { (**prev).next = leaf };
+                }
+                level.push(leaf);
+            }
+
+            let leaf = unsafe { &mut **level.last().unwrap() };
+            leaf.values.insert(Slot::new_leaf(k, v));
+        }
+
+        // Each level up points to the one below, separated by the last key of every child
+        while level.len() > 1 {
+            level = level
+                .chunks(fill)
+                .map(|children| {
+                    let mut node = Node::new_internal(max);
+                    for child in children {
+                        let c = unsafe { &**child };
+                        let k = match (c.is_leaf(), c.next.is_null()) {
+                            (true, false) => c.separator_before(unsafe { &*c.next }),
+                            (true, true) => c.last_k().unwrap().next(),
+                            (false, _) => c.last_k().unwrap(),
+                        };
+                        node.values.insert(Slot::new_internal(k, *child));
+                        node.len += c.count();
+                    }
+
+                    Box::into_raw(Box::new(node))
+                })
+                .collect();
+        }
+
+        let root = level.pop().unwrap_or(ptr::null_mut());
+        if !root.is_null() {
+            unsafe { (*root).is_root = true };
+        }
+
+        Self {
+            root,
+            max,
+            merge: None,
+            watchers: Vec::new(),
+            observer: None,
+            triggers: None,
+            pushed: None,
+            sizing: None,
+            #[cfg(feature = "shadow")]
+            shadow,
+        }
+    }
+
+    /// The fanout the tree was created with.
+    pub fn max(&self) -> usize {
+        self.max
+    }
+
+    /// The number of entries, from the count the root keeps.
+    pub fn len(&self) -> usize {
+        match self.root.is_null() {
+            true => 0,
+            false => unsafe { (*self.root).count() },
         }
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Null while the tree is empty.
+    pub(crate) fn root(&self) -> *mut Node<K, V> {
+        self.root
+    }
+
+    /// Inserts `entry`, unless a trigger vetoes it.
     pub fn insert(&mut self, entry: Slot<K, V>) {
+        _ = self.insert_checked(entry);
+    }
+
+    /// Like `insert()`, returning the veto of a trigger.
+    pub fn insert_checked(&mut self, entry: Slot<K, V>) -> Result<(), Vetoed> {
         assert!(entry.is_leaf());
+        metrics::count(Counter::Inserts);
+        let _timer = metrics::time(Op::Insert);
+        trace::span!(TRACE, "insert", key = ?entry.0);
+
+        if self.watchers.is_empty() && self.triggers.is_none() {
+            self._insert_root(entry);
+            return Ok(());
+        }
+
+        let old = self.get(entry.0).map(|old| get_left!(old));
+        self.write(entry.0, old, get_left!(entry)).map(|_| ())
+    }
+
+    /// Inserts `value` under a key past both the greatest in the tree and the last one pushed,
+    /// `K::default()` in a tree that never held any, and returns the key. Keys aren't reused once
+    /// deleted, so with `pop_first()` the tree is a queue. A vetoed push still uses up its key.
+    pub fn push(&mut self, value: V) -> K
+    where
+        K: Default,
+    {
+        let last = match self.len() {
+            0 => None,
+            n => {
+                let (leaf, i) = self.locate(n - 1);
+                Some(unsafe { (*leaf).iter().nth(i).unwrap().0.next() })
+            }
+        };
+        let key = match (self.pushed, last) {
+            (Some(pushed), Some(last)) => pushed.max(last),
+            (pushed, last) => pushed.or(last).unwrap_or_default(),
+        };
+
+        self.pushed = Some(key.next());
+        self.insert(Slot::new_leaf(key, value));
+        key
+    }
+
+    /// Puts `new` for `key`, whose value is `old`, through the triggers and subscriptions.
+    /// Returns the value written.
+    fn write(&mut self, key: K, old: Option<V>, new: V) -> Result<V, Vetoed> {
+        let mut event = match old {
+            Some(old) => Event::Update { key, old, new },
+            None => Event::Insert(key, new),
+        };
+        self.run_before(&mut event)?;
+
+        let new = match &event {
+            Event::Insert(_, new) | Event::Update { new, .. } => new.clone(),
+            Event::Delete(..) => unreachable!(),
+        };
+        self._insert_root(Slot::new_leaf(key, new.clone()));
+        self.run_after(&event);
+        self.notify(event);
 
+        Ok(new)
+    }
+
+    fn _insert_root(&mut self, entry: Slot<K, V>) {
+        self.shadow_insert(entry.0, entry.value());
         if self.root.is_null() {
             let mut root = Node::new_leaf(self.max);
             root.is_root = true;
             self.root = Box::into_raw(Box::new(root));
+
+            if let Some(observer) = &self.observer {
+                observer.on_node_alloc(NodeRef::of(self.root));
+                observer.on_root_change(None, Some(NodeRef::of(self.root)));
+            }
         }
 
-        if let Some((s, os)) = BTree::_insert(self.root, entry) {
+        let entry = match self.append(entry) {
+            Ok(()) => return,
+            Err(entry) => entry,
+        };
+
+        let observer = self.observer.as_deref();
+        let sizing = self.sizing.as_ref();
+        if let Some((s, os)) = BTree::_insert(self.root, entry, observer, sizing, &mut false) {
             assert!(get_right!(s) == self.root);
 
             let root = unsafe { &mut *self.root };
@@ -68,19 +285,136 @@ where
             node.is_root = true;
             node.values.replace(s);
             node.values.replace(os);
+            node.len = node.children_count();
 
+            let old = self.root;
             self.root = Box::into_raw(Box::new(node));
+
+            if let Some(observer) = &self.observer {
+                observer.on_node_alloc(NodeRef::of(self.root));
+                observer.on_root_change(Some(NodeRef::of(old)), Some(NodeRef::of(self.root)));
+            }
+        }
+    }
+
+    /// The fast path of `_insert_root()` for keys that belong in the rightmost leaf, as with
+    /// ascending inserts and `push()`. Follows the last children down without searching, and
+    /// hands `entry` back if a node on the way is due to split or the key belongs further left.
+    fn append(&mut self, entry: Slot<K, V>) -> Result<(), Slot<K, V>> {
+        let mut leaf = self.root;
+        loop {
+            let node = unsafe { &*leaf };
+            if node.almost_full() {
+                return Err(entry);
+            }
+            if node.is_leaf() {
+                break;
+            }
+            // The last child holds the keys from the separator of the one before it
+            if node
+                .values
+                .iter()
+                .nth_back(1)
+                .is_some_and(|s| entry.0 < s.0)
+            {
+                return Err(entry);
+            }
+
+            let last = node.values.last().unwrap();
+            leaf = get_right!(last);
+        }
+
+        let key = entry.0;
+        let leaf_node = unsafe { &mut *leaf };
+        if self.sizing.is_some() {
+            leaf_node.heat.write();
+        }
+        let added = leaf_node.values.replace(entry).is_none();
+
+        // Count the entry and move the last separators past it, as `_insert()` would
+        let mut node = self.root;
+        while node != leaf {
+            let n = unsafe { &mut *node };
+            n.len += added as usize;
+            let mut last = n.values.pop_last().unwrap();
+            if last.0 <= key {
+                last.0 = key.next();
+            }
+            node = get_right!(last);
+            n.values.insert(last);
+        }
+
+        Ok(())
+    }
+
+    /// Sets the hooks called as the tree changes shape, see `Observer`.
+    pub fn set_observer(&mut self, observer: Option<Arc<dyn Observer<K>>>) {
+        self.observer = observer;
+    }
+
+    /// Sets the function `merge()` combines operands with, such as adding them to a counter.
+    pub fn set_merge_operator<F>(&mut self, merge: F)
+    where
+        F: Fn(K, Option<V>, V) -> V + Send + Sync + 'static,
+    {
+        self.merge = Some(Box::new(merge));
+    }
+
+    /// Combines `operand` with the value of `key` using the merge operator, in one pass down the
+    /// tree if `key` is there, and returns the new value. Panics unless a merge operator is set.
+    ///
+    /// Triggers see the merged value, one that is vetoed is returned but not written.
+    pub fn merge(&mut self, key: K, operand: V) -> V {
+        let merge = self.merge.as_ref().expect("no merge operator set");
+
+        let leaf = match self.root.is_null() {
+            true => ptr::null_mut(),
+            false => Self::find_leaf(self.root, key),
+        };
+        let slot = Slot::new_internal(key, ptr::null_mut());
+        let old = match leaf.is_null() {
+            true => None,
+            false => unsafe { (*leaf).values.get(&slot).cloned() },
+        };
+
+        match old {
+            Some(Slot(_, Either::Left(old))) => {
+                if !self.watchers.is_empty() || self.triggers.is_some() {
+                    let value = merge(key, Some(old.clone()), operand);
+                    return self.write(key, Some(old), value.clone()).unwrap_or(value);
+                }
+
+                let value = merge(key, Some(old), operand);
+                self.shadow_insert(key, &value);
+                unsafe { (*leaf).values.replace(Slot::new_leaf(key, value.clone())) };
+                value
+            }
+            _ => {
+                let value = merge(key, None, operand);
+                if !self.watchers.is_empty() || self.triggers.is_some() {
+                    return self.write(key, None, value.clone()).unwrap_or(value);
+                }
+
+                self._insert_root(Slot::new_leaf(key, value.clone()));
+                value
+            }
         }
     }
 
     /// Returns a slot for the original page (lower half) and a pointer to the new page (higher
-    /// half) if there is a split.
+    /// half) if there is a split. Sets `added` if the key is new. With `sizing`, leaves that split
+    /// get the fanout it picks for them.
     #[must_use]
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
     pub fn _insert(
         raw_node: *mut Node<K, V>,
         value: Slot<K, V>,
+        observer: Option<&dyn Observer<K>>,
+        sizing: Option<&Sizing>,
+        added: &mut bool,
     ) -> Option<(Slot<K, V>, Slot<K, V>)> {
         let mut node = unsafe { &mut *raw_node };
+        let key = value.0;
 
         // If `split` is set, it will hold the updated slot for `node` and a new slot for the
         // greater node
@@ -88,15 +422,26 @@ where
         if node.almost_full() {
             let raw_gt_node = node.split();
             split = Some(raw_gt_node);
+            if let Some(sizing) = sizing.filter(|_| node.is_leaf()) {
+                let max = sizing.resize(node);
+                node.heat = Heat::default();
+                node.max = max;
+                unsafe { (*raw_gt_node).max = max };
+            }
 
-            let last = node.last_k().expect("there should be a last node");
-
-            if value.0 >= last {
+            let sep = node.separator_before(unsafe { &*raw_gt_node });
+            trace::event!(TRACE, leaf = node.is_leaf(), separator = ?sep, "split");
+            if let Some(observer) = observer {
+                let gt = NodeRef::of(raw_gt_node);
+                observer.on_node_alloc(gt);
+                observer.on_split(NodeRef::of(raw_node), gt, sep);
+            }
+            if value.0 >= sep {
                 node = unsafe { &mut *raw_gt_node };
             }
         }
 
-        let ptr = match node.find_child(value) {
+        let ptr = match node.find_child(value.0) {
             Some(ptr) => ptr,
             None if !node.is_leaf() => {
                 // Set last slot to K
@@ -104,18 +449,27 @@ where
                 l.0 = value.0.next();
                 node.values.insert(l);
 
-                match node.find_child(value) {
+                match node.find_child(value.0) {
                     Some(ptr) => ptr,
                     None => unreachable!(),
                 }
             }
             None => {
-                node.values.replace(value);
+                if sizing.is_some() {
+                    node.heat.write();
+                }
+                *added = node.values.replace(value).is_none();
                 return Node::get_separators(raw_node, split);
             }
         };
 
-        if let Some((s, os)) = BTree::_insert(ptr, value) {
+        let child = BTree::_insert(ptr, value, observer, sizing, added);
+        if *added {
+            node.len += 1;
+        }
+        if let Some((s, mut os)) = child {
+            // The greater half keeps the child's old separator, which may be past its last key
+            os.0 = node.values.iter().find(|n| key < n.0).unwrap().0;
             node.values.replace(s);
             node.values.replace(os);
         }
@@ -124,49 +478,659 @@ where
     }
 
     pub fn get(&self, key: K) -> Option<Slot<K, V>> {
+        metrics::count(Counter::Gets);
+        let _timer = metrics::time(Op::Get);
+        trace::span!(TRACE, "get", key = ?key);
         if self.root.is_null() {
+            self.shadow_get(key, None);
             return None;
         }
 
         let test = Slot::new_internal(key, ptr::null_mut());
-        Self::_get(self.root, test)
+        let have = match self.sizing {
+            Some(_) => match unsafe { Self::find_leaf(self.root, key).as_ref() } {
+                Some(leaf) => {
+                    leaf.heat.read();
+                    leaf.values.get(&test).cloned()
+                }
+                None => None,
+            },
+            None => Self::_get(self.root, test),
+        };
+        self.shadow_get(key, have.as_ref().map(|s| s.value()));
+        have
     }
 
-    fn _get(raw_node: *mut Node<K, V>, slot: Slot<K, V>) -> Option<Slot<K, V>> {
+    /// Like `get()`, returning the value where it is in the tree rather than a copy of it. The
+    /// tree can't change while the guard is held.
+    pub fn get_ref(&self, key: K) -> Option<Ref<'_, K, V>> {
+        metrics::count(Counter::Gets);
+        let _timer = metrics::time(Op::Get);
+        trace::span!(TRACE, "get", key = ?key);
+        let leaf = match self.root.is_null() {
+            true => ptr::null_mut(),
+            false => Self::find_leaf(self.root, key),
+        };
+        let slot = match unsafe { leaf.as_ref() } {
+            None => None,
+            Some(leaf) => {
+                if self.sizing.is_some() {
+                    leaf.heat.read();
+                }
+                leaf.values.get(&Slot::new_internal(key, ptr::null_mut()))
+            }
+        };
+        self.shadow_get(key, slot.map(|s| s.value()));
+
+        match slot?.1 {
+            Either::Left(ref value) => Some(Ref { key, value }),
+            Either::Right(_) => unreachable!(),
+        }
+    }
+
+    pub(crate) fn _get(raw_node: *mut Node<K, V>, slot: Slot<K, V>) -> Option<Slot<K, V>> {
         let node = unsafe { &*raw_node };
 
-        match node.find_child(slot) {
+        match node.find_child(slot.0) {
             Some(ptr) => Self::_get(ptr, slot),
-            None if node.is_leaf() => {
-                return match node.values.get(&slot) {
-                    Some(slot) => Some(*slot),
-                    None => None,
-                }
-            }
+            None if node.is_leaf() => node.values.get(&slot).cloned(),
             None => None,
         }
     }
 
+    /// Returns whether `key` was there and deleted, it isn't if a trigger vetoes it.
     pub fn delete(&mut self, key: K) -> bool {
+        self.delete_checked(key).unwrap_or(false)
+    }
+
+    /// Like `delete()`, returning the veto of a trigger.
+    pub fn delete_checked(&mut self, key: K) -> Result<bool, Vetoed> {
+        metrics::count(Counter::Deletes);
+        let _timer = metrics::time(Op::Delete);
+        trace::span!(TRACE, "delete", key = ?key);
         if self.root.is_null() {
-            return false;
+            self.shadow_delete(key, false);
+            return Ok(false);
         }
 
         let test = Slot::new_internal(key, ptr::null_mut());
-        Self::_delete(self.root, test)
+        if self.watchers.is_empty() && self.triggers.is_none() {
+            let deleted = Self::_delete(self.root, test);
+            self.shadow_delete(key, deleted);
+            return Ok(deleted);
+        }
+
+        let old = match self.get(key) {
+            Some(old) => get_left!(old),
+            None => return Ok(false),
+        };
+        let mut event = Event::Delete(key, old);
+        self.run_before(&mut event)?;
+
+        let deleted = Self::_delete(self.root, test);
+        self.shadow_delete(key, deleted);
+        self.run_after(&event);
+        self.notify(event);
+
+        Ok(true)
+    }
+
+    /// Applies every put and delete of `batch`, or none of them if a trigger vetoes one. Keys are
+    /// written in order, each run of them that belongs in one leaf after a single descent to it.
+    ///
+    /// Before triggers see every change before any is made, after triggers and subscribers see
+    /// them once all are.
+    pub fn write_batch(&mut self, batch: WriteBatch<K, V>) -> Result<(), Vetoed> {
+        trace::span!(TRACE, "write_batch", len = batch.len());
+        let mut ops = batch.into_ops();
+
+        let mut events = Vec::new();
+        if !self.watchers.is_empty() || self.triggers.is_some() {
+            for (key, op) in &mut ops {
+                let old = self.get(*key).map(|old| get_left!(old));
+                let mut event = match (old, op.take()) {
+                    (Some(old), Some(new)) => Event::Update {
+                        key: *key,
+                        old,
+                        new,
+                    },
+                    (None, Some(new)) => Event::Insert(*key, new),
+                    (Some(old), None) => Event::Delete(*key, old),
+                    (None, None) => continue,
+                };
+                self.run_before(&mut event)?;
+
+                *op = match &event {
+                    Event::Insert(_, new) | Event::Update { new, .. } => Some(new.clone()),
+                    Event::Delete(..) => None,
+                };
+                events.push(event);
+            }
+        }
+
+        self.write_runs(ops);
+        for event in events {
+            self.run_after(&event);
+            self.notify(event);
+        }
+
+        Ok(())
     }
 
-    fn _delete(raw_node: *mut Node<K, V>, slot: Slot<K, V>) -> bool {
+    /// Writes `ops`, in ascending key order, a run at a time. The keys after the first of a run
+    /// that belong in its leaf are written to it without another descent, and the counts of the
+    /// nodes above it updated once. A put into a leaf due to split, or past the last separator,
+    /// ends the run and is inserted like any other.
+    fn write_runs(&mut self, ops: Vec<(K, Option<V>)>) {
+        let mut ops = ops.into_iter().peekable();
+        let mut path = Vec::new();
+        while let Some((key, value)) = ops.next() {
+            let (leaf, upper) = self.find_leaf_path(key, &mut path);
+            if leaf.is_null() || (value.is_some() && unsafe { (*leaf).almost_full() }) {
+                match value {
+                    Some(value) => {
+                        metrics::count(Counter::Inserts);
+                        self._insert_root(Slot::new_leaf(key, value));
+                    }
+                    None => {
+                        metrics::count(Counter::Deletes);
+                        let test = Slot::new_internal(key, ptr::null_mut());
+                        let deleted = !self.root.is_null() && Self::_delete(self.root, test);
+                        self.shadow_delete(key, deleted);
+                    }
+                }
+                continue;
+            }
+
+            let leaf = unsafe { &mut *leaf };
+            let (mut added, mut deleted) = (0, 0);
+            let mut next = Some((key, value));
+            while let Some((key, value)) = next {
+                match value {
+                    Some(value) => {
+                        metrics::count(Counter::Inserts);
+                        self.shadow_insert(key, &value);
+                        if self.sizing.is_some() {
+                            leaf.heat.write();
+                        }
+                        added += leaf.values.replace(Slot::new_leaf(key, value)).is_none() as usize;
+                    }
+                    None => {
+                        metrics::count(Counter::Deletes);
+                        let removed = leaf
+                            .values
+                            .remove(&Slot::new_internal(key, ptr::null_mut()));
+                        self.shadow_delete(key, removed);
+                        deleted += removed as usize;
+                    }
+                }
+
+                next = ops.next_if(|(k, v)| {
+                    upper.is_none_or(|upper| *k < upper) && (v.is_none() || !leaf.almost_full())
+                });
+            }
+
+            for &node in &path {
+                let node = unsafe { &mut *node };
+                node.len = node.len + added - deleted;
+            }
+        }
+    }
+
+    /// Deletes every entry `f` returns `true` for in one pass, returning how many. Leaves and
+    /// subtrees whose entries all match are detached and freed whole.
+    ///
+    /// With triggers or subscriptions, each entry is deleted like `delete()` instead.
+    pub fn delete_where<F: FnMut(K, V) -> bool>(&mut self, mut f: F) -> usize {
+        if !self.watchers.is_empty() || self.triggers.is_some() {
+            let matched = self
+                .iter()
+                .filter_map(|(k, v)| f(k, v).then_some(k))
+                .collect::<Vec<_>>();
+            return matched.into_iter().filter(|k| self.delete(*k)).count();
+        }
+
+        if self.root.is_null() {
+            return 0;
+        }
+
+        #[cfg(feature = "shadow")]
+        let mut matched = Vec::new();
+        #[cfg(feature = "shadow")]
+        let mut f = |k, v| {
+            let m = f(k, v);
+            if m {
+                matched.push(k);
+            }
+            m
+        };
+
+        let observer = self.observer.as_deref();
+        let mut prev = ptr::null_mut();
+        let (deleted, all) = Self::_delete_where(self.root, &mut f, &mut prev, observer);
+        if all {
+            let old = NodeRef::of(self.root);
+            free(self.root, observer);
+            self.root = ptr::null_mut();
+
+            if let Some(observer) = observer {
+                observer.on_root_change(Some(old), None);
+            }
+        } else {
+            unsafe { (*prev).next = ptr::null_mut() };
+        }
+
+        #[cfg(feature = "shadow")]
+        self.shadow_bulk("delete_where()", |shadow| {
+            for k in matched {
+                shadow.remove(&k);
+            }
+        });
+
+        deleted
+    }
+
+    /// Deletes the matching entries below `raw_node`, returning how many and whether that was all
+    /// of them, in which case the caller detaches the node. The leaves kept are linked after
+    /// `prev`, the last leaf kept so far.
+    fn _delete_where<F: FnMut(K, V) -> bool>(
+        raw_node: *mut Node<K, V>,
+        f: &mut F,
+        prev: &mut *mut Node<K, V>,
+        observer: Option<&dyn Observer<K>>,
+    ) -> (usize, bool) {
         let node = unsafe { &mut *raw_node };
 
-        match node.find_child(slot) {
-            Some(ptr) => Self::_delete(ptr, slot),
-            None if node.is_leaf() => return node.values.remove(&slot),
+        if node.is_leaf() {
+            let keep = node
+                .iter()
+                .map(|s| !f(s.0, s.value().clone()))
+                .collect::<Vec<_>>();
+            let kept = keep.iter().filter(|k| **k).count();
+            if kept == 0 {
+                return (keep.len(), true);
+            }
+
+            let deleted = keep.len() - kept;
+            let mut keep = keep.into_iter();
+            node.values.retain(|_| keep.next().unwrap());
+            if !prev.is_null() {
+                unsafe { (**prev).next = raw_node };
+            }
+            *prev = raw_node;
+
+            return (deleted, false);
+        }
+
+        let mut deleted = 0;
+        let mut detached = Vec::new();
+        for slot in node.iter() {
+            let (n, all) = Self::_delete_where(get_right!(slot), f, prev, observer);
+            deleted += n;
+            if all {
+                detached.push(slot.clone());
+            }
+        }
+        if detached.len() == node.values.len() {
+            return (deleted, true);
+        }
+
+        node.len -= deleted;
+        let last = node.values.last().unwrap().0;
+        for slot in &detached {
+            node.values.remove(slot);
+            free(get_right!(slot), observer);
+        }
+        // Keep the last separator, so keys up to it still have a child to go to, all the way down
+        if detached.last().is_some_and(|s| s.0 == last) {
+            let mut node = node;
+            loop {
+                let mut l = node.values.pop_last().unwrap();
+                l.0 = last;
+                let child = get_right!(l);
+                node.values.insert(l);
+
+                node = unsafe { &mut *child };
+                if node.is_leaf() {
+                    break;
+                }
+            }
+        }
+
+        (deleted, false)
+    }
+
+    pub(crate) fn _delete(raw_node: *mut Node<K, V>, slot: Slot<K, V>) -> bool {
+        let node = unsafe { &mut *raw_node };
+
+        match node.find_child(slot.0) {
+            Some(ptr) => {
+                let deleted = Self::_delete(ptr, slot);
+                if deleted {
+                    node.len -= 1;
+                }
+                deleted
+            }
+            None if node.is_leaf() => node.values.remove(&slot),
             None => false,
         }
     }
 
-    #[cfg(test)]
+    /// Returns an iterator over the entries with keys in `range`, in order.
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> Range<'_, K, V> {
+        let (start, end) = (range.start_bound().cloned(), range.end_bound().cloned());
+        self.shadow_range(start, end, || self._range((start, end)));
+        self._range(range)
+    }
+
+    pub(crate) fn _range<R: RangeBounds<K>>(&self, range: R) -> Range<'_, K, V> {
+        let end = range.end_bound().cloned();
+        if self.root.is_null() {
+            return Range::new(self, ptr::null_mut(), 0, end, 0);
+        }
+
+        let upper = match end {
+            Bound::Included(k) => self.rank(k, true),
+            Bound::Excluded(k) => self.rank(k, false),
+            Bound::Unbounded => self.len(),
+        };
+        let (start, excluded) = match range.start_bound() {
+            Bound::Included(k) => (*k, false),
+            Bound::Excluded(k) => (*k, true),
+            Bound::Unbounded => {
+                return Range::new(self, Self::get_leftmost_leaf(self.root), 0, end, upper);
+            }
+        };
+        let remaining = upper.saturating_sub(self.rank(start, excluded));
+
+        let leaf = Self::find_leaf(self.root, start);
+        if leaf.is_null() {
+            return Range::new(self, leaf, 0, end, 0);
+        }
+
+        let node = unsafe { &*leaf };
+        let i = node
+            .iter()
+            .position(|s| if excluded { s.0 > start } else { s.0 >= start })
+            .unwrap_or(node.values.len());
+
+        Range::new(self, leaf, i, end, remaining)
+    }
+
+    /// The number of entries with keys less than `key`, or up to it if `inclusive`, counting the
+    /// children passed on the way down.
+    pub(crate) fn rank(&self, key: K, inclusive: bool) -> usize {
+        let mut rank = 0;
+        let mut raw_node = self.root;
+        while !raw_node.is_null() {
+            let node = unsafe { &*raw_node };
+            if node.is_leaf() {
+                return rank
+                    + node
+                        .iter()
+                        .take_while(|s| s.0 < key || inclusive && s.0 == key)
+                        .count();
+            }
+
+            raw_node = ptr::null_mut();
+            for slot in node.iter() {
+                let child = get_right!(slot);
+                if key < slot.0 {
+                    raw_node = child;
+                    break;
+                }
+                rank += unsafe { (*child).count() };
+            }
+        }
+
+        rank
+    }
+
+    pub fn iter(&self) -> Range<'_, K, V> {
+        self.range(..)
+    }
+
+    /// Returns an iterator over the leaves in order, each as a slice of its entries, for working
+    /// through the tree a block at a time.
+    pub fn leaves(&self) -> Leaves<'_, K, V> {
+        match self.root.is_null() {
+            true => Leaves::new(ptr::null_mut()),
+            false => Leaves::new(Self::get_leftmost_leaf(self.root)),
+        }
+    }
+
+    /// The entry with the least key.
+    pub fn first(&self) -> Option<(K, V)> {
+        self.iter().next()
+    }
+
+    /// Removes the entry with the least key.
+    pub fn pop_first(&mut self) -> Option<(K, V)> {
+        let first = self.first()?;
+        self.delete(first.0);
+        Some(first)
+    }
+
+    /// Calls `f` with every entry with a key in `range`, in order, to change its value in place in
+    /// one pass along the leaves. Returns how many entries there were.
+    ///
+    /// With triggers or subscriptions, each changed value is written like `insert()` instead.
+    pub fn update_range<R, F>(&mut self, range: R, mut f: F) -> usize
+    where
+        R: RangeBounds<K>,
+        F: FnMut(K, &mut V),
+    {
+        if !self.watchers.is_empty() || self.triggers.is_some() {
+            let entries = self.range(range).collect::<Vec<_>>();
+            let n = entries.len();
+            for (key, old) in entries {
+                let mut new = old.clone();
+                f(key, &mut new);
+                if new != old {
+                    _ = self.write(key, Some(old), new);
+                }
+            }
+
+            return n;
+        }
+
+        if self.root.is_null() {
+            return 0;
+        }
+        let mut leaf = match range.start_bound() {
+            Bound::Included(k) | Bound::Excluded(k) => Self::find_leaf(self.root, *k),
+            Bound::Unbounded => Self::get_leftmost_leaf(self.root),
+        };
+
+        #[cfg(feature = "shadow")]
+        let mut updated = Vec::new();
+        #[cfg(feature = "shadow")]
+        let mut f = |k, v: &mut V| {
+            f(k, v);
+            updated.push((k, v.clone()));
+        };
+
+        let mut n = 0;
+        while !leaf.is_null() {
+            let node = unsafe { &mut *leaf };
+            n += node.values.update_range(&range, |k, v| f(*k, v));
+
+            let past_end = node.last_k().is_some_and(|k| match range.end_bound() {
+                Bound::Included(end) | Bound::Excluded(end) => k >= *end,
+                Bound::Unbounded => false,
+            });
+            if past_end {
+                break;
+            }
+            leaf = node.next;
+        }
+
+        #[cfg(feature = "shadow")]
+        self.shadow_bulk("update_range()", |shadow| {
+            for (k, v) in updated {
+                assert!(shadow.insert(k, v).is_some(), "update_range() added {:?}", k);
+            }
+        });
+
+        n
+    }
+
+    /// The entry with `rank` entries before it, which there must be.
+    pub(crate) fn select(&self, rank: usize) -> (K, V) {
+        let (leaf, i) = self.locate(rank);
+        let slot = unsafe { (*leaf).iter().nth(i).unwrap() };
+        (slot.0, slot.value().clone())
+    }
+
+    /// The leaf and index of the entry with `rank` entries before it, which there must be.
+    pub(crate) fn locate(&self, mut rank: usize) -> (*mut Node<K, V>, usize) {
+        let mut node = unsafe { &*self.root };
+        while !node.is_leaf() {
+            let child = node
+                .iter()
+                .map(|s| unsafe { &*get_right!(s) })
+                .find(|child| match rank < child.count() {
+                    true => true,
+                    false => {
+                        rank -= child.count();
+                        false
+                    }
+                })
+                .expect("rank should be below the count");
+            node = child;
+        }
+
+        (node as *const _ as *mut _, rank)
+    }
+
+    /// Returns the leaf `key` belongs in, or null if it is greater than every separator.
+    fn find_leaf(raw_node: *mut Node<K, V>, key: K) -> *mut Node<K, V> {
+        let node = unsafe { &*raw_node };
+        if node.is_leaf() {
+            return raw_node;
+        }
+
+        match node.find_child(key) {
+            Some(ptr) => Self::find_leaf(ptr, key),
+            None => ptr::null_mut(),
+        }
+    }
+
+    /// Like `find_leaf()` from the root, collecting the internal nodes on the way into `path`.
+    /// Also returns the separator above the leaf, which its keys are less than, `None` for a root
+    /// leaf.
+    fn find_leaf_path(
+        &self,
+        key: K,
+        path: &mut Vec<*mut Node<K, V>>,
+    ) -> (*mut Node<K, V>, Option<K>) {
+        path.clear();
+        let mut upper = None;
+        let mut raw_node = self.root;
+        while let Some(node) = unsafe { raw_node.as_ref() } {
+            if node.is_leaf() {
+                return (raw_node, upper);
+            }
+
+            let Some(slot) = node.values.iter().find(|s| key < s.0) else {
+                break;
+            };
+            path.push(raw_node);
+            upper = Some(slot.0);
+            raw_node = get_right!(slot);
+        }
+
+        (ptr::null_mut(), None)
+    }
+
+    /// Checks the structure of the tree: the keys of every node in order and within the
+    /// separators above it, every leaf at the same depth, and the leaf chain linking the leaves in
+    /// order. Returns what is wrong, if anything.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.root.is_null() {
+            return Ok(());
+        }
+
+        let mut leaves = Vec::new();
+        Self::_validate(self.root, (None, None), &mut leaves, 0)?;
+
+        if let Some((_, depth)) = leaves.iter().find(|(_, d)| *d != leaves[0].1) {
+            return Err(format!("leaves at depths {} and {depth}", leaves[0].1));
+        }
+        let chain = leaves
+            .iter()
+            .skip(1)
+            .map(|(leaf, _)| *leaf)
+            .chain([ptr::null_mut()]);
+        for ((leaf, _), want) in leaves.iter().zip(chain) {
+            let have = unsafe { (**leaf).next };
+            if have != want {
+                return Err(format!("leaf {:?} links to {:?}, not {:?}", leaf, have, want));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Keys in `raw_node` must be within `bounds`, from the separators above it.
+    fn _validate(
+        raw_node: *mut Node<K, V>,
+        (lo, hi): (Option<K>, Option<K>),
+        leaves: &mut Vec<(*mut Node<K, V>, usize)>,
+        depth: usize,
+    ) -> Result<(), String> {
+        let node = unsafe { &*raw_node };
+
+        let keys = node.iter().map(|s| s.0).collect::<Vec<_>>();
+        if keys.windows(2).any(|w| w[0] >= w[1]) {
+            return Err(format!("node {:?} has keys out of order: {:?}", raw_node, keys));
+        }
+        // An internal node's last separator is its parent's separator for it
+        let outside = |k: &K| match node.is_leaf() {
+            true => lo.is_some_and(|lo| *k < lo) || hi.is_some_and(|hi| *k >= hi),
+            false => lo.is_some_and(|lo| *k <= lo) || hi.is_some_and(|hi| *k > hi),
+        };
+        if let Some(k) = keys.iter().find(|k| outside(k)) {
+            return Err(format!(
+                "node {:?} has key {:?} outside of {:?}..{:?}",
+                raw_node, k, lo, hi
+            ));
+        }
+
+        if node.is_leaf() {
+            leaves.push((raw_node, depth));
+            return Ok(());
+        }
+        if keys.is_empty() {
+            return Err(format!("internal node {:?} has no children", raw_node));
+        }
+        // Or keys between the two would have nowhere to go
+        if hi.is_some_and(|hi| keys[keys.len() - 1] != hi) {
+            return Err(format!(
+                "node {:?} ends at {:?}, not its separator {:?}",
+                raw_node,
+                keys[keys.len() - 1],
+                hi
+            ));
+        }
+        if node.len != node.children_count() {
+            return Err(format!(
+                "node {:?} counts {} entries, its children {}",
+                raw_node,
+                node.len,
+                node.children_count()
+            ));
+        }
+
+        let mut lo = lo;
+        for slot in node.iter() {
+            Self::_validate(get_right!(slot), (lo, Some(slot.0)), leaves, depth + 1)?;
+            lo = Some(slot.0);
+        }
+
+        Ok(())
+    }
+
     fn get_leftmost_leaf(raw_node: *mut Node<K, V>) -> *mut Node<K, V> {
         let node = unsafe { &*raw_node };
         if node.is_leaf() {
@@ -182,6 +1146,94 @@ where
     }
 }
 
+/// A value read in place by `BTree::get_ref()`, which borrows the tree.
+#[derive(Debug)]
+pub struct Ref<'a, K, V> {
+    key: K,
+    value: &'a V,
+}
+
+impl<K: Copy, V> Ref<'_, K, V> {
+    pub fn key(&self) -> K {
+        self.key
+    }
+}
+
+impl<K, V> Deref for Ref<'_, K, V> {
+    type Target = V;
+
+    fn deref(&self) -> &V {
+        self.value
+    }
+}
+
+/// Frees the subtree at `ptr`.
+fn free<K, V>(ptr: *mut Node<K, V>, observer: Option<&dyn Observer<K>>) {
+    if let Some(observer) = observer {
+        observer.on_node_free(NodeRef::of(ptr));
+    }
+
+    let node = unsafe { Box::from_raw(ptr) };
+    for slot in &node.values {
+        if let Either::Right(child) = slot.1 {
+            free(child, observer);
+        }
+    }
+}
+
+impl<K, V> Drop for BTree<K, V> {
+    fn drop(&mut self) {
+        if !self.root.is_null() {
+            free(self.root, self.observer.as_deref());
+        }
+    }
+}
+
+impl<K, V> From<BTreeMap<K, V>> for BTree<K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment,
+    V: Clone + Debug + Eq,
+{
+    /// Bulk loads the map, which is already sorted, with a fanout of `DEFAULT_MAX`.
+    fn from(map: BTreeMap<K, V>) -> Self {
+        BTree::bulk_load(DEFAULT_MAX, map)
+    }
+}
+
+impl<K, V, S> From<HashMap<K, V, S>> for BTree<K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment,
+    V: Clone + Debug + Eq,
+{
+    /// Sorts the map and bulk loads it with a fanout of `DEFAULT_MAX`.
+    fn from(map: HashMap<K, V, S>) -> Self {
+        let mut entries = map.into_iter().collect::<Vec<_>>();
+        entries.sort_unstable_by_key(|(k, _)| *k);
+        BTree::bulk_load(DEFAULT_MAX, entries)
+    }
+}
+
+impl<K, V> From<BTree<K, V>> for BTreeMap<K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment,
+    V: Clone + Debug + Eq,
+{
+    fn from(tree: BTree<K, V>) -> Self {
+        tree.iter().collect()
+    }
+}
+
+impl<K, V, S> From<BTree<K, V>> for HashMap<K, V, S>
+where
+    K: Clone + Copy + Debug + Ord + Increment + Hash,
+    V: Clone + Debug + Eq,
+    S: BuildHasher + Default,
+{
+    fn from(tree: BTree<K, V>) -> Self {
+        tree.iter().collect()
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::ops::Range;
@@ -207,6 +1259,7 @@ mod test {
     }
 
     #[test]
+    #[allow(clippy::single_match)]
     fn test_btree() {
         const MAX: usize = 8;
 
@@ -269,6 +1322,191 @@ mod test {
     }
 
     #[test]
+    fn test_btree_validate() {
+        let mut tree = BTree::new(8);
+        for (k, v) in get_inserts(0..100) {
+            tree.insert(Slot::new_leaf(k, v));
+        }
+        for k in (0..100).step_by(3) {
+            tree.delete(k);
+        }
+        tree.validate().unwrap();
+
+        // A leaf out of the chain
+        let leaf = BTree::get_leftmost_leaf(tree.root);
+        let next = unsafe { (*leaf).next };
+        unsafe { (*leaf).next = (*next).next };
+        assert!(tree.validate().is_err());
+        unsafe { (*leaf).next = next };
+        tree.validate().unwrap();
+    }
+
+    #[test]
+    fn test_btree_split_after_delete() {
+        // 12 is deleted, so the leaf's separator is past its last key when 7 and 8 split it. The
+        // greater half keeps that separator, so a later 12 goes to it rather than past the tree
+        let mut tree = BTree::new(8);
+        for k in [1, 2, 3, 5, 6, 10, 11, 12] {
+            tree.insert(Slot::new_leaf(k, k));
+        }
+        tree.delete(12);
+        tree.insert(Slot::new_leaf(7, 7));
+        tree.insert(Slot::new_leaf(8, 8));
+        tree.validate().unwrap();
+
+        tree.insert(Slot::new_leaf(12, 12));
+        tree.validate().unwrap();
+        let want = [1, 2, 3, 5, 6, 7, 8, 10, 11, 12];
+        let have = tree.iter().map(|(k, _)| k).collect::<Vec<_>>();
+        assert!(want[..] == have[..], "Want: {:?}\nHave: {:?}", want, have);
+    }
+
+    #[test]
+    fn test_btree_replace_on_split() {
+        // The leaf splits into 0, 1 and 2, 3 as 1 is replaced, which stays in the lesser leaf
+        let mut tree = BTree::new(8);
+        for k in 0..4u32 {
+            tree.insert(Slot::new_leaf(k, k));
+        }
+        tree.insert(Slot::new_leaf(1, 100));
+
+        let have = tree.iter().collect::<Vec<_>>();
+        let want = [(0, 0), (1, 100), (2, 2), (3, 3)];
+        assert!(have == want, "Want: {:?}\nHave: {:?}", want, have);
+        assert!(tree.len() == 4 && tree.height() == 2);
+        tree.validate().unwrap();
+    }
+
+    #[test]
+    fn test_btree_get_ref() {
+        let mut tree = BTree::new(4);
+        for k in 0..100u32 {
+            tree.insert(Slot::new_leaf(k, [k as u64; 16]));
+        }
+
+        for k in 0..100 {
+            let have = tree.get_ref(k).unwrap();
+            assert!(have.key() == k && have[15] == k as u64, "Have: {:?}", have);
+            // The guard points into the leaf the entry is in
+            let leaf = BTree::find_leaf(tree.root(), k);
+            let slot = unsafe { (*leaf).values.iter().find(|s| s.0 == k).unwrap() };
+            assert!(matches!(slot.1, Either::Left(ref v) if ptr::eq(v, &*have)));
+        }
+        assert!(tree.get_ref(100).is_none());
+
+        tree.delete(5);
+        assert!(tree.get_ref(5).is_none() && BTree::<u32, u32>::new(4).get_ref(0).is_none());
+    }
+
+    #[test]
+    fn test_btree_heap_values() {
+        let mut tree = BTree::new(4);
+        let values = (0..200u32)
+            .map(|k| Arc::new(k.to_string()))
+            .collect::<Vec<_>>();
+        for (k, v) in values.iter().enumerate() {
+            tree.insert(Slot::new_leaf(k as u32, v.clone()));
+        }
+        // Splits moved every value, only `values` and the tree, and its shadow, hold one
+        let held = 1 + cfg!(feature = "shadow") as usize;
+        assert!(values.iter().all(|v| Arc::strong_count(v) == 1 + held));
+
+        for k in (0..200).step_by(2) {
+            assert!(tree.delete(k));
+        }
+        assert!(tree.delete_where(|k, v| k % 3 == 0 && *v == k.to_string()) == 33);
+        tree.update_range(..50, |_, v| *v = Arc::new(format!("{}!", v)));
+        tree.validate().unwrap();
+
+        let want = (0..200u32)
+            .filter(|k| k % 2 == 1 && k % 3 != 0)
+            .map(|k| {
+                (
+                    k,
+                    if k < 50 {
+                        format!("{k}!")
+                    } else {
+                        k.to_string()
+                    },
+                )
+            })
+            .collect::<Vec<_>>();
+        let have = tree
+            .iter()
+            .map(|(k, v)| (k, (*v).clone()))
+            .collect::<Vec<_>>();
+        assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+        assert!(values.iter().enumerate().all(|(k, v)| {
+            let kept = tree.get_ref(k as u32).is_some_and(|r| Arc::ptr_eq(&r, v)) as usize;
+            Arc::strong_count(v) == 1 + kept * held
+        }));
+
+        let mut tree = BTree::new(4);
+        for k in 0..100u32 {
+            tree.insert(Slot::new_leaf(k, vec![k; k as usize]));
+        }
+        let have = tree.get(42).map(|s| s.value().clone());
+        assert!(have == Some(vec![42; 42]), "Have: {:?}", have);
+        assert!(tree.range(10..20).map(|(_, v)| v.len()).sum::<usize>() == 145);
+    }
+
+    #[test]
+    fn test_btree_push() {
+        let mut tree = BTree::new(8);
+        for i in 0..1000u64 {
+            let have = tree.push(i * 2);
+            assert!(have == i, "Want: {i}\nHave: {have}");
+        }
+        tree.validate().unwrap();
+        assert!(tree.len() == 1000 && tree.get(999).is_some_and(|s| *s.value() == 1998));
+
+        // A queue, whose keys aren't reused once popped
+        assert!(tree.pop_first() == Some((0, 0)));
+        assert!(tree.delete(999) && tree.push(7) == 1000);
+        tree.insert(Slot::new_leaf(2000, 1));
+        assert!(tree.push(8) == 2001);
+        tree.validate().unwrap();
+
+        let mut tree = BTree::new(8);
+        tree.insert(Slot::new_leaf(500u32, 0));
+        assert!(tree.push(1) == 501 && BTree::<u8, u32>::new(8).push(1) == 0);
+
+        // Ascending inserts and updates of the last key mostly take the fast path, counting
+        // entries like the slow one
+        let mut slow = BTree::new(8);
+        for k in (0..1000u32).rev() {
+            slow.insert(Slot::new_leaf(k, k));
+        }
+        let mut fast = BTree::new(8);
+        for k in 0..1000u32 {
+            fast.insert(Slot::new_leaf(k, k));
+            fast.insert(Slot::new_leaf(k, k + 1));
+        }
+        fast.validate().unwrap();
+        let want = slow.iter().map(|(k, v)| (k, v + 1)).collect::<Vec<_>>();
+        let have = fast.iter().collect::<Vec<_>>();
+        assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+        assert!(fast.len() == 1000 && fast.rank(500, false) == 500);
+    }
+
+    #[test]
+    fn test_btree_merge() {
+        let mut tree = BTree::new(8);
+        tree.set_merge_operator(|_, old: Option<u64>, bit| old.unwrap_or(0) | bit);
+
+        for i in 0..1000u32 {
+            let have = tree.merge(i % 100, 1 << (i / 100));
+            let want = (2 << (i / 100)) - 1;
+            assert!(have == want, "Want: {want}\nHave: {have}");
+        }
+        let want = (0..100).map(|k| (k, 1023)).collect::<Vec<_>>();
+        let have = tree.iter().collect::<Vec<_>>();
+        assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+        tree.validate().unwrap();
+    }
+
+    #[test]
+    #[allow(clippy::unnecessary_sort_by, clippy::cmp_null)]
     fn test_btree_scan() {
         const MAX: usize = 8;
 
@@ -295,4 +1533,179 @@ mod test {
 
         assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
     }
+
+    #[test]
+    fn test_btree_range() {
+        const MAX: usize = 8;
+
+        let mut tree = BTree::new(MAX);
+
+        let inserts = get_inserts(0..100);
+        for (k, v) in &inserts {
+            tree.insert(Slot::new_leaf(*k, *v));
+        }
+        for k in (0..100).step_by(3) {
+            tree.delete(k);
+        }
+
+        let want = (0..100u8)
+            .filter(|k| k % 3 != 0)
+            .map(|k| (k, k + 10))
+            .collect::<Vec<_>>();
+        let have = tree.iter().collect::<Vec<_>>();
+        assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+
+        let want = (10..=40u8)
+            .filter(|k| k % 3 != 0)
+            .map(|k| (k, k + 10))
+            .collect::<Vec<_>>();
+        let have = tree.range(10..=40).collect::<Vec<_>>();
+        assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+
+        let want = (13..40u8)
+            .filter(|k| k % 3 != 0)
+            .map(|k| (k, k + 10))
+            .collect::<Vec<_>>();
+        let have = tree
+            .range((Bound::Excluded(12), Bound::Excluded(40)))
+            .collect::<Vec<_>>();
+        assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+
+        let have = tree.range(200..).count();
+        assert!(have == 0, "Want: 0\nHave: {have}");
+    }
+
+    #[test]
+    fn test_btree_update_range() {
+        let mut tree = BTree::new(4);
+        for k in 0..100u32 {
+            tree.insert(Slot::new_leaf(k, k));
+        }
+        for k in (0..100).step_by(5) {
+            tree.delete(k);
+        }
+
+        let mut seen = Vec::new();
+        let have = tree.update_range(20..=40, |k, v| {
+            seen.push(k);
+            *v *= 10;
+        });
+        let want = (20..=40).filter(|k| k % 5 != 0).collect::<Vec<_>>();
+        assert!(have == want.len() && seen == want, "Want: {:?}\nHave: {:?}", want, seen);
+
+        let want = (0..100)
+            .filter(|k| k % 5 != 0)
+            .map(|k| (k, if (20..=40).contains(&k) { k * 10 } else { k }))
+            .collect::<Vec<_>>();
+        let have = tree.iter().collect::<Vec<_>>();
+        assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+
+        assert!(tree.update_range((Bound::Excluded(98), Bound::Unbounded), |_, v| *v = 0) == 1);
+        assert!(tree.update_range(200.., |_, _| unreachable!()) == 0);
+        assert!(tree.get(99).unwrap() == Slot::new_leaf(99, 0));
+        tree.validate().unwrap();
+    }
+
+    #[test]
+    fn test_btree_delete_where() {
+        let leaves = |tree: &BTree<u32, u32>| {
+            let mut n = 0;
+            let mut cur = BTree::get_leftmost_leaf(tree.root);
+            while !cur.is_null() {
+                n += 1;
+                cur = unsafe { (*cur).next };
+            }
+            n
+        };
+
+        let mut tree = BTree::bulk_load(4, (0..1000u32).map(|k| (k, k)));
+        let before = leaves(&tree);
+
+        // Whole subtrees in the middle and at the end
+        let have = tree.delete_where(|k, _| (200..600).contains(&k) || k >= 900);
+        assert!(have == 500, "Want: 500\nHave: {have}");
+        tree.validate().unwrap();
+        assert!(leaves(&tree) < before / 2 + 2, "Have: {}", leaves(&tree));
+
+        let want = (0..200).chain(600..900).map(|k| (k, k)).collect::<Vec<_>>();
+        let have = tree.iter().collect::<Vec<_>>();
+        assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+        let have = tree.range(150..650).count();
+        assert!(have == 100, "Want: 100\nHave: {have}");
+
+        for k in (300..400).chain(950..1000) {
+            tree.insert(Slot::new_leaf(k, k));
+        }
+        tree.validate().unwrap();
+        assert!(tree.range(300..).count() == 450);
+
+        // Part of every leaf
+        let have = tree.delete_where(|_, v| v % 2 == 0);
+        assert!(have == 325, "Want: 325\nHave: {have}");
+        assert!(tree.iter().all(|(k, _)| k % 2 == 1));
+        tree.validate().unwrap();
+
+        assert!(tree.delete_where(|_, _| true) == 325);
+        assert!(tree.root.is_null() && tree.iter().next().is_none());
+        tree.insert(Slot::new_leaf(1, 1));
+        assert!(tree.delete_where(|_, _| false) == 0 && tree.first() == Some((1, 1)));
+
+        // The last child detached, the separator kept has to reach down to the leaves
+        let mut tree = BTree::new(4);
+        for k in [58, 42, 8, 7] {
+            tree.insert(Slot::new_leaf(k, k));
+        }
+        tree.delete_where(|k, _| k % 7 == 0);
+        tree.validate().unwrap();
+        let have = tree.range(11..).collect::<Vec<_>>();
+        assert!(have == [(58, 58)], "Have: {:?}", have);
+    }
+
+    #[test]
+    fn test_btree_bulk_load() {
+        const MAX: usize = 8;
+
+        let entries = (0..200u8).map(|k| (k, k / 2)).collect::<Vec<_>>();
+        let mut tree = BTree::bulk_load(MAX, entries.iter().copied());
+        let have = tree.iter().collect::<Vec<_>>();
+        assert!(entries == have, "Want: {:?}\nHave: {:?}", entries, have);
+
+        // Loaded nodes split like inserted ones
+        for k in (0..200).step_by(2) {
+            tree.delete(k);
+        }
+        for (k, v) in get_inserts(0..100) {
+            tree.insert(Slot::new_leaf(k * 2 + 1, v));
+        }
+        tree.validate().unwrap();
+        for k in 0..200u8 {
+            let want = match k % 2 {
+                0 => None,
+                _ => Some(k / 2 + 10),
+            };
+            let have = tree.get(k).map(|s| get_left!(s));
+            assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+        }
+
+        assert!(BTree::<u8, u8>::bulk_load(MAX, []).iter().next().is_none());
+    }
+
+    #[test]
+    fn test_btree_conversions() {
+        let want = (0..300u32)
+            .map(|k| (k, k as u64 * 7))
+            .collect::<BTreeMap<_, _>>();
+
+        let tree = BTree::from(want.clone());
+        assert!(tree.max() == DEFAULT_MAX);
+        let have = BTreeMap::from(tree);
+        assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+
+        let map = want.clone().into_iter().collect::<HashMap<_, _>>();
+        let tree = BTree::from(map.clone());
+        let have = tree.iter().collect::<BTreeMap<_, _>>();
+        assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+        let have: HashMap<_, _> = tree.into();
+        assert!(map == have, "Want: {:?}\nHave: {:?}", map, have);
+    }
 }
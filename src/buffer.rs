@@ -0,0 +1,996 @@
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Condvar, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::thread::{self, JoinHandle};
+
+use crate::compress::CompressionStats;
+use crate::crypt::Key;
+use crate::disk::ChecksumMismatch;
+use crate::fault::FaultInjector;
+use crate::metrics::{self, Counter};
+use crate::page::{self, PageBuf, PageId, PAGE_SIZE};
+use crate::replacer::{Policy, Replacer};
+use crate::store::PageStore;
+use crate::tier::{TierStats, Tiering};
+use crate::wal::Wal;
+
+/// How much memory a `BufferPool` may use for cached pages.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Capacity {
+    Pages(usize),
+    /// Rounded down to whole pages.
+    Bytes(usize),
+}
+
+impl Capacity {
+    pub fn pages(&self) -> usize {
+        match *self {
+            Capacity::Pages(n) => n,
+            Capacity::Bytes(n) => n / PAGE_SIZE,
+        }
+    }
+}
+
+impl Default for Capacity {
+    fn default() -> Self {
+        Capacity::Pages(256)
+    }
+}
+
+/// What a `BufferPool` does with a page read from disk that doesn't match its checksum.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum ChecksumPolicy {
+    /// The read fails.
+    #[default]
+    Error,
+    /// The read fails and so does every later read of the page, without touching the disk.
+    Quarantine,
+    /// Rebuilds the page from the log with the `Repair` function, quarantining it if that fails.
+    Repair,
+}
+
+/// Rebuilds page `id` into the buffer from the log, returns `false` if the log doesn't have
+/// enough of it. Set by whatever knows the layout of the pages.
+pub type Repair = fn(&Wal, PageId, &mut PageBuf) -> io::Result<bool>;
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct PoolStats {
+    /// Name of the replacement policy the numbers were collected under.
+    pub policy: &'static str,
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    /// Modified pages written back, on eviction or by a flush.
+    pub flushes: u64,
+    /// Corrupt pages rebuilt from the log.
+    pub repairs: u64,
+    /// Pages the store was asked to read ahead, see `BufferPool::prefetch()`.
+    pub prefetches: u64,
+}
+
+impl PoolStats {
+    pub fn hit_ratio(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            n => self.hits as f64 / n as f64,
+        }
+    }
+}
+
+struct Frame {
+    page: Option<PageId>,
+    pins: usize,
+    dirty: bool,
+}
+
+struct State {
+    disk: Box<dyn PageStore>,
+    frames: Vec<Frame>,
+    table: HashMap<PageId, usize>,
+    // Frames left empty by a failed read
+    free: Vec<usize>,
+    replacer: Box<dyn Replacer>,
+    stats: PoolStats,
+    dirty: usize,
+    flush_threshold: Option<usize>,
+    // Set by the background flusher, returned by the next `sync()`
+    flush_error: Option<io::Error>,
+    wal: Option<Arc<Wal>>,
+    checksum: ChecksumPolicy,
+    repair: Option<Repair>,
+    quarantined: HashSet<PageId>,
+    tiering: Option<Box<Tiering>>,
+    shutdown: bool,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    // Wakes the background flusher
+    flush: Condvar,
+    bufs: Box<[RwLock<PageBuf>]>,
+}
+
+/// Caches a bounded number of pages of a `PageStore`, such as a `DiskManager`.
+///
+/// Pages are loaded on first access. Once every frame is in use, the `Replacer` picks a page to
+/// evict to make room, least recently used by default. Pages are handed out as `PageReadGuard`s
+/// and `PageWriteGuard`s, a page is pinned while any guard for it is alive and is never evicted
+/// while pinned.
+///
+/// Pages modified through a `PageWriteGuard` are marked dirty when the guard is dropped and are
+/// written back when evicted, by `flush()`, `flush_all()` and `sync()`, or by a background thread
+/// once more than `set_flush_threshold()` pages are dirty. A page still pinned for writing is
+/// skipped by flushes. Dropping the pool writes back what is left, ignoring errors.
+///
+/// With a `Wal` set, a page changed by an operation that hasn't committed stays cached, and the
+/// log is synced up to a page's LSN before the page is written.
+///
+/// Pages that fail their checksum are handled as the `ChecksumPolicy` says, a repaired page is
+/// dirty so the fixed copy is written back.
+pub struct BufferPool {
+    shared: Arc<Shared>,
+    flusher: Mutex<Option<JoinHandle<()>>>,
+}
+
+pub struct PageReadGuard<'a> {
+    pool: &'a BufferPool,
+    id: PageId,
+    frame: usize,
+    buf: Option<RwLockReadGuard<'a, PageBuf>>,
+}
+
+impl PageReadGuard<'_> {
+    pub fn id(&self) -> PageId {
+        self.id
+    }
+}
+
+impl Deref for PageReadGuard<'_> {
+    type Target = PageBuf;
+
+    fn deref(&self) -> &Self::Target {
+        self.buf.as_ref().unwrap()
+    }
+}
+
+impl Drop for PageReadGuard<'_> {
+    fn drop(&mut self) {
+        // Unlock before unpinning so an evicting thread never waits on the frame
+        self.buf.take();
+        self.pool.unpin(self.frame, false);
+    }
+}
+
+pub struct PageWriteGuard<'a> {
+    pool: &'a BufferPool,
+    id: PageId,
+    frame: usize,
+    buf: Option<RwLockWriteGuard<'a, PageBuf>>,
+}
+
+impl PageWriteGuard<'_> {
+    pub fn id(&self) -> PageId {
+        self.id
+    }
+}
+
+impl Deref for PageWriteGuard<'_> {
+    type Target = PageBuf;
+
+    fn deref(&self) -> &Self::Target {
+        self.buf.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for PageWriteGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.buf.as_mut().unwrap()
+    }
+}
+
+impl Drop for PageWriteGuard<'_> {
+    fn drop(&mut self) {
+        self.buf.take();
+        self.pool.unpin(self.frame, true);
+    }
+}
+
+impl BufferPool {
+    pub fn new<S: PageStore + 'static>(disk: S, capacity: Capacity) -> Self {
+        Self::with_policy(disk, capacity, Policy::default())
+    }
+
+    pub fn with_policy<S: PageStore + 'static>(
+        disk: S,
+        capacity: Capacity,
+        policy: Policy,
+    ) -> Self {
+        Self::with_replacer(disk, capacity, policy.replacer(capacity.pages()))
+    }
+
+    /// Uses a custom replacement policy, `replacer` must handle `capacity.pages()` frames.
+    pub fn with_replacer<S: PageStore + 'static>(
+        disk: S,
+        capacity: Capacity,
+        replacer: Box<dyn Replacer>,
+    ) -> Self {
+        let capacity = capacity.pages();
+        assert!(capacity > 0, "buffer pool needs at least one page");
+
+        let stats = PoolStats {
+            policy: replacer.name(),
+            ..Default::default()
+        };
+
+        Self {
+            shared: Arc::new(Shared {
+                state: Mutex::new(State {
+                    disk: Box::new(disk),
+                    frames: Vec::with_capacity(capacity),
+                    table: HashMap::with_capacity(capacity),
+                    free: Vec::new(),
+                    replacer,
+                    stats,
+                    dirty: 0,
+                    flush_threshold: None,
+                    flush_error: None,
+                    wal: None,
+                    checksum: ChecksumPolicy::default(),
+                    repair: None,
+                    quarantined: HashSet::new(),
+                    tiering: None,
+                    shutdown: false,
+                }),
+                flush: Condvar::new(),
+                bufs: (0..capacity).map(|_| RwLock::new([0; PAGE_SIZE])).collect(),
+            }),
+            flusher: Mutex::new(None),
+        }
+    }
+
+    /// The number of pages that can be cached at once.
+    pub fn capacity(&self) -> usize {
+        self.shared.bufs.len()
+    }
+
+    pub fn pages(&self) -> u64 {
+        self.shared.state.lock().unwrap().disk.pages()
+    }
+
+    pub fn compression(&self) -> Option<CompressionStats> {
+        self.shared.state.lock().unwrap().disk.compression()
+    }
+
+    /// Re-encrypts the pages with `key`, see `DiskManager::rekey()`.
+    pub fn rekey(&self, key: Key) -> io::Result<()> {
+        self.shared.state.lock().unwrap().disk.rekey(key)
+    }
+
+    /// Runs `f` with the disk, holding off every fault and write back until it returns.
+    pub(crate) fn with_disk<T>(&self, f: impl FnOnce(&dyn PageStore) -> T) -> T {
+        f(&*self.shared.state.lock().unwrap().disk)
+    }
+
+    pub fn allocate(&self) -> PageId {
+        self.shared.state.lock().unwrap().disk.allocate()
+    }
+
+    /// Pins page `id` for reading, loading it if it isn't cached.
+    pub fn fetch(&self, id: PageId) -> io::Result<PageReadGuard<'_>> {
+        let frame = self.pin(id, true)?;
+
+        Ok(PageReadGuard {
+            pool: self,
+            id,
+            frame,
+            buf: Some(self.shared.bufs[frame].read().unwrap()),
+        })
+    }
+
+    /// Pins page `id` for writing, loading it if it isn't cached.
+    pub fn fetch_mut(&self, id: PageId) -> io::Result<PageWriteGuard<'_>> {
+        let frame = self.pin(id, true)?;
+
+        Ok(PageWriteGuard {
+            pool: self,
+            id,
+            frame,
+            buf: Some(self.shared.bufs[frame].write().unwrap()),
+        })
+    }
+
+    /// Pins page `id` for writing without reading it, for when it is about to be overwritten. It
+    /// starts zeroed.
+    pub fn fetch_zeroed(&self, id: PageId) -> io::Result<PageWriteGuard<'_>> {
+        let frame = self.pin(id, false)?;
+
+        let mut buf = self.shared.bufs[frame].write().unwrap();
+        buf.fill(0);
+
+        Ok(PageWriteGuard {
+            pool: self,
+            id,
+            frame,
+            buf: Some(buf),
+        })
+    }
+
+    /// Allocates a page and pins it for writing. It starts zeroed and isn't read from disk.
+    pub fn new_page(&self) -> io::Result<PageWriteGuard<'_>> {
+        let id = self.allocate();
+        let frame = self.pin(id, false)?;
+
+        let mut buf = self.shared.bufs[frame].write().unwrap();
+        buf.fill(0);
+
+        Ok(PageWriteGuard {
+            pool: self,
+            id,
+            frame,
+            buf: Some(buf),
+        })
+    }
+
+    /// Asks the store to start reading page `id` if it isn't cached, so a fetch of it that is
+    /// coming waits less. Doesn't pin it or take a frame.
+    pub fn prefetch(&self, id: PageId) {
+        let mut state = self.shared.state.lock().unwrap();
+        if state.table.contains_key(&id) || state.quarantined.contains(&id) {
+            return;
+        }
+
+        state.stats.prefetches += 1;
+        state.disk.prefetch(id);
+    }
+
+    /// Reads the pages of `ids` that aren't cached into the pool unpinned, all at once, if the
+    /// store has batched reads in flight together, see `PageStore::batches_reads()`. At most a
+    /// quarter of the pool is read ahead, so the pages in use stay. Otherwise it only asks the
+    /// store to start reading them, like `prefetch()`.
+    ///
+    /// Best effort: if a read fails none of the pages are kept, the fetch of a page fails or
+    /// repairs it as usual.
+    pub fn read_ahead(&self, ids: &[PageId]) {
+        let bufs = &self.shared.bufs;
+        let mut state = self.shared.state.lock().unwrap();
+        let state = &mut *state;
+
+        let ids = ids
+            .iter()
+            .filter(|id| !state.table.contains_key(id) && !state.quarantined.contains(id))
+            .copied()
+            .collect::<Vec<_>>();
+        state.stats.prefetches += ids.len() as u64;
+        if !state.disk.batches_reads() {
+            for id in ids {
+                state.disk.prefetch(id);
+            }
+            return;
+        }
+
+        let mut frames = Vec::new();
+        for id in ids.into_iter().take(bufs.len() / 4) {
+            match self.take_frame(state) {
+                Ok(frame) => frames.push((id, frame)),
+                Err(_) => break,
+            }
+        }
+
+        let mut guards = frames
+            .iter()
+            .map(|(_, frame)| bufs[*frame].write().unwrap())
+            .collect::<Vec<_>>();
+        let mut pages = frames
+            .iter()
+            .zip(&mut guards)
+            .map(|((id, _), buf)| (*id, &mut **buf))
+            .collect::<Vec<_>>();
+        let read = state.disk.read_pages(&mut pages);
+        drop(pages);
+        drop(guards);
+
+        for (id, frame) in frames {
+            if read.is_err() {
+                state.free.push(frame);
+                continue;
+            }
+
+            state.frames[frame].page = Some(id);
+            state.table.insert(id, frame);
+            state.replacer.insert(frame, id);
+            if let Some(tiering) = &mut state.tiering {
+                tiering.classify(id, &bufs[frame].read().unwrap());
+            }
+        }
+    }
+
+    /// Returns the frame holding page `id` with its pin count raised, reading the page into a
+    /// free or evicted frame on a miss if `read` is set.
+    fn pin(&self, id: PageId, read: bool) -> io::Result<usize> {
+        let bufs = &self.shared.bufs;
+        let mut state = self.shared.state.lock().unwrap();
+        let state = &mut *state;
+
+        if let Some(&frame) = state.table.get(&id) {
+            state.stats.hits += 1;
+            metrics::count(Counter::PoolHits);
+            state.frames[frame].pins += 1;
+            state.replacer.access(frame);
+            if let Some(tiering) = &mut state.tiering {
+                tiering.access(id, true);
+            }
+            return Ok(frame);
+        }
+
+        state.stats.misses += 1;
+        metrics::count(Counter::PoolMisses);
+        let frame = self.take_frame(state)?;
+
+        if read {
+            let mut buf = bufs[frame].write().unwrap();
+            match state.read_page(id, &mut buf) {
+                Ok(false) => {}
+                Ok(true) => {
+                    state.frames[frame].dirty = true;
+                    state.dirty += 1;
+                }
+                Err(e) => {
+                    state.free.push(frame);
+                    return Err(e);
+                }
+            }
+        }
+
+        state.frames[frame].page = Some(id);
+        state.frames[frame].pins = 1;
+        state.table.insert(id, frame);
+        state.replacer.insert(frame, id);
+        if let Some(tiering) = &mut state.tiering {
+            tiering.access(id, false);
+            if read {
+                tiering.classify(id, &bufs[frame].read().unwrap());
+            }
+        }
+
+        Ok(frame)
+    }
+
+    /// Returns a frame holding no page, a free one or one whose page was evicted, written back
+    /// first if it was dirty.
+    fn take_frame(&self, state: &mut State) -> io::Result<usize> {
+        let bufs = &self.shared.bufs;
+        let frame = if let Some(frame) = state.free.pop() {
+            frame
+        } else if state.frames.len() < bufs.len() {
+            state.frames.push(Frame {
+                page: None,
+                pins: 0,
+                dirty: false,
+            });
+            state.frames.len() - 1
+        } else {
+            let frames = &state.frames;
+            let committed = state.wal.as_ref().map(|wal| wal.committed());
+            let tiering = state.tiering.as_deref();
+            let frame = state
+                .replacer
+                .victim(&|f| {
+                    frames[f].pins == 0
+                        && tiering.is_none_or(|t| !t.is_hot(frames[f].page.unwrap()))
+                        && (!frames[f].dirty
+                            || committed.is_none_or(|c| {
+                                bufs[f].try_read().is_ok_and(|buf| page::lsn(&buf) <= c)
+                            }))
+                })
+                .ok_or_else(|| io::Error::other("every page is pinned"))?;
+
+            let old = state.frames[frame].page.take().unwrap();
+            state.table.remove(&old);
+            state.stats.evictions += 1;
+
+            if state.frames[frame].dirty {
+                let buf = bufs[frame].read().unwrap();
+                if let Err(e) = state.write_page(old, &buf) {
+                    // Keep the page cached so the change isn't lost
+                    state.frames[frame].page = Some(old);
+                    state.table.insert(old, frame);
+                    state.replacer.insert(frame, old);
+                    return Err(e);
+                }
+
+                state.frames[frame].dirty = false;
+                state.dirty -= 1;
+                state.stats.flushes += 1;
+            }
+
+            frame
+        };
+
+        Ok(frame)
+    }
+
+    fn unpin(&self, frame: usize, dirty: bool) {
+        let mut state = self.shared.state.lock().unwrap();
+        let state = &mut *state;
+
+        if dirty {
+            if let (Some(tiering), Some(id)) = (&mut state.tiering, state.frames[frame].page) {
+                // Skipped if another writer has the page, it classifies it when it is done
+                if let Ok(buf) = self.shared.bufs[frame].try_read() {
+                    tiering.classify(id, &buf);
+                }
+            }
+        }
+
+        let frame = &mut state.frames[frame];
+        frame.pins -= 1;
+        if dirty && !frame.dirty {
+            frame.dirty = true;
+            state.dirty += 1;
+
+            if state.flush_threshold.is_some_and(|t| state.dirty > t) {
+                self.shared.flush.notify_one();
+            }
+        }
+    }
+
+    /// Returns `true` if page `id` is currently cached.
+    pub fn contains(&self, id: PageId) -> bool {
+        self.shared.state.lock().unwrap().table.contains_key(&id)
+    }
+
+    /// Returns `true` if page `id` is cached and was modified since it was last written back.
+    pub fn is_dirty(&self, id: PageId) -> bool {
+        let state = self.shared.state.lock().unwrap();
+        state.table.get(&id).is_some_and(|f| state.frames[*f].dirty)
+    }
+
+    /// The number of cached pages modified since they were last written back.
+    pub fn dirty_pages(&self) -> usize {
+        self.shared.state.lock().unwrap().dirty
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        self.shared.state.lock().unwrap().stats
+    }
+
+    /// Writes page `id` back if it is dirty. Returns `true` if it was written.
+    pub fn flush(&self, id: PageId) -> io::Result<bool> {
+        let mut state = self.shared.state.lock().unwrap();
+        match state.table.get(&id) {
+            Some(&frame) => self.shared.write_back(&mut state, frame),
+            None => Ok(false),
+        }
+    }
+
+    /// Writes back every dirty page, without waiting for them to reach the disk.
+    pub fn flush_all(&self) -> io::Result<()> {
+        let mut state = self.shared.state.lock().unwrap();
+        self.shared.write_back_all(&mut state)
+    }
+
+    /// Writes back at most `pages` dirty pages, without waiting for them to reach the disk.
+    /// Returns how many were written.
+    pub fn flush_some(&self, pages: usize) -> io::Result<usize> {
+        let mut state = self.shared.state.lock().unwrap();
+        let frames = (0..state.frames.len())
+            .filter(|f| state.frames[*f].dirty)
+            .take(pages)
+            .collect::<Vec<_>>();
+        self.shared.write_back_frames(&mut state, frames)
+    }
+
+    /// Writes back every dirty page and waits for them to reach the disk. Also returns the error
+    /// the background flusher last ran into, if any.
+    pub fn sync(&self) -> io::Result<()> {
+        let mut state = self.shared.state.lock().unwrap();
+        if let Some(e) = state.flush_error.take() {
+            return Err(e);
+        }
+
+        self.shared.write_back_all(&mut state)?;
+        state.disk.sync()
+    }
+
+    /// Sets the log pages are written back in step with.
+    pub fn set_wal(&self, wal: Option<Arc<Wal>>) {
+        self.shared.state.lock().unwrap().wal = wal;
+    }
+
+    /// Passes page writes through `faults`, see `FaultInjector`.
+    pub fn set_faults(&self, faults: Option<Arc<FaultInjector>>) {
+        self.shared.state.lock().unwrap().disk.set_faults(faults);
+    }
+
+    pub fn set_checksum_policy(&self, policy: ChecksumPolicy) {
+        self.shared.state.lock().unwrap().checksum = policy;
+    }
+
+    /// Sets how pages are rebuilt under `ChecksumPolicy::Repair`, needs a `Wal` set too.
+    pub fn set_repair(&self, repair: Option<Repair>) {
+        self.shared.state.lock().unwrap().repair = repair;
+    }
+
+    /// Pages that failed their checksum and couldn't be repaired, in order.
+    pub fn quarantined(&self) -> Vec<PageId> {
+        let mut pages = Vec::from_iter(self.shared.state.lock().unwrap().quarantined.clone());
+        pages.sort();
+        pages
+    }
+
+    /// Keeps a hot tier of pages cached, see `Tiering`, `None` leaves every page to the
+    /// replacer. The hot tier must leave room for the pages pinned at once.
+    pub fn set_tiering(&self, tiering: Option<Tiering>) {
+        let mut state = self.shared.state.lock().unwrap();
+        let state = &mut *state;
+
+        let mut tiering = tiering.map(Box::new);
+        if let Some(tiering) = &mut tiering {
+            assert!(tiering.hot_pages() < self.capacity(), "the hot tier needs a cold tier");
+            for (&id, &frame) in &state.table {
+                if let Ok(buf) = self.shared.bufs[frame].try_read() {
+                    tiering.classify(id, &buf);
+                }
+            }
+        }
+        state.tiering = tiering;
+    }
+
+    /// `None` unless tiering is set.
+    pub fn tier_stats(&self) -> Option<TierStats> {
+        let state = self.shared.state.lock().unwrap();
+        let cached = state.table.len();
+        state.tiering.as_ref().map(|t| t.stats(cached))
+    }
+
+    /// Starts a background thread that flushes every dirty page whenever more than `pages` are
+    /// dirty, `None` stops flushing in the background.
+    pub fn set_flush_threshold(&self, pages: Option<usize>) {
+        self.shared.state.lock().unwrap().flush_threshold = pages;
+        self.shared.flush.notify_one();
+
+        let mut flusher = self.flusher.lock().unwrap();
+        if pages.is_some() && flusher.is_none() {
+            let shared = self.shared.clone();
+            *flusher = Some(thread::spawn(move || shared.flusher()));
+        }
+    }
+}
+
+impl State {
+    /// Reads a page from disk, applying the checksum policy. Returns `true` if it was repaired.
+    fn read_page(&mut self, id: PageId, buf: &mut PageBuf) -> io::Result<bool> {
+        if self.quarantined.contains(&id) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("page {} is quarantined", id.0),
+            ));
+        }
+
+        let e = match self.disk.read_page(id, buf) {
+            Ok(()) => return Ok(false),
+            Err(e) if ChecksumMismatch::page(&e).is_none() => return Err(e),
+            Err(e) => e,
+        };
+
+        match (self.checksum, &self.wal, self.repair) {
+            (ChecksumPolicy::Error, _, _) => return Err(e),
+            (ChecksumPolicy::Repair, Some(wal), Some(repair)) => {
+                // Best effort, a failed repair is as good as none
+                if let Ok(true) = repair(wal, id, buf) {
+                    self.stats.repairs += 1;
+                    return Ok(true);
+                }
+            }
+            _ => {}
+        }
+
+        self.quarantined.insert(id);
+        Err(e)
+    }
+
+    /// Writes a page to disk once the log records up to its LSN are.
+    fn write_page(&mut self, id: PageId, buf: &PageBuf) -> io::Result<()> {
+        if let Some(wal) = &self.wal {
+            wal.sync_to(page::lsn(buf))?;
+        }
+
+        self.disk.write_page(id, buf)
+    }
+}
+
+impl Shared {
+    /// Writes back the page in `frame` if it is dirty and not pinned for writing.
+    fn write_back(&self, state: &mut State, frame: usize) -> io::Result<bool> {
+        Ok(self.write_back_frames(state, [frame])? == 1)
+    }
+
+    fn write_back_all(&self, state: &mut State) -> io::Result<()> {
+        self.write_back_frames(state, 0..state.frames.len())?;
+        Ok(())
+    }
+
+    /// Writes back the dirty pages in `frames` in one batch, skipping those pinned for writing.
+    /// Returns how many were written.
+    fn write_back_frames(
+        &self,
+        state: &mut State,
+        frames: impl IntoIterator<Item = usize>,
+    ) -> io::Result<usize> {
+        let committed = state.wal.as_ref().map(|wal| wal.committed());
+
+        let mut batch = Vec::new();
+        for frame in frames {
+            if !state.frames[frame].dirty {
+                continue;
+            }
+
+            // The writer marks it dirty again when its guard is dropped
+            let buf = match self.bufs[frame].try_read() {
+                Ok(buf) => buf,
+                Err(_) => continue,
+            };
+            // Changed by an operation that hasn't committed
+            if committed.is_some_and(|c| page::lsn(&buf) > c) {
+                continue;
+            }
+
+            batch.push((frame, buf));
+        }
+
+        if let Some(wal) = &state.wal {
+            let lsn = batch.iter().map(|(_, buf)| page::lsn(buf)).max();
+            wal.sync_to(lsn.unwrap_or(0))?;
+        }
+        let pages = batch
+            .iter()
+            .map(|(f, buf)| (state.frames[*f].page.unwrap(), &**buf))
+            .collect::<Vec<_>>();
+        state.disk.write_pages(&pages)?;
+
+        for (frame, _) in &batch {
+            state.frames[*frame].dirty = false;
+        }
+        state.dirty -= batch.len();
+        state.stats.flushes += batch.len() as u64;
+
+        Ok(batch.len())
+    }
+
+    fn flusher(&self) {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if state.shutdown {
+                return;
+            }
+
+            match state.flush_threshold {
+                Some(t) if state.dirty > t => {
+                    if let Err(e) = self.write_back_all(&mut state) {
+                        state.flush_error = Some(e);
+                    }
+                    // Pages pinned for writing stay dirty, wait for them to be dropped
+                    state = self.flush.wait(state).unwrap();
+                }
+                _ => state = self.flush.wait(state).unwrap(),
+            }
+        }
+    }
+}
+
+impl Drop for BufferPool {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().shutdown = true;
+        self.shared.flush.notify_one();
+        if let Some(flusher) = self.flusher.lock().unwrap().take() {
+            let _ = flusher.join();
+        }
+
+        let mut state = self.shared.state.lock().unwrap();
+        let _ = self.shared.write_back_all(&mut state);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+    use std::time::Duration;
+
+    use std::os::unix::fs::FileExt;
+
+    use crate::disk::{ChecksumMismatch, DiskManager};
+    use crate::page::{PageId, PAGE_SIZE};
+    use crate::replacer::Policy;
+
+    use super::{BufferPool, Capacity, ChecksumPolicy};
+
+    #[test]
+    fn test_lru_eviction() {
+        let dir = tempfile::tempdir().unwrap();
+        let disk = DiskManager::create(dir.path().join("pages")).unwrap();
+
+        let pool = BufferPool::new(disk, Capacity::Bytes(3 * 4096 + 100));
+        assert!(pool.capacity() == 3);
+
+        let mut pages = Vec::new();
+        for i in 0..3 {
+            let mut page = pool.new_page().unwrap();
+            page[0] = i + 1;
+            pages.push(page.id());
+        }
+
+        // Touch page 0 so page 1 is the least recently used
+        assert!(pool.fetch(pages[0]).unwrap()[0] == 1);
+        pool.new_page().unwrap()[0] = 4;
+        assert!(!pool.contains(pages[1]));
+        assert!(pool.contains(pages[0]));
+
+        // Written back on eviction and reloaded
+        assert!(pool.fetch(pages[1]).unwrap()[0] == 2);
+        assert!(!pool.contains(pages[2]));
+        assert!(pool.fetch(PageId(2)).unwrap()[0] == 3);
+
+        let stats = pool.stats();
+        assert!(stats.policy == "lru");
+        assert!(stats.hits == 1 && stats.misses == 6 && stats.evictions == 3, "{:?}", stats);
+    }
+
+    #[test]
+    fn test_pinned_pages_stay() {
+        let dir = tempfile::tempdir().unwrap();
+        let disk = DiskManager::create(dir.path().join("pages")).unwrap();
+
+        let pool = BufferPool::new(disk, Capacity::Pages(2));
+        let a = pool.new_page().unwrap().id();
+        let b = pool.new_page().unwrap().id();
+        let c = pool.allocate();
+
+        let pinned = pool.fetch(a).unwrap();
+        let also_pinned = pool.fetch(a).unwrap();
+        drop(pool.fetch(b).unwrap());
+
+        // `a` is the least recently used but pinned
+        let have = pool.fetch(c).unwrap();
+        assert!(pool.contains(a) && !pool.contains(b));
+
+        // Every frame is pinned
+        assert!(pool.fetch(b).is_err());
+        drop(have);
+
+        // Still pinned by the second guard
+        drop(pinned);
+        drop(pool.fetch(b).unwrap());
+        assert!(pool.contains(a) && !pool.contains(c));
+
+        drop(also_pinned);
+        drop(pool.fetch(c).unwrap());
+        assert!(!pool.contains(a));
+    }
+
+    #[test]
+    fn test_flush() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pages");
+
+        let pool = BufferPool::new(DiskManager::create(&path).unwrap(), Capacity::Pages(8));
+        let mut pages = Vec::new();
+        for i in 0..4 {
+            let mut page = pool.new_page().unwrap();
+            page[0] = i + 1;
+            pages.push(page.id());
+        }
+        assert!(pool.dirty_pages() == 4);
+
+        let on_disk = |id: PageId| {
+            let disk = DiskManager::open(&path).unwrap();
+            let mut buf = [0; PAGE_SIZE];
+            if id.0 < disk.pages() {
+                disk.read_page(id, &mut buf).unwrap();
+            }
+            buf[0]
+        };
+        assert!(on_disk(pages[0]) == 0);
+
+        assert!(pool.flush(pages[0]).unwrap());
+        assert!(!pool.flush(pages[0]).unwrap());
+        assert!(!pool.is_dirty(pages[0]) && pool.is_dirty(pages[1]));
+        assert!(on_disk(pages[0]) == 1);
+
+        // Still pinned for writing
+        let writing = pool.fetch_mut(pages[1]).unwrap();
+        pool.flush_all().unwrap();
+        assert!(pool.dirty_pages() == 1 && pool.is_dirty(pages[1]));
+        drop(writing);
+
+        pool.sync().unwrap();
+        assert!(pool.dirty_pages() == 0);
+        assert!(pages.iter().all(|id| on_disk(*id) == id.0 as u8 + 1));
+        assert!(pool.stats().flushes == 4);
+    }
+
+    #[test]
+    fn test_background_flush() {
+        let dir = tempfile::tempdir().unwrap();
+        let disk = DiskManager::create(dir.path().join("pages")).unwrap();
+
+        let pool = BufferPool::new(disk, Capacity::Pages(8));
+        pool.set_flush_threshold(Some(2));
+
+        for _ in 0..2 {
+            pool.new_page().unwrap();
+        }
+        assert!(pool.dirty_pages() == 2);
+
+        pool.new_page().unwrap();
+        for _ in 0..1000 {
+            if pool.dirty_pages() == 0 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert!(pool.dirty_pages() == 0);
+        assert!(pool.stats().flushes == 3);
+    }
+
+    #[test]
+    fn test_scan_resistance() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let hits = |policy| {
+            let disk = DiskManager::create(dir.path().join("pages")).unwrap();
+            let pool = BufferPool::with_policy(disk, Capacity::Pages(8), policy);
+            let pages = (0..64).map(|_| pool.allocate()).collect::<Vec<_>>();
+
+            // A small hot set used between scans over pages that are never used again
+            for round in 0..8 {
+                for _ in 0..2 {
+                    for id in &pages[..4] {
+                        pool.fetch(*id).unwrap();
+                    }
+                }
+                for id in &pages[4 + round * 7..4 + (round + 1) * 7] {
+                    pool.fetch(*id).unwrap();
+                }
+            }
+
+            pool.stats()
+        };
+
+        let lru = hits(Policy::Lru);
+        for policy in [Policy::LruK(2), Policy::TwoQ] {
+            let have = hits(policy);
+            assert!(have.hits > lru.hits, "Want more hits than {:?}\nHave: {:?}", lru, have);
+        }
+    }
+
+    #[test]
+    fn test_checksum_policy() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pages");
+
+        let pool = BufferPool::new(DiskManager::create(&path).unwrap(), Capacity::Pages(8));
+        for i in 0..2 {
+            pool.new_page().unwrap()[100] = i + 1;
+        }
+        drop(pool);
+
+        // Flip a bit in page 1
+        let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.write_all_at(&[0x82], PAGE_SIZE as u64 + 100).unwrap();
+
+        let pool = BufferPool::new(DiskManager::open(&path).unwrap(), Capacity::Pages(8));
+        assert!(pool.fetch(PageId(0)).unwrap()[100] == 1);
+        let err = pool.fetch(PageId(1)).err().unwrap();
+        assert!(ChecksumMismatch::page(&err) == Some(PageId(1)), "Have: {err}");
+        assert!(pool.quarantined().is_empty());
+
+        pool.set_checksum_policy(ChecksumPolicy::Quarantine);
+        assert!(pool.fetch(PageId(1)).is_err());
+        assert!(pool.quarantined() == [PageId(1)]);
+
+        // Quarantined pages aren't read again
+        let err = pool.fetch(PageId(1)).err().unwrap();
+        assert!(ChecksumMismatch::page(&err).is_none(), "Have: {err}");
+    }
+}
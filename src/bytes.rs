@@ -0,0 +1,168 @@
+use std::cmp::Ordering;
+use std::fmt::{self, Debug};
+
+use crate::btree::Increment;
+use crate::page::Encode;
+
+/// A byte string of up to `N` bytes, ordered lexicographically, so keys and values of any length
+/// up to `N` can be stored inline.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Bytes<const N: usize> {
+    buf: [u8; N],
+    len: u16,
+}
+
+impl<const N: usize> Bytes<N> {
+    /// `None` if `bytes` is longer than `N`.
+    pub fn new(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() > N || N > u16::MAX as usize {
+            return None;
+        }
+
+        let mut buf = [0; N];
+        buf[..bytes.len()].copy_from_slice(bytes);
+        Some(Self {
+            buf,
+            len: bytes.len() as u16,
+        })
+    }
+
+    /// The first `len` bytes of `buf`, for constants.
+    pub(crate) const fn from_raw(buf: [u8; N], len: usize) -> Self {
+        assert!(len <= N && N <= u16::MAX as usize);
+        Self {
+            buf,
+            len: len as u16,
+        }
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.buf[..self.len as usize]
+    }
+
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl<const N: usize> Default for Bytes<N> {
+    fn default() -> Self {
+        Self {
+            buf: [0; N],
+            len: 0,
+        }
+    }
+}
+
+impl<const N: usize> PartialOrd for Bytes<N> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<const N: usize> Ord for Bytes<N> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.as_slice().cmp(other.as_slice())
+    }
+}
+
+impl<const N: usize> Debug for Bytes<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "b\"{}\"", self.as_slice().escape_ascii())
+    }
+}
+
+impl<const N: usize> Increment for Bytes<N> {
+    const MAX: Self = Self {
+        buf: [u8::MAX; N],
+        len: N as u16,
+    };
+
+    fn increment(&mut self) {
+        *self = self.next();
+    }
+
+    /// The least byte string after this one: a zero byte appended, or if it is full, the last
+    /// byte that isn't `0xff` incremented and the rest cut off.
+    fn next(&self) -> Self {
+        let mut next = *self;
+        if self.len() < N {
+            next.len += 1;
+            return next;
+        }
+
+        let i = self
+            .buf
+            .iter()
+            .rposition(|b| *b != u8::MAX)
+            .expect("there is no byte string after the greatest");
+        next.buf[i] += 1;
+        next.buf[i + 1..].fill(0);
+        next.len = i as u16 + 1;
+        next
+    }
+}
+
+impl<const N: usize> Encode for Bytes<N> {
+    const SIZE: usize = N + 2;
+
+    fn encode(&self, buf: &mut [u8]) {
+        buf[..2].copy_from_slice(&self.len.to_le_bytes());
+        buf[2..].copy_from_slice(&self.buf);
+    }
+
+    fn decode(buf: &[u8]) -> Self {
+        let mut bytes = [0; N];
+        bytes.copy_from_slice(&buf[2..]);
+        Self {
+            buf: bytes,
+            len: u16::from_le_bytes([buf[0], buf[1]]).min(N as u16),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::btree::{BTree, Increment};
+    use crate::page::Encode;
+
+    use super::Bytes;
+
+    #[test]
+    fn test_bytes() {
+        let b = |s: &[u8]| Bytes::<4>::new(s).unwrap();
+
+        assert!(b(b"ab") < b(b"ab\0") && b(b"ab\0") < b(b"abc") && b(b"abc") < b(b"b"));
+        assert!(Bytes::<4>::new(b"abcde").is_none());
+
+        // The next byte string is the least one after it
+        let cases: [(&[u8], &[u8]); 4] = [
+            (b"ab", b"ab\0"),
+            (b"abcd", b"abce"),
+            (b"ab\xff\xff", b"ac"),
+            (b"", b"\0"),
+        ];
+        for (k, want) in cases {
+            let have = b(k).next();
+            assert!(have == b(want), "Want: {:?}\nHave: {:?}", b(want), have);
+        }
+
+        let mut buf = [0; 6];
+        b(b"xyz").encode(&mut buf);
+        assert!(Bytes::<4>::decode(&buf) == b(b"xyz"));
+
+        let mut keys =
+            ["ab", "a", "abcd", "b", "", "ba", "ab\u{7f}", "zzzz"].map(|k| b(k.as_bytes()));
+        keys.sort();
+        let tree = BTree::bulk_load(4, keys.iter().map(|k| (*k, k.len())));
+        for k in keys {
+            let have = tree.get(k).map(|s| s.0);
+            assert!(have == Some(k), "Want: {:?}\nHave: {:?}", k, have);
+        }
+        assert!(tree.get(b(b"abc")).is_none());
+    }
+}
@@ -0,0 +1,220 @@
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::ops::RangeBounds;
+
+use crate::btree::{BTree, Increment};
+use crate::get_left;
+use crate::slot::{Either, Slot};
+
+/// Picks the key of the entry to evict.
+pub type Pick<K, V> = Box<dyn FnMut(&BTree<K, V>) -> K + Send>;
+
+/// Which entry a `BoundedBTree` evicts when it is over capacity.
+pub enum Eviction<K, V> {
+    /// The entry with the smallest key, the oldest for keys that are timestamps.
+    Smallest,
+    /// The least recently inserted or read entry.
+    Lru,
+    /// The entry whose key is returned, which must be in the tree.
+    Custom(Pick<K, V>),
+}
+
+/// A `BTree` that holds at most `capacity` weight of entries, evicting entries by its `Eviction`
+/// policy when an insert takes it over. Each entry weighs 1 unless a weigher is set.
+///
+/// The entry just inserted may be the one evicted, e.g. one with the smallest key under
+/// `Eviction::Smallest`.
+pub struct BoundedBTree<K, V> {
+    tree: BTree<K, V>,
+    capacity: usize,
+    weight: usize,
+    eviction: Eviction<K, V>,
+    weigher: fn(&K, &V) -> usize,
+    on_evict: Option<Box<dyn FnMut(K, V) + Send>>,
+    // Recency for `Eviction::Lru`, by key and by tick
+    tick: u64,
+    used: BTreeMap<K, u64>,
+    order: BTreeMap<u64, K>,
+}
+
+impl<K, V> BoundedBTree<K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment,
+    V: Clone + Copy + Debug + Eq,
+{
+    pub fn new(max: usize, capacity: usize, eviction: Eviction<K, V>) -> Self {
+        Self {
+            tree: BTree::new(max),
+            capacity,
+            weight: 0,
+            eviction,
+            weigher: |_, _| 1,
+            on_evict: None,
+            tick: 0,
+            used: BTreeMap::new(),
+            order: BTreeMap::new(),
+        }
+    }
+
+    /// Sets the weight of entries, e.g. their size in bytes. Only takes effect on an empty tree.
+    pub fn set_weigher(&mut self, weigher: fn(&K, &V) -> usize) {
+        assert!(self.weight == 0, "the weigher can only be set on an empty tree");
+        self.weigher = weigher;
+    }
+
+    /// Sets a callback called with every evicted entry.
+    pub fn on_evict<F: FnMut(K, V) + Send + 'static>(&mut self, f: F) {
+        self.on_evict = Some(Box::new(f));
+    }
+
+    pub fn insert(&mut self, key: K, value: V) {
+        if let Some(old) = self.peek(key) {
+            self.weight -= (self.weigher)(&key, &old);
+        }
+        self.weight += (self.weigher)(&key, &value);
+        self.tree.insert(Slot::new_leaf(key, value));
+        self.touch(key);
+
+        while self.weight > self.capacity {
+            self.evict();
+        }
+    }
+
+    fn evict(&mut self) {
+        let key = match &mut self.eviction {
+            Eviction::Smallest => self.tree.first().unwrap().0,
+            Eviction::Lru => *self.order.first_key_value().unwrap().1,
+            Eviction::Custom(pick) => pick(&self.tree),
+        };
+        let value = self
+            .remove(key)
+            .expect("the eviction policy picked a key that isn't in the tree");
+
+        if let Some(on_evict) = &mut self.on_evict {
+            on_evict(key, value);
+        }
+    }
+
+    /// Reads `key`, which counts as a use under `Eviction::Lru`.
+    pub fn get(&mut self, key: K) -> Option<V> {
+        let value = self.peek(key)?;
+        self.touch(key);
+        Some(value)
+    }
+
+    /// Reads `key` without counting as a use.
+    pub fn peek(&self, key: K) -> Option<V> {
+        self.tree.get(key).map(|e| get_left!(e))
+    }
+
+    pub fn delete(&mut self, key: K) -> bool {
+        self.remove(key).is_some()
+    }
+
+    fn remove(&mut self, key: K) -> Option<V> {
+        let value = self.peek(key)?;
+        self.tree.delete(key);
+        self.weight -= (self.weigher)(&key, &value);
+        if let Some(tick) = self.used.remove(&key) {
+            self.order.remove(&tick);
+        }
+
+        Some(value)
+    }
+
+    fn touch(&mut self, key: K) {
+        if !matches!(self.eviction, Eviction::Lru) {
+            return;
+        }
+
+        self.tick += 1;
+        if let Some(tick) = self.used.insert(key, self.tick) {
+            self.order.remove(&tick);
+        }
+        self.order.insert(self.tick, key);
+    }
+
+    /// Returns an iterator over the entries with keys in `range`, in order. Doesn't count as a
+    /// use.
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> impl Iterator<Item = (K, V)> + '_ {
+        self.tree.range(range)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (K, V)> + '_ {
+        self.range(..)
+    }
+
+    pub fn weight(&self) -> usize {
+        self.weight
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Sets the capacity, evicting entries until they fit.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.weight > self.capacity {
+            self.evict();
+        }
+    }
+
+    pub fn inner(&self) -> &BTree<K, V> {
+        &self.tree
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use crate::btree::BTree;
+
+    use super::{BoundedBTree, Eviction};
+
+    #[test]
+    fn test_bounded() {
+        // A time-series cache of the last 100 points
+        let evicted = Arc::new(Mutex::new(Vec::new()));
+        let mut cache = BoundedBTree::new(8, 100, Eviction::Smallest);
+        let sink = evicted.clone();
+        cache.on_evict(move |k, v| sink.lock().unwrap().push((k, v)));
+        for ts in 0..250u64 {
+            cache.insert(ts, ts * 2);
+        }
+        let want = (150..250).map(|ts| (ts, ts * 2)).collect::<Vec<_>>();
+        let have = cache.iter().collect::<Vec<_>>();
+        assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+        let want = (0..150).map(|ts| (ts, ts * 2)).collect::<Vec<_>>();
+        let have = evicted.lock().unwrap().clone();
+        assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+        cache.inner().validate().unwrap();
+
+        let mut cache = BoundedBTree::new(4, 3, Eviction::Lru);
+        for k in 0..3u32 {
+            cache.insert(k, k);
+        }
+        cache.get(0);
+        cache.insert(3, 3);
+        let have = cache.iter().map(|e| e.0).collect::<Vec<_>>();
+        assert!(have == [0, 2, 3], "Have: {:?}", have);
+
+        // Weighted by value, evicting the largest key
+        let pick = Box::new(|tree: &BTree<u32, u32>| tree.iter().last().unwrap().0);
+        let mut cache = BoundedBTree::new(4, 10, Eviction::Custom(pick));
+        cache.set_weigher(|_, v| *v as usize);
+        cache.insert(1, 4);
+        cache.insert(2, 4);
+        cache.insert(3, 1);
+        cache.insert(2, 2);
+        assert!(cache.weight() == 7);
+        cache.insert(0, 5);
+        let have = cache.iter().collect::<Vec<_>>();
+        assert!(have == [(0, 5), (1, 4)], "Have: {:?}", have);
+        assert!(cache.weight() == 9);
+
+        cache.set_capacity(5);
+        assert!(cache.weight() == 5 && cache.peek(0) == Some(5));
+    }
+}
@@ -0,0 +1,181 @@
+use std::cmp::Ordering;
+
+use crate::btree::Increment;
+use crate::bytes::Bytes;
+use crate::compare::Comparator;
+
+/// Orders byte string keys ignoring ASCII case, so `b"Key"` and `b"KEY"` are the same key. A tree
+/// keeps the spelling a key was last inserted with.
+pub struct CaseInsensitive;
+
+impl<const N: usize> Comparator<Bytes<N>> for CaseInsensitive {
+    const MAX: Bytes<N> = Bytes::MAX;
+
+    fn compare(a: &Bytes<N>, b: &Bytes<N>) -> Ordering {
+        let lower = |b: &u8| b.to_ascii_lowercase();
+        a.as_slice()
+            .iter()
+            .map(lower)
+            .cmp(b.as_slice().iter().map(lower))
+    }
+
+    /// A zero byte appended, or if the key is full, its last byte that isn't `0xff` set to `0xff`
+    /// and the rest cut off.
+    fn next(key: &Bytes<N>) -> Bytes<N> {
+        if key.len() < N {
+            return key.next();
+        }
+
+        let mut buf = key.as_slice().to_vec();
+        let i = buf
+            .iter()
+            .rposition(|b| *b != u8::MAX)
+            .expect("there is no key after the greatest");
+        buf[i] = u8::MAX;
+        buf.truncate(i + 1);
+        Bytes::new(&buf).unwrap()
+    }
+}
+
+/// Orders UTF-8 keys by a simple collation, comparing their characters case folded and with the
+/// accents of Latin-1 letters stripped, see `fold()`. So `"Ångström"` and `"ANGSTROM"` are the
+/// same key. Invalid UTF-8 compares as if replaced by U+FFFD.
+pub struct Collated;
+
+/// The characters of `s` as `Collated` compares them.
+pub fn fold(s: &str) -> impl Iterator<Item = char> + '_ {
+    s.chars().flat_map(char::to_lowercase).map(strip_accent)
+}
+
+fn strip_accent(c: char) -> char {
+    match c {
+        'à'..='å' => 'a',
+        'ç' => 'c',
+        'è'..='ë' => 'e',
+        'ì'..='ï' => 'i',
+        'ñ' => 'n',
+        'ò'..='ö' | 'ø' => 'o',
+        'ù'..='ü' => 'u',
+        'ý' | 'ÿ' => 'y',
+        c => c,
+    }
+}
+
+/// The greatest character encoded in each number of bytes, which fold to themselves.
+const GREATEST: [char; 4] = ['\u{7f}', '\u{7ff}', '\u{ffff}', '\u{10ffff}'];
+
+/// The greatest characters fitting in `N` bytes.
+const fn greatest<const N: usize>() -> Bytes<N> {
+    let mut buf = [0; N];
+    let mut i = 0;
+    while i < N {
+        let room = if N - i < 4 { N - i } else { 4 };
+        let c = GREATEST[room - 1];
+        c.encode_utf8(buf.split_at_mut(i).1);
+        i += room;
+    }
+
+    Bytes::from_raw(buf, N)
+}
+
+impl<const N: usize> Comparator<Bytes<N>> for Collated {
+    const MAX: Bytes<N> = greatest();
+
+    fn compare(a: &Bytes<N>, b: &Bytes<N>) -> Ordering {
+        let a = String::from_utf8_lossy(a.as_slice());
+        let b = String::from_utf8_lossy(b.as_slice());
+        fold(&a).cmp(fold(&b))
+    }
+
+    /// A zero byte appended, or if the key is full, its last character that can be replaced by a
+    /// greater one in the same room replaced and the rest cut off.
+    fn next(key: &Bytes<N>) -> Bytes<N> {
+        if key.len() < N {
+            return key.next();
+        }
+
+        let s = String::from_utf8_lossy(key.as_slice());
+        for (i, c) in s.char_indices().rev() {
+            let room = N.saturating_sub(i);
+            if room == 0 {
+                continue;
+            }
+
+            let greater = GREATEST[room.min(4) - 1];
+            if fold(c.encode_utf8(&mut [0; 4])).next().unwrap() < greater {
+                let next = format!("{}{greater}", &s[..i]);
+                return Bytes::new(next.as_bytes()).unwrap();
+            }
+        }
+
+        panic!("there is no key after the greatest")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cmp::Ordering;
+
+    use crate::bytes::Bytes;
+    use crate::compare::{Comparator, OrderedBTree};
+
+    use super::{CaseInsensitive, Collated};
+
+    #[test]
+    fn test_collation() {
+        let b = |s: &str| Bytes::<12>::new(s.as_bytes()).unwrap();
+
+        let mut tree = OrderedBTree::<_, _, CaseInsensitive>::new(4);
+        for (i, word) in ["Key", "apple", "KEY", "Banana", "ZOO", "banana"]
+            .iter()
+            .enumerate()
+        {
+            tree.insert(b(word), i);
+        }
+        let want = [("apple", 1), ("banana", 5), ("KEY", 2), ("ZOO", 4)].map(|(k, v)| (b(k), v));
+        let have = tree.iter().collect::<Vec<_>>();
+        assert!(have == want, "Want: {:?}\nHave: {:?}", want, have);
+        assert!(tree.get(b("zoo")) == Some(4) && tree.get(b("zo")).is_none());
+        tree.inner().validate().unwrap();
+
+        let mut tree = OrderedBTree::<_, _, Collated>::new(4);
+        let words = [
+            "Ångström",
+            "zebra",
+            "café",
+            "ANGSTROM",
+            "Éclair",
+            "CAFE",
+            "apple",
+        ];
+        for (i, word) in words.iter().enumerate() {
+            tree.insert(b(word), i);
+        }
+        let want = [
+            ("ANGSTROM", 3),
+            ("apple", 6),
+            ("CAFE", 5),
+            ("Éclair", 4),
+            ("zebra", 1),
+        ];
+        let want = want.map(|(k, v)| (b(k), v));
+        let have = tree.iter().collect::<Vec<_>>();
+        assert!(have == want, "Want: {:?}\nHave: {:?}", want, have);
+        assert!(tree.get(b("eclair")) == Some(4));
+        tree.inner().validate().unwrap();
+
+        // Full keys
+        for k in [
+            "abcdefghijkl",
+            "ÅÅÅÅÅÅ",
+            "xy\u{7f}\u{7f}\u{10ffff}\u{10ffff}",
+        ] {
+            let next = Collated::next(&b(k));
+            assert!(Collated::compare(&b(k), &next) == Ordering::Less, "{:?} {:?}", k, next);
+            let max = <Collated as Comparator<Bytes<12>>>::MAX;
+            assert!(Collated::compare(&next, &max) != Ordering::Greater);
+        }
+        let next = CaseInsensitive::next(&b("ABCDEFGHIJ\u{7f}\u{7f}"));
+        assert!(CaseInsensitive::compare(&b("abcdefghij\u{7f}\u{7f}"), &next) == Ordering::Less);
+    }
+}
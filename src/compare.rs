@@ -0,0 +1,334 @@
+use std::cmp::Ordering;
+use std::fmt::{self, Debug};
+use std::marker::PhantomData;
+use std::ops::{Bound, RangeBounds};
+
+use crate::btree::{BTree, Increment};
+use crate::page::Encode;
+use crate::slot::Slot;
+
+/// An order for keys of type `K`, used in place of `K`'s own by wrapping keys in `Ordered`.
+///
+/// Besides comparing, the trees need a key after any key for their separators. `next()` must
+/// return a key greater than `key` in this order, it needn't be the least one. It isn't called
+/// with `MAX`, `Ordered` has a key of its own after it.
+pub trait Comparator<K> {
+    /// The greatest key in this order.
+    const MAX: K;
+
+    fn compare(a: &K, b: &K) -> Ordering;
+    fn next(key: &K) -> K;
+}
+
+/// A key ordered by the comparator `C` rather than by its `Ord` impl, such as a
+/// `BTree<Ordered<K, C>, V>`. Keys equal under `C` are the same key.
+pub struct Ordered<K, C> {
+    pub key: K,
+    // After every key, with `key` at `C::MAX`, so there is a separator past `C::MAX`
+    end: bool,
+    _order: PhantomData<fn() -> C>,
+}
+
+impl<K, C> Ordered<K, C> {
+    pub const fn new(key: K) -> Self {
+        Self {
+            key,
+            end: false,
+            _order: PhantomData,
+        }
+    }
+}
+
+impl<K, C> From<K> for Ordered<K, C> {
+    fn from(key: K) -> Self {
+        Self::new(key)
+    }
+}
+
+impl<K: Clone, C> Clone for Ordered<K, C> {
+    fn clone(&self) -> Self {
+        Self {
+            key: self.key.clone(),
+            end: self.end,
+            _order: PhantomData,
+        }
+    }
+}
+
+impl<K: Copy, C> Copy for Ordered<K, C> {}
+
+impl<K: Debug, C> Debug for Ordered<K, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.end {
+            f.write_str("after ")?;
+        }
+        self.key.fmt(f)
+    }
+}
+
+impl<K, C: Comparator<K>> PartialEq for Ordered<K, C> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<K, C: Comparator<K>> Eq for Ordered<K, C> {}
+
+impl<K, C: Comparator<K>> PartialOrd for Ordered<K, C> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<K, C: Comparator<K>> Ord for Ordered<K, C> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.end, other.end) {
+            (false, false) => C::compare(&self.key, &other.key),
+            (a, b) => a.cmp(&b),
+        }
+    }
+}
+
+impl<K, C: Comparator<K>> Increment for Ordered<K, C> {
+    const MAX: Self = Self::new(C::MAX);
+
+    fn increment(&mut self) {
+        *self = self.next();
+    }
+
+    fn next(&self) -> Self {
+        if self.end || C::compare(&self.key, &C::MAX) == Ordering::Equal {
+            return Self {
+                key: C::MAX,
+                end: true,
+                _order: PhantomData,
+            };
+        }
+
+        Self::new(C::next(&self.key))
+    }
+}
+
+/// Encoded as the key followed by a byte set for the key after `C::MAX`.
+impl<K: Encode, C> Encode for Ordered<K, C> {
+    const SIZE: usize = K::SIZE + 1;
+
+    fn encode(&self, buf: &mut [u8]) {
+        self.key.encode(&mut buf[..K::SIZE]);
+        buf[K::SIZE] = self.end as u8;
+    }
+
+    fn decode(buf: &[u8]) -> Self {
+        Self {
+            key: K::decode(&buf[..K::SIZE]),
+            end: buf[K::SIZE] != 0,
+            _order: PhantomData,
+        }
+    }
+}
+
+/// Orders integer keys largest first.
+pub struct Descending;
+
+macro_rules! impl_descending {
+    ($( $t:ty ),*) => {
+        $(
+        impl Comparator<$t> for Descending {
+            const MAX: $t = <$t>::MIN;
+
+            fn compare(a: &$t, b: &$t) -> Ordering {
+                b.cmp(a)
+            }
+
+            fn next(key: &$t) -> $t {
+                key.saturating_sub(1)
+            }
+        }
+        )*
+    };
+}
+
+impl_descending!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+/// A `BTree` of keys ordered by the comparator `C`, taking and returning the keys themselves.
+pub struct OrderedBTree<K, V, C> {
+    tree: BTree<Ordered<K, C>, V>,
+}
+
+/// A `BTree` with the largest keys first, so `first()` is the greatest key and a range from `hi`
+/// to `lo` runs down.
+pub type ReverseBTree<K, V> = OrderedBTree<K, V, Descending>;
+
+impl<K, V, C> OrderedBTree<K, V, C>
+where
+    K: Clone + Copy + Debug,
+    V: Clone + Copy + Debug + Eq,
+    C: Comparator<K>,
+{
+    pub fn new(max: usize) -> Self {
+        Self {
+            tree: BTree::new(max),
+        }
+    }
+
+    pub fn insert(&mut self, key: K, value: V) {
+        self.tree.insert(Slot::new_leaf(Ordered::new(key), value));
+    }
+
+    pub fn get(&self, key: K) -> Option<V> {
+        self.tree
+            .range(Ordered::new(key)..=Ordered::new(key))
+            .next()
+            .map(|e| e.1)
+    }
+
+    pub fn delete(&mut self, key: K) -> bool {
+        self.tree.delete(Ordered::new(key))
+    }
+
+    /// Returns an iterator over the entries with keys in `range` under `C`, in `C`'s order.
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> impl Iterator<Item = (K, V)> + '_ {
+        let ordered = |bound: Bound<&K>| bound.map(|k| Ordered::new(*k));
+        let range = (ordered(range.start_bound()), ordered(range.end_bound()));
+        self.tree.range(range).map(|(k, v)| (k.key, v))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (K, V)> + '_ {
+        self.range(..)
+    }
+
+    /// The entry first in `C`'s order.
+    pub fn first(&self) -> Option<(K, V)> {
+        self.tree.first().map(|(k, v)| (k.key, v))
+    }
+
+    pub fn pop_first(&mut self) -> Option<(K, V)> {
+        self.tree.pop_first().map(|(k, v)| (k.key, v))
+    }
+
+    pub fn inner(&self) -> &BTree<Ordered<K, C>, V> {
+        &self.tree
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cmp::Ordering;
+    use std::ops::Bound;
+
+    use crate::btree::{BTree, Increment};
+    use crate::bytes::Bytes;
+    #[cfg(feature = "fs")]
+    use crate::paged::PagedBTree;
+    use crate::slot::Slot;
+
+    #[cfg(feature = "fs")]
+    use super::Descending;
+    use super::{Comparator, Ordered, ReverseBTree};
+
+    /// ASCII case-insensitive, with the bytes themselves breaking ties.
+    struct CaseInsensitive;
+
+    impl Comparator<Bytes<8>> for CaseInsensitive {
+        const MAX: Bytes<8> = Bytes::MAX;
+
+        fn compare(a: &Bytes<8>, b: &Bytes<8>) -> Ordering {
+            let lower = |b: &Bytes<8>| b.as_slice().to_ascii_lowercase();
+            lower(a).cmp(&lower(b)).then(a.cmp(b))
+        }
+
+        fn next(key: &Bytes<8>) -> Bytes<8> {
+            // Appending a zero byte sorts after, the test's keys are never full
+            key.next()
+        }
+    }
+
+    type Key = Ordered<Bytes<8>, CaseInsensitive>;
+
+    #[test]
+    fn test_comparator() {
+        let key = |s: &str| Key::new(Bytes::new(s.as_bytes()).unwrap());
+        let words = [
+            "b", "Apple", "C", "a", "apple", "B", "ant", "Bee", "bed", "Ax",
+        ];
+
+        let mut tree = BTree::new(4);
+        for (i, word) in words.iter().enumerate() {
+            tree.insert(Slot::new_leaf(key(word), i));
+        }
+        let want = [
+            "a", "ant", "Apple", "apple", "Ax", "B", "b", "bed", "Bee", "C",
+        ];
+        let have = tree.iter().map(|(k, _)| k).collect::<Vec<_>>();
+        assert!(have == want.map(key), "Want: {:?}\nHave: {:?}", want, have);
+        tree.validate().unwrap();
+
+        let have = tree
+            .range(key("B")..key("Bf"))
+            .map(|e| e.1)
+            .collect::<Vec<_>>();
+        assert!(have == [5, 0, 8, 7], "Have: {:?}", have);
+
+        #[cfg(feature = "fs")]
+        {
+            let dir = tempfile::tempdir().unwrap();
+            let mut tree = PagedBTree::<Key, u64>::create(dir.path().join("tree"), 4).unwrap();
+            for (i, word) in words.iter().enumerate() {
+                tree.insert(key(word), i as u64).unwrap();
+            }
+            let have = tree
+                .iter()
+                .unwrap()
+                .into_iter()
+                .map(|(k, _)| k)
+                .collect::<Vec<_>>();
+            assert!(have == want.map(key), "Want: {:?}\nHave: {:?}", want, have);
+        }
+    }
+
+    #[test]
+    fn test_reverse() {
+        let mut tree = ReverseBTree::new(8);
+        for k in (-50..50).rev().chain(-50..50) {
+            tree.insert(k, k * 2);
+        }
+        tree.insert(i32::MIN + 1, 0);
+        tree.inner().validate().unwrap();
+
+        let have = tree
+            .range((Bound::Included(10), Bound::Excluded(5)))
+            .collect::<Vec<_>>();
+        let want = (6..=10).rev().map(|k| (k, k * 2)).collect::<Vec<_>>();
+        assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+        assert!(tree.get(-7) == Some(-14) && tree.get(50).is_none());
+
+        let have = [(); 3].map(|_| tree.pop_first().unwrap());
+        assert!(have == [(49, 98), (48, 96), (47, 94)], "Have: {:?}", have);
+        assert!(tree.delete(i32::MIN + 1) && tree.iter().last() == Some((-50, -100)));
+
+        // The least key of the type is the last in the order, with no key after it
+        let mut tree = ReverseBTree::<u32, u32>::new(8);
+        for k in 0..20 {
+            tree.insert(k, k);
+        }
+        tree.inner().validate().unwrap();
+        let want = (0..20).rev().map(|k| (k, k)).collect::<Vec<_>>();
+        let have = tree.iter().collect::<Vec<_>>();
+        assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+        assert!(tree.get(0) == Some(0) && tree.range(1..).last() == Some((0, 0)));
+        assert!(tree.delete(0) && tree.iter().last() == Some((1, 1)));
+
+        #[cfg(feature = "fs")]
+        {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("tree");
+            let mut tree = PagedBTree::<Ordered<u32, Descending>, u64>::create(path, 4).unwrap();
+            for k in (0..20).chain([u32::MAX]) {
+                tree.insert(Ordered::new(k), k as u64).unwrap();
+            }
+            tree.validate().unwrap();
+            let have = tree.iter().unwrap();
+            assert!(have.len() == 21 && have[20].1 == 0, "Have: {:?}", have);
+        }
+    }
+}
@@ -0,0 +1,549 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+
+use crate::crypt::{Cipher, Key};
+use crate::disk::{self, ChecksumMismatch};
+use crate::format::{self, VERSION};
+use crate::page::{PageBuf, PageId, PAGE_SIZE};
+
+/// Starts a file of compressed pages, a file of plain pages starts with its meta page.
+pub(crate) const MAGIC: &[u8; 8] = b"BPTPACK\0";
+// Magic, codec u8, encrypted u8, format version u8, padding, level i32, sealed key check
+const FILE_HEADER: u64 = 16 + (KEY_CHECK + Cipher::OVERHEAD) as u64;
+const KEY_CHECK: usize = 16;
+// Page u64, length u32, codec u8, crc32c u32
+const RECORD_HEADER: usize = 17;
+/// Compaction waits for at least this much dead space, and for more of it than live space.
+const COMPACT_BYTES: u64 = 1 << 20;
+
+const NONE: u8 = 0;
+const LZ4: u8 = 1;
+const ZSTD: u8 = 2;
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// How pages are compressed on disk. Pages are only compressed as they are written back, those in
+/// the `BufferPool` stay as they are.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum Codec {
+    #[default]
+    None,
+    /// Fast, for pages read often.
+    Lz4,
+    /// Smaller pages for more CPU, at the given level.
+    #[cfg(feature = "zstd")]
+    Zstd(i32),
+}
+
+impl Codec {
+    fn encode(self, header: &mut [u8]) {
+        let (id, level): (u8, i32) = match self {
+            Codec::None => (NONE, 0),
+            Codec::Lz4 => (LZ4, 0),
+            #[cfg(feature = "zstd")]
+            Codec::Zstd(level) => (ZSTD, level),
+        };
+        header[8] = id;
+        header[12..16].copy_from_slice(&level.to_le_bytes());
+    }
+
+    fn decode(header: &[u8]) -> io::Result<Self> {
+        match header[8] {
+            NONE => Ok(Codec::None),
+            LZ4 => Ok(Codec::Lz4),
+            #[cfg(feature = "zstd")]
+            ZSTD => Ok(Codec::Zstd(i32::from_le_bytes(header[12..16].try_into().unwrap()))),
+            #[cfg(not(feature = "zstd"))]
+            ZSTD => Err(unsupported()),
+            id => Err(invalid(format!("unknown codec {id}"))),
+        }
+    }
+
+    /// Returns the codec the page was compressed with, `NONE` if it didn't shrink.
+    fn compress(self, page: &PageBuf) -> io::Result<(u8, Vec<u8>)> {
+        let (id, data) = match self {
+            Codec::None => return Ok((NONE, page.to_vec())),
+            Codec::Lz4 => (LZ4, lz4_flex::block::compress(page)),
+            #[cfg(feature = "zstd")]
+            Codec::Zstd(level) => (ZSTD, zstd::bulk::compress(page, level)?),
+        };
+
+        match data.len() < PAGE_SIZE {
+            true => Ok((id, data)),
+            false => Ok((NONE, page.to_vec())),
+        }
+    }
+}
+
+#[cfg(not(feature = "zstd"))]
+fn unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "pages are compressed with zstd, which needs the zstd feature",
+    )
+}
+
+fn decompress(id: u8, data: &[u8], buf: &mut PageBuf) -> io::Result<()> {
+    let len = match id {
+        NONE if data.len() == PAGE_SIZE => {
+            buf.copy_from_slice(data);
+            PAGE_SIZE
+        }
+        NONE => data.len(),
+        LZ4 => lz4_flex::block::decompress_into(data, buf).map_err(|e| invalid(e.to_string()))?,
+        #[cfg(feature = "zstd")]
+        ZSTD => zstd::bulk::decompress_to_buffer(data, &mut buf[..])?,
+        #[cfg(not(feature = "zstd"))]
+        ZSTD => return Err(unsupported()),
+        id => return Err(invalid(format!("unknown codec {id}"))),
+    };
+
+    match len == PAGE_SIZE {
+        true => Ok(()),
+        false => Err(invalid(format!("page decompressed to {len} bytes"))),
+    }
+}
+
+/// How well the pages of a compressed file shrank, from `DiskManager::compression()`.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct CompressionStats {
+    pub codec: Codec,
+    /// Pages written to the file so far.
+    pub pages: u64,
+    /// Bytes taken by the latest copy of each page.
+    pub live_bytes: u64,
+    /// Bytes taken by older copies, reclaimed when the file is compacted.
+    pub dead_bytes: u64,
+}
+
+impl CompressionStats {
+    /// Uncompressed over compressed size of the live pages.
+    pub fn ratio(&self) -> f64 {
+        match self.live_bytes {
+            0 => 1.0,
+            n => (self.pages * PAGE_SIZE as u64) as f64 / n as f64,
+        }
+    }
+}
+
+/// Where the latest copy of a page is.
+#[derive(Debug, Clone, Copy)]
+struct Slot {
+    offset: u64,
+    len: usize,
+}
+
+/// A file of compressed pages, which may also be encrypted.
+///
+/// Compressed pages vary in size so they can't be kept at fixed offsets. Instead every write
+/// appends a record of the page to the file, and the latest record of each page is found by
+/// scanning the file when it is opened. A torn record at the end is dropped, just like a torn page
+/// in a file of plain pages. Once older records take more space than the live ones, the live ones
+/// are copied to a new file which replaces the old one.
+///
+/// With a key, the data of every record is sealed with the page's ID, so a record can't be passed
+/// off as another page's. The header holds a sealed block that checks the key on open.
+pub(crate) struct Packed {
+    path: PathBuf,
+    codec: Codec,
+    cipher: Option<Cipher>,
+    slots: Vec<Option<Slot>>,
+    end: u64,
+    dead: u64,
+}
+
+impl Packed {
+    pub fn create(path: &Path, codec: Codec, key: Option<&Key>) -> io::Result<(File, Self)> {
+        Self::create_with_cipher(path, codec, key.map(Cipher::new))
+    }
+
+    fn create_with_cipher(
+        path: &Path,
+        codec: Codec,
+        cipher: Option<Cipher>,
+    ) -> io::Result<(File, Self)> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+
+        let mut header = [0; FILE_HEADER as usize];
+        header[..8].copy_from_slice(MAGIC);
+        codec.encode(&mut header);
+        header[10] = VERSION as u8;
+        if let Some(cipher) = &cipher {
+            header[9] = 1;
+            header[16..].copy_from_slice(&cipher.seal(MAGIC, &[0; KEY_CHECK]));
+        }
+        file.write_all_at(&header, 0)?;
+
+        let packed = Self {
+            path: path.to_owned(),
+            codec,
+            cipher,
+            slots: Vec::new(),
+            end: FILE_HEADER,
+            dead: 0,
+        };
+
+        Ok((file, packed))
+    }
+
+    /// Returns `true` if `file` holds compressed pages.
+    pub fn is_packed(file: &File) -> io::Result<bool> {
+        let mut magic = [0; 8];
+        match file.read_exact_at(&mut magic, 0) {
+            Ok(()) => Ok(&magic == MAGIC),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Finds the latest record of every page, dropping a torn record at the end. Fails if `key`
+    /// isn't the one the file was created with, or isn't given for an encrypted file.
+    pub fn open(path: &Path, file: &File, key: Option<&Key>) -> io::Result<Self> {
+        let mut header = [0; FILE_HEADER as usize];
+        file.read_exact_at(&mut header, 0)?;
+        format::check_packed(&header)?;
+        let codec = Codec::decode(&header)?;
+
+        let cipher = match (header[9] == 1, key) {
+            (true, Some(key)) => {
+                let cipher = Cipher::new(key);
+                if cipher.open(MAGIC, &header[16..]).is_none() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "file is encrypted with another key",
+                    ));
+                }
+                Some(cipher)
+            }
+            (true, None) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "file is encrypted, open it with its key",
+                ))
+            }
+            (false, Some(_)) => {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "file isn't encrypted"))
+            }
+            (false, None) => None,
+        };
+
+        let mut packed = Self {
+            path: path.to_owned(),
+            codec,
+            cipher,
+            slots: Vec::new(),
+            end: FILE_HEADER,
+            dead: 0,
+        };
+
+        let mut reader = BufReader::new(file);
+        reader.seek(SeekFrom::Start(FILE_HEADER))?;
+        let mut record = Vec::new();
+        loop {
+            record.resize(RECORD_HEADER, 0);
+            if !read_record(&mut reader, &mut record)? {
+                break;
+            }
+
+            let len = u32::from_le_bytes(record[8..12].try_into().unwrap()) as usize;
+            if len > PAGE_SIZE + Cipher::OVERHEAD {
+                break;
+            }
+            record.resize(RECORD_HEADER + len, 0);
+            if !read_record(&mut reader, &mut record[RECORD_HEADER..])? || !verify(&record) {
+                break;
+            }
+
+            let id = u64::from_le_bytes(record[0..8].try_into().unwrap());
+            packed.place(PageId(id), record.len());
+        }
+
+        if file.metadata()?.len() > packed.end {
+            file.set_len(packed.end)?;
+        }
+
+        Ok(packed)
+    }
+
+    /// Records that the latest copy of `id` was appended to the end of the file.
+    fn place(&mut self, id: PageId, len: usize) {
+        let i = id.0 as usize;
+        if i >= self.slots.len() {
+            self.slots.resize(i + 1, None);
+        }
+
+        if let Some(old) = self.slots[i].replace(Slot {
+            offset: self.end,
+            len,
+        }) {
+            self.dead += old.len as u64;
+        }
+        self.end += len as u64;
+    }
+
+    /// The number of pages written so far.
+    pub fn pages(&self) -> u64 {
+        self.slots.len() as u64
+    }
+
+    /// Appends a record of `data`, compressed with `codec`, to `out`.
+    fn encode(&self, id: PageId, codec: u8, data: &[u8], out: &mut Vec<u8>) {
+        let sealed;
+        let data = match &self.cipher {
+            Some(cipher) => {
+                sealed = cipher.seal(&id.0.to_le_bytes(), data);
+                &sealed[..]
+            }
+            None => data,
+        };
+
+        let start = out.len();
+        out.extend_from_slice(&id.0.to_le_bytes());
+        out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        out.push(codec);
+        out.extend_from_slice(&[0; 4]);
+        out.extend_from_slice(data);
+
+        let crc = checksum(&out[start..]);
+        out[start + 13..start + 17].copy_from_slice(&crc.to_le_bytes());
+    }
+
+    /// Returns the codec and compressed data of the record of `id`, `None` if it is corrupt.
+    fn decode(&self, id: PageId, record: &[u8]) -> Option<(u8, Vec<u8>)> {
+        if !verify(record) {
+            return None;
+        }
+
+        let data = &record[RECORD_HEADER..];
+        match &self.cipher {
+            Some(cipher) => Some((record[12], cipher.open(&id.0.to_le_bytes(), data)?)),
+            None => Some((record[12], data.to_vec())),
+        }
+    }
+
+    /// The file's header, which a copy of its records needs to be read.
+    pub fn header(&self, file: &File) -> io::Result<Vec<u8>> {
+        let mut header = vec![0; FILE_HEADER as usize];
+        file.read_exact_at(&mut header, 0)?;
+        Ok(header)
+    }
+
+    /// The latest record of `id` as it is stored, `None` if it was never written.
+    pub fn read_record(&self, file: &File, id: PageId) -> io::Result<Option<Vec<u8>>> {
+        let slot = match self.slots.get(id.0 as usize) {
+            Some(Some(slot)) => *slot,
+            _ => return Ok(None),
+        };
+
+        let mut record = vec![0; slot.len];
+        file.read_exact_at(&mut record, slot.offset)?;
+        Ok(Some(record))
+    }
+
+    /// Reads the latest copy of a page, a page never written is zeroed. A corrupt record fails with
+    /// a `ChecksumMismatch`.
+    pub fn read(&self, file: &File, id: PageId, buf: &mut PageBuf) -> io::Result<()> {
+        let record = match self.read_record(file, id)? {
+            Some(record) => record,
+            None => {
+                buf.fill(0);
+                return Ok(());
+            }
+        };
+
+        let mismatch = || io::Error::new(io::ErrorKind::InvalidData, ChecksumMismatch(id));
+        let (codec, data) = self.decode(id, &record).ok_or_else(mismatch)?;
+        decompress(codec, &data, buf).map_err(|e| match e.kind() {
+            io::ErrorKind::Unsupported => e,
+            _ => mismatch(),
+        })
+    }
+
+    /// Appends a record of every page in one write.
+    pub fn write(&mut self, file: &mut File, pages: &[(PageId, &PageBuf)]) -> io::Result<()> {
+        let mut records = Vec::new();
+        let mut placed = Vec::with_capacity(pages.len());
+        for (id, page) in pages {
+            let (codec, data) = self.codec.compress(page)?;
+
+            let start = records.len();
+            self.encode(*id, codec, &data, &mut records);
+            placed.push((*id, records.len() - start));
+        }
+
+        file.write_all_at(&records, self.end)?;
+        for (id, len) in placed {
+            self.place(id, len);
+        }
+
+        if self.dead >= COMPACT_BYTES && self.dead > self.end - FILE_HEADER - self.dead {
+            self.compact(file, self.cipher.clone())?;
+        }
+
+        Ok(())
+    }
+
+    /// Re-encrypts every page with `key`, by compacting the file. A crash leaves the file
+    /// encrypted with either key.
+    pub fn rekey(&mut self, file: &mut File, key: &Key) -> io::Result<()> {
+        if self.cipher.is_none() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "file isn't encrypted"));
+        }
+
+        self.compact(file, Some(Cipher::new(key)))
+    }
+
+    /// Copies the latest record of every page to a new file sealed with `cipher`, then replaces
+    /// the old file with it.
+    fn compact(&mut self, file: &mut File, cipher: Option<Cipher>) -> io::Result<()> {
+        let mut tmp = self.path.as_os_str().to_owned();
+        tmp.push("-compact");
+        let tmp = PathBuf::from(tmp);
+
+        let (mut new, mut packed) = Self::create_with_cipher(&tmp, self.codec, cipher)?;
+        new.seek(SeekFrom::Start(FILE_HEADER))?;
+        let mut writer = BufWriter::new(&new);
+        let mut records = Vec::new();
+        for id in (0..self.pages()).map(PageId) {
+            let record = match self.read_record(file, id)? {
+                Some(record) => record,
+                None => continue,
+            };
+            let (codec, data) = self
+                .decode(id, &record)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, ChecksumMismatch(id)))?;
+
+            records.clear();
+            packed.encode(id, codec, &data, &mut records);
+            writer.write_all(&records)?;
+            packed.place(id, records.len());
+        }
+        writer.flush()?;
+        drop(writer);
+        new.sync_data()?;
+
+        fs::rename(&tmp, &self.path)?;
+        disk::sync_dir(&self.path)?;
+
+        packed.path = self.path.clone();
+        *self = packed;
+        *file = new;
+
+        Ok(())
+    }
+
+    pub fn stats(&self) -> CompressionStats {
+        CompressionStats {
+            codec: self.codec,
+            pages: self.slots.iter().flatten().count() as u64,
+            live_bytes: self.end - FILE_HEADER - self.dead,
+            dead_bytes: self.dead,
+        }
+    }
+}
+
+/// Returns `false` if the file ends first.
+fn read_record(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<bool> {
+    match reader.read_exact(buf) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// CRC32C of a record, without the checksum field itself.
+fn checksum(record: &[u8]) -> u32 {
+    let crc = crc32c::crc32c(&record[..13]);
+    crc32c::crc32c_append(crc, &record[RECORD_HEADER..])
+}
+
+fn verify(record: &[u8]) -> bool {
+    u32::from_le_bytes(record[13..17].try_into().unwrap()) == checksum(record)
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::File;
+
+    use rand::rngs::StdRng;
+    use rand::{RngCore, SeedableRng};
+
+    use crate::page::{PageBuf, PageId, PAGE_SIZE};
+
+    use super::{Codec, Packed};
+
+    #[test]
+    fn test_packed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pages");
+
+        let codecs = [
+            Codec::Lz4,
+            #[cfg(feature = "zstd")]
+            Codec::Zstd(3),
+        ];
+        for codec in codecs {
+            let (mut file, mut packed) = Packed::create(&path, codec, None).unwrap();
+
+            // Compressible pages, then pages of noise rewritten until the file is compacted
+            let text = |i: u64| -> PageBuf {
+                let line = format!("page {i} holds a line of text repeated over and over. ");
+                let mut page = [0; PAGE_SIZE];
+                for (b, c) in page.iter_mut().zip(line.bytes().cycle()) {
+                    *b = c;
+                }
+                page
+            };
+            let noise = |i: u64| -> PageBuf {
+                let mut page = [0; PAGE_SIZE];
+                StdRng::seed_from_u64(i).fill_bytes(&mut page);
+                page
+            };
+
+            let pages = (0..20).map(|i| (PageId(i), text(i))).collect::<Vec<_>>();
+            let batch = pages.iter().map(|(id, p)| (*id, p)).collect::<Vec<_>>();
+            packed.write(&mut file, &batch).unwrap();
+            let stats = packed.stats();
+            assert!(stats.pages == 20 && stats.ratio() > 10.0, "{:?}", stats);
+
+            let mut compacted = false;
+            for round in 0..600 {
+                let page = noise(round);
+                packed.write(&mut file, &[(PageId(20), &page)]).unwrap();
+                compacted |= packed.stats().dead_bytes == 0;
+            }
+            assert!(compacted && !dir.path().join("pages-compact").exists());
+            let size = file.metadata().unwrap().len();
+            drop(file);
+
+            // A torn record at the end is dropped
+            let file = File::options().write(true).open(&path).unwrap();
+            file.set_len(size - 100).unwrap();
+            drop(file);
+
+            let file = File::options().read(true).write(true).open(&path).unwrap();
+            assert!(Packed::is_packed(&file).unwrap());
+            let packed = Packed::open(&path, &file, None).unwrap();
+            assert!(packed.pages() == 21);
+
+            let mut buf = [0; PAGE_SIZE];
+            for (id, want) in &pages {
+                packed.read(&file, *id, &mut buf).unwrap();
+                assert!(buf == *want, "Page: {}", id.0);
+            }
+            packed.read(&file, PageId(20), &mut buf).unwrap();
+            assert!(buf == noise(598));
+            packed.read(&file, PageId(21), &mut buf).unwrap();
+            assert!(buf == [0; PAGE_SIZE]);
+        }
+    }
+}
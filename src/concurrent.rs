@@ -0,0 +1,705 @@
+use std::collections::VecDeque;
+use std::fmt::Debug;
+use std::iter::FusedIterator;
+use std::ops::{Bound, RangeBounds};
+use std::ptr;
+use std::time::Duration;
+
+use crate::btree::{BTree, Increment};
+use crate::epoch::Collector;
+use crate::latch::{LatchError, LatchGuard, LatchId, LatchManager, LatchMode};
+use crate::metrics::{self, Counter, Op};
+use crate::node::Node;
+use crate::slot::{Either, Slot, Slots};
+use crate::sync::{AtomicPtr, Ordering};
+use crate::trace;
+use crate::{get_left, get_right};
+
+/// A tree that can be read from any number of threads while a writer is active.
+///
+/// The root is published through an atomic pointer. Writers are serialised and never change the
+/// structure of a node a reader can reach: an insert that splits copies the path from the root to
+/// the affected leaf, applies the change to the copies (splitting them as needed, including the
+/// root) and publishes the new root with a single store. Replaced nodes are retired through the
+/// epoch `Collector` so readers that loaded the old root can finish their descent.
+///
+/// Inserts that fit in their leaf and all deletes modify the leaf in place under the node's
+/// `SeqLock` instead. Readers copy each node's slots out, validate the sequence and retry that node
+/// if a write overlapped, so `get` never blocks.
+///
+/// Writers take `TREE_LATCH` exclusively through the tree's `LatchManager`. It is the only latch
+/// the tree takes: with one writer at a time, leaves changed in place need only their `SeqLock`.
+///
+/// Lookups always descend from the root, so the leaf `next` chain is not maintained.
+pub struct ConcurrentBTree<K, V> {
+    root: AtomicPtr<Node<K, V>>,
+    max: usize,
+    latches: LatchManager,
+    collector: Collector,
+}
+
+/// Serialises writers, taken at level 0.
+pub const TREE_LATCH: LatchId = 0;
+
+unsafe impl<K: Send + Sync, V: Send + Sync> Send for ConcurrentBTree<K, V> {}
+unsafe impl<K: Send + Sync, V: Send + Sync> Sync for ConcurrentBTree<K, V> {}
+
+impl<K, V> ConcurrentBTree<K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment,
+    V: Clone + Copy + Debug + Eq,
+{
+    pub fn new(max: usize) -> Self {
+        Self {
+            root: AtomicPtr::new(ptr::null_mut()),
+            max,
+            latches: LatchManager::new(),
+            collector: Collector::new(),
+        }
+    }
+
+    pub fn insert(&self, entry: Slot<K, V>) {
+        let writer = self
+            .latches
+            .acquire(TREE_LATCH, 0, LatchMode::Exclusive)
+            .expect("tree latch should be acquired first");
+
+        self._insert(entry, writer);
+    }
+
+    /// Like `insert`, but gives up if another writer holds the tree for longer than `timeout`. The
+    /// error carries the wait-for graph at that point.
+    pub fn try_insert(&self, entry: Slot<K, V>, timeout: Duration) -> Result<(), LatchError> {
+        let writer = self
+            .latches
+            .try_acquire(TREE_LATCH, 0, LatchMode::Exclusive, timeout)?;
+
+        self._insert(entry, writer);
+        Ok(())
+    }
+
+    pub fn latches(&self) -> &LatchManager {
+        &self.latches
+    }
+
+    fn _insert(&self, entry: Slot<K, V>, _writer: LatchGuard<'_>) {
+        assert!(entry.is_leaf());
+        metrics::count(Counter::Inserts);
+        let _timer = metrics::time(Op::Insert);
+        trace::span!(TRACE, "insert", key = ?entry.0);
+
+        let old = self.root.load(Ordering::SeqCst);
+        if let Some(raw_leaf) = Self::find_leaf_in_place(old, entry) {
+            let leaf = unsafe { &mut *raw_leaf };
+            let _w = leaf.seq.write();
+            leaf.values.replace(entry);
+            return;
+        }
+
+        let mut retired = Vec::new();
+        let root = if old.is_null() {
+            let mut root = Node::new_leaf(self.max);
+            root.is_root = true;
+            Box::into_raw(Box::new(root))
+        } else {
+            Self::copy_path(old, entry, &mut retired)
+        };
+
+        let root = match BTree::_insert(root, entry, None, None, &mut false) {
+            Some((s, os)) => {
+                assert!(get_right!(s) == root);
+                unsafe { (*root).is_root = false };
+
+                let mut node = Node::new_internal(self.max);
+                node.is_root = true;
+                node.values.replace(s);
+                node.values.replace(os);
+
+                Box::into_raw(Box::new(node))
+            }
+            None => root,
+        };
+
+        self.publish(root, retired);
+    }
+
+    pub fn get(&self, key: K) -> Option<Slot<K, V>> {
+        metrics::count(Counter::Gets);
+        let _timer = metrics::time(Op::Get);
+        trace::span!(TRACE, "get", key = ?key);
+        let _guard = self.collector.pin();
+
+        let root = self.root.load(Ordering::SeqCst);
+        if root.is_null() {
+            return None;
+        }
+
+        let test = Slot::new_internal(key, ptr::null_mut());
+        Self::_get(root, test)
+    }
+
+    fn _get(mut raw_node: *mut Node<K, V>, slot: Slot<K, V>) -> Option<Slot<K, V>> {
+        let mut slots = Vec::new();
+
+        loop {
+            Self::read_slots(raw_node, &mut slots);
+
+            if unsafe { (*raw_node).is_leaf() } {
+                let i = slots.binary_search_by(|s| s.0.cmp(&slot.0)).ok()?;
+                return Some(slots[i]);
+            }
+
+            let n = slots.iter().find(|n| slot < **n)?;
+            raw_node = get_right!(n);
+        }
+    }
+
+    /// Copies the slots of a node into `slots`, retrying until the copy was not overlapped by a
+    /// write. The caller must be pinned.
+    fn read_slots(raw_node: *mut Node<K, V>, slots: &mut Vec<Slot<K, V>>) {
+        let seq = unsafe { &(*raw_node).seq };
+        loop {
+            let start = seq.read_begin();
+            unsafe { Slots::copy_racy(ptr::addr_of!((*raw_node).values), slots) };
+            if seq.read_validate(start) {
+                return;
+            }
+            metrics::count(Counter::Restarts);
+        }
+    }
+
+    /// Returns an iterator over the entries with keys in `range`, in order.
+    ///
+    /// The iterator holds no latch or epoch pin between calls to `next`. Each leaf is copied out
+    /// under its seqlock and the next leaf is found by descending again from the root with the
+    /// leaf's separator, so splits and deletes that happen in between are picked up rather than
+    /// followed through freed nodes. Every leaf is read consistently, but the scan as a whole is
+    /// not a snapshot.
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> ConcurrentRange<'_, K, V> {
+        ConcurrentRange {
+            tree: self,
+            next: Some(range.start_bound().cloned()),
+            end: range.end_bound().cloned(),
+            buf: VecDeque::new(),
+        }
+    }
+
+    pub fn iter(&self) -> ConcurrentRange<'_, K, V> {
+        self.range(..)
+    }
+
+    /// Appends the entries of the leaf `start` falls in that are within `(start, end)` to `out`,
+    /// and returns the leaf's separator, where the next leaf starts. Keys only ever move to the
+    /// right of a separator, so seeking from it never returns a key twice.
+    fn seek(&self, start: Bound<K>, end: Bound<K>, out: &mut VecDeque<(K, V)>) -> Option<K> {
+        let _guard = self.collector.pin();
+
+        let mut raw_node = self.root.load(Ordering::SeqCst);
+        if raw_node.is_null() {
+            return None;
+        }
+
+        let mut slots = Vec::new();
+        let mut upper = None;
+        loop {
+            Self::read_slots(raw_node, &mut slots);
+            if unsafe { (*raw_node).is_leaf() } {
+                break;
+            }
+
+            let n = match start {
+                Bound::Included(k) | Bound::Excluded(k) => slots.iter().find(|n| k < n.0)?,
+                Bound::Unbounded => slots.first()?,
+            };
+            upper = Some(n.0);
+            raw_node = get_right!(n);
+        }
+
+        let entries = slots.iter().filter(|s| (start, end).contains(&s.0));
+        out.extend(entries.map(|s| (s.0, get_left!(s))));
+
+        upper
+    }
+
+    /// Calls `f` with every entry with a key in `range`, in order, to change its value in place.
+    /// Returns how many entries there were.
+    ///
+    /// Works a leaf at a time, taking `TREE_LATCH` for each leaf and releasing it before the next,
+    /// so other writers can go in between and readers see each leaf before or after its change.
+    /// Like `range`, it isn't atomic as a whole.
+    pub fn update_range<R, F>(&self, range: R, mut f: F) -> usize
+    where
+        R: RangeBounds<K>,
+        F: FnMut(K, &mut V),
+    {
+        let end = range.end_bound().cloned();
+        let mut next = Some(range.start_bound().cloned());
+        let mut n = 0;
+
+        while let Some(start) = next {
+            let _writer = self
+                .latches
+                .acquire(TREE_LATCH, 0, LatchMode::Exclusive)
+                .expect("tree latch should be acquired first");
+
+            // Nodes only change structure under the tree latch, so they can be read directly
+            let mut raw_node = self.root.load(Ordering::SeqCst);
+            let mut upper = None;
+            while !raw_node.is_null() && unsafe { !(*raw_node).is_leaf() } {
+                let node = unsafe { &*raw_node };
+                let n = match start {
+                    Bound::Included(k) | Bound::Excluded(k) => node.iter().find(|n| k < n.0),
+                    Bound::Unbounded => node.first(),
+                };
+                raw_node = n.map_or(ptr::null_mut(), |n| get_right!(n));
+                upper = n.map(|n| n.0);
+            }
+            if raw_node.is_null() {
+                break;
+            }
+
+            let node = unsafe { &mut *raw_node };
+            let _w = node.seq.write();
+            n += node.values.update_range(&(start, end), |k, v| f(*k, v));
+
+            next = upper
+                .filter(|u| match end {
+                    Bound::Included(end) => *u <= end,
+                    Bound::Excluded(end) => *u < end,
+                    Bound::Unbounded => true,
+                })
+                .map(Bound::Included);
+        }
+
+        n
+    }
+
+    pub fn delete(&self, key: K) -> bool {
+        metrics::count(Counter::Deletes);
+        let _timer = metrics::time(Op::Delete);
+        trace::span!(TRACE, "delete", key = ?key);
+        let _writer = self
+            .latches
+            .acquire(TREE_LATCH, 0, LatchMode::Exclusive)
+            .expect("tree latch should be acquired first");
+
+        let test = Slot::new_internal(key, ptr::null_mut());
+        let mut raw_node = self.root.load(Ordering::SeqCst);
+        while !raw_node.is_null() {
+            let node = unsafe { &mut *raw_node };
+            match node.find_child(key) {
+                Some(ptr) => raw_node = ptr,
+                None if node.is_leaf() => {
+                    if node.values.get(&test).is_none() {
+                        return false;
+                    }
+
+                    let _w = node.seq.write();
+                    return node.values.remove(&test);
+                }
+                None => return false,
+            }
+        }
+
+        false
+    }
+
+    /// Returns the leaf `value` belongs in if it can be inserted there without changing the
+    /// structure of any node on the way down.
+    fn find_leaf_in_place(raw_node: *mut Node<K, V>, value: Slot<K, V>) -> Option<*mut Node<K, V>> {
+        if raw_node.is_null() {
+            return None;
+        }
+
+        let node = unsafe { &*raw_node };
+        if node.almost_full() {
+            return None;
+        }
+
+        match node.find_child(value.0) {
+            Some(ptr) => Self::find_leaf_in_place(ptr, value),
+            None if node.is_leaf() && node.values.len() < node.values.capacity() => Some(raw_node),
+            None => None,
+        }
+    }
+
+    /// Copies every node `value` passes through on its way to a leaf, following the same route
+    /// `BTree::_insert` will take. The originals are pushed onto `retired`.
+    fn copy_path(
+        raw_node: *mut Node<K, V>,
+        value: Slot<K, V>,
+        retired: &mut Vec<*mut Node<K, V>>,
+    ) -> *mut Node<K, V> {
+        let node = unsafe { &*raw_node };
+        let mut copy = node.clone();
+        retired.push(raw_node);
+
+        if !node.is_leaf() {
+            let child = match node.find_child(value.0) {
+                Some(ptr) => ptr,
+                None => {
+                    let last = node
+                        .values
+                        .last()
+                        .expect("internal node should not be empty");
+                    get_right!(last)
+                }
+            };
+
+            let slot = *node
+                .values
+                .iter()
+                .find(|s| get_right!(s) == child)
+                .expect("child should have a slot");
+            let child = Self::copy_path(child, value, retired);
+            copy.values.replace(Slot::new_internal(slot.0, child));
+        }
+
+        Box::into_raw(Box::new(copy))
+    }
+
+    fn publish(&self, root: *mut Node<K, V>, retired: Vec<*mut Node<K, V>>) {
+        self.root.store(root, Ordering::SeqCst);
+
+        for ptr in retired {
+            unsafe { self.collector.retire(ptr) };
+        }
+        self.collector.collect();
+    }
+}
+
+/// Iterates over a key range of a `ConcurrentBTree` a leaf at a time, see
+/// `ConcurrentBTree::range`.
+pub struct ConcurrentRange<'a, K, V> {
+    tree: &'a ConcurrentBTree<K, V>,
+    // Where to seek for the next leaf, `None` once the range is exhausted
+    next: Option<Bound<K>>,
+    end: Bound<K>,
+    buf: VecDeque<(K, V)>,
+}
+
+impl<K, V> Iterator for ConcurrentRange<'_, K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment,
+    V: Clone + Copy + Debug + Eq,
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.buf.is_empty() {
+            let start = self.next?;
+            let upper = self.tree.seek(start, self.end, &mut self.buf);
+
+            self.next = upper
+                .filter(|u| match self.end {
+                    Bound::Included(end) => *u <= end,
+                    Bound::Excluded(end) => *u < end,
+                    Bound::Unbounded => true,
+                })
+                .map(Bound::Included);
+        }
+
+        self.buf.pop_front()
+    }
+}
+
+impl<K, V> FusedIterator for ConcurrentRange<'_, K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment,
+    V: Clone + Copy + Debug + Eq,
+{
+}
+
+impl<K, V> Drop for ConcurrentBTree<K, V> {
+    fn drop(&mut self) {
+        fn free<K, V>(raw_node: *mut Node<K, V>) {
+            let node = unsafe { Box::from_raw(raw_node) };
+            for slot in &node.values {
+                if let Either::Right(ptr) = slot.1 {
+                    free(ptr);
+                }
+            }
+        }
+
+        let root = self.root.load(Ordering::SeqCst);
+        if !root.is_null() {
+            free(root);
+        }
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod test {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::get_left;
+    use crate::latch::{LatchError, LatchMode};
+    use crate::slot::{Either, Slot};
+
+    use super::{ConcurrentBTree, TREE_LATCH};
+
+    #[test]
+    fn test_concurrent_btree() {
+        const MAX: usize = 8;
+
+        let tree = ConcurrentBTree::new(MAX);
+        for k in 0..100u32 {
+            tree.insert(Slot::new_leaf(k, k + 10));
+        }
+
+        for k in 0..100u32 {
+            let test = match tree.get(k) {
+                Some(t) => t,
+                None => panic!("Could not find {k}"),
+            };
+
+            let have = get_left!(test);
+            assert!(have == k + 10, "Want: {}\nHave: {have}", k + 10);
+        }
+
+        for k in (0..100u32).step_by(2) {
+            assert!(tree.delete(k));
+        }
+        assert!(!tree.delete(0));
+
+        for k in 0..100u32 {
+            let have = tree.get(k).is_some();
+            assert!(have == (k % 2 == 1), "Key: {k}\nHave: {have}");
+        }
+    }
+
+    #[test]
+    fn test_readers_during_root_splits() {
+        const MAX: usize = 4;
+
+        let tree = ConcurrentBTree::new(MAX);
+        for k in 0..10u32 {
+            tree.insert(Slot::new_leaf(k, k));
+        }
+
+        let done = AtomicBool::new(false);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    while !done.load(Ordering::Relaxed) {
+                        for k in 0..10u32 {
+                            let test = match tree.get(k) {
+                                Some(t) => t,
+                                None => panic!("Could not find {k}"),
+                            };
+
+                            let have = get_left!(test);
+                            assert!(have == k, "Want: {k}\nHave: {have}");
+                        }
+                        thread::yield_now();
+                    }
+                });
+            }
+
+            for k in 10..500u32 {
+                tree.insert(Slot::new_leaf(k, k));
+            }
+            done.store(true, Ordering::Relaxed);
+        });
+
+        for k in 0..500u32 {
+            assert!(tree.get(k).is_some(), "Could not find {k}");
+        }
+    }
+
+    #[test]
+    fn test_readers_during_in_place_writes() {
+        const MAX: usize = 8;
+
+        let tree = ConcurrentBTree::new(MAX);
+        for k in 0..100u32 {
+            tree.insert(Slot::new_leaf(k, k));
+        }
+
+        let done = AtomicBool::new(false);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    while !done.load(Ordering::Relaxed) {
+                        for k in (1..100u32).step_by(2) {
+                            let test = match tree.get(k) {
+                                Some(t) => t,
+                                None => panic!("Could not find {k}"),
+                            };
+
+                            let have = get_left!(test);
+                            assert!(have % 100 == k, "Want: {k}\nHave: {have}");
+                        }
+                        thread::yield_now();
+                    }
+                });
+            }
+
+            for round in 1..20u32 {
+                for k in 0..100u32 {
+                    if k % 2 == 0 {
+                        tree.delete(k);
+                    } else {
+                        tree.insert(Slot::new_leaf(k, k + round * 100));
+                    }
+                }
+            }
+            done.store(true, Ordering::Relaxed);
+        });
+
+        for k in 0..100u32 {
+            let have = tree.get(k).is_some();
+            assert!(have == (k % 2 == 1), "Key: {k}\nHave: {have}");
+        }
+    }
+
+    #[test]
+    fn test_range_during_writes() {
+        const MAX: usize = 8;
+
+        let tree = ConcurrentBTree::new(MAX);
+        for k in (0..1000u32).step_by(2) {
+            tree.insert(Slot::new_leaf(k, k));
+        }
+
+        let want = (200..=600u32)
+            .step_by(2)
+            .map(|k| (k, k))
+            .collect::<Vec<_>>();
+        let have = tree.range(200..=600).collect::<Vec<_>>();
+        assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+
+        thread::scope(|s| {
+            s.spawn(|| {
+                for k in (1..1000u32).step_by(2) {
+                    tree.insert(Slot::new_leaf(k, k));
+                }
+            });
+
+            for _ in 0..20 {
+                let have = tree.iter().collect::<Vec<_>>();
+                assert!(have.windows(2).all(|w| w[0].0 < w[1].0), "Out of order: {:?}", have);
+
+                // Even keys were there for the whole scan
+                let even = have.iter().filter(|(k, _)| k % 2 == 0).count();
+                assert!(even == 500, "Want: 500\nHave: {even}");
+                thread::yield_now();
+            }
+        });
+
+        let have = tree.iter().count();
+        assert!(have == 1000, "Want: 1000\nHave: {have}");
+    }
+
+    #[test]
+    fn test_update_range_during_writes() {
+        const MAX: usize = 8;
+
+        let tree = ConcurrentBTree::new(MAX);
+        for k in (0..1000u32).step_by(2) {
+            tree.insert(Slot::new_leaf(k, k));
+        }
+
+        let updated = thread::scope(|s| {
+            s.spawn(|| {
+                for k in (1..1000u32).step_by(2) {
+                    tree.insert(Slot::new_leaf(k, k));
+                }
+            });
+
+            tree.update_range(100..900, |k, v| {
+                assert!(*v == k, "Want: {k}\nHave: {v}");
+                *v += 10_000;
+            })
+        });
+
+        // Every even key in the range was updated once, odd ones if they were there in time
+        let have = tree.iter().filter(|(_, v)| *v >= 10_000).count();
+        assert!(have == updated && updated >= 400, "Want: {updated}\nHave: {have}");
+        for (k, v) in tree.iter() {
+            let even = (100..900).contains(&k) && k % 2 == 0;
+            assert!(v % 10_000 == k && (v > k || !even), "Key: {k}\nHave: {v}");
+        }
+    }
+
+    #[test]
+    fn test_try_insert_times_out() {
+        const MAX: usize = 8;
+
+        let tree = ConcurrentBTree::new(MAX);
+        tree.insert(Slot::new_leaf(1, 1));
+
+        let (tx, rx) = mpsc::channel();
+        let (done_tx, done_rx) = mpsc::channel::<()>();
+        let tree = &tree;
+        thread::scope(|s| {
+            s.spawn(move || {
+                let _writer = tree
+                    .latches()
+                    .acquire(TREE_LATCH, 0, LatchMode::Exclusive)
+                    .unwrap();
+                tx.send(()).unwrap();
+                done_rx.recv().unwrap();
+            });
+            rx.recv().unwrap();
+
+            let have = tree.try_insert(Slot::new_leaf(2, 2), Duration::from_millis(20));
+            match have {
+                Err(LatchError::Timeout { latch, graph, .. }) => {
+                    assert!(latch == TREE_LATCH);
+                    assert!(graph.edges.len() == 1, "Have: {graph}");
+                }
+                _ => panic!("Want: timeout\nHave: {:?}", have),
+            }
+            assert!(tree.get(2).is_none());
+
+            done_tx.send(()).unwrap();
+        });
+
+        tree.try_insert(Slot::new_leaf(2, 2), Duration::from_millis(20))
+            .unwrap();
+        assert!(tree.get(2).is_some());
+    }
+}
+
+#[cfg(all(test, loom))]
+mod loom_test {
+    use loom::sync::Arc;
+    use loom::thread;
+
+    use crate::slot::Slot;
+
+    use super::ConcurrentBTree;
+
+    #[test]
+    fn loom_get_during_insert() {
+        let mut builder = loom::model::Builder::new();
+        builder.preemption_bound = Some(2);
+
+        builder.check(|| {
+            const MAX: usize = 4;
+
+            let tree = Arc::new(ConcurrentBTree::new(MAX));
+            tree.insert(Slot::new_leaf(0u32, 0u32));
+
+            let writer = {
+                let tree = tree.clone();
+                thread::spawn(move || {
+                    // Inserts in place, then splits the root
+                    tree.insert(Slot::new_leaf(1, 1));
+                    tree.insert(Slot::new_leaf(2, 2));
+                    tree.delete(1);
+                })
+            };
+
+            assert!(tree.get(0).is_some(), "Could not find 0");
+            writer.join().unwrap();
+
+            assert!(tree.get(1).is_none());
+            assert!(tree.get(2).is_some());
+        });
+    }
+}
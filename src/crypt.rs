@@ -0,0 +1,61 @@
+use std::fmt::{self, Debug};
+
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use rand::RngCore;
+
+const NONCE: usize = 24;
+const TAG: usize = 16;
+
+/// A 256-bit key a tree's pages and log are encrypted with.
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub struct Key(pub [u8; 32]);
+
+impl Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Key(..)")
+    }
+}
+
+/// XChaCha20-Poly1305 with a random nonce per message, so nonces never need to be tracked.
+#[derive(Clone)]
+pub(crate) struct Cipher(XChaCha20Poly1305);
+
+impl Cipher {
+    /// Bytes a sealed message takes beyond the plaintext, for its nonce and tag.
+    pub const OVERHEAD: usize = NONCE + TAG;
+
+    pub fn new(key: &Key) -> Self {
+        Self(XChaCha20Poly1305::new(&key.0.into()))
+    }
+
+    /// Encrypts `data`, authenticating it along with `aad`. Returns the nonce, the ciphertext and
+    /// the tag.
+    pub fn seal(&self, aad: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut nonce = [0; NONCE];
+        rand::thread_rng().fill_bytes(&mut nonce);
+
+        let sealed = self
+            .0
+            .encrypt(XNonce::from_slice(&nonce), Payload { msg: data, aad })
+            .expect("message fits the cipher");
+
+        let mut out = Vec::with_capacity(Self::OVERHEAD + data.len());
+        out.extend_from_slice(&nonce);
+        out.extend_from_slice(&sealed);
+        out
+    }
+
+    /// Decrypts what `seal()` returned, `None` if it was sealed with another key or `aad`, or
+    /// was modified.
+    pub fn open(&self, aad: &[u8], sealed: &[u8]) -> Option<Vec<u8>> {
+        if sealed.len() < Self::OVERHEAD {
+            return None;
+        }
+
+        let (nonce, msg) = sealed.split_at(NONCE);
+        self.0
+            .decrypt(XNonce::from_slice(nonce), Payload { msg, aad })
+            .ok()
+    }
+}
@@ -0,0 +1,152 @@
+use std::fmt::{Debug, Display};
+use std::io::{self, BufRead, Write};
+use std::str::FromStr;
+
+use crate::btree::{BTree, Increment};
+use crate::slot::Slot;
+
+const HEADER: &str = "key,value";
+/// Rows read between calls to the progress callback of `BTree::import_csv()`.
+pub const PROGRESS_ROWS: u64 = 10_000;
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// How `BTree::import_csv()` builds the tree.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum CsvImport {
+    /// The rows are in ascending key order, for example after `sort -t, -k1,1n`, and are bulk
+    /// loaded. A row out of order fails the import.
+    Sorted,
+    /// The rows are inserted one by one in any order, a later row for a key replaces an earlier
+    /// one.
+    Insert,
+}
+
+impl<K, V> BTree<K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment,
+    V: Clone + Copy + Debug + Eq,
+{
+    /// Writes a `key,value` header then a row per entry, in order, returning the number of rows.
+    /// Keys and values are written with `Display`, and aren't quoted.
+    pub fn export_csv<W: Write>(&self, mut writer: W) -> io::Result<u64>
+    where
+        K: Display,
+        V: Display,
+    {
+        writeln!(writer, "{HEADER}")?;
+
+        let mut rows = 0;
+        for (k, v) in self.iter() {
+            writeln!(writer, "{k},{v}")?;
+            rows += 1;
+        }
+        writer.flush()?;
+
+        Ok(rows)
+    }
+
+    /// Builds a tree from `key,value` rows, as written by `export_csv()`, with or without the
+    /// header. `progress` is called with the rows read so far every `PROGRESS_ROWS` rows, and
+    /// once at the end.
+    pub fn import_csv<R: BufRead>(
+        max: usize,
+        reader: R,
+        import: CsvImport,
+        mut progress: impl FnMut(u64),
+    ) -> io::Result<Self>
+    where
+        K: FromStr,
+        V: FromStr,
+    {
+        let mut rows = 0;
+        let mut entries = Vec::new();
+        let mut tree = BTree::new(max);
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            if (i == 0 && line == HEADER) || line.is_empty() {
+                continue;
+            }
+
+            let row = line
+                .split_once(',')
+                .and_then(|(k, v)| Some((k.trim().parse().ok()?, v.trim().parse().ok()?)));
+            let (k, v): (K, V) = match row {
+                Some(row) => row,
+                None => return Err(invalid(format!("line {}: invalid row {:?}", i + 1, line))),
+            };
+
+            match import {
+                CsvImport::Sorted => {
+                    if let Some((last, _)) = entries.last() {
+                        if k <= *last {
+                            return Err(invalid(format!(
+                                "line {}: key {:?} out of order after {:?}",
+                                i + 1,
+                                k,
+                                last
+                            )));
+                        }
+                    }
+                    entries.push((k, v));
+                }
+                CsvImport::Insert => tree.insert(Slot::new_leaf(k, v)),
+            }
+
+            rows += 1;
+            if rows % PROGRESS_ROWS == 0 {
+                progress(rows);
+            }
+        }
+        progress(rows);
+
+        Ok(match import {
+            CsvImport::Sorted => BTree::bulk_load(max, entries),
+            CsvImport::Insert => tree,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::btree::BTree;
+
+    use super::{CsvImport, PROGRESS_ROWS};
+
+    #[test]
+    fn test_csv() {
+        let tree = BTree::bulk_load(8, (0..25_000i64).map(|k| (k - 100, k as u64 * 2)));
+
+        let mut csv = Vec::new();
+        assert!(tree.export_csv(&mut csv).unwrap() == 25_000);
+        assert!(csv.starts_with(b"key,value\n-100,0\n-99,2\n"));
+
+        let want = tree.iter().collect::<Vec<_>>();
+        for import in [CsvImport::Sorted, CsvImport::Insert] {
+            let mut calls = Vec::new();
+            let have = BTree::<i64, u64>::import_csv(8, &csv[..], import, |rows| calls.push(rows))
+                .unwrap()
+                .iter()
+                .collect::<Vec<_>>();
+            assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+
+            let want = vec![PROGRESS_ROWS, PROGRESS_ROWS * 2, 25_000];
+            assert!(want == calls, "Want: {:?}\nHave: {:?}", want, calls);
+        }
+
+        // Without a header, in any order when inserted
+        let csv = "3,30\n1,10\n2, 20\n1,11\n";
+        let tree = BTree::<u32, u32>::import_csv(8, csv.as_bytes(), CsvImport::Insert, |_| {});
+        let have = tree.unwrap().iter().collect::<Vec<_>>();
+        let want = vec![(1, 11), (2, 20), (3, 30)];
+        assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+
+        let err = BTree::<u32, u32>::import_csv(8, csv.as_bytes(), CsvImport::Sorted, |_| {});
+        assert!(err.is_err());
+        let err = BTree::<u32, u32>::import_csv(8, "1,a\n".as_bytes(), CsvImport::Insert, |_| {});
+        let err = err.err().unwrap().to_string();
+        assert!(err == "line 1: invalid row \"1,a\"", "Have: {err}");
+    }
+}
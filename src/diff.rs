@@ -0,0 +1,148 @@
+use std::fmt::Debug;
+use std::iter::FusedIterator;
+
+use crate::btree::{BTree, Increment};
+use crate::iter::Range;
+
+/// A difference between an old and a new tree.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Diff<K, V> {
+    /// The key is only in the new tree.
+    Added(K, V),
+    /// The key is only in the old tree.
+    Removed(K, V),
+    Changed {
+        key: K,
+        old: V,
+        new: V,
+    },
+}
+
+impl<K: Copy, V> Diff<K, V> {
+    pub fn key(&self) -> K {
+        match self {
+            Diff::Added(key, _) | Diff::Removed(key, _) | Diff::Changed { key, .. } => *key,
+        }
+    }
+}
+
+impl<K, V> BTree<K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment,
+    V: Clone + Copy + Debug + Eq,
+{
+    /// Returns the differences from this tree to `new`, in key order, walking both leaf chains
+    /// together.
+    pub fn diff<'a>(&'a self, new: &'a BTree<K, V>) -> BTreeDiff<'a, K, V> {
+        BTreeDiff {
+            old: self.iter(),
+            new: new.iter(),
+        }
+    }
+}
+
+/// See `BTree::diff`.
+pub struct BTreeDiff<'a, K, V> {
+    old: Range<'a, K, V>,
+    new: Range<'a, K, V>,
+}
+
+impl<K, V> Iterator for BTreeDiff<'_, K, V>
+where
+    K: Copy + Ord,
+    V: Copy + Eq,
+{
+    type Item = Diff<K, V>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (key, old, new) = match (self.old.peek(), self.new.peek()) {
+                (None, None) => return None,
+                (Some((k, v)), None) => (k, Some(v), None),
+                (None, Some((k, v))) => (k, None, Some(v)),
+                (Some((k, v)), Some(new)) if k < new.0 => (k, Some(v), None),
+                (Some(old), Some((k, v))) if k < old.0 => (k, None, Some(v)),
+                (Some((k, old)), Some((_, new))) => (k, Some(old), Some(new)),
+            };
+
+            match (old, new) {
+                (Some(old), Some(new)) => {
+                    self.old.next();
+                    self.new.next();
+                    if old != new {
+                        return Some(Diff::Changed { key, old, new });
+                    }
+                }
+                (Some(old), None) => {
+                    self.old.next();
+                    return Some(Diff::Removed(key, old));
+                }
+                (None, Some(new)) => {
+                    self.new.next();
+                    return Some(Diff::Added(key, new));
+                }
+                (None, None) => unreachable!(),
+            }
+        }
+    }
+}
+
+impl<K: Copy + Ord, V: Copy + Eq> FusedIterator for BTreeDiff<'_, K, V> {}
+
+#[cfg(test)]
+mod test {
+    use crate::btree::BTree;
+    use crate::persistent::PersistentBTree;
+    use crate::slot::Slot;
+
+    use super::Diff;
+
+    #[test]
+    fn test_diff() {
+        let old = BTree::bulk_load(4, (0..300u32).map(|k| (k, k)));
+        let mut new = BTree::bulk_load(4, (0..300u32).map(|k| (k, k)));
+        for k in (0..300).step_by(50) {
+            new.delete(k);
+        }
+        for k in [7, 299] {
+            new.insert(Slot::new_leaf(k, 0));
+        }
+        for k in [1000, 1001] {
+            new.insert(Slot::new_leaf(k, k));
+        }
+
+        let mut want = (0..300)
+            .step_by(50)
+            .map(|k| Diff::Removed(k, k))
+            .collect::<Vec<_>>();
+        want.extend([7, 299].map(|k| Diff::Changed {
+            key: k,
+            old: k,
+            new: 0,
+        }));
+        want.extend([1000, 1001].map(|k| Diff::Added(k, k)));
+        want.sort_by_key(|d| d.key());
+        let have = old.diff(&new).collect::<Vec<_>>();
+        assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+
+        let reverse = new.diff(&old).collect::<Vec<_>>();
+        assert!(reverse.len() == want.len() && matches!(reverse[0], Diff::Added(0, 0)));
+        assert!(old.diff(&old).next().is_none());
+
+        // Snapshots of a copy-on-write tree
+        let mut tree = PersistentBTree::new(4);
+        for k in 0..300u32 {
+            tree.insert(k, k);
+        }
+        let snapshot = tree.snapshot();
+        assert!(snapshot.diff(&tree).next().is_none());
+        for k in (0..300).step_by(50) {
+            tree.delete(k);
+        }
+        for k in [7, 299, 1000, 1001] {
+            tree.insert(k, if k < 1000 { 0 } else { k });
+        }
+        let have = snapshot.diff(&tree).collect::<Vec<_>>();
+        assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+    }
+}
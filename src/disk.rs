@@ -0,0 +1,510 @@
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::fs::{FileExt, OpenOptionsExt};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::compress::{Codec, CompressionStats, Packed};
+use crate::crypt::Key;
+use crate::fault::FaultInjector;
+use crate::page::{self, PageBuf, PageId, PAGE_SIZE};
+use crate::trace;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+use crate::uring::{Op, Ring};
+
+/// Held by the `io::Error` for a page read from disk that doesn't match its checksum.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct ChecksumMismatch(pub PageId);
+
+impl ChecksumMismatch {
+    /// Returns the corrupt page if `e` is a checksum mismatch.
+    pub fn page(e: &io::Error) -> Option<PageId> {
+        e.get_ref()?.downcast_ref::<Self>().map(|c| c.0)
+    }
+}
+
+impl Display for ChecksumMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "page {} does not match its checksum", self.0 .0)
+    }
+}
+
+impl Error for ChecksumMismatch {}
+
+/// Syncs the directory holding `path`, so a file renamed to it stays renamed.
+pub(crate) fn sync_dir(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => File::open(dir)?.sync_all(),
+        _ => File::open(".")?.sync_all(),
+    }
+}
+
+/// How a `DiskManager` reads and writes pages.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum Backend {
+    /// Through the OS page cache.
+    #[default]
+    Buffered,
+    /// Bypasses the OS page cache with `O_DIRECT`, so pages are only cached once, by the
+    /// `BufferPool`. Not every file system supports it.
+    Direct,
+    /// Through the OS page cache, with an io_uring so batches of pages are written together and
+    /// scans read leaves ahead together.
+    /// Falls back to `Buffered` without the `io-uring` feature, off Linux, or if the kernel
+    /// refuses to set up a ring.
+    IoUring,
+}
+
+/// A page aligned for `O_DIRECT`.
+#[repr(C, align(4096))]
+struct Aligned(PageBuf);
+
+/// Reads and writes whole pages of a single file. Pages are checksummed as they are written and
+/// verified as they are read.
+///
+/// A file created by `create_compressed()` or `create_encrypted()` holds compressed or encrypted
+/// pages instead, see `Codec`. They are always read and written through the OS page cache.
+pub struct DiskManager {
+    file: File,
+    pages: u64,
+    backend: Backend,
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    ring: Option<Ring>,
+    packed: Option<Packed>,
+    faults: Option<Arc<FaultInjector>>,
+}
+
+impl DiskManager {
+    /// Creates an empty file at `path`, truncating any existing one.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::create_with_backend(path, Backend::default())
+    }
+
+    pub fn create_with_backend<P: AsRef<Path>>(path: P, backend: Backend) -> io::Result<Self> {
+        let file = Self::options(backend)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+
+        Ok(Self::new(file, 0, backend))
+    }
+
+    /// Creates an empty file at `path` whose pages are compressed with `codec` as they are
+    /// written, truncating any existing one.
+    pub fn create_compressed<P: AsRef<Path>>(path: P, codec: Codec) -> io::Result<Self> {
+        let (file, packed) = Packed::create(path.as_ref(), codec, None)?;
+        Ok(Self::packed(file, packed))
+    }
+
+    /// Like `create_compressed`, also encrypting pages with `key`.
+    pub fn create_encrypted<P: AsRef<Path>>(path: P, codec: Codec, key: Key) -> io::Result<Self> {
+        let (file, packed) = Packed::create(path.as_ref(), codec, Some(&key))?;
+        Ok(Self::packed(file, packed))
+    }
+
+    fn packed(file: File, packed: Packed) -> Self {
+        let mut disk = Self::new(file, packed.pages(), Backend::Buffered);
+        disk.packed = Some(packed);
+        disk
+    }
+
+    /// Opens the file at `path`, which may have been created compressed but not encrypted.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::open_with_backend(path, Backend::default())
+    }
+
+    pub fn open_with_backend<P: AsRef<Path>>(path: P, backend: Backend) -> io::Result<Self> {
+        Self::open_file(path.as_ref(), backend, false)
+    }
+
+    /// Like `open_with_backend()`, but truncates a page a crash left partly appended to the end
+    /// of the file rather than failing. Only for a file with a log to rebuild the page from.
+    pub(crate) fn open_torn(path: &Path, backend: Backend) -> io::Result<Self> {
+        Self::open_file(path, backend, true)
+    }
+
+    fn open_file(path: &Path, backend: Backend, torn: bool) -> io::Result<Self> {
+        let file = Self::options(Backend::Buffered).open(path)?;
+        if Packed::is_packed(&file)? {
+            let packed = Packed::open(path, &file, None)?;
+            return Ok(Self::packed(file, packed));
+        }
+
+        let file = match backend {
+            Backend::Direct => Self::options(backend).open(path)?,
+            _ => file,
+        };
+
+        let mut len = file.metadata()?.len();
+        if len % PAGE_SIZE as u64 != 0 && torn {
+            len -= len % PAGE_SIZE as u64;
+            file.set_len(len)?;
+        }
+        if len % PAGE_SIZE as u64 != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("file length {len} is not a multiple of the page size"),
+            ));
+        }
+
+        Ok(Self::new(file, len / PAGE_SIZE as u64, backend))
+    }
+
+    /// Opens a file created by `create_encrypted()` with the same `key`.
+    pub fn open_encrypted<P: AsRef<Path>>(path: P, key: Key) -> io::Result<Self> {
+        let path = path.as_ref();
+
+        let file = Self::options(Backend::Buffered).open(path)?;
+        if !Packed::is_packed(&file)? {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "file isn't encrypted"));
+        }
+
+        let packed = Packed::open(path, &file, Some(&key))?;
+        Ok(Self::packed(file, packed))
+    }
+
+    /// Re-encrypts every page with `key`, which the file must be opened with from then on. A
+    /// crash leaves it encrypted with either key. Fails unless the file is encrypted.
+    pub fn rekey(&mut self, key: Key) -> io::Result<()> {
+        match &mut self.packed {
+            Some(packed) => packed.rekey(&mut self.file, &key),
+            None => Err(io::Error::new(io::ErrorKind::InvalidInput, "file isn't encrypted")),
+        }
+    }
+
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    fn new(file: File, pages: u64, backend: Backend) -> Self {
+        let ring = match backend {
+            Backend::IoUring => Ring::new().ok(),
+            _ => None,
+        };
+        let backend = match (backend, &ring) {
+            (Backend::IoUring, None) => Backend::Buffered,
+            (backend, _) => backend,
+        };
+
+        Self {
+            file,
+            pages,
+            backend,
+            ring,
+            packed: None,
+            faults: None,
+        }
+    }
+
+    #[cfg(not(all(feature = "io-uring", target_os = "linux")))]
+    fn new(file: File, pages: u64, backend: Backend) -> Self {
+        let backend = match backend {
+            Backend::IoUring => Backend::Buffered,
+            backend => backend,
+        };
+
+        Self {
+            file,
+            pages,
+            backend,
+            packed: None,
+            faults: None,
+        }
+    }
+
+    fn options(backend: Backend) -> OpenOptions {
+        let mut options = OpenOptions::new();
+        options.read(true).write(true);
+        if backend == Backend::Direct {
+            options.custom_flags(libc::O_DIRECT);
+        }
+
+        options
+    }
+
+    /// Passes page writes through `faults`, see `FaultInjector`.
+    pub fn set_faults(&mut self, faults: Option<Arc<FaultInjector>>) {
+        self.faults = faults;
+    }
+
+    /// The backend in use, after any fallback.
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    /// How well pages compress, `None` unless the file was created compressed.
+    pub fn compression(&self) -> Option<CompressionStats> {
+        self.packed.as_ref().map(|packed| packed.stats())
+    }
+
+    /// The number of pages allocated so far.
+    pub fn pages(&self) -> u64 {
+        self.pages
+    }
+
+    /// Reserves a new page at the end of the file, it exists on disk once first written.
+    pub fn allocate(&mut self) -> PageId {
+        self.pages += 1;
+        PageId(self.pages - 1)
+    }
+
+    pub fn read_page(&self, id: PageId, buf: &mut PageBuf) -> io::Result<()> {
+        assert!(id.0 < self.pages, "page {} was never allocated", id.0);
+        trace::event!(TRACE, page = id.0, "read page");
+
+        let offset = id.0 * PAGE_SIZE as u64;
+        let read = match self.backend {
+            _ if self.packed.is_some() => self.packed.as_ref().unwrap().read(&self.file, id, buf),
+            Backend::Direct => {
+                let mut page = Box::new(Aligned([0; PAGE_SIZE]));
+                let read = self.file.read_exact_at(&mut page.0, offset);
+                *buf = page.0;
+                read
+            }
+            #[cfg(all(feature = "io-uring", target_os = "linux"))]
+            Backend::IoUring => {
+                let file = &self.file;
+                let ring = self.ring.as_ref().unwrap();
+                ring.run(&mut [Op::Read { file, buf, offset }])
+            }
+            _ => self.file.read_exact_at(buf, offset),
+        };
+
+        match read {
+            // Allocated but not written yet
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                buf.fill(0);
+                Ok(())
+            }
+            Err(e) => Err(e),
+            Ok(()) if !page::verify(buf) => {
+                Err(io::Error::new(io::ErrorKind::InvalidData, ChecksumMismatch(id)))
+            }
+            Ok(()) => Ok(()),
+        }
+    }
+
+    /// Reads every page, all at once with an io_uring, see `batches_reads()`. Fails if any read
+    /// does.
+    pub fn read_pages(&self, pages: &mut [(PageId, &mut PageBuf)]) -> io::Result<()> {
+        #[cfg(all(feature = "io-uring", target_os = "linux"))]
+        if let (Some(ring), None) = (&self.ring, &self.packed) {
+            trace::event!(TRACE, pages = pages.len(), "read pages");
+            let file = &self.file;
+            let mut ops = pages
+                .iter_mut()
+                .map(|(id, buf)| {
+                    assert!(id.0 < self.pages, "page {} was never allocated", id.0);
+                    Op::Read {
+                        file,
+                        buf: &mut buf[..],
+                        offset: id.0 * PAGE_SIZE as u64,
+                    }
+                })
+                .collect::<Vec<_>>();
+            ring.run(&mut ops)?;
+
+            return match pages.iter().find(|(_, buf)| !page::verify(buf)) {
+                Some((id, _)) => {
+                    Err(io::Error::new(io::ErrorKind::InvalidData, ChecksumMismatch(*id)))
+                }
+                None => Ok(()),
+            };
+        }
+
+        pages
+            .iter_mut()
+            .try_for_each(|(id, buf)| self.read_page(*id, buf))
+    }
+
+    /// Returns `true` if `read_pages()` has its reads in flight together, with an io_uring on an
+    /// uncompressed file.
+    pub fn batches_reads(&self) -> bool {
+        #[cfg(all(feature = "io-uring", target_os = "linux"))]
+        return self.ring.is_some() && self.packed.is_none();
+
+        #[cfg(not(all(feature = "io-uring", target_os = "linux")))]
+        false
+    }
+
+    /// Asks the OS to start reading page `id` into its cache, for a read that is coming. Does
+    /// nothing for direct I/O and compressed files, or off Linux.
+    pub fn prefetch(&self, id: PageId) {
+        #[cfg(target_os = "linux")]
+        if self.backend != Backend::Direct && self.packed.is_none() && id.0 < self.pages {
+            use std::os::fd::AsRawFd;
+
+            let offset = (id.0 * PAGE_SIZE as u64) as libc::off_t;
+            // Only a hint, a failure costs nothing but the read it would have saved
+            unsafe {
+                libc::posix_fadvise(
+                    self.file.as_raw_fd(),
+                    offset,
+                    PAGE_SIZE as libc::off_t,
+                    libc::POSIX_FADV_WILLNEED,
+                )
+            };
+        }
+        #[cfg(not(target_os = "linux"))]
+        let _ = id;
+    }
+
+    /// What a copy of the file must start with before its pages, empty unless it is compressed.
+    pub fn header(&self) -> io::Result<Vec<u8>> {
+        match &self.packed {
+            Some(packed) => packed.header(&self.file),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Page `id` as it is stored, still compressed or encrypted. `None` if it was never written.
+    pub fn read_raw(&self, id: PageId) -> io::Result<Option<Vec<u8>>> {
+        if let Some(packed) = &self.packed {
+            return packed.read_record(&self.file, id);
+        }
+
+        let mut buf = vec![0; PAGE_SIZE];
+        match self.file.read_exact_at(&mut buf, id.0 * PAGE_SIZE as u64) {
+            Ok(()) => Ok(Some(buf)),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn write_page(&mut self, id: PageId, buf: &PageBuf) -> io::Result<()> {
+        self.write_pages(&[(id, buf)])
+    }
+
+    /// Writes every page, all at once with an io_uring or to a compressed file.
+    pub fn write_pages(&mut self, pages: &[(PageId, &PageBuf)]) -> io::Result<()> {
+        trace::event!(TRACE, pages = pages.len(), "write pages");
+        let pages = pages
+            .iter()
+            .map(|(id, buf)| {
+                assert!(id.0 < self.pages, "page {} was never allocated", id.0);
+
+                let mut page = Box::new(Aligned(**buf));
+                page::set_checksum(&mut page.0);
+                (*id, page)
+            })
+            .collect::<Vec<_>>();
+
+        if let Some(packed) = &mut self.packed {
+            let pages = pages
+                .iter()
+                .map(|(id, page)| (*id, &page.0))
+                .collect::<Vec<_>>();
+            return packed.write(&mut self.file, &pages);
+        }
+
+        if let Some(faults) = &self.faults {
+            for (id, page) in &pages {
+                faults.write_at(&self.file, &page.0, id.0 * PAGE_SIZE as u64)?;
+            }
+            return Ok(());
+        }
+
+        #[cfg(all(feature = "io-uring", target_os = "linux"))]
+        if let Some(ring) = &self.ring {
+            let file = &self.file;
+            let mut ops = pages
+                .iter()
+                .map(|(id, page)| Op::Write {
+                    file,
+                    buf: &page.0,
+                    offset: id.0 * PAGE_SIZE as u64,
+                })
+                .collect::<Vec<_>>();
+            return ring.run(&mut ops);
+        }
+
+        for (id, page) in &pages {
+            self.file.write_all_at(&page.0, id.0 * PAGE_SIZE as u64)?;
+        }
+
+        Ok(())
+    }
+
+    /// Waits for every written page to reach the disk.
+    pub fn sync(&self) -> io::Result<()> {
+        trace::span!(DEBUG, "disk sync");
+        if let Some(faults) = &self.faults {
+            faults.check()?;
+        }
+
+        #[cfg(all(feature = "io-uring", target_os = "linux"))]
+        if let Some(ring) = &self.ring {
+            return ring.run(&mut [Op::Sync { file: &self.file }]);
+        }
+
+        self.file.sync_data()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::page::{PageId, PAGE_SIZE};
+
+    use super::{Backend, DiskManager};
+
+    #[test]
+    fn test_direct_io() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pages");
+
+        let mut disk = DiskManager::create_with_backend(&path, Backend::Direct).unwrap();
+        let ids = (0..3).map(|_| disk.allocate()).collect::<Vec<_>>();
+        for id in &ids[..2] {
+            disk.write_page(*id, &[id.0 as u8 + 1; PAGE_SIZE]).unwrap();
+        }
+        drop(disk);
+
+        let disk = DiskManager::open_with_backend(&path, Backend::Direct).unwrap();
+        assert!(disk.pages() == 2 && disk.backend() == Backend::Direct);
+
+        let mut buf = [0; PAGE_SIZE];
+        disk.read_page(PageId(1), &mut buf).unwrap();
+        assert!(buf[100] == 2);
+
+        // Buffered reads see the same pages
+        let disk = DiskManager::open(&path).unwrap();
+        disk.read_page(PageId(0), &mut buf).unwrap();
+        assert!(buf[100] == 1);
+    }
+
+    #[test]
+    fn test_io_uring() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pages");
+
+        let mut disk = DiskManager::create_with_backend(&path, Backend::IoUring).unwrap();
+        let want = cfg!(all(feature = "io-uring", target_os = "linux"));
+        assert!((disk.backend() == Backend::IoUring) == want, "{:?}", disk.backend());
+
+        let pages = (0..100u8)
+            .map(|i| (disk.allocate(), [i; PAGE_SIZE]))
+            .collect::<Vec<_>>();
+        let batch = pages.iter().map(|(id, buf)| (*id, buf)).collect::<Vec<_>>();
+        disk.write_pages(&batch).unwrap();
+        disk.sync().unwrap();
+        let unwritten = disk.allocate();
+
+        let mut buf = [0xff; PAGE_SIZE];
+        for (id, want) in &pages {
+            disk.read_page(*id, &mut buf).unwrap();
+            assert!(buf[8..] == want[8..], "Page: {}", id.0);
+        }
+        disk.read_page(unwritten, &mut buf).unwrap();
+        assert!(buf == [0; PAGE_SIZE]);
+
+        // Read together, the same
+        assert!(disk.batches_reads() == want);
+        let mut have = vec![[0xff; PAGE_SIZE]; 101];
+        let ids = pages.iter().map(|(id, _)| *id).chain([unwritten]);
+        let mut batch = ids.zip(have.iter_mut()).collect::<Vec<_>>();
+        disk.read_pages(&mut batch).unwrap();
+        for (i, (id, want)) in pages.iter().enumerate() {
+            assert!(have[i][8..] == want[8..], "Page: {}", id.0);
+        }
+        assert!(have[100] == [0; PAGE_SIZE]);
+    }
+}
@@ -0,0 +1,171 @@
+use std::fmt::{self, Debug, Display};
+
+use crate::btree::{BTree, Increment};
+use crate::get_right;
+use crate::node::Node;
+use crate::slot::Either;
+
+/// How a tree is rendered by `BTree::display()` and `PagedBTree::write_tree()`.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct DisplayOptions {
+    /// Cuts the `Debug` output of every value to this many characters.
+    pub truncate: Option<usize>,
+    /// Starts every node with its address, or its page ID in a paged tree.
+    pub addresses: bool,
+}
+
+impl DisplayOptions {
+    pub(crate) fn value<V: Debug>(&self, v: &V) -> String {
+        let v = format!("{:?}", v);
+        match self.truncate {
+            Some(n) if v.chars().count() > n => {
+                format!("{}..", v.chars().take(n).collect::<String>())
+            }
+            _ => v,
+        }
+    }
+}
+
+/// A node as it is rendered: its address and its slots, either separator keys or entries.
+pub(crate) struct Rendered {
+    pub address: String,
+    pub slots: Vec<String>,
+}
+
+/// Writes one line per level, root first, of every node in the level from left to right:
+///
+/// ```text
+/// 0: [4, 8]
+/// 1: [0: a, 1: b, 2: c, 3: d] [4: e, 5: f, 6: g, 7: h]
+/// ```
+pub(crate) fn write_levels<W: fmt::Write + ?Sized>(
+    f: &mut W,
+    levels: &[Vec<Rendered>],
+    options: &DisplayOptions,
+) -> fmt::Result {
+    for (depth, level) in levels.iter().enumerate() {
+        write!(f, "{depth}:")?;
+        for node in level {
+            match options.addresses {
+                true => write!(f, " {} [{}]", node.address, node.slots.join(", "))?,
+                false => write!(f, " [{}]", node.slots.join(", "))?,
+            }
+        }
+        writeln!(f)?;
+    }
+
+    Ok(())
+}
+
+/// Renders a `BTree` level by level, see `BTree::display()`.
+pub struct TreeDisplay<'a, K, V> {
+    tree: &'a BTree<K, V>,
+    options: DisplayOptions,
+}
+
+impl<K, V> Display for TreeDisplay<'_, K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment,
+    V: Clone + Debug + Eq,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut levels = Vec::new();
+        let mut level: Vec<*mut Node<K, V>> = Vec::new();
+        if !self.tree.root().is_null() {
+            level.push(self.tree.root());
+        }
+
+        while !level.is_empty() {
+            let mut below = Vec::new();
+            let rendered = level
+                .iter()
+                .map(|ptr| {
+                    let node = unsafe { &**ptr };
+                    let slots = node
+                        .iter()
+                        .map(|slot| match &slot.1 {
+                            Either::Left(v) => format!("{:?}: {}", slot.0, self.options.value(v)),
+                            Either::Right(_) => {
+                                below.push(get_right!(slot));
+                                format!("{:?}", slot.0)
+                            }
+                        })
+                        .collect();
+
+                    Rendered {
+                        address: format!("{:?}", ptr),
+                        slots,
+                    }
+                })
+                .collect();
+
+            levels.push(rendered);
+            level = below;
+        }
+
+        write_levels(f, &levels, &self.options)
+    }
+}
+
+impl<K, V> BTree<K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment,
+    V: Clone + Debug + Eq,
+{
+    /// Renders the nodes of the tree level by level when formatted with `{}`, so it can be written
+    /// to any `fmt::Write` or `io::Write`, or logged.
+    pub fn display(&self, options: DisplayOptions) -> TreeDisplay<'_, K, V> {
+        TreeDisplay {
+            tree: self,
+            options,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fmt::Write;
+
+    use crate::btree::BTree;
+
+    use super::DisplayOptions;
+
+    #[test]
+    fn test_display() {
+        let tree = BTree::bulk_load(8, (0..10u32).map(|k| (k, k as u64 * 1000)));
+
+        let mut have = String::new();
+        write!(have, "{}", tree.display(DisplayOptions::default())).unwrap();
+        let want = "0: [4, 8, 10]\n\
+                    1: [0: 0, 1: 1000, 2: 2000, 3: 3000] [4: 4000, 5: 5000, 6: 6000, 7: 7000] \
+                    [8: 8000, 9: 9000]\n";
+        assert!(want == have, "Want: {want}\nHave: {have}");
+
+        let options = DisplayOptions {
+            truncate: Some(2),
+            addresses: true,
+        };
+        let have = tree.display(options).to_string();
+        assert!(have.contains("[0: 0, 1: 10.., 2: 20.., 3: 30..]"), "Have: {have}");
+        assert!(have.starts_with("0: 0x"), "Have: {have}");
+
+        let have = BTree::<u32, u64>::new(8).display(options).to_string();
+        assert!(have.is_empty(), "Have: {have}");
+
+        // A paged tree is rendered with its page IDs
+        #[cfg(feature = "fs")]
+        {
+            use crate::buffer::Capacity;
+            use crate::paged::PagedBTree;
+
+            let mut paged = PagedBTree::create_in_memory(8, Capacity::Pages(8)).unwrap();
+            for k in 0..3u32 {
+                paged.insert(k, k as u64).unwrap();
+            }
+            let mut have = Vec::new();
+            paged.write_tree(&mut have, options).unwrap();
+            let have = String::from_utf8(have).unwrap();
+            assert!(have == "0: #1 [0: 0, 1: 1, 2: 2]\n", "Have: {have}");
+        }
+    }
+}
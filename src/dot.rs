@@ -0,0 +1,157 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Debug;
+use std::io::{self, Write};
+
+use crate::btree::{BTree, Increment};
+use crate::slot::Either;
+
+/// Escapes the characters that mean something in a record label.
+fn escape(s: String) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, '{' | '}' | '|' | '<' | '>' | '"' | '\\') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// A node as it is drawn by `write_dot()`.
+pub(crate) struct DotNode {
+    pub id: u64,
+    pub leaf: bool,
+    /// An internal node's separator keys or a leaf's entries, as they are shown.
+    pub slots: Vec<String>,
+    /// The ID of the child of every separator.
+    pub children: Vec<u64>,
+    pub next: Option<u64>,
+}
+
+/// Writes `nodes` as a Graphviz DOT graph, for `dot -Tsvg`.
+///
+/// Every node is a record of its slots. An internal node's slots show the separator keys, each
+/// with an edge to its child, and a leaf's the entries. Dashed edges follow the leaf chain.
+pub(crate) fn write_dot<W: Write>(mut writer: W, nodes: &[DotNode]) -> io::Result<()> {
+    writeln!(writer, "digraph btree {{")?;
+    writeln!(writer, "    node [shape=record];")?;
+
+    for node in nodes {
+        let id = node.id;
+        let slots = node
+            .slots
+            .iter()
+            .enumerate()
+            .map(|(i, slot)| match node.leaf {
+                true => escape(slot.clone()),
+                false => format!("<s{i}> {}", escape(slot.clone())),
+            })
+            .collect::<Vec<_>>();
+        let style = if node.leaf {
+            ", style=filled, fillcolor=lightgrey"
+        } else {
+            ""
+        };
+        writeln!(writer, "    n{id} [label=\"{}\"{style}];", slots.join("|"))?;
+
+        for (i, child) in node.children.iter().enumerate() {
+            writeln!(writer, "    n{id}:s{i} -> n{child};")?;
+        }
+    }
+
+    for node in nodes {
+        if let Some(next) = node.next {
+            let id = node.id;
+            writeln!(writer, "    n{id} -> n{next} [style=dashed, constraint=false];")?;
+        }
+    }
+
+    writeln!(writer, "}}")?;
+    writer.flush()
+}
+
+impl<K, V> BTree<K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment,
+    V: Clone + Debug + Eq,
+{
+    /// Writes the nodes of the tree to `writer` as a Graphviz DOT graph, for `dot -Tsvg`.
+    ///
+    /// Every node is a record of its slots. An internal node's slots show the separator keys, each
+    /// with an edge to its child, and a leaf's the entries. Dashed edges follow the leaf chain.
+    pub fn to_dot<W: Write>(&self, writer: W) -> io::Result<()> {
+        // Numbered breadth first, so the output is the same for the same tree
+        let mut ids = HashMap::new();
+        let mut queue = VecDeque::new();
+        if !self.root().is_null() {
+            ids.insert(self.root(), 0);
+            queue.push_back(self.root());
+        }
+
+        let mut nodes = Vec::new();
+        let mut leaves = Vec::new();
+        while let Some(ptr) = queue.pop_front() {
+            let node = unsafe { &*ptr };
+
+            let mut children = Vec::new();
+            let slots = node
+                .iter()
+                .map(|slot| match &slot.1 {
+                    Either::Left(v) => format!("{:?}: {:?}", slot.0, v),
+                    &Either::Right(child) => {
+                        let next = ids.len() as u64;
+                        children.push(*ids.entry(child).or_insert(next));
+                        queue.push_back(child);
+                        format!("{:?}", slot.0)
+                    }
+                })
+                .collect();
+            if node.is_leaf() {
+                leaves.push((nodes.len(), node.next));
+            }
+
+            nodes.push(DotNode {
+                id: ids[&ptr],
+                leaf: node.is_leaf(),
+                slots,
+                children,
+                next: None,
+            });
+        }
+
+        for (i, next) in leaves {
+            nodes[i].next = ids.get(&next).copied();
+        }
+
+        write_dot(writer, &nodes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::btree::BTree;
+
+    #[test]
+    fn test_to_dot() {
+        let tree = BTree::bulk_load(8, (0..40u32).map(|k| (k, k as u64)));
+
+        let mut buf = Vec::new();
+        tree.to_dot(&mut buf).unwrap();
+        let dot = String::from_utf8(buf).unwrap();
+        assert!(dot.starts_with("digraph btree {\n") && dot.ends_with("}\n"), "{dot}");
+
+        // 10 leaves of 4 under 3 internal nodes, under the root
+        let count = |pat: &str| dot.lines().filter(|l| l.contains(pat)).count();
+        assert!(count("label=") == 14, "{dot}");
+        assert!(count("style=filled") == 10, "{dot}");
+        assert!(count("style=dashed") == 9, "{dot}");
+        assert!(count(":s") == 13, "{dot}");
+        assert!(dot.contains("n0 [label=\"<s0> 16|<s1> 32|<s2> 40\"];"), "{dot}");
+        assert!(dot.contains("\"36: 36|37: 37|38: 38|39: 39\""), "{dot}");
+
+        let mut buf = Vec::new();
+        BTree::<u32, u64>::new(8).to_dot(&mut buf).unwrap();
+        let have = String::from_utf8(buf).unwrap();
+        assert!(have == "digraph btree {\n    node [shape=record];\n}\n", "Have: {have}");
+    }
+}
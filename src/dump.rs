@@ -0,0 +1,133 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{Debug, Write};
+
+use crate::btree::{BTree, Increment};
+use crate::get_right;
+use crate::slot::Either;
+
+/// `Debug` output of `v` as a JSON string.
+fn string<T: Debug>(v: &T) -> String {
+    let mut out = String::from("\"");
+    for c in format!("{:?}", v).chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => _ = write!(out, "\\u{:04x}", c as u32),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn list<T, F: FnMut(&T) -> String>(items: &[T], f: F) -> String {
+    items.iter().map(f).collect::<Vec<_>>().join(",")
+}
+
+impl<K, V> BTree<K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment,
+    V: Clone + Debug + Eq,
+{
+    /// Describes every node of the tree as JSON, for tools that inspect its structure:
+    ///
+    /// ```text
+    /// {"max":8,"nodes":[
+    ///   {"id":0,"leaf":false,"depth":0,"fill":0.5,"keys":["4","8"],"children":[1,2]},
+    ///   {"id":1,"leaf":true,"depth":1,"fill":1.0,"keys":["0",...],"values":["0",...],"next":2},
+    ///   ...
+    /// ]}
+    /// ```
+    ///
+    /// Nodes are numbered breadth first from the root. Keys and values are their `Debug` output,
+    /// an internal node's keys are the separators of its children. `fill` is a node's slots over
+    /// the number it holds before splitting, and `next` is null for the last leaf.
+    pub fn dump_json(&self) -> String {
+        // Nodes split once they hold half of `max`
+        let capacity = (self.max() / 2).max(2) as f64;
+
+        let mut ids = HashMap::new();
+        let mut queue = VecDeque::new();
+        if !self.root().is_null() {
+            ids.insert(self.root(), 0);
+            queue.push_back((self.root(), 0));
+        }
+
+        let mut nodes = Vec::new();
+        while let Some((ptr, depth)) = queue.pop_front() {
+            let node = unsafe { &*ptr };
+            let slots = node.iter().collect::<Vec<_>>();
+            let mut out = format!(
+                "{{\"id\":{},\"leaf\":{},\"depth\":{depth},\"fill\":{},\"keys\":[{}]",
+                ids[&ptr],
+                node.is_leaf(),
+                slots.len() as f64 / capacity,
+                list(&slots, |s| string(&s.0)),
+            );
+
+            match node.is_leaf() {
+                true => {
+                    let values = list(&slots, |s| match &s.1 {
+                        Either::Left(v) => string(v),
+                        Either::Right(_) => unreachable!(),
+                    });
+                    // The next leaf is numbered later, filled in below
+                    _ = write!(out, ",\"values\":[{values}],\"next\":");
+                    nodes.push((out, Some(node.next)));
+                }
+                false => {
+                    let children = list(&slots, |s| {
+                        let child = get_right!(s);
+                        let next = ids.len();
+                        let id = *ids.entry(child).or_insert(next);
+                        queue.push_back((child, depth + 1));
+                        id.to_string()
+                    });
+                    _ = write!(out, ",\"children\":[{children}]}}");
+                    nodes.push((out, None));
+                }
+            }
+        }
+
+        let nodes = list(&nodes, |(out, next)| match next {
+            Some(next) => match ids.get(next) {
+                Some(id) => format!("{out}{id}}}"),
+                None => format!("{out}null}}"),
+            },
+            None => out.clone(),
+        });
+        format!("{{\"max\":{},\"nodes\":[{nodes}]}}", self.max())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::btree::BTree;
+
+    #[test]
+    fn test_dump_json() {
+        let tree = BTree::bulk_load(8, (0..6u32).map(|k| (k, k as u64)));
+
+        let want = concat!(
+            r#"{"max":8,"nodes":["#,
+            r#"{"id":0,"leaf":false,"depth":0,"fill":0.5,"keys":["4","6"],"children":[1,2]},"#,
+            r#"{"id":1,"leaf":true,"depth":1,"fill":1,"keys":["0","1","2","3"],"#,
+            r#""values":["0","1","2","3"],"next":2},"#,
+            r#"{"id":2,"leaf":true,"depth":1,"fill":0.5,"keys":["4","5"],"values":["4","5"],"#,
+            r#""next":null}]}"#,
+        );
+        let have = tree.dump_json();
+        assert!(want == have, "Want: {want}\nHave: {have}");
+
+        let value = serde_json::from_str::<serde_json::Value>(&have).unwrap();
+        assert!(value["nodes"][2]["keys"][1] == "5");
+
+        // Debug output is escaped
+        let have = BTree::bulk_load(8, [(1u8, '"'), (2, '\\')]).dump_json();
+        let value = serde_json::from_str::<serde_json::Value>(&have).unwrap();
+        assert!(value["nodes"][0]["values"] == serde_json::json!(["'\"'", "'\\\\'"]), "{have}");
+
+        let have = BTree::<u32, u64>::new(8).dump_json();
+        assert!(have == r#"{"max":8,"nodes":[]}"#, "Have: {have}");
+    }
+}
@@ -0,0 +1,230 @@
+use crate::sync::{self, fence, AtomicU64, Mutex, Ordering};
+
+// Loom explores every access to every slot, keep its models small
+const DEFAULT_SLOTS: usize = if cfg!(loom) { 2 } else { 64 };
+
+/// Epoch-based reclamation for nodes unlinked while readers may still hold pointers to them.
+///
+/// Readers `pin()` the current epoch for the duration of a traversal. Writers unlink a node first
+/// and then `retire()` it; it is only freed once every reader pinned at or before the epoch it was
+/// retired in has finished.
+pub struct Collector {
+    epoch: AtomicU64,
+    pins: Box<[AtomicU64]>,
+    garbage: Mutex<Vec<Retired>>,
+}
+
+struct Retired {
+    epoch: u64,
+    ptr: *mut (),
+    free: unsafe fn(*mut ()),
+}
+
+// Retired pointers are only dereferenced by `free`, once no reader can reach them
+unsafe impl Send for Retired {}
+
+/// Keeps the epoch a reader entered with pinned until dropped.
+pub struct Guard<'a> {
+    collector: &'a Collector,
+    slot: usize,
+}
+
+impl Drop for Guard<'_> {
+    fn drop(&mut self) {
+        self.collector.pins[self.slot].store(0, Ordering::SeqCst);
+    }
+}
+
+unsafe fn free_box<T>(ptr: *mut ()) {
+    drop(Box::from_raw(ptr as *mut T));
+}
+
+impl Default for Collector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Collector {
+    pub fn new() -> Self {
+        Self::with_slots(DEFAULT_SLOTS)
+    }
+
+    /// `slots` bounds the number of concurrently pinned readers, further readers spin until one
+    /// unpins.
+    pub fn with_slots(slots: usize) -> Self {
+        assert!(slots > 0);
+
+        Self {
+            epoch: AtomicU64::new(1),
+            pins: (0..slots).map(|_| AtomicU64::new(0)).collect(),
+            garbage: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn pin(&self) -> Guard<'_> {
+        let epoch = self.epoch.load(Ordering::SeqCst);
+        loop {
+            for (slot, pin) in self.pins.iter().enumerate() {
+                if pin
+                    .compare_exchange(0, epoch, Ordering::SeqCst, Ordering::Relaxed)
+                    .is_ok()
+                {
+                    // Orders the pin before the reader's loads, pairs with the fence in `collect`
+                    fence(Ordering::SeqCst);
+                    return Guard {
+                        collector: self,
+                        slot,
+                    };
+                }
+            }
+
+            sync::spin_loop();
+        }
+    }
+
+    /// Schedules `ptr` to be freed once no pinned reader can still observe it.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must come from `Box::into_raw`, must already be unreachable for new readers and must
+    /// not be retired twice.
+    pub unsafe fn retire<T>(&self, ptr: *mut T) {
+        let mut garbage = self.garbage.lock().unwrap();
+        let epoch = self.epoch.fetch_add(1, Ordering::SeqCst);
+        garbage.push(Retired {
+            epoch,
+            ptr: ptr as *mut (),
+            free: free_box::<T>,
+        });
+    }
+
+    /// Frees everything retired before the oldest pinned epoch.
+    pub fn collect(&self) {
+        let mut garbage = self.garbage.lock().unwrap();
+
+        // Orders the writer's unlinking store before reading the pins
+        fence(Ordering::SeqCst);
+        let oldest = self
+            .pins
+            .iter()
+            .map(|p| p.load(Ordering::SeqCst))
+            .filter(|e| *e != 0)
+            .min()
+            .unwrap_or(u64::MAX);
+
+        garbage.retain(|r| {
+            if r.epoch < oldest {
+                unsafe { (r.free)(r.ptr) };
+                return false;
+            }
+
+            true
+        });
+    }
+
+    pub fn pending(&self) -> usize {
+        self.garbage.lock().unwrap().len()
+    }
+}
+
+impl Drop for Collector {
+    fn drop(&mut self) {
+        for r in self.garbage.lock().unwrap().drain(..) {
+            unsafe { (r.free)(r.ptr) };
+        }
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use super::Collector;
+
+    struct Counted(Arc<AtomicUsize>);
+
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn test_retire_waits_for_readers() {
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let collector = Collector::new();
+
+        let guard = collector.pin();
+        unsafe { collector.retire(Box::into_raw(Box::new(Counted(dropped.clone())))) };
+
+        collector.collect();
+        let have = dropped.load(Ordering::SeqCst);
+        assert!(have == 0, "Want: 0\nHave: {have}");
+
+        drop(guard);
+        collector.collect();
+        let have = dropped.load(Ordering::SeqCst);
+        assert!(have == 1, "Want: 1\nHave: {have}");
+    }
+
+    #[test]
+    fn test_later_readers_do_not_block() {
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let collector = Collector::new();
+
+        unsafe { collector.retire(Box::into_raw(Box::new(Counted(dropped.clone())))) };
+        let _guard = collector.pin();
+
+        collector.collect();
+        let have = dropped.load(Ordering::SeqCst);
+        assert!(have == 1, "Want: 1\nHave: {have}");
+    }
+}
+
+#[cfg(all(test, loom))]
+mod loom_test {
+    use loom::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+    use loom::sync::Arc;
+    use loom::thread;
+
+    use super::Collector;
+
+    struct Flagged(Arc<AtomicBool>);
+
+    impl Drop for Flagged {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn loom_retired_pointer_outlives_readers() {
+        loom::model(|| {
+            let freed = Arc::new(AtomicBool::new(false));
+            let first = Box::into_raw(Box::new(Flagged(freed.clone())));
+            let collector = Arc::new(Collector::new());
+            let root = Arc::new(AtomicPtr::new(first));
+
+            let reader = {
+                let (collector, root, freed) = (collector.clone(), root.clone(), freed.clone());
+                thread::spawn(move || {
+                    let _guard = collector.pin();
+                    if root.load(Ordering::SeqCst) == first {
+                        assert!(!freed.load(Ordering::SeqCst), "Pinned pointer was freed");
+                    }
+                })
+            };
+
+            let second = Box::into_raw(Box::new(Flagged(Arc::new(AtomicBool::new(false)))));
+            root.store(second, Ordering::SeqCst);
+            unsafe { collector.retire(first) };
+            collector.collect();
+
+            reader.join().unwrap();
+
+            drop(unsafe { Box::from_raw(second) });
+        });
+    }
+}
@@ -0,0 +1,204 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::paged::{Options, PagedBTree};
+
+fn crashed() -> io::Error {
+    io::Error::other("injected crash")
+}
+
+#[derive(Default)]
+struct State {
+    writes: u64,
+    crash_at: Option<u64>,
+    torn: bool,
+    crashed: bool,
+}
+
+/// Fails the writes of a `DiskManager` and a `Wal` from a chosen point on, as if the process
+/// crashed there, to test recovery.
+///
+/// Every page written back and every append to the log counts as a write. The write that crashes
+/// fails, or with `torn` set only its first half reaches the file, then every write and sync after
+/// it fails too. Compressed and encrypted files aren't covered.
+#[derive(Default)]
+pub struct FaultInjector {
+    state: Mutex<State>,
+}
+
+impl FaultInjector {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    /// Crashes on the `writes`th write from now.
+    pub fn crash_after(&self, writes: u64, torn: bool) {
+        let state = &mut *self.state.lock().unwrap();
+        state.crash_at = Some(state.writes + writes.max(1));
+        state.torn = torn;
+    }
+
+    pub fn crashed(&self) -> bool {
+        self.state.lock().unwrap().crashed
+    }
+
+    /// Writes made so far, not counting the one that crashed.
+    pub fn writes(&self) -> u64 {
+        self.state.lock().unwrap().writes
+    }
+
+    /// Lets writes through again, for the files to be recovered.
+    pub fn reset(&self) {
+        *self.state.lock().unwrap() = State::default();
+    }
+
+    pub(crate) fn write_at(&self, file: &File, buf: &[u8], offset: u64) -> io::Result<()> {
+        let state = &mut *self.state.lock().unwrap();
+        if state.crashed {
+            return Err(crashed());
+        }
+
+        if state.crash_at == Some(state.writes + 1) {
+            state.crashed = true;
+            if state.torn {
+                file.write_all_at(&buf[..buf.len() / 2], offset)?;
+            }
+            return Err(crashed());
+        }
+
+        state.writes += 1;
+        file.write_all_at(buf, offset)
+    }
+
+    /// Fails once crashed, so nothing is taken as durable after the crash.
+    pub(crate) fn check(&self) -> io::Result<()> {
+        match self.state.lock().unwrap().crashed {
+            true => Err(crashed()),
+            false => Ok(()),
+        }
+    }
+}
+
+/// What `crash_test()` went through.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct CrashReport {
+    pub rounds: usize,
+    pub crashes: usize,
+    /// Operations that crashed but were recovered anyway, their commit reached the log.
+    pub recovered: usize,
+}
+
+/// Runs random inserts and deletes on a `u32` to `u64` tree at `path`, crashing it at a random
+/// write every round and checking the recovered tree is valid and holds every operation that
+/// returned, plus at most the one that crashed. `options` must have a log.
+pub fn crash_test(
+    path: &Path,
+    options: Options,
+    rounds: usize,
+    seed: u64,
+) -> io::Result<CrashReport> {
+    assert!(options.wal.is_some(), "crash test without a log");
+
+    let mut rng = StdRng::seed_from_u64(seed);
+    let faults = FaultInjector::new();
+    let mut report = CrashReport::default();
+    let mut model = BTreeMap::new();
+    // The operation that crashed, as the key and value it may have left
+    let mut crashed: Option<(u32, Option<u64>)> = None;
+
+    drop(PagedBTree::<u32, u64>::create_with_options(path, 8, options)?);
+    for round in 0..rounds {
+        let mut tree = PagedBTree::<u32, u64>::open_with_options(path, options)?;
+
+        tree.validate()
+            .map_err(|e| io::Error::new(e.kind(), format!("round {round}: {e}")))?;
+        let have = tree.iter()?.into_iter().collect::<BTreeMap<_, _>>();
+        if let Some((k, v)) = crashed.take() {
+            let mut applied = model.clone();
+            match v {
+                Some(v) => applied.insert(k, v),
+                None => applied.remove(&k),
+            };
+            if have == applied && have != model {
+                report.recovered += 1;
+                model = applied;
+            }
+        }
+        if have != model || tree.len() != model.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("round {round}: recovered {} entries, want {}", have.len(), model.len()),
+            ));
+        }
+
+        tree.set_faults(Some(faults.clone()));
+        faults.crash_after(rng.gen_range(1..400), rng.gen_bool(0.5));
+        for _ in 0..200 {
+            let k = rng.gen_range(0..2000);
+            let (res, op) = match rng.gen_range(0..10) {
+                0..=5 => (tree.insert(k, round as u64).map(|_| ()), Some((k, Some(round as u64)))),
+                6..=8 => (tree.delete(k).map(|_| ()), Some((k, None))),
+                _ => (tree.checkpoint(), None),
+            };
+
+            match (res, op) {
+                (Ok(()), Some((k, Some(v)))) => _ = model.insert(k, v),
+                (Ok(()), Some((k, None))) => _ = model.remove(&k),
+                (Ok(()), None) => {}
+                (Err(_), op) if faults.crashed() => {
+                    crashed = op;
+                    break;
+                }
+                (Err(e), _) => return Err(e),
+            }
+        }
+
+        if faults.crashed() {
+            report.crashes += 1;
+        }
+        // Write backs fail if it crashed, like a process that died
+        drop(tree);
+        faults.reset();
+        report.rounds += 1;
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::buffer::Capacity;
+    use crate::paged::Options;
+    use crate::wal::SyncPolicy;
+
+    use super::crash_test;
+
+    #[test]
+    fn test_crash_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree");
+
+        for policy in [SyncPolicy::PerCommit, SyncPolicy::Off] {
+            let mut crashes = 0;
+            for seed in 0..8 {
+                let options = Options {
+                    capacity: Capacity::Pages([16, 32, 64][seed as usize % 3]),
+                    wal: Some(policy),
+                    ..Default::default()
+                };
+                let report = crash_test(&path, options, 40, seed)
+                    .unwrap_or_else(|e| panic!("{:?}, seed {}: {}", policy, seed, e));
+                assert!(report.rounds == 40);
+                crashes += report.crashes;
+            }
+            assert!(crashes > 8 * 20, "Have: {crashes}");
+        }
+    }
+}
@@ -0,0 +1,259 @@
+//! C bindings, declared in `include/bplustree.h`, which `test_header` keeps in step with them.
+//!
+//! A tree is an opaque `BPlusTree` handle holding keys of up to `BPLUSTREE_KEY_SIZE` bytes and
+//! values of up to `BPLUSTREE_VALUE_SIZE` bytes, ordered by their bytes. Functions returning an
+//! `int` return -1 on bad arguments, such as a key that is too long, or if the tree panicked.
+
+use std::ffi::c_void;
+use std::ops::Bound;
+use std::os::raw::c_int;
+use std::panic::{self, AssertUnwindSafe};
+use std::{ptr, slice};
+
+use crate::btree::BTree;
+use crate::bytes::Bytes;
+use crate::get_left;
+use crate::slot::{Either, Slot};
+
+pub const BPLUSTREE_KEY_SIZE: usize = 64;
+pub const BPLUSTREE_VALUE_SIZE: usize = 256;
+
+type Key = Bytes<BPLUSTREE_KEY_SIZE>;
+type Value = Bytes<BPLUSTREE_VALUE_SIZE>;
+
+pub struct BPlusTree(BTree<Key, Value>);
+
+/// Called by `bplustree_scan()` with every entry in the range, returns non-zero to stop.
+pub type BPlusTreeScan = extern "C" fn(
+    ctx: *mut c_void,
+    key: *const u8,
+    key_len: usize,
+    value: *const u8,
+    value_len: usize,
+) -> c_int;
+
+/// Runs `f`, returning -1 if it panics rather than unwinding into C.
+fn guard(f: impl FnOnce() -> Option<c_int>) -> c_int {
+    panic::catch_unwind(AssertUnwindSafe(f))
+        .ok()
+        .flatten()
+        .unwrap_or(-1)
+}
+
+/// `len` bytes at `ptr` as a byte string, `None` if they don't fit or `ptr` is null.
+unsafe fn bytes<const N: usize>(ptr: *const u8, len: usize) -> Option<Bytes<N>> {
+    match (ptr.is_null(), len) {
+        (_, 0) => Some(Bytes::default()),
+        (true, _) => None,
+        (false, len) => Bytes::new(slice::from_raw_parts(ptr, len)),
+    }
+}
+
+/// Creates an empty tree, free it with `bplustree_free()`. Returns null if `max` is less than 4.
+#[no_mangle]
+pub extern "C" fn bplustree_new(max: usize) -> *mut BPlusTree {
+    if max < 4 {
+        return ptr::null_mut();
+    }
+
+    Box::into_raw(Box::new(BPlusTree(BTree::new(max))))
+}
+
+/// Inserts or replaces the value of `key`. Returns 0.
+///
+/// # Safety
+///
+/// `tree` is from `bplustree_new()`, `key` and `value` point to `key_len` and `value_len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn bplustree_insert(
+    tree: *mut BPlusTree,
+    key: *const u8,
+    key_len: usize,
+    value: *const u8,
+    value_len: usize,
+) -> c_int {
+    guard(|| {
+        let tree = tree.as_mut()?;
+        let key = bytes(key, key_len)?;
+        let value = bytes(value, value_len)?;
+        tree.0.insert(Slot::new_leaf(key, value));
+        Some(0)
+    })
+}
+
+/// Copies the value of `key` to `out`, up to `out_cap` bytes, and sets `out_len` to its length.
+/// Returns 1 if `key` was found, 0 if not.
+///
+/// # Safety
+///
+/// `tree` is from `bplustree_new()`, `key` points to `key_len` bytes, `out` to `out_cap` bytes and
+/// `out_len` to a `size_t`.
+#[no_mangle]
+pub unsafe extern "C" fn bplustree_get(
+    tree: *const BPlusTree,
+    key: *const u8,
+    key_len: usize,
+    out: *mut u8,
+    out_cap: usize,
+    out_len: *mut usize,
+) -> c_int {
+    guard(|| {
+        let tree = tree.as_ref()?;
+        let key = bytes::<BPLUSTREE_KEY_SIZE>(key, key_len)?;
+        if out_len.is_null() || (out.is_null() && out_cap > 0) {
+            return None;
+        }
+
+        let slot = match tree.0.get(key) {
+            Some(slot) => slot,
+            None => return Some(0),
+        };
+        let value = get_left!(slot);
+        let n = value.len().min(out_cap);
+        if n > 0 {
+            ptr::copy_nonoverlapping(value.as_slice().as_ptr(), out, n);
+        }
+        *out_len = value.len();
+        Some(1)
+    })
+}
+
+/// Returns 1 if `key` was deleted, 0 if it wasn't in the tree.
+///
+/// # Safety
+///
+/// `tree` is from `bplustree_new()` and `key` points to `key_len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn bplustree_delete(
+    tree: *mut BPlusTree,
+    key: *const u8,
+    key_len: usize,
+) -> c_int {
+    guard(|| {
+        let tree = tree.as_mut()?;
+        let key = bytes(key, key_len)?;
+        Some(tree.0.delete(key) as c_int)
+    })
+}
+
+/// Calls `scan` with every entry from `start` up to but not including `end`, in order. A null
+/// `start` or `end` leaves that end of the range open. The pointers passed to `scan` are valid
+/// until it returns. Returns the number of entries `scan` was called with.
+///
+/// # Safety
+///
+/// `tree` is from `bplustree_new()`, `start` and `end` point to `start_len` and `end_len` bytes or
+/// are null, and `scan` doesn't change the tree.
+#[no_mangle]
+pub unsafe extern "C" fn bplustree_scan(
+    tree: *const BPlusTree,
+    start: *const u8,
+    start_len: usize,
+    end: *const u8,
+    end_len: usize,
+    scan: BPlusTreeScan,
+    ctx: *mut c_void,
+) -> c_int {
+    guard(|| {
+        let tree = tree.as_ref()?;
+        let start = match start.is_null() {
+            true => Bound::Unbounded,
+            false => Bound::Included(bytes::<BPLUSTREE_KEY_SIZE>(start, start_len)?),
+        };
+        let end = match end.is_null() {
+            true => Bound::Unbounded,
+            false => Bound::Excluded(bytes::<BPLUSTREE_KEY_SIZE>(end, end_len)?),
+        };
+
+        let mut n = 0;
+        for (k, v) in tree.0.range((start, end)) {
+            n += 1;
+            let k = k.as_slice();
+            let v = v.as_slice();
+            if scan(ctx, k.as_ptr(), k.len(), v.as_ptr(), v.len()) != 0 {
+                break;
+            }
+        }
+        Some(n)
+    })
+}
+
+/// Frees a tree from `bplustree_new()`, null is ignored.
+///
+/// # Safety
+///
+/// `tree` isn't used after.
+#[no_mangle]
+pub unsafe extern "C" fn bplustree_free(tree: *mut BPlusTree) {
+    if !tree.is_null() {
+        drop(Box::from_raw(tree));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::ffi::c_void;
+    use std::os::raw::c_int;
+    use std::ptr;
+
+    use super::*;
+
+    extern "C" fn collect(
+        ctx: *mut c_void,
+        key: *const u8,
+        key_len: usize,
+        _value: *const u8,
+        _value_len: usize,
+    ) -> c_int {
+        let keys = unsafe { &mut *(ctx as *mut Vec<Vec<u8>>) };
+        keys.push(unsafe { slice::from_raw_parts(key, key_len) }.to_vec());
+        (keys.len() == 3) as c_int
+    }
+
+    #[test]
+    fn test_ffi() {
+        unsafe {
+            let tree = bplustree_new(8);
+            for i in 0..100u32 {
+                let k = format!("key-{i:03}");
+                let v = vec![i as u8; i as usize];
+                let rc = bplustree_insert(tree, k.as_ptr(), k.len(), v.as_ptr(), v.len());
+                assert!(rc == 0);
+            }
+
+            let mut out = [0u8; 8];
+            let mut len = 0;
+            let k = b"key-042";
+            let rc = bplustree_get(tree, k.as_ptr(), k.len(), out.as_mut_ptr(), 8, &mut len);
+            assert!(rc == 1 && len == 42 && out == [42; 8], "{rc} {len} {:?}", out);
+            assert!(bplustree_delete(tree, k.as_ptr(), k.len()) == 1);
+            let rc = bplustree_get(tree, k.as_ptr(), k.len(), out.as_mut_ptr(), 8, &mut len);
+            assert!(rc == 0);
+
+            let long = [b'k'; BPLUSTREE_KEY_SIZE + 1];
+            let rc = bplustree_insert(tree, long.as_ptr(), long.len(), ptr::null(), 0);
+            assert!(rc == -1);
+
+            let mut keys: Vec<Vec<u8>> = Vec::new();
+            let (start, end) = (b"key-041", b"key-050");
+            let ctx = &mut keys as *mut Vec<Vec<u8>> as *mut c_void;
+            let n = bplustree_scan(tree, start.as_ptr(), 7, end.as_ptr(), 7, collect, ctx);
+            let want = [b"key-041", b"key-043", b"key-044"].map(|k| k.to_vec());
+            assert!(n == 3 && keys == want, "{n} {:?}", keys);
+
+            bplustree_free(tree);
+            assert!(bplustree_new(2).is_null());
+        }
+    }
+
+    #[test]
+    fn test_header() {
+        // Generated by the build script from this file
+        let want = include_str!(concat!(env!("OUT_DIR"), "/bplustree.h"));
+        let have = include_str!("../include/bplustree.h");
+        assert!(
+            want == have,
+            "include/bplustree.h is out of date, regenerate it with cbindgen, see Cargo.toml"
+        );
+    }
+}
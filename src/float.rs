@@ -0,0 +1,210 @@
+use std::cmp::Ordering;
+use std::fmt::{self, Debug, Display};
+use std::hash::{Hash, Hasher};
+use std::num::ParseFloatError;
+use std::str::FromStr;
+
+use crate::btree::Increment;
+use crate::key::KeyEncode;
+use crate::page::Encode;
+
+macro_rules! float_key {
+    ($( $(#[$doc:meta])* $name:ident($f:ty, $u:ty) ),*) => {
+        $(
+        $(#[$doc])*
+        ///
+        /// Keys are ordered by `total_cmp()`: negative NaNs first, then negative infinity up to
+        /// `-0.0`, `0.0` up to infinity, then positive NaNs. `-0.0` is less than `0.0` and NaNs
+        /// with different payloads are different keys.
+        #[derive(Clone, Copy, Default)]
+        pub struct $name(pub $f);
+
+        impl $name {
+            const SIGN: $u = 1 << (<$u>::BITS - 1);
+
+            /// The bits of the float as an unsigned integer in the same order.
+            fn ordered(self) -> $u {
+                let bits = self.0.to_bits();
+                match bits & Self::SIGN {
+                    0 => bits | Self::SIGN,
+                    _ => !bits,
+                }
+            }
+
+            fn from_ordered(bits: $u) -> Self {
+                match bits & Self::SIGN {
+                    0 => Self(<$f>::from_bits(!bits)),
+                    _ => Self(<$f>::from_bits(bits & !Self::SIGN)),
+                }
+            }
+        }
+
+        impl PartialEq for $name {
+            fn eq(&self, other: &Self) -> bool {
+                self.cmp(other) == Ordering::Equal
+            }
+        }
+
+        impl Eq for $name {}
+
+        impl PartialOrd for $name {
+            fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+                Some(self.cmp(other))
+            }
+        }
+
+        impl Ord for $name {
+            fn cmp(&self, other: &Self) -> Ordering {
+                self.0.total_cmp(&other.0)
+            }
+        }
+
+        impl Hash for $name {
+            fn hash<H: Hasher>(&self, state: &mut H) {
+                self.0.to_bits().hash(state);
+            }
+        }
+
+        impl Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                Debug::fmt(&self.0, f)
+            }
+        }
+
+        impl Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                Display::fmt(&self.0, f)
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = ParseFloatError;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                s.parse().map(Self)
+            }
+        }
+
+        impl From<$f> for $name {
+            fn from(f: $f) -> Self {
+                Self(f)
+            }
+        }
+
+        impl From<$name> for $f {
+            fn from(key: $name) -> Self {
+                key.0
+            }
+        }
+
+        impl Increment for $name {
+            /// The positive NaN with every payload bit set.
+            const MAX: Self = Self(<$f>::from_bits(!Self::SIGN));
+
+            fn increment(&mut self) {
+                *self = self.next();
+            }
+
+            /// The next float in the total order, which may be a NaN.
+            fn next(&self) -> Self {
+                Self::from_ordered(self.ordered() + 1)
+            }
+        }
+
+        impl Encode for $name {
+            const SIZE: usize = size_of::<$f>();
+
+            fn encode(&self, buf: &mut [u8]) {
+                buf.copy_from_slice(&self.0.to_le_bytes());
+            }
+
+            fn decode(buf: &[u8]) -> Self {
+                Self(<$f>::from_le_bytes(buf.try_into().unwrap()))
+            }
+        }
+
+        impl KeyEncode for $name {
+            fn encode_key(&self, out: &mut Vec<u8>) {
+                self.ordered().encode_key(out);
+            }
+
+            fn decode_key(buf: &mut &[u8]) -> Option<Self> {
+                <$u>::decode_key(buf).map(Self::from_ordered)
+            }
+        }
+        )*
+    };
+}
+
+float_key!(
+    /// An `f64` usable as a key.
+    F64Key(f64, u64),
+    /// An `f32` usable as a key.
+    F32Key(f32, u32)
+);
+
+#[cfg(test)]
+mod test {
+    use crate::btree::{BTree, Increment};
+    use crate::key::KeyEncode;
+    use crate::slot::Slot;
+
+    use super::{F32Key, F64Key};
+
+    #[test]
+    fn test_float_keys() {
+        let nan = f64::NAN;
+        let want = [
+            -nan,
+            f64::NEG_INFINITY,
+            -1.5,
+            -0.0,
+            0.0,
+            f64::MIN_POSITIVE,
+            2.0,
+            nan,
+        ];
+
+        let mut keys = [
+            2.0,
+            -0.0,
+            nan,
+            -1.5,
+            f64::MIN_POSITIVE,
+            -nan,
+            0.0,
+            f64::NEG_INFINITY,
+        ]
+        .map(F64Key);
+        keys.sort();
+        let have = keys.map(|k| k.0.to_bits());
+        assert!(have == want.map(f64::to_bits), "Want: {:?}\nHave: {:?}", want, keys);
+
+        let encoded = keys.map(|k| k.to_key());
+        assert!(encoded.windows(2).all(|w| w[0] < w[1]));
+        assert!(keys
+            .iter()
+            .all(|k| F64Key::from_key(&k.to_key()) == Some(*k)));
+
+        // The next key is the next float
+        assert!(F64Key(-0.0).next() == F64Key(0.0));
+        assert!(F64Key(0.0).next() == F64Key(f64::from_bits(1)));
+        assert!(F64Key(-f64::from_bits(1)).next() == F64Key(-0.0));
+        assert!(F32Key(1.0).next() == F32Key(1.0 + f32::EPSILON));
+        assert!(F64Key(f64::INFINITY).next().0.is_nan());
+        assert!(F64Key::MAX.0.is_nan() && F64Key::MAX > F64Key(nan));
+
+        let mut tree = BTree::new(4);
+        for i in -50..50 {
+            tree.insert(Slot::new_leaf(F64Key(i as f64 / 4.0), i));
+        }
+        tree.insert(Slot::new_leaf(F64Key(nan), 100));
+        let have = tree
+            .range(F64Key(-0.5)..F64Key(0.5))
+            .map(|e| e.1)
+            .collect::<Vec<_>>();
+        assert!(have == [-2, -1, 0, 1], "Have: {:?}", have);
+        assert!(tree.get(F64Key(nan)).is_some() && tree.get(F64Key(-nan)).is_none());
+        tree.validate().unwrap();
+    }
+}
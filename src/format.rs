@@ -0,0 +1,334 @@
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::fs::File;
+use std::io;
+use std::marker::PhantomData;
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+
+use crate::compress;
+use crate::page::{self, Encode, PageBuf, PageId, PAGE_SIZE};
+
+/// The version of the file format written by this build. Files written before versions were
+/// stamped are told apart by their layout:
+///
+/// 1. 16 byte page headers, the meta page without a header.
+/// 2. 24 byte page headers with LSNs, the meta page after one.
+/// 3. Checksummed pages, compressed and encrypted files.
+pub const VERSION: u32 = 3;
+
+// Every version
+const MAGIC: &[u8; 8] = b"BPTREE\0\0";
+const LEAF: u8 = 1;
+const INTERNAL: u8 = 2;
+const META: u8 = 4;
+
+/// Held by the `io::Error` for a file in a format this build can't read. Older files can be
+/// brought up to date by `PagedBTree::migrate()`.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct UnsupportedVersion(pub u32);
+
+impl UnsupportedVersion {
+    /// Returns the file's version if `e` is for an unsupported one.
+    pub fn version(e: &io::Error) -> Option<u32> {
+        e.get_ref()?.downcast_ref::<Self>().map(|v| v.0)
+    }
+}
+
+impl Display for UnsupportedVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 < VERSION {
+            true => write!(
+                f,
+                "file is format version {}, migrate it to version {VERSION} first",
+                self.0
+            ),
+            false => write!(
+                f,
+                "file is format version {}, newer than version {VERSION} this build reads",
+                self.0
+            ),
+        }
+    }
+}
+
+impl Error for UnsupportedVersion {}
+
+impl From<UnsupportedVersion> for io::Error {
+    fn from(v: UnsupportedVersion) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, v)
+    }
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Returns the format version of the tree file at `path`. A file that can't be told apart is
+/// taken to be current, so a torn meta page is still left to recovery.
+pub fn version<P: AsRef<Path>>(path: P) -> io::Result<u32> {
+    let file = File::open(path)?;
+
+    let mut buf = [0; PAGE_SIZE];
+    let n = file.read_at(&mut buf, 0)?;
+    if buf.starts_with(compress::MAGIC) {
+        return Ok(packed(&buf));
+    }
+    if n < PAGE_SIZE {
+        return Ok(VERSION);
+    }
+    if buf.starts_with(MAGIC) {
+        return Ok(1);
+    }
+
+    let meta = buf[0] == META && &buf[V2.meta..V2.meta + 8] == MAGIC;
+    match stamped(&buf[V2.meta..]) {
+        v if meta && page::verify(&buf) && v > 0 => Ok(v),
+        // Version 2 left the checksum field zeroed
+        _ if meta && !page::verify(&buf) && buf[4..8] == [0; 4] => Ok(2),
+        _ => Ok(VERSION),
+    }
+}
+
+/// The version stamped in the tree's metadata, 0 for files written before it was.
+pub(crate) fn stamped(meta: &[u8]) -> u32 {
+    u32::from_le_bytes(meta[48..52].try_into().unwrap())
+}
+
+/// The version in the header of a compressed file, which were all current until it was stamped.
+fn packed(header: &[u8]) -> u32 {
+    match header[10] {
+        0 => VERSION,
+        v => v as u32,
+    }
+}
+
+pub(crate) fn check_packed(header: &[u8]) -> io::Result<()> {
+    match packed(header) {
+        VERSION => Ok(()),
+        v => Err(UnsupportedVersion(v).into()),
+    }
+}
+
+/// Fails with `UnsupportedVersion` unless the file at `path` is in the current format.
+pub(crate) fn check(path: &Path) -> io::Result<()> {
+    match version(path)? {
+        VERSION => Ok(()),
+        v => Err(UnsupportedVersion(v).into()),
+    }
+}
+
+/// Where an older version keeps things in a page.
+struct Layout {
+    header: usize,
+    next: usize,
+    meta: usize,
+}
+
+const V1: Layout = Layout {
+    header: 16,
+    next: 4,
+    meta: 0,
+};
+const V2: Layout = Layout {
+    header: 24,
+    next: 16,
+    meta: 16,
+};
+
+/// A tree in a file of an older version, read without a `BufferPool` to be rewritten.
+pub(crate) struct Legacy<K, V> {
+    file: File,
+    layout: Layout,
+    root: Option<PageId>,
+    pub max: usize,
+    _types: PhantomData<(K, V)>,
+}
+
+impl<K: Encode, V: Encode> Legacy<K, V> {
+    pub fn open(path: &Path, version: u32) -> io::Result<Self> {
+        let layout = match version {
+            1 => V1,
+            2 => V2,
+            v => return Err(UnsupportedVersion(v).into()),
+        };
+
+        let mut legacy = Self {
+            file: File::open(path)?,
+            layout,
+            root: None,
+            max: 0,
+            _types: PhantomData,
+        };
+
+        let page = legacy.page(PageId::META)?;
+        let buf = &page[legacy.layout.meta..];
+        let root = u64::from_le_bytes(buf[8..16].try_into().unwrap());
+        let k = u16::from_le_bytes([buf[28], buf[29]]) as usize;
+        let v = u16::from_le_bytes([buf[30], buf[31]]) as usize;
+        if k != K::SIZE || v != V::SIZE {
+            return Err(invalid(format!(
+                "tree stores {k} byte keys and {v} byte values, want {} and {}",
+                K::SIZE,
+                V::SIZE
+            )));
+        }
+
+        legacy.root = Some(PageId(root)).filter(|id| *id != PageId::META);
+        legacy.max = u32::from_le_bytes(buf[24..28].try_into().unwrap()) as usize;
+        Ok(legacy)
+    }
+
+    fn page(&self, id: PageId) -> io::Result<PageBuf> {
+        let mut buf = [0; PAGE_SIZE];
+        self.file.read_exact_at(&mut buf, id.0 * PAGE_SIZE as u64)?;
+        Ok(buf)
+    }
+
+    /// Returns every entry in order, following the leaf chain from the leftmost leaf.
+    pub fn entries(&self) -> io::Result<Vec<(K, V)>> {
+        let header = self.layout.header;
+        let count = |buf: &PageBuf| u16::from_le_bytes([buf[2], buf[3]]) as usize;
+        let id_at = |buf: &PageBuf, at: usize| {
+            PageId(u64::from_le_bytes(buf[at..at + 8].try_into().unwrap()))
+        };
+
+        let mut id = match self.root {
+            Some(root) => root,
+            None => return Ok(Vec::new()),
+        };
+        let mut buf = self.page(id)?;
+        while buf[0] == INTERNAL {
+            if count(&buf) == 0 {
+                return Err(invalid(format!("internal page {} is empty", id.0)));
+            }
+            id = id_at(&buf, header + K::SIZE);
+            buf = self.page(id)?;
+        }
+
+        let size = K::SIZE + V::SIZE;
+        let mut entries = Vec::new();
+        loop {
+            if buf[0] != LEAF || header + count(&buf) * size > PAGE_SIZE {
+                return Err(invalid(format!("page {} isn't a leaf", id.0)));
+            }
+
+            for i in 0..count(&buf) {
+                let at = header + i * size;
+                let k = K::decode(&buf[at..at + K::SIZE]);
+                let v = V::decode(&buf[at + K::SIZE..at + size]);
+                entries.push((k, v));
+            }
+
+            id = id_at(&buf, self.layout.next);
+            if id == PageId::META {
+                return Ok(entries);
+            }
+            buf = self.page(id)?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::os::unix::fs::FileExt;
+    use std::path::Path;
+
+    use crate::page::{self, Encode, PAGE_SIZE};
+    use crate::paged::PagedBTree;
+
+    use super::{
+        version, Layout, UnsupportedVersion, INTERNAL, LEAF, MAGIC, META, V1, V2, VERSION,
+    };
+
+    /// Writes a tree of `u32` keys and `u64` values in an older layout, leaves full to the brim.
+    fn write_legacy(path: &Path, layout: &Layout, entries: &[(u32, u64)]) {
+        let per_leaf = (PAGE_SIZE - layout.header) / 12;
+        let leaves = entries.chunks(per_leaf).collect::<Vec<_>>();
+        let root = leaves.len() as u64 + 1;
+
+        let mut pages = vec![[0; PAGE_SIZE]; leaves.len() + 2];
+        let meta = &mut pages[0];
+        if layout.meta > 0 {
+            meta[0] = META;
+        }
+        let buf = &mut meta[layout.meta..];
+        buf[0..8].copy_from_slice(MAGIC);
+        buf[8..16].copy_from_slice(&root.to_le_bytes());
+        buf[16..24].copy_from_slice(&(entries.len() as u64).to_le_bytes());
+        buf[24..28].copy_from_slice(&(per_leaf as u32).to_le_bytes());
+        buf[28..30].copy_from_slice(&4u16.to_le_bytes());
+        buf[30..32].copy_from_slice(&8u16.to_le_bytes());
+
+        for (i, leaf) in leaves.iter().enumerate() {
+            let buf = &mut pages[i + 1];
+            buf[0] = LEAF;
+            buf[2..4].copy_from_slice(&(leaf.len() as u16).to_le_bytes());
+            let next = if i + 1 < leaves.len() {
+                i as u64 + 2
+            } else {
+                0
+            };
+            buf[layout.next..layout.next + 8].copy_from_slice(&next.to_le_bytes());
+            for (j, (k, v)) in leaf.iter().enumerate() {
+                let at = layout.header + j * 12;
+                k.encode(&mut buf[at..at + 4]);
+                v.encode(&mut buf[at + 4..at + 12]);
+            }
+
+            let root = &mut pages[root as usize];
+            let at = layout.header + i * 12;
+            (leaf.last().unwrap().0 + 1).encode(&mut root[at..at + 4]);
+            root[at + 4..at + 12].copy_from_slice(&(i as u64 + 1).to_le_bytes());
+        }
+        let buf = &mut pages[root as usize];
+        buf[0] = INTERNAL;
+        buf[2..4].copy_from_slice(&(leaves.len() as u16).to_le_bytes());
+
+        let file = fs::File::create(path).unwrap();
+        for (i, page) in pages.iter().enumerate() {
+            file.write_all_at(page, (i * PAGE_SIZE) as u64).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_migrate() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree");
+        let want = (0..2000u32).map(|k| (k * 3, k as u64)).collect::<Vec<_>>();
+
+        for (v, layout) in [(1, V1), (2, V2)] {
+            write_legacy(&path, &layout, &want);
+            assert!(version(&path).unwrap() == v);
+
+            let e = PagedBTree::<u32, u64>::open(&path).err().unwrap();
+            assert!(UnsupportedVersion::version(&e) == Some(v), "{e}");
+            assert!(PagedBTree::<u64, u64>::migrate(&path).is_err());
+
+            assert!(PagedBTree::<u32, u64>::migrate(&path).unwrap() == Some(v));
+            assert!(version(&path).unwrap() == VERSION);
+            assert!(PagedBTree::<u32, u64>::migrate(&path).unwrap().is_none());
+
+            let tree = PagedBTree::<u32, u64>::open(&path).unwrap();
+            let have = tree.iter().unwrap();
+            assert!(tree.len() == 2000, "Have: {}", tree.len());
+            assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+        }
+
+        // A version from the future
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+        let mut meta = [0; PAGE_SIZE];
+        file.read_exact_at(&mut meta, 0).unwrap();
+        meta[V2.meta + 48..V2.meta + 52].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        page::set_checksum(&mut meta);
+        file.write_all_at(&meta, 0).unwrap();
+
+        let e = PagedBTree::<u32, u64>::open(&path).err().unwrap();
+        assert!(UnsupportedVersion::version(&e) == Some(VERSION + 1), "{e}");
+    }
+}
@@ -0,0 +1,108 @@
+use std::fmt::Debug;
+use std::ops::RangeBounds;
+
+use crate::btree::{BTree, Increment};
+use crate::iter::Range;
+use crate::slot::Slot;
+
+/// An immutable tree produced by `BTree::freeze`.
+///
+/// There is no way to mutate it, so it is `Send + Sync` whenever its keys and values are and can
+/// be shared between threads behind an `Arc` without any locking.
+pub struct FrozenBTree<K, V> {
+    tree: BTree<K, V>,
+}
+
+impl<K, V> BTree<K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment,
+    V: Clone + Debug + Eq,
+{
+    pub fn freeze(self) -> FrozenBTree<K, V> {
+        FrozenBTree { tree: self }
+    }
+}
+
+impl<K, V> From<BTree<K, V>> for FrozenBTree<K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment,
+    V: Clone + Debug + Eq,
+{
+    fn from(tree: BTree<K, V>) -> Self {
+        tree.freeze()
+    }
+}
+
+impl<K, V> FrozenBTree<K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment,
+    V: Clone + Debug + Eq,
+{
+    pub fn get(&self, key: K) -> Option<Slot<K, V>> {
+        self.tree.get(key)
+    }
+
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> Range<'_, K, V> {
+        self.tree.range(range)
+    }
+
+    pub fn iter(&self) -> Range<'_, K, V> {
+        self.tree.iter()
+    }
+
+    /// Turns the tree back into a mutable one.
+    pub fn thaw(self) -> BTree<K, V> {
+        self.tree
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::thread;
+
+    use crate::btree::BTree;
+    use crate::get_left;
+    use crate::slot::{Either, Slot};
+
+    use super::FrozenBTree;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn test_frozen_btree() {
+        const MAX: usize = 8;
+
+        assert_send_sync::<FrozenBTree<u32, u32>>();
+
+        let mut tree = BTree::new(MAX);
+        for k in 0..500u32 {
+            tree.insert(Slot::new_leaf(k, k + 10));
+        }
+
+        let frozen = Arc::new(tree.freeze());
+        thread::scope(|s| {
+            for t in 0..4u32 {
+                let frozen = frozen.clone();
+                s.spawn(move || {
+                    for k in (t..500).step_by(4) {
+                        let test = match frozen.get(k) {
+                            Some(t) => t,
+                            None => panic!("Could not find {k}"),
+                        };
+
+                        let have = get_left!(test);
+                        assert!(have == k + 10, "Want: {}\nHave: {have}", k + 10);
+                    }
+
+                    let have = frozen.range(100..200).count();
+                    assert!(have == 100, "Want: 100\nHave: {have}");
+                });
+            }
+        });
+
+        let mut tree = Arc::into_inner(frozen).unwrap().thaw();
+        tree.insert(Slot::new_leaf(500, 510));
+        assert!(tree.iter().count() == 501);
+    }
+}
@@ -0,0 +1,169 @@
+use std::fmt::Debug;
+use std::ops::{Bound, RangeBounds};
+
+use crate::btree::{BTree, Increment};
+use crate::slot::Slot;
+
+/// A primary key, or a bound below or above every primary key of a secondary key.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy)]
+enum Primary<PK> {
+    Min,
+    Key(PK),
+    Max,
+}
+
+/// An entry of a `SecondaryIndex`, ordered by secondary key then primary key.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy)]
+struct Entry<SK, PK> {
+    sk: SK,
+    pk: Primary<PK>,
+}
+
+impl<SK: Increment + Copy, PK: Copy> Increment for Entry<SK, PK> {
+    const MAX: Self = Self {
+        sk: SK::MAX,
+        pk: Primary::Max,
+    };
+
+    fn increment(&mut self) {
+        *self = self.next();
+    }
+
+    /// Past every primary key of the secondary key, or on to the next secondary key.
+    fn next(&self) -> Self {
+        match self.pk {
+            Primary::Max => Self {
+                sk: self.sk.next(),
+                pk: Primary::Max,
+            },
+            _ => Self {
+                sk: self.sk,
+                pk: Primary::Max,
+            },
+        }
+    }
+}
+
+/// An index from secondary keys `SK` to the primary keys `PK` of the records that have them,
+/// stored as one `(SK, PK)` entry per record so a secondary key can map to many records.
+///
+/// The index doesn't see the records, the caller keeps it in sync by passing each record's old
+/// and new secondary key to `update`.
+pub struct SecondaryIndex<PK, SK> {
+    tree: BTree<Entry<SK, PK>, ()>,
+}
+
+impl<PK, SK> SecondaryIndex<PK, SK>
+where
+    PK: Clone + Copy + Debug + Ord,
+    SK: Clone + Copy + Debug + Ord + Increment,
+{
+    pub fn new(max: usize) -> Self {
+        Self {
+            tree: BTree::new(max),
+        }
+    }
+
+    pub fn insert(&mut self, pk: PK, sk: SK) {
+        self.tree.insert(Slot::new_leaf(Self::entry(sk, pk), ()));
+    }
+
+    /// Returns whether the record `pk` was indexed under `sk`.
+    pub fn remove(&mut self, pk: PK, sk: SK) -> bool {
+        self.tree.delete(Self::entry(sk, pk))
+    }
+
+    /// Moves the record `pk` from `old` to `new`, either of which is `None` for a record that is
+    /// being inserted or deleted, or has no secondary key.
+    pub fn update(&mut self, pk: PK, old: Option<SK>, new: Option<SK>) {
+        if old == new {
+            return;
+        }
+
+        if let Some(old) = old {
+            self.remove(pk, old);
+        }
+        if let Some(new) = new {
+            self.insert(pk, new);
+        }
+    }
+
+    fn entry(sk: SK, pk: PK) -> Entry<SK, PK> {
+        Entry {
+            sk,
+            pk: Primary::Key(pk),
+        }
+    }
+
+    /// Returns the primary keys of the records with secondary key `sk`, in order.
+    pub fn get(&self, sk: SK) -> impl Iterator<Item = PK> + '_ {
+        self.range(sk..=sk).map(|(_, pk)| pk)
+    }
+
+    /// Returns the secondary and primary keys of the records with secondary keys in `range`, in
+    /// order.
+    pub fn range<R: RangeBounds<SK>>(&self, range: R) -> impl Iterator<Item = (SK, PK)> + '_ {
+        let bound = |bound: Bound<&SK>, included, excluded| match bound {
+            Bound::Included(sk) => Bound::Included(Entry {
+                sk: *sk,
+                pk: included,
+            }),
+            Bound::Excluded(sk) => Bound::Excluded(Entry {
+                sk: *sk,
+                pk: excluded,
+            }),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        let start = bound(range.start_bound(), Primary::Min, Primary::Max);
+        let end = bound(range.end_bound(), Primary::Max, Primary::Min);
+
+        self.tree.range((start, end)).map(|(e, _)| match e.pk {
+            Primary::Key(pk) => (e.sk, pk),
+            _ => unreachable!(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::SecondaryIndex;
+
+    #[test]
+    fn test_secondary_index() {
+        // Records keyed by id, indexed by age
+        let mut ages = (0..200u32).map(|id| id % 50).collect::<Vec<_>>();
+        let mut index = SecondaryIndex::new(4);
+        for (id, age) in ages.iter().enumerate() {
+            index.update(id as u32, None, Some(*age));
+        }
+
+        let have = index.get(7).collect::<Vec<_>>();
+        assert!(have == [7, 57, 107, 157], "Have: {:?}", have);
+
+        for id in [7, 57] {
+            let old = ages[id as usize];
+            ages[id as usize] = 49;
+            index.update(id, Some(old), Some(49));
+        }
+        index.update(107, Some(7), None);
+        assert!(!index.remove(107, 7));
+
+        let have = index.get(7).collect::<Vec<_>>();
+        assert!(have == [157], "Have: {:?}", have);
+        let have = index.range(48..).collect::<Vec<_>>();
+        let want = [
+            (48, 48),
+            (48, 98),
+            (48, 148),
+            (48, 198),
+            (49, 7),
+            (49, 49),
+            (49, 57),
+            (49, 99),
+            (49, 149),
+            (49, 199),
+        ];
+        assert!(have == want, "Want: {:?}\nHave: {:?}", want, have);
+        assert!(index.range(10..12).count() == 8 && index.get(50).next().is_none());
+    }
+}
@@ -0,0 +1,250 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Debug;
+use std::ops::{Bound, RangeBounds};
+
+use crate::btree::{BTree, Increment};
+use crate::slot::Slot;
+
+/// A key of an `InternedBTree` as stored in its nodes: the ID of an interned string, then the
+/// rest of the key.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy)]
+pub struct InternedKey<K> {
+    pub id: u64,
+    pub rest: K,
+}
+
+impl<K: Increment> Increment for InternedKey<K> {
+    const MAX: Self = Self {
+        id: u64::MAX,
+        rest: K::MAX,
+    };
+
+    fn increment(&mut self) {
+        *self = self.next();
+    }
+
+    /// Past every key of the string, the next ID is never given out.
+    fn next(&self) -> Self {
+        Self {
+            id: self.id + 1,
+            rest: K::MAX,
+        }
+    }
+}
+
+/// Maps strings to IDs in the same order, so keys compare the same with their strings interned.
+///
+/// A new string takes the ID halfway between its neighbours'. When they are adjacent, every ID is
+/// spread out evenly again, which changes the IDs of strings already interned.
+#[derive(Debug, Default)]
+pub struct Dictionary {
+    ids: BTreeMap<String, u64>,
+    strings: HashMap<u64, String>,
+}
+
+impl Dictionary {
+    pub fn id(&self, s: &str) -> Option<u64> {
+        self.ids.get(s).copied()
+    }
+
+    pub fn string(&self, id: u64) -> Option<&str> {
+        self.strings.get(&id).map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    /// Returns the ID of `s`, or `None` if it needs one and there is no room between its
+    /// neighbours.
+    fn intern(&mut self, s: &str) -> Option<u64> {
+        if let Some(id) = self.id(s) {
+            return Some(id);
+        }
+
+        // IDs 0 and `u64::MAX` are never given out
+        let lo = self
+            .ids
+            .range::<str, _>((Bound::Unbounded, Bound::Excluded(s)))
+            .next_back()
+            .map_or(0, |e| *e.1);
+        let hi = self
+            .ids
+            .range::<str, _>((Bound::Excluded(s), Bound::Unbounded))
+            .next()
+            .map_or(u64::MAX, |e| *e.1);
+        if hi - lo < 2 {
+            return None;
+        }
+
+        let id = lo + (hi - lo) / 2;
+        self.ids.insert(s.to_string(), id);
+        self.strings.insert(id, s.to_string());
+        Some(id)
+    }
+
+    /// Spreads the IDs evenly, returning the new ID of each old one.
+    fn respace(&mut self) -> HashMap<u64, u64> {
+        let step = u64::MAX / (self.ids.len() as u64 + 2);
+        let mut moved = HashMap::new();
+        self.strings.clear();
+        for (i, (s, id)) in self.ids.iter_mut().enumerate() {
+            let new = step * (i as u64 + 1);
+            moved.insert(*id, new);
+            *id = new;
+            self.strings.insert(new, s.clone());
+        }
+
+        moved
+    }
+}
+
+/// A `BTree` for keys made of a string from a small set, such as a host or service name, and the
+/// rest of the key, such as a timestamp. The strings are kept once in a dictionary owned by the
+/// tree and the nodes hold their IDs, which are in the same order, so entries stay in order of
+/// string then rest.
+///
+/// Strings stay in the dictionary after their last entry is deleted.
+pub struct InternedBTree<K, V> {
+    tree: BTree<InternedKey<K>, V>,
+    dictionary: Dictionary,
+}
+
+impl<K, V> InternedBTree<K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment,
+    V: Clone + Copy + Debug + Eq,
+{
+    pub fn new(max: usize) -> Self {
+        Self {
+            tree: BTree::new(max),
+            dictionary: Dictionary::default(),
+        }
+    }
+
+    pub fn insert(&mut self, s: &str, rest: K, value: V) {
+        let id = match self.dictionary.intern(s) {
+            Some(id) => id,
+            None => {
+                self.respace();
+                self.dictionary.intern(s).unwrap()
+            }
+        };
+
+        self.tree
+            .insert(Slot::new_leaf(InternedKey { id, rest }, value));
+    }
+
+    /// Rebuilds the tree with the IDs spread out again.
+    fn respace(&mut self) {
+        let moved = self.dictionary.respace();
+        let entries = self
+            .tree
+            .iter()
+            .map(|(k, v)| {
+                let key = InternedKey {
+                    id: moved[&k.id],
+                    rest: k.rest,
+                };
+                (key, v)
+            })
+            .collect::<Vec<_>>();
+        self.tree = BTree::bulk_load(self.tree.max(), entries);
+    }
+
+    pub fn get(&self, s: &str, rest: K) -> Option<V> {
+        self.range(s, rest..=rest).next().map(|e| e.1)
+    }
+
+    pub fn delete(&mut self, s: &str, rest: K) -> bool {
+        match self.dictionary.id(s) {
+            Some(id) => self.tree.delete(InternedKey { id, rest }),
+            None => false,
+        }
+    }
+
+    /// Returns the entries of the string `s` with the rest of their keys in `range`, in order.
+    pub fn range<R: RangeBounds<K>>(&self, s: &str, range: R) -> impl Iterator<Item = (K, V)> + '_ {
+        let (start, end) = match self.dictionary.id(s) {
+            Some(id) => {
+                let key = |rest: &K| InternedKey { id, rest: *rest };
+                let start = match range.start_bound() {
+                    Bound::Included(k) => Bound::Included(key(k)),
+                    Bound::Excluded(k) => Bound::Excluded(key(k)),
+                    Bound::Unbounded => Bound::Excluded(InternedKey {
+                        id: id - 1,
+                        rest: K::MAX,
+                    }),
+                };
+                let end = match range.end_bound() {
+                    Bound::Included(k) => Bound::Included(key(k)),
+                    Bound::Excluded(k) => Bound::Excluded(key(k)),
+                    Bound::Unbounded => Bound::Included(key(&K::MAX)),
+                };
+                (start, end)
+            }
+            // An empty range
+            None => (Bound::Excluded(InternedKey::MAX), Bound::Included(InternedKey::MAX)),
+        };
+
+        self.tree.range((start, end)).map(|(k, v)| (k.rest, v))
+    }
+
+    /// Returns every entry, in order of string then rest.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, K, V)> + '_ {
+        self.tree.iter().map(|(k, v)| {
+            let s = self.dictionary.string(k.id).unwrap();
+            (s, k.rest, v)
+        })
+    }
+
+    pub fn dictionary(&self) -> &Dictionary {
+        &self.dictionary
+    }
+
+    pub fn inner(&self) -> &BTree<InternedKey<K>, V> {
+        &self.tree
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::InternedBTree;
+
+    #[test]
+    fn test_interning() {
+        let services = (0..80)
+            .map(|i| format!("service-{:02}", i))
+            .collect::<Vec<_>>();
+
+        // Ascending names use up the gap below the greatest one fastest
+        let mut tree = InternedBTree::new(8);
+        for ts in 0..20u64 {
+            for (i, service) in services.iter().enumerate() {
+                tree.insert(service, ts, i as u64 * 100 + ts);
+            }
+        }
+        assert!(tree.dictionary().len() == 80);
+        tree.inner().validate().unwrap();
+
+        let want = (0..80)
+            .flat_map(|i| (0..20).map(move |ts| (i, ts)))
+            .map(|(i, ts)| (services[i].as_str(), ts, i as u64 * 100 + ts))
+            .collect::<Vec<_>>();
+        let have = tree.iter().collect::<Vec<_>>();
+        assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+
+        let have = tree.range("service-07", 5..8).collect::<Vec<_>>();
+        assert!(have == [(5, 705), (6, 706), (7, 707)], "Have: {:?}", have);
+        assert!(tree.range("service-79", ..).count() == 20);
+        assert!(tree.range("service-80", ..).next().is_none());
+
+        assert!(tree.delete("service-03", 19) && !tree.delete("service-03", 19));
+        assert!(tree.get("service-03", 19).is_none() && tree.get("service-03", 18) == Some(318));
+        assert!(tree.get("nope", 0).is_none());
+    }
+}
@@ -0,0 +1,427 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+#[cfg(feature = "rayon")]
+use std::fmt::Debug;
+use std::iter::FusedIterator;
+use std::marker::PhantomData;
+use std::ops::Bound;
+
+use crate::btree::BTree;
+#[cfg(feature = "rayon")]
+use crate::btree::Increment;
+use crate::node::Node;
+use crate::slot::Slot;
+
+/// Iterates over the entries of a key range in order, following the leaf chain. Knows how many
+/// entries are left from the counts of the internal nodes.
+pub struct Range<'a, K, V> {
+    tree: &'a BTree<K, V>,
+    node: *mut Node<K, V>,
+    i: usize,
+    end: Bound<K>,
+    remaining: usize,
+}
+
+impl<'a, K: Ord, V> Range<'a, K, V> {
+    /// `node` is the leaf of `tree` to start at and `i` the index of the first slot to return from
+    /// it, with `remaining` entries from there to `end`.
+    pub(crate) fn new(
+        tree: &'a BTree<K, V>,
+        node: *mut Node<K, V>,
+        i: usize,
+        end: Bound<K>,
+        remaining: usize,
+    ) -> Self {
+        if let Some(node) = unsafe { node.as_ref() } {
+            node.prefetch_next();
+            if tree.sizing.is_some() {
+                node.heat.read();
+            }
+        }
+
+        Self {
+            tree,
+            node,
+            i,
+            end,
+            remaining,
+        }
+    }
+}
+
+impl<K, V> Range<'_, K, V>
+where
+    K: Copy + Ord,
+    V: Clone,
+{
+    /// Skips the entries with keys less than `key`, passing over whole leaves without looking at
+    /// their entries.
+    pub(crate) fn seek(&mut self, key: K) {
+        while !self.node.is_null() {
+            let node = unsafe { &*self.node };
+            if node.values.last().is_some_and(|last| last.0 >= key) {
+                let i = node.values.iter().position(|s| s.0 >= key).unwrap();
+                self.remaining = self.remaining.saturating_sub(i.saturating_sub(self.i));
+                self.i = self.i.max(i);
+                return;
+            }
+
+            let skipped = node.values.len().saturating_sub(self.i);
+            self.remaining = self.remaining.saturating_sub(skipped);
+            self.node = node.next;
+            self.i = 0;
+        }
+    }
+
+    pub(crate) fn peek(&self) -> Option<(K, V)> {
+        self.clone().next()
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl<K, V> Range<'_, K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment,
+    V: Clone + Debug + Eq,
+{
+    /// Splits off the second half of the range, from the start of the leaf its middle entry is
+    /// in. `None` if the range doesn't reach past the leaf it is on.
+    pub(crate) fn split(mut self) -> (Self, Option<Self>) {
+        let first = match self.peek() {
+            Some((k, _)) => k,
+            None => return (self, None),
+        };
+        let rank = self.tree.rank(first, false);
+        let (leaf, _) = self.tree.locate(rank + self.remaining / 2);
+        if leaf == self.node {
+            return (self, None);
+        }
+
+        let start = unsafe { (*leaf).first().unwrap().0 };
+        let left = self.tree.rank(start, false) - rank;
+        let right = Range::new(self.tree, leaf, 0, self.end, self.remaining - left);
+        self.end = Bound::Excluded(start);
+        self.remaining = left;
+
+        (self, Some(right))
+    }
+}
+
+impl<K: Clone, V> Clone for Range<'_, K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            tree: self.tree,
+            node: self.node,
+            i: self.i,
+            end: self.end.clone(),
+            remaining: self.remaining,
+        }
+    }
+}
+
+impl<K, V> Iterator for Range<'_, K, V>
+where
+    K: Copy + Ord,
+    V: Clone,
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.node.is_null() {
+            let node = unsafe { &*self.node };
+
+            let slot = match node.values.iter().nth(self.i) {
+                Some(slot) => slot,
+                None => {
+                    self.node = node.next;
+                    self.i = 0;
+                    if let Some(node) = unsafe { self.node.as_ref() } {
+                        node.prefetch_next();
+                        if self.tree.sizing.is_some() {
+                            node.heat.read();
+                        }
+                    }
+                    continue;
+                }
+            };
+
+            let past_end = match self.end {
+                Bound::Included(end) => slot.0 > end,
+                Bound::Excluded(end) => slot.0 >= end,
+                Bound::Unbounded => false,
+            };
+            if past_end {
+                self.node = std::ptr::null_mut();
+                return None;
+            }
+
+            self.i += 1;
+            self.remaining -= 1;
+            return Some((slot.0, slot.value().clone()));
+        }
+
+        None
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<K: Copy + Ord, V: Clone> ExactSizeIterator for Range<'_, K, V> {}
+
+impl<K: Copy + Ord, V: Clone> FusedIterator for Range<'_, K, V> {}
+
+/// Iterates over the leaves of a tree in order, yielding the entries of each as a slice. Empty
+/// leaves are skipped.
+pub struct Leaves<'a, K, V> {
+    node: *mut Node<K, V>,
+    _tree: PhantomData<&'a BTree<K, V>>,
+}
+
+impl<K, V> Leaves<'_, K, V> {
+    /// `node` is the leaf to start at.
+    pub(crate) fn new(node: *mut Node<K, V>) -> Self {
+        Self {
+            node,
+            _tree: PhantomData,
+        }
+    }
+}
+
+impl<'a, K: Ord, V> Iterator for Leaves<'a, K, V> {
+    type Item = &'a [Slot<K, V>];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.node.is_null() {
+            let node = unsafe { &*self.node };
+            self.node = node.next;
+
+            let slots = node.values.iter().as_slice();
+            if !slots.is_empty() {
+                return Some(slots);
+            }
+        }
+
+        None
+    }
+}
+
+impl<K: Ord, V> FusedIterator for Leaves<'_, K, V> {}
+
+/// What `Merge` yields for a key that more than one source has.
+#[derive(Debug, Clone, Copy)]
+pub enum Duplicates<K, V> {
+    /// The value of the first source with the key.
+    First,
+    /// The value of the last source with the key, for sources from oldest to newest.
+    Last,
+    /// Every entry, in source order.
+    All,
+    /// The values folded together in source order.
+    Combine(fn(K, V, V) -> V),
+}
+
+/// Merges sources that are each in key order, such as the `Range`s of several `BTree`s or
+/// `FrozenBTree`s, into one iterator in key order. Keys that more than one source has are resolved
+/// by `Duplicates`.
+pub struct Merge<I, K, V> {
+    sources: Vec<I>,
+    // The next entry of each source
+    heads: Vec<Option<V>>,
+    // The keys of the heads, least key then first source on top
+    heap: BinaryHeap<Reverse<(K, usize)>>,
+    duplicates: Duplicates<K, V>,
+}
+
+impl<I, K, V> Merge<I, K, V>
+where
+    I: Iterator<Item = (K, V)>,
+    K: Copy + Ord,
+    V: Copy,
+{
+    pub fn new<S: IntoIterator<Item = I>>(sources: S, duplicates: Duplicates<K, V>) -> Self {
+        let sources = sources.into_iter().collect::<Vec<_>>();
+        let mut merge = Self {
+            heads: vec![None; sources.len()],
+            heap: BinaryHeap::with_capacity(sources.len()),
+            sources,
+            duplicates,
+        };
+        for i in 0..merge.sources.len() {
+            merge.advance(i);
+        }
+
+        merge
+    }
+
+    fn advance(&mut self, i: usize) {
+        if let Some((k, v)) = self.sources[i].next() {
+            self.heads[i] = Some(v);
+            self.heap.push(Reverse((k, i)));
+        }
+    }
+
+    fn pop(&mut self) -> Option<(K, V)> {
+        let Reverse((k, i)) = self.heap.pop()?;
+        let v = self.heads[i].take().unwrap();
+        self.advance(i);
+
+        Some((k, v))
+    }
+}
+
+impl<I, K, V> Iterator for Merge<I, K, V>
+where
+    I: Iterator<Item = (K, V)>,
+    K: Copy + Ord,
+    V: Copy,
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (k, mut v) = self.pop()?;
+        if let Duplicates::All = self.duplicates {
+            return Some((k, v));
+        }
+
+        while self
+            .heap
+            .peek()
+            .is_some_and(|Reverse((next, _))| *next == k)
+        {
+            let (_, dup) = self.pop().unwrap();
+            v = match self.duplicates {
+                Duplicates::First => v,
+                Duplicates::Last => dup,
+                Duplicates::Combine(f) => f(k, v, dup),
+                Duplicates::All => unreachable!(),
+            };
+        }
+
+        Some((k, v))
+    }
+}
+
+// Sources aren't read again once the heap is empty
+impl<I, K, V> FusedIterator for Merge<I, K, V>
+where
+    I: Iterator<Item = (K, V)>,
+    K: Copy + Ord,
+    V: Copy,
+{
+}
+
+#[cfg(test)]
+mod test {
+    use std::ops::Bound;
+
+    use crate::btree::BTree;
+    use crate::get_left;
+    use crate::slot::{Either, Slot};
+
+    use super::{Duplicates, Merge, Range};
+
+    #[test]
+    fn test_merge() {
+        let a = BTree::bulk_load(4, (0..100u32).step_by(2).map(|k| (k, 1)));
+        let b = BTree::bulk_load(4, (0..100u32).step_by(3).map(|k| (k, 10))).freeze();
+        let c = BTree::bulk_load(4, (50..60u32).map(|k| (k, 100)));
+        let sources = || [a.range(40..70), b.range(40..70), c.range(40..70)];
+
+        let sum = |k: u32| {
+            let mut v = 0;
+            v += if k.is_multiple_of(2) { 1 } else { 0 };
+            v += if k.is_multiple_of(3) { 10 } else { 0 };
+            v += if (50..60).contains(&k) { 100 } else { 0 };
+            v
+        };
+        let want = (40..70)
+            .map(|k| (k, sum(k)))
+            .filter(|(_, v)| *v > 0)
+            .collect::<Vec<_>>();
+        let have = Merge::new(sources(), Duplicates::Combine(|_, a, b| a + b)).collect::<Vec<_>>();
+        assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+
+        let first = |k: u32| {
+            [
+                (k.is_multiple_of(2), 1),
+                (k.is_multiple_of(3), 10),
+                ((50..60).contains(&k), 100),
+            ]
+        };
+        let want = want
+            .iter()
+            .map(|(k, _)| (*k, first(*k).into_iter().find(|s| s.0).unwrap().1))
+            .collect::<Vec<_>>();
+        let have = Merge::new(sources(), Duplicates::First).collect::<Vec<_>>();
+        assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+
+        let have = Merge::new(sources(), Duplicates::Last).find(|(k, _)| *k == 54);
+        assert!(have == Some((54, 100)), "Have: {:?}", have);
+
+        let have = Merge::new(sources(), Duplicates::All)
+            .filter(|(k, _)| *k == 54)
+            .collect::<Vec<_>>();
+        assert!(have == [(54, 1), (54, 10), (54, 100)], "Have: {:?}", have);
+        let none = Vec::<Range<'_, u32, u32>>::new();
+        assert!(Merge::new(none, Duplicates::All).next().is_none());
+    }
+
+    #[test]
+    fn test_range_len() {
+        let mut tree = BTree::new(4);
+        for k in (0..500u32).rev() {
+            tree.insert(Slot::new_leaf(k * 2, k));
+        }
+        tree.delete_where(|k, _| (200..400).contains(&k));
+        for k in (0..1000).step_by(3) {
+            tree.delete(k);
+        }
+
+        let ranges = [
+            (Bound::Unbounded, Bound::Unbounded),
+            (Bound::Included(101), Bound::Excluded(600)),
+            (Bound::Excluded(100), Bound::Included(600)),
+            (Bound::Included(250), Bound::Included(350)),
+            (Bound::Excluded(998), Bound::Unbounded),
+            (Bound::Unbounded, Bound::Excluded(0)),
+            (Bound::Included(2000), Bound::Unbounded),
+        ];
+        for range in ranges {
+            let mut iter = tree.range(range);
+            let want = tree.range(range).count();
+            assert!(iter.len() == want, "Range: {:?}\nWant: {}\nHave: {}", range, want, iter.len());
+            iter.next();
+            assert!(iter.len() == want.saturating_sub(1));
+
+            // Seeking skips entries and leaves
+            iter.seek(500);
+            let want = iter.clone().count();
+            assert!(iter.len() == want, "Range: {:?}\nWant: {}\nHave: {}", range, want, iter.len());
+        }
+
+        let mut iter = tree.iter();
+        assert!(iter.len() == tree.len() && iter.by_ref().count() == tree.len());
+        assert!(iter.next().is_none() && iter.len() == 0);
+    }
+
+    #[test]
+    fn test_leaves() {
+        let mut tree = BTree::bulk_load(8, (0..100u32).map(|k| (k, k * 2)));
+        let have = tree.leaves().map(|leaf| leaf.len()).collect::<Vec<_>>();
+        assert!(have.len() == 25 && have.iter().all(|n| *n == 4), "Have: {:?}", have);
+
+        // The entries in order, without the leaves emptied by deleting
+        tree.delete_where(|k, _| (20..40).contains(&k) || k % 7 == 0);
+        let want = tree.iter().collect::<Vec<_>>();
+        let have = tree
+            .leaves()
+            .flat_map(|leaf| leaf.iter().map(|s| (s.0, get_left!(s))))
+            .collect::<Vec<_>>();
+        assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+        assert!(tree.leaves().all(|leaf| !leaf.is_empty()));
+
+        assert!(BTree::<u32, u32>::new(8).leaves().next().is_none());
+    }
+}
@@ -0,0 +1,392 @@
+use std::error::Error;
+use std::fmt::{self, Debug, Display};
+use std::marker::PhantomData;
+use std::ops::{Bound, RangeBounds};
+
+use crate::btree::{BTree, Increment};
+use crate::bytes::Bytes;
+use crate::slot::{Either, Slot};
+
+/// Maps keys to byte strings ordered the same way, so keys of any type can be stored in a tree
+/// of byte strings.
+///
+/// Every encoding is self-delimiting, so the encodings of tuples are those of their fields back
+/// to back. Integers are big-endian with the sign bit flipped. Byte strings end with `00 01` and
+/// have their zero bytes escaped as `00 ff`, so a string sorts before any it is a prefix of.
+pub trait KeyEncode: Sized {
+    /// Appends the encoding to `out`.
+    fn encode_key(&self, out: &mut Vec<u8>);
+
+    /// Decodes a key from the front of `buf` and advances it past the key. `None` if `buf` doesn't
+    /// start with an encoding of `Self`.
+    fn decode_key(buf: &mut &[u8]) -> Option<Self>;
+
+    fn to_key(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode_key(&mut out);
+        out
+    }
+
+    /// `None` unless all of `buf` is a single key.
+    fn from_key(mut buf: &[u8]) -> Option<Self> {
+        let key = Self::decode_key(&mut buf)?;
+        buf.is_empty().then_some(key)
+    }
+}
+
+fn take<'a>(buf: &mut &'a [u8], n: usize) -> Option<&'a [u8]> {
+    if buf.len() < n {
+        return None;
+    }
+
+    let (head, tail) = buf.split_at(n);
+    *buf = tail;
+    Some(head)
+}
+
+macro_rules! impl_key_encode_unsigned {
+    ($( $t:ty ),*) => {
+        $(
+        impl KeyEncode for $t {
+            fn encode_key(&self, out: &mut Vec<u8>) {
+                out.extend_from_slice(&self.to_be_bytes());
+            }
+
+            fn decode_key(buf: &mut &[u8]) -> Option<Self> {
+                let bytes = take(buf, size_of::<$t>())?;
+                Some(<$t>::from_be_bytes(bytes.try_into().unwrap()))
+            }
+        }
+        )*
+    };
+}
+
+macro_rules! impl_key_encode_signed {
+    ($( $t:ty => $u:ty ),*) => {
+        $(
+        impl KeyEncode for $t {
+            fn encode_key(&self, out: &mut Vec<u8>) {
+                ((*self as $u) ^ (1 << (<$u>::BITS - 1))).encode_key(out);
+            }
+
+            fn decode_key(buf: &mut &[u8]) -> Option<Self> {
+                let u = <$u>::decode_key(buf)?;
+                Some((u ^ (1 << (<$u>::BITS - 1))) as $t)
+            }
+        }
+        )*
+    };
+}
+
+impl_key_encode_unsigned!(u8, u16, u32, u64, u128);
+impl_key_encode_signed!(i8 => u8, i16 => u16, i32 => u32, i64 => u64, i128 => u128);
+
+impl KeyEncode for bool {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        out.push(*self as u8);
+    }
+
+    fn decode_key(buf: &mut &[u8]) -> Option<Self> {
+        match take(buf, 1)? {
+            [0] => Some(false),
+            [1] => Some(true),
+            _ => None,
+        }
+    }
+}
+
+impl KeyEncode for Vec<u8> {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        for b in self {
+            match b {
+                0 => out.extend_from_slice(&[0, 0xff]),
+                b => out.push(*b),
+            }
+        }
+        out.extend_from_slice(&[0, 1]);
+    }
+
+    fn decode_key(buf: &mut &[u8]) -> Option<Self> {
+        let mut bytes = Vec::new();
+        loop {
+            match take(buf, 1)? {
+                [0] => match take(buf, 1)? {
+                    [0xff] => bytes.push(0),
+                    [1] => return Some(bytes),
+                    _ => return None,
+                },
+                [b] => bytes.push(*b),
+                _ => unreachable!(),
+            }
+        }
+    }
+}
+
+impl KeyEncode for String {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        // Strings order by their bytes, like `str`
+        self.as_bytes().to_vec().encode_key(out);
+    }
+
+    fn decode_key(buf: &mut &[u8]) -> Option<Self> {
+        String::from_utf8(Vec::decode_key(buf)?).ok()
+    }
+}
+
+macro_rules! impl_key_encode_tuple {
+    ($( ($( $t:ident $i:tt ),+) ),*) => {
+        $(
+        impl<$( $t: KeyEncode ),+> KeyEncode for ($( $t, )+) {
+            fn encode_key(&self, out: &mut Vec<u8>) {
+                $( self.$i.encode_key(out); )+
+            }
+
+            fn decode_key(buf: &mut &[u8]) -> Option<Self> {
+                Some(($( $t::decode_key(buf)?, )+))
+            }
+        }
+        )*
+    };
+}
+
+impl_key_encode_tuple!(
+    (A 0),
+    (A 0, B 1),
+    (A 0, B 1, C 2),
+    (A 0, B 1, C 2, D 3)
+);
+
+/// Leading fields of the tuple key `K`. The encoding of a key starts with the encoding of each of
+/// its prefixes, so the keys with a prefix are a range of encoded keys, see
+/// `EncodedBTree::prefix()`.
+pub trait PrefixOf<K>: KeyEncode {}
+
+impl<A: KeyEncode, B: KeyEncode> PrefixOf<(A, B)> for (A,) {}
+impl<A: KeyEncode, B: KeyEncode, C: KeyEncode> PrefixOf<(A, B, C)> for (A,) {}
+impl<A: KeyEncode, B: KeyEncode, C: KeyEncode> PrefixOf<(A, B, C)> for (A, B) {}
+impl<A: KeyEncode, B: KeyEncode, C: KeyEncode, D: KeyEncode> PrefixOf<(A, B, C, D)> for (A,) {}
+impl<A: KeyEncode, B: KeyEncode, C: KeyEncode, D: KeyEncode> PrefixOf<(A, B, C, D)> for (A, B) {}
+impl<A: KeyEncode, B: KeyEncode, C: KeyEncode, D: KeyEncode> PrefixOf<(A, B, C, D)> for (A, B, C) {}
+
+/// A key encoded longer than the `N` bytes an `EncodedBTree` stores.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct KeyTooLong {
+    pub len: usize,
+    pub max: usize,
+}
+
+impl Display for KeyTooLong {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "key encodes to {} bytes, more than {}", self.len, self.max)
+    }
+}
+
+impl Error for KeyTooLong {}
+
+/// A `BTree` over keys of any `KeyEncode` type, stored encoded in up to `N` bytes.
+pub struct EncodedBTree<K, V, const N: usize = 64> {
+    tree: BTree<Bytes<N>, V>,
+    _key: PhantomData<K>,
+}
+
+impl<K, V, const N: usize> EncodedBTree<K, V, N>
+where
+    K: KeyEncode,
+    V: Clone + Copy + Debug + Eq,
+{
+    pub fn new(max: usize) -> Self {
+        Self {
+            tree: BTree::new(max),
+            _key: PhantomData,
+        }
+    }
+
+    fn encode(key: &K) -> Result<Bytes<N>, KeyTooLong> {
+        let buf = key.to_key();
+        Bytes::new(&buf).ok_or(KeyTooLong {
+            len: buf.len(),
+            max: N,
+        })
+    }
+
+    pub fn insert(&mut self, key: &K, value: V) -> Result<(), KeyTooLong> {
+        self.tree.insert(Slot::new_leaf(Self::encode(key)?, value));
+        Ok(())
+    }
+
+    pub fn get(&self, key: &K) -> Option<V> {
+        match self.tree.get(Self::encode(key).ok()?)?.1 {
+            Either::Left(v) => Some(v),
+            Either::Right(_) => unreachable!(),
+        }
+    }
+
+    pub fn delete(&mut self, key: &K) -> bool {
+        match Self::encode(key) {
+            Ok(key) => self.tree.delete(key),
+            Err(_) => false,
+        }
+    }
+
+    /// Returns an iterator over the entries with keys in `range`, in order.
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> impl Iterator<Item = (K, V)> + '_ {
+        // A bound too long to store sorts right after its first `N` bytes, before any longer
+        // stored key
+        let bound = |bound: Bound<&K>, start: bool| match bound {
+            Bound::Included(k) | Bound::Excluded(k) => {
+                let buf = k.to_key();
+                match Bytes::new(&buf) {
+                    Some(b) if matches!(bound, Bound::Included(_)) => Bound::Included(b),
+                    Some(b) => Bound::Excluded(b),
+                    None if start => Bound::Excluded(Bytes::new(&buf[..N]).unwrap()),
+                    None => Bound::Included(Bytes::new(&buf[..N]).unwrap()),
+                }
+            }
+            Bound::Unbounded => Bound::Unbounded,
+        };
+
+        let range = (bound(range.start_bound(), true), bound(range.end_bound(), false));
+        self.range_encoded(range)
+    }
+
+    /// Returns an iterator over the entries with keys starting with `prefix`, in order, such as
+    /// every `(column, row)` key of a column with `prefix(&(column,))`.
+    pub fn prefix<P: PrefixOf<K>>(&self, prefix: &P) -> impl Iterator<Item = (K, V)> + '_ {
+        let mut buf = prefix.to_key();
+        let start = match Bytes::new(&buf) {
+            Some(start) => Bound::Included(start),
+            // No key is that long
+            None => Bound::Excluded(Bytes::MAX),
+        };
+
+        // The least byte string after every one starting with the prefix, if there is one
+        let end = match buf.iter().rposition(|b| *b != u8::MAX) {
+            Some(i) => {
+                buf[i] += 1;
+                buf.truncate(i + 1);
+                Bytes::new(&buf).map_or(Bound::Unbounded, Bound::Excluded)
+            }
+            None => Bound::Unbounded,
+        };
+
+        self.range_encoded((start, end))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (K, V)> + '_ {
+        self.range_encoded((Bound::Unbounded, Bound::Unbounded))
+    }
+
+    pub(crate) fn range_encoded(
+        &self,
+        range: (Bound<Bytes<N>>, Bound<Bytes<N>>),
+    ) -> impl Iterator<Item = (K, V)> + '_ {
+        self.tree.range(range).map(|(k, v)| {
+            let key = K::from_key(k.as_slice()).expect("stored keys are encoded keys");
+            (key, v)
+        })
+    }
+
+    /// The tree of encoded keys.
+    pub fn inner(&self) -> &BTree<Bytes<N>, V> {
+        &self.tree
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{EncodedBTree, KeyEncode, KeyTooLong};
+
+    #[test]
+    fn test_key_encode() {
+        fn check<K: KeyEncode + Ord + std::fmt::Debug + Clone>(mut keys: Vec<K>) {
+            keys.sort();
+            let encoded = keys.iter().map(|k| k.to_key()).collect::<Vec<_>>();
+            let mut sorted = encoded.clone();
+            sorted.sort();
+            assert!(encoded == sorted, "Want: {:?}\nHave: {:?}", keys, sorted);
+
+            for (k, buf) in keys.iter().zip(&encoded) {
+                let have = K::from_key(buf);
+                assert!(have.as_ref() == Some(k), "Want: {:?}\nHave: {:?}", k, have);
+            }
+        }
+
+        check(vec![i64::MIN, -300, -1, 0, 1, 255, 256, i64::MAX]);
+        check(vec![0u32, 7, 1 << 16, u32::MAX]);
+        check(
+            ["", "a", "a\0", "a\0\0", "a\u{1}", "ab", "b", "\u{ff}"]
+                .map(String::from)
+                .to_vec(),
+        );
+        check(vec![(1u8, -2i32), (1, 5), (0, i32::MAX), (2, i32::MIN)]);
+        check(vec![
+            (String::from("a"), 2u64, false),
+            (String::from("a\0"), 1, true),
+            (String::from("ab"), 0, false),
+            (String::from("a"), 2, true),
+        ]);
+
+        assert!(u32::from_key(&[0, 0, 0]).is_none());
+        assert!(u32::from_key(&[0, 0, 0, 0, 0]).is_none());
+        assert!(String::from_key(&[b'a', 0, 2]).is_none());
+
+        let mut tree = EncodedBTree::<(String, i64), u32, 16>::new(4);
+        for (i, name) in ["carol", "alice", "bob"].iter().enumerate() {
+            for n in -5..5 {
+                tree.insert(&(name.to_string(), n), i as u32).unwrap();
+            }
+        }
+        let long = (String::from("a name too long to store"), 0);
+        assert!(tree.insert(&long, 0) == Err(KeyTooLong { len: 34, max: 16 }));
+        assert!(tree.get(&long).is_none() && !tree.delete(&long));
+        assert!(tree.delete(&(String::from("bob"), 0)));
+
+        let want = [-2, -1, 1, 2]
+            .map(|n| ((String::from("bob"), n), 2))
+            .to_vec();
+        let range = (String::from("bob"), -2)..(String::from("bob"), 3);
+        let have = tree.range(range).collect::<Vec<_>>();
+        assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+
+        // Before every "alice", the bound being too long
+        let have = tree.range(long..(String::from("bob"), -5)).count();
+        assert!(have == 10, "Have: {}", have);
+        let have = tree.range((String::from("alice"), 4)..).count();
+        assert!(have == 20, "Have: {}", have);
+        assert!(tree.iter().count() == 29);
+        tree.inner().validate().unwrap();
+    }
+
+    #[test]
+    fn test_prefix() {
+        // Secondary index entries, by column value then row
+        let mut tree = EncodedBTree::<(u8, String, u64), (), 32>::new(4);
+        for row in 0..100u64 {
+            let name = ["ann", "an", "bo", "an\0"][row as usize % 4].to_string();
+            tree.insert(&(row as u8 % 3 + 253, name, row), ()).unwrap();
+        }
+
+        let want = (0..100)
+            .filter(|row| row % 4 == 1 && row % 3 == 2)
+            .collect::<Vec<_>>();
+        let have = tree
+            .prefix(&(255, String::from("an")))
+            .map(|((_, _, row), _)| row)
+            .collect::<Vec<_>>();
+        assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+
+        // Every byte of the prefix is 0xff
+        let have = tree.prefix(&(255,)).count();
+        assert!(have == 33, "Have: {}", have);
+        let have = tree
+            .prefix(&(253,))
+            .map(|((c, _, _), _)| c)
+            .collect::<Vec<_>>();
+        assert!(have == [253; 34], "Have: {:?}", have);
+
+        let have = tree.prefix(&(0,)).count();
+        assert!(have == 0, "Have: {}", have);
+        let long = (254, "a prefix much too long to be stored".to_string());
+        assert!(tree.prefix(&long).count() == 0);
+    }
+}
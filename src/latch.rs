@@ -0,0 +1,394 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::time::{Duration, Instant};
+
+use crate::sync::{thread, Condvar, Mutex};
+
+/// Identifies a latch, node latches use the node's address.
+pub type LatchId = usize;
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum LatchMode {
+    Shared,
+    Exclusive,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct WaitForEdge {
+    pub waiter: thread::ThreadId,
+    pub holder: thread::ThreadId,
+    pub latch: LatchId,
+    pub mode: LatchMode,
+}
+
+/// Which threads are waiting on latches held by which other threads.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct WaitForGraph {
+    pub edges: Vec<WaitForEdge>,
+}
+
+impl WaitForGraph {
+    /// Returns `true` if some threads are waiting on each other. The acquisition order checks
+    /// should make this impossible, so a cycle points at latching outside the manager.
+    pub fn has_cycle(&self) -> bool {
+        fn visit(
+            graph: &WaitForGraph,
+            t: thread::ThreadId,
+            path: &mut Vec<thread::ThreadId>,
+            done: &mut HashSet<thread::ThreadId>,
+        ) -> bool {
+            if path.contains(&t) {
+                return true;
+            }
+            if !done.insert(t) {
+                return false;
+            }
+
+            path.push(t);
+            let cycle = graph
+                .edges
+                .iter()
+                .filter(|e| e.waiter == t)
+                .any(|e| visit(graph, e.holder, path, done));
+            path.pop();
+
+            cycle
+        }
+
+        let mut done = HashSet::new();
+        self.edges
+            .iter()
+            .any(|e| visit(self, e.waiter, &mut Vec::new(), &mut done))
+    }
+}
+
+impl Display for WaitForGraph {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.edges.is_empty() {
+            return write!(f, "no waiters");
+        }
+
+        for (i, e) in self.edges.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(
+                f,
+                "{:?} waits for {:?} on latch {:#x} ({:?})",
+                e.waiter, e.holder, e.latch, e.mode
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum LatchError {
+    /// The latch is not below every latch the thread already holds.
+    OrderViolation {
+        latch: LatchId,
+        level: usize,
+        held: LatchId,
+        held_level: usize,
+    },
+    /// The latch could not be acquired in time, `graph` is the wait-for graph at that point.
+    Timeout {
+        latch: LatchId,
+        mode: LatchMode,
+        graph: WaitForGraph,
+    },
+}
+
+impl Display for LatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LatchError::OrderViolation {
+                latch,
+                level,
+                held,
+                held_level,
+            } => write!(
+                f,
+                "latch {latch:#x} at level {level} acquired while holding {held:#x} at level \
+                 {held_level}"
+            ),
+            LatchError::Timeout { latch, mode, graph } => {
+                write!(f, "timed out acquiring latch {latch:#x} ({mode:?}):\n{graph}")
+            }
+        }
+    }
+}
+
+impl Error for LatchError {}
+
+struct Holders {
+    mode: LatchMode,
+    threads: Vec<thread::ThreadId>,
+}
+
+#[derive(Default)]
+struct State {
+    holders: HashMap<LatchId, Holders>,
+    // (level, latch) of every latch each thread holds
+    held: HashMap<thread::ThreadId, Vec<(usize, LatchId)>>,
+    waiting: HashMap<thread::ThreadId, (LatchId, LatchMode)>,
+}
+
+/// Hands out every latch taken on the tree.
+///
+/// Latches carry a level, the depth of what they protect. A thread may only acquire a latch
+/// ordered after every latch it holds, by level and then by id, so latching is always top-down
+/// and left to right and threads can't deadlock on each other. Waits can be bounded with
+/// `try_acquire`, which reports the wait-for graph when it times out.
+#[derive(Default)]
+pub struct LatchManager {
+    state: Mutex<State>,
+    released: Condvar,
+}
+
+/// Releases the latch when dropped, on behalf of the thread that acquired it, wherever the guard
+/// was sent to.
+pub struct LatchGuard<'a> {
+    manager: &'a LatchManager,
+    latch: LatchId,
+    owner: thread::ThreadId,
+}
+
+impl Drop for LatchGuard<'_> {
+    fn drop(&mut self) {
+        self.manager.release(self.latch, self.owner);
+    }
+}
+
+impl LatchManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Waits for as long as it takes, only fails if acquiring would break the latch order.
+    pub fn acquire(
+        &self,
+        latch: LatchId,
+        level: usize,
+        mode: LatchMode,
+    ) -> Result<LatchGuard<'_>, LatchError> {
+        self._acquire(latch, level, mode, None)
+    }
+
+    pub fn try_acquire(
+        &self,
+        latch: LatchId,
+        level: usize,
+        mode: LatchMode,
+        timeout: Duration,
+    ) -> Result<LatchGuard<'_>, LatchError> {
+        self._acquire(latch, level, mode, Some(Instant::now() + timeout))
+    }
+
+    fn _acquire(
+        &self,
+        latch: LatchId,
+        level: usize,
+        mode: LatchMode,
+        deadline: Option<Instant>,
+    ) -> Result<LatchGuard<'_>, LatchError> {
+        let me = thread::current().id();
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(&(held_level, held)) = state
+            .held
+            .get(&me)
+            .and_then(|h| h.iter().find(|h| **h >= (level, latch)))
+        {
+            return Err(LatchError::OrderViolation {
+                latch,
+                level,
+                held,
+                held_level,
+            });
+        }
+
+        loop {
+            let free = match state.holders.get(&latch) {
+                Some(h) => mode == LatchMode::Shared && h.mode == LatchMode::Shared,
+                None => true,
+            };
+            if free {
+                break;
+            }
+
+            let timeout = match deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(timeout) => Some(timeout),
+                    None => {
+                        let graph = Self::graph(&state);
+                        state.waiting.remove(&me);
+                        return Err(LatchError::Timeout { latch, mode, graph });
+                    }
+                },
+                None => None,
+            };
+
+            state.waiting.insert(me, (latch, mode));
+            state = match timeout {
+                Some(timeout) => self.released.wait_timeout(state, timeout).unwrap().0,
+                None => self.released.wait(state).unwrap(),
+            };
+        }
+
+        state.waiting.remove(&me);
+        state
+            .holders
+            .entry(latch)
+            .or_insert(Holders {
+                mode,
+                threads: Vec::new(),
+            })
+            .threads
+            .push(me);
+        state.held.entry(me).or_default().push((level, latch));
+
+        Ok(LatchGuard {
+            manager: self,
+            latch,
+            owner: me,
+        })
+    }
+
+    fn release(&self, latch: LatchId, me: thread::ThreadId) {
+        let mut state = self.state.lock().unwrap();
+
+        let holders = state
+            .holders
+            .get_mut(&latch)
+            .expect("released latch should be held");
+        holders.threads.retain(|t| *t != me);
+        if holders.threads.is_empty() {
+            state.holders.remove(&latch);
+        }
+
+        let held = state.held.get_mut(&me).expect("thread should hold latches");
+        held.retain(|(_, l)| *l != latch);
+        if held.is_empty() {
+            state.held.remove(&me);
+        }
+
+        self.released.notify_all();
+    }
+
+    pub fn wait_for_graph(&self) -> WaitForGraph {
+        Self::graph(&self.state.lock().unwrap())
+    }
+
+    fn graph(state: &State) -> WaitForGraph {
+        let mut edges = Vec::new();
+        for (waiter, (latch, mode)) in &state.waiting {
+            if let Some(holders) = state.holders.get(latch) {
+                for holder in &holders.threads {
+                    edges.push(WaitForEdge {
+                        waiter: *waiter,
+                        holder: *holder,
+                        latch: *latch,
+                        mode: *mode,
+                    });
+                }
+            }
+        }
+
+        WaitForGraph { edges }
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod test {
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::{LatchError, LatchManager, LatchMode};
+
+    #[test]
+    fn test_latch_order() {
+        let latches = LatchManager::new();
+
+        let root = latches.acquire(1, 0, LatchMode::Exclusive).unwrap();
+        let child = latches.acquire(2, 1, LatchMode::Shared).unwrap();
+        let sibling = latches.acquire(3, 1, LatchMode::Shared).unwrap();
+
+        // Upwards and leftwards are refused
+        let have = latches.acquire(5, 0, LatchMode::Shared);
+        assert!(matches!(have, Err(LatchError::OrderViolation { held: 2, .. })));
+        drop(sibling);
+        let have = latches.acquire(1, 1, LatchMode::Shared);
+        assert!(matches!(have, Err(LatchError::OrderViolation { held: 2, .. })));
+
+        drop(child);
+        latches.acquire(5, 0, LatchMode::Shared).unwrap();
+        drop(root);
+    }
+
+    #[test]
+    fn test_latch_timeout() {
+        let latches = LatchManager::new();
+
+        let (tx, rx) = mpsc::channel();
+        let (done_tx, done_rx) = mpsc::channel::<()>();
+        let latches = &latches;
+        thread::scope(|s| {
+            let holder = s.spawn(move || {
+                let _shared = latches.acquire(1, 0, LatchMode::Shared).unwrap();
+                tx.send(()).unwrap();
+                done_rx.recv().unwrap();
+            });
+            rx.recv().unwrap();
+
+            // Shared latches are compatible
+            drop(latches.acquire(1, 0, LatchMode::Shared).unwrap());
+
+            let have = latches.try_acquire(1, 0, LatchMode::Exclusive, Duration::from_millis(20));
+            let graph = match have {
+                Err(LatchError::Timeout {
+                    latch: 1, graph, ..
+                }) => graph,
+                _ => panic!("Want: timeout\nHave: {:?}", have.err()),
+            };
+
+            let want = holder.thread().id();
+            assert!(graph.edges.len() == 1, "Have: {graph}");
+            assert!(graph.edges[0].holder == want, "Have: {graph}");
+            assert!(graph.edges[0].waiter == thread::current().id(), "Have: {graph}");
+            assert!(!graph.has_cycle());
+
+            done_tx.send(()).unwrap();
+        });
+
+        latches
+            .try_acquire(1, 0, LatchMode::Exclusive, Duration::from_millis(20))
+            .unwrap();
+        assert!(latches.wait_for_graph().edges.is_empty());
+    }
+
+    #[test]
+    fn test_latch_released_elsewhere() {
+        let latches = LatchManager::new();
+
+        // Dropped by a thread holding a latch of its own, the guard still releases the latch for
+        // the thread that acquired it
+        let guard = latches.acquire(2, 1, LatchMode::Exclusive).unwrap();
+        thread::scope(|s| {
+            s.spawn(|| {
+                let _held = latches.acquire(3, 1, LatchMode::Shared).unwrap();
+                drop(guard);
+            });
+        });
+
+        let _root = latches
+            .try_acquire(1, 0, LatchMode::Exclusive, Duration::from_millis(20))
+            .unwrap();
+        latches
+            .try_acquire(2, 1, LatchMode::Exclusive, Duration::from_millis(20))
+            .unwrap();
+    }
+}
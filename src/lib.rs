@@ -1,6 +1,99 @@
+#[cfg(feature = "tokio")]
+pub mod aio;
+#[cfg(all(feature = "hugepages", target_os = "linux"))]
+pub mod arena;
+#[cfg(feature = "arrow")]
+pub mod arrow;
+#[cfg(feature = "fs")]
+pub mod backup;
+pub mod batch;
+pub mod betree;
 pub mod btree;
+#[cfg(feature = "fs")]
+pub mod buffer;
+pub mod bytes;
+pub mod cache;
+pub mod collate;
+pub mod compare;
+#[cfg(feature = "fs")]
+pub mod compress;
+pub mod concurrent;
+#[cfg(feature = "fs")]
+pub mod crypt;
+pub mod csv;
+pub mod diff;
+#[cfg(feature = "fs")]
+pub mod disk;
+pub mod display;
+mod dot;
+mod dump;
+pub mod epoch;
+#[cfg(feature = "fs")]
+pub mod fault;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod float;
+#[cfg(feature = "fs")]
+pub mod format;
+pub mod frozen;
+pub mod index;
+pub mod intern;
+pub mod iter;
+pub mod key;
+pub mod latch;
+pub mod lsm;
+#[cfg(feature = "fs")]
+pub mod maintain;
+#[cfg(feature = "fs")]
+pub mod mapped;
+pub mod metrics;
+pub mod mvcc;
 pub mod node;
+pub mod observe;
+#[cfg(any(test, feature = "test-util"))]
+pub mod oracle;
+pub mod page;
+#[cfg(feature = "fs")]
+pub mod paged;
+#[cfg(feature = "rayon")]
+pub mod par;
+pub mod persistent;
+pub mod quantile;
+pub mod replacer;
+#[cfg(feature = "fs")]
+pub mod run;
+#[cfg(feature = "rand")]
+mod sample;
+#[cfg(feature = "fs")]
+pub mod scan;
+pub mod seqlock;
+#[cfg(feature = "serde")]
+mod serialize;
+pub mod set;
+mod shadow;
+pub mod sharded;
+pub mod sizing;
 pub mod slot;
+mod snapshot;
+pub mod stats;
+#[cfg(feature = "fs")]
+pub mod store;
+mod sync;
+#[cfg(feature = "fs")]
+pub mod tier;
+#[cfg(feature = "fs")]
+pub mod tombstone;
+mod trace;
+pub mod trigger;
+pub mod ttl;
+pub mod txn;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+mod uring;
+#[cfg(feature = "fs")]
+pub mod wal;
+pub mod watch;
+#[cfg(any(test, feature = "test-util"))]
+pub mod workload;
 
 #[macro_export]
 macro_rules! get_left {
@@ -0,0 +1,166 @@
+use std::fmt::Debug;
+use std::iter::{FusedIterator, Peekable};
+use std::ops::RangeBounds;
+
+use crate::btree::{BTree, Increment};
+use crate::frozen::FrozenBTree;
+use crate::iter::Range;
+use crate::slot::{Either, Slot};
+
+/// A read-mostly tree in two levels: a base bulk-loaded once and never changed, and a small delta
+/// taking the writes since. Reads look in the delta first, then the base.
+///
+/// `merge` folds the delta into a new base, so writes are best batched between merges.
+pub struct LsmBTree<K, V> {
+    base: FrozenBTree<K, V>,
+    // `None` marks a delete of a key that may be in the base
+    delta: BTree<K, Option<V>>,
+    max: usize,
+}
+
+impl<K, V> LsmBTree<K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment,
+    V: Clone + Copy + Debug + Eq,
+{
+    pub fn new(max: usize) -> Self {
+        Self::bulk_load(max, [])
+    }
+
+    /// Loads the base from entries in ascending key order, see `BTree::bulk_load`.
+    pub fn bulk_load<I: IntoIterator<Item = (K, V)>>(max: usize, entries: I) -> Self {
+        Self {
+            base: BTree::bulk_load(max, entries).freeze(),
+            delta: BTree::new(max),
+            max,
+        }
+    }
+
+    pub fn insert(&mut self, key: K, value: V) {
+        self.delta.insert(Slot::new_leaf(key, Some(value)));
+    }
+
+    pub fn delete(&mut self, key: K) {
+        self.delta.insert(Slot::new_leaf(key, None));
+    }
+
+    pub fn get(&self, key: K) -> Option<V> {
+        if let Some(slot) = self.delta.get(key) {
+            return match slot.1 {
+                Either::Left(v) => v,
+                Either::Right(_) => unreachable!(),
+            };
+        }
+
+        match self.base.get(key)?.1 {
+            Either::Left(v) => Some(v),
+            Either::Right(_) => unreachable!(),
+        }
+    }
+
+    /// Returns an iterator over the entries with keys in `range`, in order, merging the delta
+    /// over the base.
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> LsmRange<'_, K, V> {
+        let range = (range.start_bound().cloned(), range.end_bound().cloned());
+
+        LsmRange {
+            delta: self.delta.range(range).peekable(),
+            base: self.base.range(range).peekable(),
+        }
+    }
+
+    pub fn iter(&self) -> LsmRange<'_, K, V> {
+        self.range(..)
+    }
+
+    /// The number of writes waiting in the delta.
+    pub fn delta_len(&self) -> usize {
+        self.delta.iter().count()
+    }
+
+    /// Replaces the base with one bulk-loaded from the merged entries and empties the delta.
+    pub fn merge(&mut self) {
+        let merged = BTree::bulk_load(self.max, self.iter());
+        self.base = merged.freeze();
+        self.delta = BTree::new(self.max);
+    }
+
+    pub fn base(&self) -> &FrozenBTree<K, V> {
+        &self.base
+    }
+}
+
+pub struct LsmRange<'a, K, V>
+where
+    K: Copy + Ord,
+    V: Copy,
+{
+    delta: Peekable<Range<'a, K, Option<V>>>,
+    base: Peekable<Range<'a, K, V>>,
+}
+
+impl<K, V> Iterator for LsmRange<'_, K, V>
+where
+    K: Copy + Ord,
+    V: Copy,
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (k, v) = match (self.delta.peek(), self.base.peek()) {
+                (Some(d), Some(b)) if d.0 < b.0 => self.delta.next()?,
+                (Some(d), Some(b)) if d.0 == b.0 => {
+                    self.base.next();
+                    self.delta.next()?
+                }
+                (_, Some(_)) => return self.base.next(),
+                (Some(_), None) => self.delta.next()?,
+                (None, None) => return None,
+            };
+
+            if let Some(v) = v {
+                return Some((k, v));
+            }
+        }
+    }
+}
+
+impl<K: Copy + Ord, V: Copy> FusedIterator for LsmRange<'_, K, V> {}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use super::LsmBTree;
+
+    #[test]
+    fn test_lsm() {
+        let mut tree = LsmBTree::bulk_load(8, (0..1000u32).map(|k| (k, k)));
+        let mut want = (0..1000u32).map(|k| (k, k)).collect::<BTreeMap<_, _>>();
+        for k in (0..1200).step_by(7) {
+            tree.insert(k, k + 1);
+            want.insert(k, k + 1);
+        }
+        for k in (0..1100).step_by(5) {
+            tree.delete(k);
+            want.remove(&k);
+        }
+
+        assert!(tree.get(7) == Some(8) && tree.get(35).is_none() && tree.get(1) == Some(1));
+        let have = tree.range(100..=300).collect::<Vec<_>>();
+        let want_range = want
+            .range(100..=300)
+            .map(|(k, v)| (*k, *v))
+            .collect::<Vec<_>>();
+        assert!(want_range == have, "Want: {:?}\nHave: {:?}", want_range, have);
+
+        tree.merge();
+        assert!(tree.delta_len() == 0);
+        let want = want.into_iter().collect::<Vec<_>>();
+        let have = tree.iter().collect::<Vec<_>>();
+        assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+        let have = tree.base().iter().collect::<Vec<_>>();
+        assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+    }
+}
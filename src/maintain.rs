@@ -0,0 +1,359 @@
+use std::fmt::Debug;
+use std::io;
+use std::ops::RangeBounds;
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::btree::Increment;
+use crate::page::Encode;
+use crate::paged::PagedBTree;
+use crate::wal::Wal;
+
+/// How the maintenance worker of a `MaintainedPagedBTree` paces itself.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct MaintenanceOptions {
+    /// How often the worker wakes up.
+    pub interval: Duration,
+    /// How long the tree must go without an operation before the worker does anything.
+    pub idle: Duration,
+    /// The most pages written back, and the most leaves merged, each time the worker wakes up.
+    pub pages: usize,
+    /// Checkpoints once the log holds more than this many bytes.
+    pub checkpoint_bytes: u64,
+}
+
+impl Default for MaintenanceOptions {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_millis(100),
+            idle: Duration::from_millis(50),
+            pages: 64,
+            checkpoint_bytes: 4 << 20,
+        }
+    }
+}
+
+/// What the maintenance worker has done so far.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct MaintenanceStats {
+    /// Times the worker found the tree idle and did a round of work.
+    pub rounds: u64,
+    pub flushed: u64,
+    pub checkpoints: u64,
+    /// Pages freed by merging leaves.
+    pub merged: u64,
+    /// Versions dropped by the jobs from `prune_with()`.
+    pub pruned: u64,
+}
+
+type Prune = Box<dyn FnMut() -> usize + Send>;
+
+struct State {
+    /// `None` while the worker is stopped.
+    options: Option<MaintenanceOptions>,
+    last_op: Instant,
+    stats: MaintenanceStats,
+    // Set by the worker, returned by the next `stop()`
+    error: Option<io::Error>,
+}
+
+struct Shared<K, V> {
+    tree: RwLock<PagedBTree<K, V>>,
+    wal: Option<Arc<Wal>>,
+    state: Mutex<State>,
+    // Wakes the worker when it is stopped
+    wake: Condvar,
+    prune: Mutex<Vec<Prune>>,
+}
+
+/// A `PagedBTree` with a background worker that tidies it up while it is idle.
+///
+/// Once started, the worker wakes up every `interval` and, if no operation has run for `idle`,
+/// writes back dirty pages, checkpoints a large log, merges underfull leaves and runs the jobs
+/// from `prune_with()`, such as `MvccBTree::gc()`. It never waits for the tree, a round is skipped
+/// if an operation is running. Reads share the tree, writes take it exclusively.
+pub struct MaintainedPagedBTree<K, V> {
+    shared: Arc<Shared<K, V>>,
+    worker: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl<K, V> MaintainedPagedBTree<K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment + Encode + Send + Sync + 'static,
+    V: Clone + Copy + Debug + Encode + Send + Sync + 'static,
+{
+    /// Wraps `tree` with the worker stopped.
+    pub fn new(mut tree: PagedBTree<K, V>) -> Self {
+        let wal = tree.defer_sync(true);
+        Self {
+            shared: Arc::new(Shared {
+                tree: RwLock::new(tree),
+                wal,
+                state: Mutex::new(State {
+                    options: None,
+                    last_op: Instant::now(),
+                    stats: MaintenanceStats::default(),
+                    error: None,
+                }),
+                wake: Condvar::new(),
+                prune: Mutex::new(Vec::new()),
+            }),
+            worker: Mutex::new(None),
+        }
+    }
+
+    /// Starts the worker, or changes the options of a running one.
+    pub fn start(&self, options: MaintenanceOptions) {
+        self.shared.state.lock().unwrap().options = Some(options);
+        self.shared.wake.notify_one();
+
+        let mut worker = self.worker.lock().unwrap();
+        if worker.is_none() {
+            let shared = self.shared.clone();
+            *worker = Some(thread::spawn(move || shared.worker()));
+        }
+    }
+
+    /// Stops the worker, waiting for a round in progress. Returns the error the worker last ran
+    /// into, if any.
+    pub fn stop(&self) -> io::Result<()> {
+        self.shared.state.lock().unwrap().options = None;
+        self.shared.wake.notify_one();
+        if let Some(worker) = self.worker.lock().unwrap().take() {
+            let _ = worker.join();
+        }
+
+        match self.shared.state.lock().unwrap().error.take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.worker.lock().unwrap().is_some()
+    }
+
+    pub fn stats(&self) -> MaintenanceStats {
+        self.shared.state.lock().unwrap().stats
+    }
+
+    /// Runs `prune` every round, it returns the number of versions dropped.
+    pub fn prune_with<F: FnMut() -> usize + Send + 'static>(&self, prune: F) {
+        self.shared.prune.lock().unwrap().push(Box::new(prune));
+    }
+
+    /// Runs `f` with shared access to the tree.
+    pub fn read<T>(&self, f: impl FnOnce(&PagedBTree<K, V>) -> T) -> T {
+        let tree = self.shared.tree.read().unwrap();
+        let out = f(&tree);
+        self.shared.state.lock().unwrap().last_op = Instant::now();
+        out
+    }
+
+    /// Runs `f` with exclusive access to the tree. Under `SyncPolicy::Group` the commits of
+    /// writers running together are synced at once, after the tree is released.
+    pub fn write<T>(
+        &self,
+        f: impl FnOnce(&mut PagedBTree<K, V>) -> io::Result<T>,
+    ) -> io::Result<T> {
+        let mut tree = self.shared.tree.write().unwrap();
+        let out = f(&mut tree)?;
+        drop(tree);
+
+        self.shared.state.lock().unwrap().last_op = Instant::now();
+        self.shared.wait_durable()?;
+        Ok(out)
+    }
+
+    pub fn get(&self, key: K) -> io::Result<Option<V>> {
+        self.read(|tree| tree.get(key))
+    }
+
+    pub fn insert(&self, key: K, value: V) -> io::Result<Option<V>> {
+        self.write(|tree| tree.insert(key, value))
+    }
+
+    pub fn delete(&self, key: K) -> io::Result<Option<V>> {
+        self.write(|tree| tree.delete(key))
+    }
+
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> io::Result<Vec<(K, V)>> {
+        self.read(|tree| tree.range(range))
+    }
+
+    pub fn len(&self) -> usize {
+        self.read(|tree| tree.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Stops the worker and returns the tree.
+    pub fn into_inner(self) -> io::Result<PagedBTree<K, V>> {
+        self.stop()?;
+        let shared = self.shared.clone();
+        drop(self);
+
+        // The worker held the only other reference
+        let shared = Arc::try_unwrap(shared).ok().unwrap();
+        let mut tree = shared.tree.into_inner().unwrap();
+        tree.defer_sync(false);
+        Ok(tree)
+    }
+}
+
+impl<K, V> Shared<K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment + Encode,
+    V: Clone + Copy + Debug + Encode,
+{
+    fn wait_durable(&self) -> io::Result<()> {
+        match &self.wal {
+            Some(wal) => wal.wait_durable(wal.committed()),
+            None => Ok(()),
+        }
+    }
+
+    fn worker(&self) {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            let options = match state.options {
+                Some(options) => options,
+                None => return,
+            };
+            state = self.wake.wait_timeout(state, options.interval).unwrap().0;
+
+            let options = match state.options {
+                Some(options) => options,
+                None => return,
+            };
+            if state.last_op.elapsed() < options.idle {
+                continue;
+            }
+
+            drop(state);
+            let round = self.round(&options);
+            state = self.state.lock().unwrap();
+            match round {
+                Ok(Some(round)) => {
+                    let stats = &mut state.stats;
+                    stats.rounds += 1;
+                    stats.flushed += round.flushed;
+                    stats.checkpoints += round.checkpoints;
+                    stats.merged += round.merged;
+                    stats.pruned += round.pruned;
+                }
+                Ok(None) => {}
+                Err(e) => state.error = Some(e),
+            }
+        }
+    }
+
+    /// Does a round of work unless an operation is running.
+    fn round(&self, options: &MaintenanceOptions) -> io::Result<Option<MaintenanceStats>> {
+        let mut round = MaintenanceStats::default();
+        {
+            let mut tree = match self.tree.try_write() {
+                Ok(tree) => tree,
+                Err(_) => return Ok(None),
+            };
+
+            round.merged = tree.merge_underfull(options.pages)? as u64;
+            round.flushed = tree.pool().flush_some(options.pages)? as u64;
+            if tree.log_size() > options.checkpoint_bytes {
+                tree.checkpoint()?;
+                round.checkpoints = 1;
+            }
+        }
+        self.wait_durable()?;
+
+        // Without the tree, so operations can run meanwhile
+        for prune in self.prune.lock().unwrap().iter_mut() {
+            round.pruned += prune() as u64;
+        }
+
+        Ok(Some(round))
+    }
+}
+
+impl<K, V> Drop for MaintainedPagedBTree<K, V> {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().options = None;
+        self.shared.wake.notify_one();
+        if let Some(worker) = self.worker.lock().unwrap().take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use crate::paged::{Options, PagedBTree};
+    use crate::wal::SyncPolicy;
+
+    use super::{MaintainedPagedBTree, MaintenanceOptions};
+
+    #[test]
+    fn test_maintenance() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree");
+        let options = Options {
+            wal: Some(SyncPolicy::PerCommit),
+            ..Default::default()
+        };
+
+        let tree = PagedBTree::<u32, u64>::create_with_options(&path, 8, options).unwrap();
+        let tree = MaintainedPagedBTree::new(tree);
+        for k in 0..2000 {
+            tree.insert(k, k as u64).unwrap();
+        }
+        for k in (0..2000).filter(|k| k % 8 != 0) {
+            tree.delete(k).unwrap();
+        }
+        let free = tree.read(|tree| tree.space().free_pages);
+
+        let runs = Arc::new(AtomicUsize::new(0));
+        let counter = runs.clone();
+        tree.prune_with(move || counter.fetch_add(1, Ordering::Relaxed));
+
+        tree.start(MaintenanceOptions {
+            interval: Duration::from_millis(1),
+            idle: Duration::from_millis(1),
+            pages: 16,
+            checkpoint_bytes: 0,
+        });
+        assert!(tree.is_running());
+        let start = Instant::now();
+        while tree.stats().merged < 100 || tree.read(|tree| tree.pool().dirty_pages()) > 0 {
+            assert!(start.elapsed() < Duration::from_secs(10), "{:?}", tree.stats());
+            thread::sleep(Duration::from_millis(5));
+        }
+        tree.stop().unwrap();
+        assert!(!tree.is_running());
+
+        let stats = tree.stats();
+        assert!(stats.checkpoints > 0 && stats.flushed > 0, "{:?}", stats);
+        assert!(runs.load(Ordering::Relaxed) as u64 >= stats.rounds);
+        let have = tree.read(|tree| tree.space().free_pages);
+        assert!(have >= free + 100, "Want: >= {}\nHave: {}", free + 100, have);
+
+        let want = (0..2000)
+            .step_by(8)
+            .map(|k| (k, k as u64))
+            .collect::<Vec<_>>();
+        let have = tree.range(..).unwrap();
+        assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+
+        drop(tree.into_inner().unwrap());
+        let tree = PagedBTree::<u32, u64>::open_with_options(&path, options).unwrap();
+        let have = tree.iter().unwrap();
+        assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+    }
+}
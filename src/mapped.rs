@@ -0,0 +1,273 @@
+use std::fmt::Debug;
+use std::fs::{self, File};
+use std::io;
+use std::marker::PhantomData;
+use std::ops::{Bound, RangeBounds};
+use std::path::Path;
+
+use memmap2::{Advice, Mmap, MmapOptions};
+
+use crate::btree::Increment;
+use crate::compress::MAGIC;
+use crate::format;
+use crate::page::{self, Encode, PageBuf, PageId, PageNode, PAGE_SIZE};
+use crate::paged::{is_before_end, wal_path, Meta};
+use crate::wal::FILE_HEADER;
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// How pages of a `MappedBTree` are expected to be accessed, passed on to the kernel.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum Access {
+    #[default]
+    Normal,
+    /// Reads ahead aggressively, for scans.
+    Sequential,
+    /// Disables read ahead, for point lookups.
+    Random,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct MapOptions {
+    pub access: Access,
+    /// Reads the whole file in when it is mapped, so no lookup faults.
+    pub prefault: bool,
+    /// Checks every page against its checksum when the file is mapped.
+    pub verify: bool,
+}
+
+/// A read-only `PagedBTree` file mapped into memory.
+///
+/// Pages are read straight from the mapping without a `BufferPool` or copies, the kernel's page
+/// cache decides what stays in memory. The file must not be modified while it is mapped, and a
+/// tree with a log still to recover has to be opened by `PagedBTree` first.
+pub struct MappedBTree<K, V> {
+    map: Mmap,
+    root: Option<PageId>,
+    len: u64,
+    _types: PhantomData<(K, V)>,
+}
+
+impl<K, V> MappedBTree<K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment + Encode,
+    V: Clone + Copy + Debug + Encode,
+{
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::open_with_options(path, MapOptions::default())
+    }
+
+    pub fn open_with_options<P: AsRef<Path>>(path: P, options: MapOptions) -> io::Result<Self> {
+        let path = path.as_ref();
+
+        // Only the header means every change in the log reached the file
+        match fs::metadata(wal_path(path)) {
+            Ok(wal) if wal.len() > FILE_HEADER => {
+                return Err(invalid("tree has a log to recover".into()));
+            }
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+
+        format::check(path)?;
+        let file = File::open(path)?;
+        let mut map = MmapOptions::new();
+        if options.prefault {
+            map.populate();
+        }
+        // SAFETY: the file isn't modified while it is mapped, as documented
+        let map = unsafe { map.map(&file)? };
+
+        if map.starts_with(MAGIC) {
+            return Err(invalid("compressed or encrypted trees can't be mapped".into()));
+        }
+        if map.len() < PAGE_SIZE || map.len() % PAGE_SIZE != 0 {
+            return Err(invalid(format!(
+                "file length {} is not a whole number of pages",
+                map.len()
+            )));
+        }
+        map.advise(match options.access {
+            Access::Normal => Advice::Normal,
+            Access::Sequential => Advice::Sequential,
+            Access::Random => Advice::Random,
+        })?;
+
+        let tree = Self {
+            root: None,
+            len: 0,
+            map,
+            _types: PhantomData,
+        };
+        if options.verify {
+            for id in 0..tree.pages() {
+                if !page::verify(tree.page(PageId(id))?) {
+                    return Err(invalid(format!("page {id} does not match its checksum")));
+                }
+            }
+        }
+
+        let meta = Meta::decode::<K, V>(tree.page(PageId::META)?)?;
+        Ok(Self {
+            root: meta.root,
+            len: meta.len,
+            ..tree
+        })
+    }
+
+    fn pages(&self) -> u64 {
+        (self.map.len() / PAGE_SIZE) as u64
+    }
+
+    fn page(&self, id: PageId) -> io::Result<&PageBuf> {
+        if id.0 >= self.pages() {
+            return Err(invalid(format!("page {} is past the end of the file", id.0)));
+        }
+
+        let at = id.0 as usize * PAGE_SIZE;
+        Ok(self.map[at..at + PAGE_SIZE].try_into().unwrap())
+    }
+
+    /// Returns the leaf `key` belongs in, or `None` if it is greater than every separator.
+    fn find_leaf(&self, key: K) -> io::Result<Option<&PageBuf>> {
+        let mut id = match self.root {
+            Some(root) => root,
+            None => return Ok(None),
+        };
+
+        loop {
+            let page = self.page(id)?;
+            if PageNode::<K, V>::is_leaf(page) {
+                return Ok(Some(page));
+            }
+
+            id = match PageNode::<K, V>::find_child(page, key) {
+                Some(child) => child,
+                None => return Ok(None),
+            };
+        }
+    }
+
+    pub fn get(&self, key: K) -> io::Result<Option<V>> {
+        Ok(self
+            .find_leaf(key)?
+            .and_then(|leaf| PageNode::<K, V>::find_entry(leaf, key)))
+    }
+
+    /// Returns the entries with keys in `range`, in order, following the leaf chain.
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> io::Result<Vec<(K, V)>> {
+        let start = match range.start_bound() {
+            Bound::Included(k) | Bound::Excluded(k) => self.find_leaf(*k)?,
+            Bound::Unbounded => self.leftmost_leaf()?,
+        };
+
+        let mut out = Vec::new();
+        let mut leaf = match start {
+            Some(page) => Some(PageNode::decode(page)?),
+            None => None,
+        };
+        while let Some(PageNode::Leaf { entries, next }) = leaf {
+            for (k, v) in entries {
+                if !is_before_end(&range, k) {
+                    return Ok(out);
+                }
+                if range.contains(&k) {
+                    out.push((k, v));
+                }
+            }
+
+            leaf = match next {
+                Some(id) => Some(PageNode::decode(self.page(id)?)?),
+                None => None,
+            };
+        }
+
+        Ok(out)
+    }
+
+    pub fn iter(&self) -> io::Result<Vec<(K, V)>> {
+        self.range(..)
+    }
+
+    fn leftmost_leaf(&self) -> io::Result<Option<&PageBuf>> {
+        let mut id = match self.root {
+            Some(root) => root,
+            None => return Ok(None),
+        };
+
+        loop {
+            let page = self.page(id)?;
+            match PageNode::<K, V>::decode(page)? {
+                PageNode::Leaf { .. } => return Ok(Some(page)),
+                PageNode::Internal(children) => id = children[0].1,
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::os::unix::fs::FileExt;
+
+    use crate::page::PAGE_SIZE;
+    use crate::paged::{Options, PagedBTree};
+    use crate::wal::SyncPolicy;
+
+    use super::{Access, MapOptions, MappedBTree};
+
+    #[test]
+    fn test_mapped_btree() {
+        const MAX: usize = 8;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree");
+
+        let options = Options {
+            wal: Some(SyncPolicy::Off),
+            ..Default::default()
+        };
+        let mut tree = PagedBTree::create_with_options(&path, MAX, options).unwrap();
+        for k in (0..1000u32).rev() {
+            tree.insert(k, k as u64 * 2).unwrap();
+        }
+        tree.sync().unwrap();
+
+        // The changes are only in the log
+        assert!(MappedBTree::<u32, u64>::open(&path).is_err());
+        tree.checkpoint().unwrap();
+
+        let options = MapOptions {
+            access: Access::Sequential,
+            prefault: true,
+            verify: true,
+        };
+        let mapped = MappedBTree::<u32, u64>::open_with_options(&path, options).unwrap();
+        assert!(mapped.len() == 1000);
+        for k in 0..1001u32 {
+            let want = tree.get(k).unwrap();
+            let have = mapped.get(k).unwrap();
+            assert!(want == have, "Key: {k}\nWant: {:?}\nHave: {:?}", want, have);
+        }
+        let want = tree.range(100..=900).unwrap();
+        let have = mapped.range(100..=900).unwrap();
+        assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+        assert!(mapped.iter().unwrap().len() == 1000);
+        drop(mapped);
+        drop(tree);
+
+        let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.write_all_at(&[0xff], PAGE_SIZE as u64 + 100).unwrap();
+        assert!(MappedBTree::<u32, u64>::open(&path).is_ok());
+        assert!(MappedBTree::<u32, u64>::open_with_options(&path, options).is_err());
+    }
+}
@@ -0,0 +1,339 @@
+//! Process-wide counters of what every tree in the process does, for monitoring systems to
+//! scrape through `snapshot()`. Each count is a relaxed atomic add.
+//!
+//! Operations can also be timed into a latency histogram per kind of operation, read as
+//! percentiles through `latencies()`. Timing takes two clock reads per operation, so it is off
+//! until `record_latencies(true)`.
+//!
+//! Counting and timing are done with the `metrics` feature, on by default. Without it they
+//! compile out, `snapshot()` is always zero and `latencies()` always empty.
+
+#[cfg(feature = "metrics")]
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+#[cfg(feature = "metrics")]
+use std::time::Instant;
+
+// The merge and pool counters are only counted by the `fs` types
+#[cfg_attr(not(feature = "fs"), allow(dead_code))]
+#[derive(Clone, Copy)]
+pub(crate) enum Counter {
+    Inserts,
+    Gets,
+    Deletes,
+    Splits,
+    Merges,
+    Restarts,
+    PoolHits,
+    PoolMisses,
+}
+
+#[cfg(feature = "metrics")]
+static COUNTERS: [AtomicU64; 8] = [const { AtomicU64::new(0) }; 8];
+
+// Scans are only timed by the `fs` types
+#[cfg_attr(not(feature = "fs"), allow(dead_code))]
+#[derive(Clone, Copy)]
+pub(crate) enum Op {
+    Get,
+    Insert,
+    Delete,
+    Scan,
+}
+
+// Latencies in nanoseconds are bucketed by their highest set bit and the `SUB_BITS` bits after it,
+// so a bucket is within 1/16 of the values in it, HDR histogram style. Values below
+// `1 << SUB_BITS` get a bucket each.
+const SUB_BITS: u32 = 4;
+const SUB_BUCKETS: usize = 1 << SUB_BITS;
+const BUCKETS: usize = (64 - SUB_BITS as usize + 1) * SUB_BUCKETS;
+
+#[cfg(feature = "metrics")]
+static TIMING: AtomicBool = AtomicBool::new(false);
+
+#[cfg(feature = "metrics")]
+static HISTOGRAMS: [[AtomicU64; BUCKETS]; 4] =
+    [const { [const { AtomicU64::new(0) }; BUCKETS] }; 4];
+
+#[cfg_attr(not(feature = "metrics"), allow(dead_code))]
+fn bucket(nanos: u64) -> usize {
+    if nanos < SUB_BUCKETS as u64 {
+        return nanos as usize;
+    }
+
+    let high = 63 - nanos.leading_zeros();
+    let sub = (nanos >> (high - SUB_BITS)) as usize & (SUB_BUCKETS - 1);
+    (high - SUB_BITS + 1) as usize * SUB_BUCKETS + sub
+}
+
+/// The greatest value that falls in `bucket`.
+fn bucket_max(bucket: usize) -> u64 {
+    if bucket < SUB_BUCKETS {
+        return bucket as u64;
+    }
+
+    let shift = (bucket / SUB_BUCKETS) as u32 - 1;
+    let lower = ((SUB_BUCKETS + bucket % SUB_BUCKETS) as u64) << shift;
+    lower + ((1 << shift) - 1)
+}
+
+/// The counters at one point, since the process started or they were last `reset()`.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct Metrics {
+    pub inserts: u64,
+    pub gets: u64,
+    pub deletes: u64,
+    /// Nodes split, of in-memory and paged trees.
+    pub splits: u64,
+    /// Leaves merged by `PagedBTree::merge_underfull()`.
+    pub merges: u64,
+    /// Reads of a `ConcurrentBTree` node retried because a write overlapped them.
+    pub restarts: u64,
+    pub pool_hits: u64,
+    pub pool_misses: u64,
+}
+
+impl Metrics {
+    /// The counts between `earlier` and `self`.
+    pub fn since(&self, earlier: &Metrics) -> Metrics {
+        Metrics {
+            inserts: self.inserts - earlier.inserts,
+            gets: self.gets - earlier.gets,
+            deletes: self.deletes - earlier.deletes,
+            splits: self.splits - earlier.splits,
+            merges: self.merges - earlier.merges,
+            restarts: self.restarts - earlier.restarts,
+            pool_hits: self.pool_hits - earlier.pool_hits,
+            pool_misses: self.pool_misses - earlier.pool_misses,
+        }
+    }
+}
+
+#[cfg(feature = "metrics")]
+#[inline(always)]
+pub(crate) fn count(counter: Counter) {
+    COUNTERS[counter as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+#[cfg(not(feature = "metrics"))]
+#[inline(always)]
+pub(crate) fn count(_: Counter) {}
+
+#[cfg(feature = "metrics")]
+pub fn snapshot() -> Metrics {
+    let load = |counter: Counter| COUNTERS[counter as usize].load(Ordering::Relaxed);
+    Metrics {
+        inserts: load(Counter::Inserts),
+        gets: load(Counter::Gets),
+        deletes: load(Counter::Deletes),
+        splits: load(Counter::Splits),
+        merges: load(Counter::Merges),
+        restarts: load(Counter::Restarts),
+        pool_hits: load(Counter::PoolHits),
+        pool_misses: load(Counter::PoolMisses),
+    }
+}
+
+#[cfg(not(feature = "metrics"))]
+pub fn snapshot() -> Metrics {
+    Metrics::default()
+}
+
+/// Times the operation until it is dropped, if latencies are being recorded.
+#[cfg(feature = "metrics")]
+pub(crate) struct Timer(Option<(Op, Instant)>);
+
+#[cfg(feature = "metrics")]
+impl Drop for Timer {
+    fn drop(&mut self) {
+        if let Some((op, start)) = self.0 {
+            let nanos = start.elapsed().as_nanos().min(u64::MAX as u128) as u64;
+            HISTOGRAMS[op as usize][bucket(nanos)].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(feature = "metrics")]
+#[inline(always)]
+pub(crate) fn time(op: Op) -> Timer {
+    Timer(TIMING.load(Ordering::Relaxed).then(|| (op, Instant::now())))
+}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) struct Timer;
+
+#[cfg(not(feature = "metrics"))]
+#[inline(always)]
+pub(crate) fn time(_: Op) -> Timer {
+    Timer
+}
+
+/// Starts or stops timing operations into `latencies()`. Needs a clock, which
+/// wasm32-unknown-unknown doesn't have.
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+pub fn record_latencies(on: bool) {
+    #[cfg(feature = "metrics")]
+    TIMING.store(on, Ordering::Relaxed);
+}
+
+/// The latencies of one kind of operation.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Histogram {
+    counts: Vec<u64>,
+}
+
+impl Histogram {
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// The latency `p` percent of operations took at most, overestimated by up to 1/16. Zero
+    /// without any operations.
+    pub fn percentile(&self, p: f64) -> Duration {
+        let rank = ((p / 100.0 * self.count() as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bucket, n) in self.counts.iter().enumerate() {
+            seen += n;
+            if seen >= rank {
+                return Duration::from_nanos(bucket_max(bucket));
+            }
+        }
+
+        Duration::ZERO
+    }
+
+    pub fn max(&self) -> Duration {
+        self.percentile(100.0)
+    }
+}
+
+/// Latencies per kind of operation, of every tree in the process since timing started or they
+/// were last `reset()`. Scans are timed for `PagedBTree::range()`, which reads the whole range.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Latencies {
+    pub get: Histogram,
+    pub insert: Histogram,
+    pub delete: Histogram,
+    pub scan: Histogram,
+}
+
+pub fn latencies() -> Latencies {
+    #[cfg(feature = "metrics")]
+    let histogram = |op: Op| Histogram {
+        counts: HISTOGRAMS[op as usize]
+            .iter()
+            .map(|n| n.load(Ordering::Relaxed))
+            .collect(),
+    };
+
+    #[cfg(not(feature = "metrics"))]
+    let histogram = |_: Op| Histogram {
+        counts: vec![0; BUCKETS],
+    };
+
+    Latencies {
+        get: histogram(Op::Get),
+        insert: histogram(Op::Insert),
+        delete: histogram(Op::Delete),
+        scan: histogram(Op::Scan),
+    }
+}
+
+/// Sets every counter and latency to zero. Counts made while resetting may be lost, compare snapshots with
+/// `Metrics::since()` to count from a point without losing any.
+pub fn reset() {
+    #[cfg(feature = "metrics")]
+    for counter in COUNTERS.iter().chain(HISTOGRAMS.iter().flatten()) {
+        counter.store(0, Ordering::Relaxed);
+    }
+}
+
+#[cfg(all(test, feature = "metrics"))]
+mod test {
+    use std::sync::Arc;
+    use std::thread;
+
+    use crate::btree::BTree;
+    #[cfg(feature = "fs")]
+    use crate::buffer::Capacity;
+    use crate::concurrent::ConcurrentBTree;
+    #[cfg(feature = "fs")]
+    use crate::paged::PagedBTree;
+    use crate::slot::Slot;
+
+    use super::{bucket, bucket_max, latencies, record_latencies, snapshot};
+
+    // Other tests count too, so these are lower bounds
+    #[test]
+    fn test_metrics() {
+        let start = snapshot();
+        let mut tree = BTree::new(4);
+        for k in 0..100u32 {
+            tree.insert(Slot::new_leaf(k, k));
+        }
+        for k in 0..50 {
+            tree.get(k);
+            tree.delete(k);
+        }
+        let have = snapshot().since(&start);
+        assert!(have.inserts >= 100 && have.gets >= 50 && have.deletes >= 50, "{:?}", have);
+        assert!(have.splits >= 40, "{:?}", have);
+
+        // Readers retry nodes the writer changes under them
+        let tree = Arc::new(ConcurrentBTree::new(8));
+        let start = snapshot();
+        thread::scope(|s| {
+            let reader = tree.clone();
+            s.spawn(move || {
+                for i in 0..100_000u32 {
+                    reader.get(i % 64);
+                }
+            });
+            for i in 0..20_000u32 {
+                tree.insert(Slot::new_leaf(i % 64, i));
+            }
+        });
+        let have = snapshot().since(&start);
+        assert!(have.gets >= 100_000 && have.inserts >= 20_000, "{:?}", have);
+
+        #[cfg(feature = "fs")]
+        {
+            let start = snapshot();
+            let mut tree = PagedBTree::create_in_memory(8, Capacity::Pages(16)).unwrap();
+            for k in 0..1000u32 {
+                tree.insert(k, k as u64).unwrap();
+            }
+            for k in (0..1000).filter(|k| k % 4 != 0) {
+                tree.delete(k).unwrap();
+            }
+            let merged = tree.merge_underfull(usize::MAX).unwrap() as u64;
+            let have = snapshot().since(&start);
+            assert!(have.merges >= merged && merged > 0, "{:?}", have);
+            assert!(have.pool_hits > 0 && have.pool_misses > 0, "{:?}", have);
+        }
+    }
+
+    #[test]
+    fn test_latencies() {
+        for v in (0..62).flat_map(|s| [1u64 << s, (1 << s) + 1, (1u64 << s) * 3 / 2]) {
+            let max = bucket_max(bucket(v));
+            assert!(max >= v && max - v <= v / 16, "Value: {}\nHave: {}", v, max);
+            assert!(bucket(max) == bucket(v) && bucket(max + 1) == bucket(v) + 1);
+        }
+        assert!(bucket(u64::MAX) < super::BUCKETS);
+
+        record_latencies(true);
+        let mut tree = BTree::new(8);
+        for k in 0..1000u32 {
+            tree.insert(Slot::new_leaf(k, k));
+            tree.get(k);
+        }
+        record_latencies(false);
+
+        let have = latencies();
+        assert!(have.insert.count() >= 1000 && have.get.count() >= 1000, "{:?}", have);
+        let (p50, p99) = (have.get.percentile(50.0), have.get.percentile(99.0));
+        assert!(p50 <= p99 && p99 <= have.get.max(), "p50 {:?}, p99 {:?}", p50, p99);
+        assert!(p50 > std::time::Duration::ZERO);
+    }
+}
@@ -0,0 +1,699 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Debug;
+use std::iter::FusedIterator;
+use std::ops::{Bound, RangeBounds};
+use std::sync::RwLock;
+
+use crate::btree::{BTree, Increment};
+use crate::get_left;
+use crate::slot::{Either, Slot};
+
+pub type Timestamp = u64;
+
+// Number of keys a snapshot scan visits per acquisition of the tree's lock
+const SCAN_BATCH: usize = 64;
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct Version<V> {
+    pub ts: Timestamp,
+    /// `None` marks a delete.
+    pub value: Option<V>,
+}
+
+/// Every retained version of a key, oldest first.
+#[derive(Debug, Clone)]
+pub struct VersionChain<V> {
+    versions: Vec<Version<V>>,
+}
+
+impl<V> Default for VersionChain<V> {
+    fn default() -> Self {
+        Self {
+            versions: Vec::new(),
+        }
+    }
+}
+
+impl<V: Copy> VersionChain<V> {
+    pub fn push(&mut self, version: Version<V>) {
+        match self.versions.last_mut() {
+            Some(last) if last.ts == version.ts => *last = version,
+            Some(last) => {
+                assert!(last.ts < version.ts, "versions must be appended in commit order");
+                self.versions.push(version);
+            }
+            None => self.versions.push(version),
+        }
+    }
+
+    /// Returns the newest version committed at or before `ts`.
+    pub fn at(&self, ts: Timestamp) -> Option<&Version<V>> {
+        self.versions.iter().rev().find(|v| v.ts <= ts)
+    }
+
+    pub fn latest(&self) -> Option<&Version<V>> {
+        self.versions.last()
+    }
+
+    /// Drops the versions no reader at `oldest` or later can see and returns how many were
+    /// dropped. The version visible at `oldest` is kept unless it is a delete.
+    pub fn prune(&mut self, oldest: Timestamp) -> usize {
+        let visible = match self.versions.iter().rposition(|v| v.ts <= oldest) {
+            Some(i) => i,
+            None => return 0,
+        };
+
+        let mut pruned = visible;
+        self.versions.drain(..visible);
+        if self.versions[0].value.is_none() {
+            self.versions.remove(0);
+            pruned += 1;
+        }
+
+        pruned
+    }
+
+    pub fn len(&self) -> usize {
+        self.versions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.versions.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Version<V>> {
+        self.versions.iter()
+    }
+}
+
+/// How much history `gc` keeps beyond what registered readers need.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum Retention {
+    /// Only keep the versions registered readers can see.
+    #[default]
+    Readers,
+    /// Keep enough versions to read the tree as of any of the last `n` timestamps.
+    Window(Timestamp),
+    /// Never prune.
+    All,
+}
+
+struct Entry<K, V> {
+    key: K,
+    chain: VersionChain<V>,
+}
+
+struct State<K, V> {
+    // Maps keys to their entry in `entries`
+    index: BTree<K, usize>,
+    entries: Vec<Entry<K, V>>,
+    free: Vec<usize>,
+    clock: Timestamp,
+    readers: BTreeMap<Timestamp, usize>,
+    retention: Retention,
+    // Oldest timestamp the tree can still be read at, raised by `gc`
+    horizon: Timestamp,
+}
+
+/// A tree that keeps a chain of committed versions per key.
+///
+/// Every write is stamped with the next logical timestamp. Reads at timestamp `T` see the newest
+/// version committed at or before `T`, so readers registered with `begin_read` get a stable view
+/// while writers keep appending. `gc` prunes versions no registered reader can see anymore.
+pub struct MvccBTree<K, V> {
+    state: RwLock<State<K, V>>,
+}
+
+impl<K, V> MvccBTree<K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment,
+    V: Clone + Copy + Debug + Eq,
+{
+    pub fn new(max: usize) -> Self {
+        Self::with_retention(max, Retention::default())
+    }
+
+    pub fn with_retention(max: usize, retention: Retention) -> Self {
+        Self {
+            state: RwLock::new(State {
+                index: BTree::new(max),
+                entries: Vec::new(),
+                free: Vec::new(),
+                clock: 0,
+                readers: BTreeMap::new(),
+                retention,
+                horizon: 0,
+            }),
+        }
+    }
+
+    pub fn retention(&self) -> Retention {
+        self.state.read().unwrap().retention
+    }
+
+    /// Takes effect on the next `gc`. History that was already pruned is not recovered.
+    pub fn set_retention(&self, retention: Retention) {
+        self.state.write().unwrap().retention = retention;
+    }
+
+    /// Returns the oldest timestamp `as_of` can still read the tree at.
+    pub fn horizon(&self) -> Timestamp {
+        self.state.read().unwrap().horizon
+    }
+
+    /// Returns the timestamp of the last commit.
+    pub fn now(&self) -> Timestamp {
+        self.state.read().unwrap().clock
+    }
+
+    /// Returns the commit timestamp of the new version.
+    pub fn insert(&self, key: K, value: V) -> Timestamp {
+        self.write(key, Some(value))
+    }
+
+    /// Returns the commit timestamp of the delete, or `None` if `key` has no live version.
+    pub fn delete(&self, key: K) -> Option<Timestamp> {
+        let mut state = self.state.write().unwrap();
+        let clock = state.clock;
+        Self::_get(&state, key, clock)?;
+
+        Some(Self::_write(&mut state, key, None))
+    }
+
+    fn write(&self, key: K, value: Option<V>) -> Timestamp {
+        let mut state = self.state.write().unwrap();
+        Self::_write(&mut state, key, value)
+    }
+
+    fn _write(state: &mut State<K, V>, key: K, value: Option<V>) -> Timestamp {
+        state.clock += 1;
+        let ts = state.clock;
+        Self::apply(state, key, value, ts);
+
+        ts
+    }
+
+    fn apply(state: &mut State<K, V>, key: K, value: Option<V>, ts: Timestamp) {
+        let i = match state.index.get(key) {
+            Some(slot) => get_left!(slot),
+            None => {
+                let entry = Entry {
+                    key,
+                    chain: VersionChain::default(),
+                };
+                let i = match state.free.pop() {
+                    Some(i) => {
+                        state.entries[i] = entry;
+                        i
+                    }
+                    None => {
+                        state.entries.push(entry);
+                        state.entries.len() - 1
+                    }
+                };
+                state.index.insert(Slot::new_leaf(key, i));
+
+                i
+            }
+        };
+
+        state.entries[i].chain.push(Version { ts, value });
+    }
+
+    /// Applies `writes` under a single commit timestamp, unless one of the keys had a version
+    /// committed after `start`, in which case nothing is applied and that key is returned.
+    pub(crate) fn commit_writes(
+        &self,
+        start: Timestamp,
+        writes: &BTreeMap<K, Option<V>>,
+    ) -> Result<Timestamp, K> {
+        let mut state = self.state.write().unwrap();
+
+        for key in writes.keys() {
+            let latest = state
+                .index
+                .get(*key)
+                .and_then(|slot| state.entries[get_left!(slot)].chain.latest().map(|v| v.ts));
+            if latest.is_some_and(|ts| ts > start) {
+                return Err(*key);
+            }
+        }
+
+        if writes.is_empty() {
+            return Ok(state.clock);
+        }
+
+        let clock = state.clock;
+        state.clock += 1;
+        let ts = state.clock;
+        for (key, value) in writes {
+            if value.is_none() && Self::_get(&state, *key, clock).is_none() {
+                continue;
+            }
+
+            Self::apply(&mut state, *key, *value, ts);
+        }
+
+        Ok(ts)
+    }
+
+    /// Returns the latest committed value of `key`.
+    pub fn get(&self, key: K) -> Option<V> {
+        let state = self.state.read().unwrap();
+        Self::_get(&state, key, state.clock)
+    }
+
+    /// Returns the value of `key` as of `ts`.
+    pub fn get_at(&self, key: K, ts: Timestamp) -> Option<V> {
+        let state = self.state.read().unwrap();
+        Self::_get(&state, key, ts)
+    }
+
+    fn _get(state: &State<K, V>, key: K, ts: Timestamp) -> Option<V> {
+        let slot = state.index.get(key)?;
+        state.entries[get_left!(slot)].chain.at(ts)?.value
+    }
+
+    /// Returns every retained version of `key`, oldest first.
+    pub fn versions(&self, key: K) -> Vec<Version<V>> {
+        let state = self.state.read().unwrap();
+        match state.index.get(key) {
+            Some(slot) => state.entries[get_left!(slot)]
+                .chain
+                .iter()
+                .copied()
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Registers a reader at the current timestamp, versions it can see are kept by `gc` until
+    /// `end_read` is called with the returned timestamp.
+    pub fn begin_read(&self) -> Timestamp {
+        let mut state = self.state.write().unwrap();
+        let ts = state.clock;
+        *state.readers.entry(ts).or_insert(0) += 1;
+
+        ts
+    }
+
+    pub fn end_read(&self, ts: Timestamp) {
+        let mut state = self.state.write().unwrap();
+        match state.readers.get_mut(&ts) {
+            Some(1) => {
+                state.readers.remove(&ts);
+            }
+            Some(n) => *n -= 1,
+            None => panic!("no reader registered at {ts}"),
+        }
+    }
+
+    /// Returns a read-only view of the tree as of now.
+    pub fn snapshot(&self) -> Snapshot<'_, K, V> {
+        Snapshot {
+            tree: self,
+            ts: self.begin_read(),
+        }
+    }
+
+    /// Returns a read-only view of the tree as it was at `ts`, or `None` if `ts` is in the future
+    /// or older than the retained history.
+    pub fn as_of(&self, ts: Timestamp) -> Option<Snapshot<'_, K, V>> {
+        let mut state = self.state.write().unwrap();
+        if ts < state.horizon || ts > state.clock {
+            return None;
+        }
+
+        *state.readers.entry(ts).or_insert(0) += 1;
+
+        Some(Snapshot { tree: self, ts })
+    }
+
+    /// Appends the entries visible at `ts` of at most `SCAN_BATCH` keys within `(start, end)` to
+    /// `out` and returns the last key visited, or `None` if the range is exhausted.
+    fn scan_batch(
+        &self,
+        start: Bound<K>,
+        end: Bound<K>,
+        ts: Timestamp,
+        out: &mut VecDeque<(K, V)>,
+    ) -> Option<K> {
+        let state = self.state.read().unwrap();
+
+        let mut last = None;
+        for (i, (k, e)) in state.index.range((start, end)).enumerate() {
+            if i == SCAN_BATCH {
+                return last;
+            }
+
+            if let Some(v) = state.entries[e].chain.at(ts).and_then(|v| v.value) {
+                out.push_back((k, v));
+            }
+            last = Some(k);
+        }
+
+        None
+    }
+
+    pub fn oldest_reader(&self) -> Option<Timestamp> {
+        self.state.read().unwrap().readers.keys().next().copied()
+    }
+
+    /// Prunes versions older than both the oldest registered reader and the history kept by the
+    /// `Retention` policy, and returns how many were dropped. Keys left without versions are
+    /// removed.
+    pub fn gc(&self) -> usize {
+        let mut state = self.state.write().unwrap();
+        let retained = match state.retention {
+            Retention::Readers => state.clock,
+            Retention::Window(n) => state.clock.saturating_sub(n),
+            Retention::All => return 0,
+        };
+        let oldest = match state.readers.keys().next() {
+            Some(ts) => retained.min(*ts),
+            None => retained,
+        };
+        state.horizon = state.horizon.max(oldest);
+
+        let state = &mut *state;
+        let mut pruned = 0;
+        for (i, entry) in state.entries.iter_mut().enumerate() {
+            if entry.chain.is_empty() {
+                continue;
+            }
+
+            pruned += entry.chain.prune(oldest);
+            if entry.chain.is_empty() {
+                state.index.delete(entry.key);
+                state.free.push(i);
+            }
+        }
+
+        pruned
+    }
+
+    /// Like `gc`, then compacts the index and the version chains, rebuilding the index packed
+    /// without the keys whose delete was pruned. Returns how many versions were dropped.
+    pub fn vacuum(&self) -> usize {
+        let pruned = self.gc();
+
+        let mut state = self.state.write().unwrap();
+        let state = &mut *state;
+        state.entries.retain(|e| !e.chain.is_empty());
+        state.entries.sort_by_key(|e| e.key);
+        state.free.clear();
+        let max = state.index.max();
+        state.index =
+            BTree::bulk_load(max, state.entries.iter().enumerate().map(|(i, e)| (e.key, i)));
+
+        pruned
+    }
+}
+
+/// A read-only view of an `MvccBTree` pinned at the timestamp it was taken at.
+///
+/// Writes committed after the snapshot are invisible through it, and the versions it can see are
+/// kept by `gc` until it is dropped.
+pub struct Snapshot<'a, K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment,
+    V: Clone + Copy + Debug + Eq,
+{
+    tree: &'a MvccBTree<K, V>,
+    ts: Timestamp,
+}
+
+impl<K, V> Snapshot<'_, K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment,
+    V: Clone + Copy + Debug + Eq,
+{
+    pub fn ts(&self) -> Timestamp {
+        self.ts
+    }
+
+    pub fn get(&self, key: K) -> Option<V> {
+        self.tree.get_at(key, self.ts)
+    }
+
+    /// Scans `range` as of the snapshot. The tree is only locked while a batch of entries is
+    /// collected, so writers are not blocked for the length of the scan.
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> SnapshotRange<'_, K, V> {
+        SnapshotRange {
+            tree: self.tree,
+            ts: self.ts,
+            next: Some(range.start_bound().cloned()),
+            end: range.end_bound().cloned(),
+            buf: VecDeque::new(),
+        }
+    }
+
+    pub fn iter(&self) -> SnapshotRange<'_, K, V> {
+        self.range(..)
+    }
+}
+
+impl<K, V> Drop for Snapshot<'_, K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment,
+    V: Clone + Copy + Debug + Eq,
+{
+    fn drop(&mut self) {
+        self.tree.end_read(self.ts);
+    }
+}
+
+pub struct SnapshotRange<'a, K, V> {
+    tree: &'a MvccBTree<K, V>,
+    ts: Timestamp,
+    // Start of the next batch, `None` once the range is exhausted
+    next: Option<Bound<K>>,
+    end: Bound<K>,
+    buf: VecDeque<(K, V)>,
+}
+
+impl<K, V> Iterator for SnapshotRange<'_, K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment,
+    V: Clone + Copy + Debug + Eq,
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.buf.is_empty() {
+            let start = self.next?;
+            let last = self
+                .tree
+                .scan_batch(start, self.end, self.ts, &mut self.buf);
+            self.next = last.map(Bound::Excluded);
+        }
+
+        self.buf.pop_front()
+    }
+}
+
+impl<K, V> FusedIterator for SnapshotRange<'_, K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment,
+    V: Clone + Copy + Debug + Eq,
+{
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+
+    use super::{MvccBTree, Retention, Version};
+
+    #[test]
+    fn test_mvcc_versions() {
+        const MAX: usize = 8;
+
+        let tree = MvccBTree::new(MAX);
+
+        let mut commits = Vec::new();
+        for round in 0..3u32 {
+            for k in 0..50u32 {
+                commits.push((k, round, tree.insert(k, k + round * 100)));
+            }
+        }
+
+        for (k, round, ts) in &commits {
+            let want = Some(k + round * 100);
+            let have = tree.get_at(*k, *ts);
+            assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+        }
+
+        let ts = tree.delete(7).unwrap();
+        assert!(tree.get(7).is_none());
+        assert!(tree.get_at(7, ts - 1) == Some(207));
+        assert!(tree.delete(7).is_none());
+
+        let want = vec![
+            Version {
+                ts: 8,
+                value: Some(7),
+            },
+            Version {
+                ts: 58,
+                value: Some(107),
+            },
+            Version {
+                ts: 108,
+                value: Some(207),
+            },
+            Version { ts, value: None },
+        ];
+        let have = tree.versions(7);
+        assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+    }
+
+    #[test]
+    fn test_mvcc_gc() {
+        const MAX: usize = 8;
+
+        let tree = MvccBTree::new(MAX);
+        for k in 0..10u32 {
+            tree.insert(k, k);
+        }
+
+        let reader = tree.begin_read();
+        for k in 0..10u32 {
+            tree.insert(k, k + 10);
+        }
+        tree.delete(0);
+
+        // The reader still sees the first versions
+        let have = tree.gc();
+        assert!(have == 0, "Want: 0\nHave: {have}");
+        assert!(tree.get_at(0, reader) == Some(0));
+
+        tree.end_read(reader);
+        assert!(tree.oldest_reader().is_none());
+
+        // One old version for 1..10, all three versions of 0
+        let have = tree.gc();
+        assert!(have == 12, "Want: 12\nHave: {have}");
+        assert!(tree.versions(0).is_empty());
+        for k in 1..10u32 {
+            let have = tree.versions(k).len();
+            assert!(have == 1, "Want: 1\nHave: {have}");
+        }
+
+        // Freed entries are reused
+        tree.insert(0, 100);
+        assert!(tree.get(0) == Some(100));
+
+        // Vacuum also drops the deleted key from the index
+        tree.delete(5);
+        let have = tree.vacuum();
+        assert!(have == 2, "Want: 2\nHave: {have}");
+        assert!(tree.versions(5).is_empty() && tree.get(0) == Some(100));
+        let state = tree.state.read().unwrap();
+        assert!(state.entries.len() == 9 && state.free.is_empty());
+        state.index.validate().unwrap();
+    }
+
+    #[test]
+    fn test_snapshot() {
+        const MAX: usize = 8;
+
+        let tree = MvccBTree::new(MAX);
+        for k in 0..200u32 {
+            tree.insert(k, k);
+        }
+
+        let snapshot = tree.snapshot();
+        thread::scope(|s| {
+            s.spawn(|| {
+                for k in 0..200u32 {
+                    if k % 2 == 0 {
+                        tree.delete(k);
+                    } else {
+                        tree.insert(k, k + 1000);
+                    }
+                }
+                for k in 200..300u32 {
+                    tree.insert(k, k);
+                }
+                tree.gc();
+            });
+
+            for _ in 0..10 {
+                let want = (0..200u32).map(|k| (k, k)).collect::<Vec<_>>();
+                let have = snapshot.iter().collect::<Vec<_>>();
+                assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+            }
+        });
+
+        let want = (50..=100u32).map(|k| (k, k)).collect::<Vec<_>>();
+        let have = snapshot.range(50..=100).collect::<Vec<_>>();
+        assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+        assert!(snapshot.get(250).is_none());
+
+        let ts = snapshot.ts();
+        drop(snapshot);
+        assert!(tree.oldest_reader().is_none());
+        tree.gc();
+        assert!(tree.get_at(0, ts).is_none());
+
+        let later = tree.snapshot();
+        let want = (1..200u32)
+            .step_by(2)
+            .map(|k| (k, k + 1000))
+            .chain((200..300).map(|k| (k, k)));
+        let have = later.iter().collect::<Vec<_>>();
+        assert!(
+            want.clone().eq(have.iter().copied()),
+            "Want: {:?}\nHave: {:?}",
+            want.collect::<Vec<_>>(),
+            have
+        );
+    }
+
+    #[test]
+    fn test_as_of() {
+        const MAX: usize = 8;
+
+        let tree = MvccBTree::with_retention(MAX, Retention::Window(100));
+
+        let mut commits = Vec::new();
+        for round in 0..10u32 {
+            for k in 0..50u32 {
+                tree.insert(k, round);
+            }
+            commits.push((round, tree.now()));
+        }
+        tree.gc();
+
+        let horizon = tree.horizon();
+        assert!(horizon == 400, "Want: 400\nHave: {horizon}");
+        for (round, ts) in commits {
+            let view = match tree.as_of(ts) {
+                Some(view) => view,
+                None => {
+                    assert!(ts < horizon, "Could not read at {ts}");
+                    continue;
+                }
+            };
+
+            let want = (0..50u32).map(|k| (k, round)).collect::<Vec<_>>();
+            let have = view.iter().collect::<Vec<_>>();
+            assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+        }
+
+        assert!(tree.as_of(tree.now() + 1).is_none());
+
+        // A historical view holds back gc like any other reader
+        let view = tree.as_of(horizon).unwrap();
+        tree.set_retention(Retention::Readers);
+        tree.gc();
+        assert!(view.get(0) == Some(7));
+        drop(view);
+
+        tree.gc();
+        let horizon = tree.horizon();
+        assert!(horizon == tree.now(), "Want: {}\nHave: {horizon}", tree.now());
+        assert!(tree.versions(0).len() == 1);
+    }
+}
@@ -1,65 +1,140 @@
-use std::collections::{btree_set, BTreeSet};
 use std::fmt::Debug;
 use std::ptr;
+use std::slice;
+use std::sync::atomic::{AtomicU32, Ordering};
 
 use crate::btree::Increment;
 use crate::get_right;
-use crate::slot::{Either, Slot};
+use crate::metrics::{self, Counter};
+use crate::seqlock::SeqLock;
+use crate::slot::{Either, Slot, Slots};
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone, Copy)]
 pub enum NodeType {
     Internal,
     Leaf,
 }
 
-#[derive(Debug)]
+/// What a leaf has been used for since it was made by a split, kept for `Sizing`.
+#[derive(Debug, Default)]
+pub struct Heat {
+    /// Lookups and scans that read the leaf. Like `writes`, only counted while the tree has a
+    /// `Sizing`.
+    pub reads: AtomicU32,
+    pub writes: u32,
+}
+
+impl Heat {
+    pub fn read(&self) {
+        self.reads.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn write(&mut self) {
+        self.writes = self.writes.saturating_add(1);
+    }
+}
+
+impl Clone for Heat {
+    fn clone(&self) -> Self {
+        Self {
+            reads: AtomicU32::new(self.reads.load(Ordering::Relaxed)),
+            writes: self.writes,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Node<K, V> {
     pub t: NodeType,
-    pub values: BTreeSet<Slot<K, V>>,
+    pub values: Slots<K, V>,
     pub next: *mut Node<K, V>,
     pub max: usize,
     pub is_root: bool,
+    pub seq: SeqLock,
+    /// The entries below an internal node, see `count()`. Kept by `BTree`, not by
+    /// `ConcurrentBTree`, whose writes to leaves don't pass through their parents.
+    pub len: usize,
+    pub heat: Heat,
+}
+
+/// Hints the CPU to start loading the cache line at `ptr`. Never faults, even on null.
+#[inline(always)]
+fn prefetch<T>(ptr: *const T) {
+    #[cfg(target_arch = "x86_64")]
+    unsafe {
+        use std::arch::x86_64::{_mm_prefetch, _MM_HINT_T0};
+        _mm_prefetch(ptr as *const i8, _MM_HINT_T0);
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    let _ = ptr;
+}
+
+impl<K: Ord, V> Node<K, V> {
+    /// For a scan entering this leaf, starts loading the slots of the next leaf and the header of
+    /// the one after it, so the scan isn't waiting on each leaf in turn. The next leaf's header
+    /// was prefetched the same way from the leaf before this one.
+    #[inline]
+    pub fn prefetch_next(&self) {
+        if let Some(next) = unsafe { self.next.as_ref() } {
+            prefetch(next.values.as_ptr());
+            prefetch(next.next);
+        }
+    }
 }
 
 impl<K, V> Node<K, V>
 where
     K: Copy + Debug + Ord + Increment,
-    V: Copy + Debug + Eq,
+    V: Clone + Debug + Eq,
 {
     pub fn new_leaf(max: usize) -> Self {
         Self {
             t: NodeType::Leaf,
-            values: BTreeSet::new(),
+            values: Slots::with_capacity(max + 1),
             next: ptr::null_mut(),
             max,
             is_root: false,
+            seq: SeqLock::new(),
+            len: 0,
+            heat: Heat::default(),
         }
     }
 
     pub fn new_internal(max: usize) -> Self {
         Self {
             t: NodeType::Internal,
-            values: BTreeSet::new(),
+            values: Slots::with_capacity(max + 1),
             next: ptr::null_mut(),
             max,
             is_root: false,
+            seq: SeqLock::new(),
+            len: 0,
+            heat: Heat::default(),
         }
     }
 
     /// Returns greater half, new key for it and new key for replace
     pub fn split(&mut self) -> *mut Node<K, V> {
+        metrics::count(Counter::Splits);
         let len = self.values.len();
-        let mid = *self
+        let mid = self
             .values
             .iter()
             .nth(len / 2)
-            .expect("there should be a mid slot");
+            .expect("there should be a mid slot")
+            .0;
 
         let mut gt_node = match self.t {
             NodeType::Internal => Node::new_internal(self.max),
             NodeType::Leaf => Node::new_leaf(self.max),
         };
-        gt_node.values = self.values.split_off(&mid);
+        gt_node.values = self
+            .values
+            .split_off(&Slot::new_internal(mid, ptr::null_mut()));
+        if !self.is_leaf() {
+            gt_node.len = gt_node.children_count();
+            self.len = self.children_count();
+        }
 
         let gt_node = Box::into_raw(Box::new(gt_node));
         if self.is_leaf() {
@@ -70,6 +145,7 @@ where
         gt_node
     }
 
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
     pub fn get_separators(
         ptr: *mut Node<K, V>,
         other: Option<*mut Node<K, V>>,
@@ -78,11 +154,9 @@ where
             // Using last values for separators
 
             let me = unsafe { &*ptr };
-            let ls = me.values.last().unwrap();
-            let k = if me.is_leaf() { ls.0.next() } else { ls.0 };
-            let s = Slot::new_internal(k, ptr);
-
             let o = unsafe { &*optr };
+            let s = Slot::new_internal(me.separator_before(o), ptr);
+
             let ls = o.values.last().unwrap();
             let k = if o.is_leaf() { ls.0.next() } else { ls.0 };
             let os = Slot::new_internal(k, optr);
@@ -91,24 +165,52 @@ where
         })
     }
 
+    /// The separator of a node split from `gt`. For a leaf it is the key after its last one,
+    /// unless `gt` starts before that, which a `next()` that isn't the least key after another
+    /// allows.
+    pub fn separator_before(&self, gt: &Node<K, V>) -> K {
+        let last = self.last_k().unwrap();
+        match (self.is_leaf(), gt.first()) {
+            (true, Some(first)) => last.next().min(first.0),
+            (true, None) => last.next(),
+            (false, _) => last,
+        }
+    }
+
+    /// The entries in or below the node.
+    pub fn count(&self) -> usize {
+        match self.is_leaf() {
+            true => self.values.len(),
+            false => self.len,
+        }
+    }
+
+    /// The entries in the children of an internal node, by their counts.
+    pub fn children_count(&self) -> usize {
+        self.values
+            .iter()
+            .map(|s| unsafe { (*get_right!(s)).count() })
+            .sum()
+    }
+
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
     pub fn set_last(node: &mut Node<K, V>, optr: *mut Node<K, V>) {
         let o = unsafe { &*optr };
         let ls = o.values.last().unwrap();
         let k = if o.is_leaf() { ls.0.next() } else { ls.0 };
         let s = Slot::new_internal(k, optr);
-        match node.values.replace(s) {
-            Some(s) => eprintln!("SLOT DISAPPEARING: {:?}", s),
-            None => {}
+        if let Some(s) = node.values.replace(s) {
+            eprintln!("SLOT DISAPPEARING: {:?}", s);
         }
     }
 
     /// Returns `None` if self is a leaf.
-    pub fn find_child(&self, value: Slot<K, V>) -> Option<*mut Node<K, V>> {
+    pub fn find_child(&self, key: K) -> Option<*mut Node<K, V>> {
         if self.is_leaf() {
             return None;
         }
 
-        let n = self.values.iter().find(|n| value < **n)?;
+        let n = self.values.iter().find(|n| key < n.0)?;
         Some(get_right!(n))
     }
 
@@ -124,58 +226,23 @@ where
         self.values.first().map(|s| s.0)
     }
 
-    pub fn first_v(&self) -> Option<Either<V, *mut Node<K, V>>> {
-        self.values.first().map(|s| s.1)
+    pub fn first_v(&self) -> Option<&Either<V, *mut Node<K, V>>> {
+        self.values.first().map(|s| &s.1)
     }
 
     pub fn last_k(&self) -> Option<K> {
         self.values.last().map(|s| s.0)
     }
 
-    pub fn last_v(&self) -> Option<Either<V, *mut Node<K, V>>> {
-        self.values.last().map(|s| s.1)
+    pub fn last_v(&self) -> Option<&Either<V, *mut Node<K, V>>> {
+        self.values.last().map(|s| &s.1)
     }
 
     pub fn is_leaf(&self) -> bool {
         self.t == NodeType::Leaf
     }
 
-    pub fn iter(&self) -> btree_set::Iter<Slot<K, V>> {
+    pub fn iter(&self) -> slice::Iter<'_, Slot<K, V>> {
         self.values.iter()
     }
-
-    #[cfg(test)]
-    pub fn print(raw_node: *mut Node<K, V>)
-    where
-        K: std::fmt::Debug,
-        V: std::fmt::Debug,
-    {
-        if raw_node.is_null() {
-            return;
-        }
-
-        let node = unsafe { &(*raw_node) };
-        match node.t {
-            NodeType::Internal => {
-                println!("Internal Node: {:?}", raw_node);
-                println!("Contents: {:?}", node.values);
-                println!("Is root: {:?}", node.is_root);
-                println!("Next (should be null): {:?}", node.next);
-                println!();
-
-                for slot in &node.values {
-                    match slot.1 {
-                        Either::Left(_) => unreachable!(),
-                        Either::Right(ptr) => Self::print(ptr),
-                    }
-                }
-            }
-            NodeType::Leaf => {
-                println!("Leaf Node {:?}", raw_node);
-                println!("Next: {:?}", node.next);
-                println!("Contents: {:?}", node.values);
-                println!();
-            }
-        }
-    }
 }
@@ -0,0 +1,140 @@
+use crate::node::{Node, NodeType};
+
+/// A node as reported to an `Observer`. `id` is the node's address for in-memory trees and its
+/// page for paged ones.
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub struct NodeRef {
+    pub id: u64,
+    pub leaf: bool,
+}
+
+impl NodeRef {
+    pub(crate) fn of<K, V>(node: *mut Node<K, V>) -> Self {
+        Self {
+            id: node as u64,
+            leaf: unsafe { (*node).t == NodeType::Leaf },
+        }
+    }
+}
+
+/// Hooks called as a tree changes shape, set with `set_observer()`, for metrics, visualizations
+/// or keeping state of one's own per node. Every hook does nothing by default.
+///
+/// They are called while the tree is being changed, so they must not call back into it.
+pub trait Observer<K>: Send + Sync {
+    /// `node` was split, `new` took its greater half and `separator` is the key `node` holds the
+    /// keys below.
+    fn on_split(&self, _node: NodeRef, _new: NodeRef, _separator: K) {}
+
+    /// `freed` was merged into `into`, its left sibling.
+    fn on_merge(&self, _into: NodeRef, _freed: NodeRef) {}
+
+    /// The root changed from `old` to `new`, `None` while the tree is empty.
+    fn on_root_change(&self, _old: Option<NodeRef>, _new: Option<NodeRef>) {}
+
+    fn on_node_alloc(&self, _node: NodeRef) {}
+
+    fn on_node_free(&self, _node: NodeRef) {}
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use crate::btree::BTree;
+    #[cfg(feature = "fs")]
+    use crate::buffer::Capacity;
+    #[cfg(feature = "fs")]
+    use crate::paged::PagedBTree;
+    use crate::slot::Slot;
+
+    use super::{NodeRef, Observer};
+
+    #[derive(Default)]
+    struct Counts {
+        splits: usize,
+        leaf_splits: i64,
+        merges: usize,
+        roots: Vec<Option<NodeRef>>,
+        live: i64,
+        leaves: i64,
+    }
+
+    #[derive(Default)]
+    struct Counter(Mutex<Counts>);
+
+    impl Observer<u32> for Counter {
+        fn on_split(&self, node: NodeRef, new: NodeRef, _: u32) {
+            assert!(node.leaf == new.leaf);
+            let mut counts = self.0.lock().unwrap();
+            counts.splits += 1;
+            counts.leaf_splits += node.leaf as i64;
+        }
+
+        fn on_merge(&self, into: NodeRef, freed: NodeRef) {
+            assert!(into.leaf && freed.leaf);
+            self.0.lock().unwrap().merges += 1;
+        }
+
+        fn on_root_change(&self, old: Option<NodeRef>, new: Option<NodeRef>) {
+            let mut counts = self.0.lock().unwrap();
+            assert!(counts.roots.last().copied().flatten() == old);
+            counts.roots.push(new);
+        }
+
+        fn on_node_alloc(&self, node: NodeRef) {
+            let mut counts = self.0.lock().unwrap();
+            counts.live += 1;
+            counts.leaves += node.leaf as i64;
+        }
+
+        fn on_node_free(&self, node: NodeRef) {
+            let mut counts = self.0.lock().unwrap();
+            counts.live -= 1;
+            counts.leaves -= node.leaf as i64;
+        }
+    }
+
+    #[test]
+    fn test_observer() {
+        let counter = Arc::new(Counter::default());
+        let mut tree = BTree::new(8);
+        tree.set_observer(Some(counter.clone()));
+        for k in 0..1000u32 {
+            tree.insert(Slot::new_leaf(k, k));
+        }
+
+        {
+            let counts = counter.0.lock().unwrap();
+            assert!(counts.leaves == counts.leaf_splits + 1, "Have: {}", counts.leaves);
+            // Every split adds a node, and so does every root
+            let want = counts.splits as i64 + counts.roots.len() as i64;
+            assert!(counts.live == want, "Want: {}\nHave: {}", want, counts.live);
+            assert!(counts.roots.len() > 2);
+        }
+        drop(tree);
+        assert!(counter.0.lock().unwrap().live == 0);
+
+        #[cfg(feature = "fs")]
+        {
+            let counter = Arc::new(Counter::default());
+            let mut tree = PagedBTree::create_in_memory(8, Capacity::Pages(16)).unwrap();
+            tree.set_observer(Some(counter.clone()));
+            for k in 0..1000u32 {
+                tree.insert(k, k as u64).unwrap();
+            }
+            for k in (0..1000).filter(|k| k % 4 != 0) {
+                tree.delete(k).unwrap();
+            }
+            let freed = tree.merge_underfull(usize::MAX).unwrap();
+            for k in 0..1000 {
+                tree.delete(k).unwrap();
+            }
+
+            let counts = counter.0.lock().unwrap();
+            assert!(counts.merges == freed && counts.splits > 0);
+            assert!(counts.live == 0 && counts.leaves == 0, "Have: {}", counts.live);
+            assert!(counts.roots.last() == Some(&None));
+        }
+    }
+}
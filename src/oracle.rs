@@ -0,0 +1,356 @@
+//! A harness that applies the same operations to a tree and to `std::collections::BTreeMap`, and
+//! reports the first operation they disagree on. With the `test-util` feature it is exported for
+//! testing code built on the trees, along with proptest strategies for operation sequences, which
+//! proptest shrinks to a minimal failing sequence.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Debug, Display};
+use std::ops::{Bound, Range};
+
+use proptest::prelude::*;
+
+use crate::btree::{BTree, Increment};
+use crate::concurrent::ConcurrentBTree;
+use crate::get_left;
+#[cfg(feature = "fs")]
+use crate::page::Encode;
+#[cfg(feature = "fs")]
+use crate::paged::PagedBTree;
+use crate::slot::{Either, Slot};
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Op<K, V> {
+    Insert(K, V),
+    Get(K),
+    Delete(K),
+    Range(Bound<K>, Bound<K>),
+}
+
+/// A tree the oracle can check. Operations that fail panic.
+pub trait Subject<K, V> {
+    fn insert(&mut self, key: K, value: V);
+    fn get(&mut self, key: K) -> Option<V>;
+    /// Returns whether `key` was there.
+    fn delete(&mut self, key: K) -> bool;
+    fn range(&mut self, range: (Bound<K>, Bound<K>)) -> Vec<(K, V)>;
+
+    fn validate(&self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// The first operation a tree and the model disagreed on, or the invariant it broke.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Divergence {
+    /// Operations applied before this one.
+    pub step: usize,
+    pub op: String,
+    pub want: String,
+    pub have: String,
+}
+
+impl Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "step {}, {}\nWant: {}\nHave: {}", self.step, self.op, self.want, self.have)
+    }
+}
+
+impl std::error::Error for Divergence {}
+
+pub struct Oracle<K, V, T> {
+    tree: T,
+    model: BTreeMap<K, V>,
+    step: usize,
+}
+
+impl<K, V, T> Oracle<K, V, T>
+where
+    K: Clone + Copy + Debug + Ord,
+    V: Clone + Copy + Debug + Eq,
+    T: Subject<K, V>,
+{
+    /// Checks `tree`, which must be empty.
+    pub fn new(tree: T) -> Self {
+        Self {
+            tree,
+            model: BTreeMap::new(),
+            step: 0,
+        }
+    }
+
+    pub fn apply(&mut self, op: &Op<K, V>) -> Result<(), Divergence> {
+        let diverged = |want: &dyn Debug, have: &dyn Debug| Divergence {
+            step: self.step,
+            op: format!("{:?}", op),
+            want: format!("{:?}", want),
+            have: format!("{:?}", have),
+        };
+
+        match *op {
+            Op::Insert(key, value) => {
+                self.tree.insert(key, value);
+                self.model.insert(key, value);
+            }
+            Op::Get(key) => {
+                let (want, have) = (self.model.get(&key).copied(), self.tree.get(key));
+                if want != have {
+                    return Err(diverged(&want, &have));
+                }
+            }
+            Op::Delete(key) => {
+                let (want, have) = (self.model.remove(&key).is_some(), self.tree.delete(key));
+                if want != have {
+                    return Err(diverged(&want, &have));
+                }
+            }
+            Op::Range(start, end) => {
+                let want = self
+                    .model
+                    .range((start, end))
+                    .map(|(k, v)| (*k, *v))
+                    .collect::<Vec<_>>();
+                let have = self.tree.range((start, end));
+                if want != have {
+                    return Err(diverged(&want, &have));
+                }
+            }
+        }
+
+        self.step += 1;
+        Ok(())
+    }
+
+    /// Compares every entry and validates the tree.
+    pub fn check(&mut self) -> Result<(), Divergence> {
+        let op = Op::Range(Bound::Unbounded, Bound::Unbounded);
+        self.apply(&op)?;
+        self.step -= 1;
+
+        self.tree.validate().map_err(|e| Divergence {
+            step: self.step,
+            op: "validate".into(),
+            want: "a valid tree".into(),
+            have: e,
+        })
+    }
+
+    /// Applies `ops` in order, then checks the whole tree.
+    pub fn run(&mut self, ops: &[Op<K, V>]) -> Result<(), Divergence> {
+        for op in ops {
+            self.apply(op)?;
+        }
+
+        self.check()
+    }
+
+    pub fn tree(&self) -> &T {
+        &self.tree
+    }
+}
+
+/// A range from two keys, which may come in either order, as `BTreeMap::range` accepts.
+fn bounds<K: Ord>(a: Bound<K>, b: Bound<K>) -> (Bound<K>, Bound<K>) {
+    fn key<K>(b: &Bound<K>) -> Option<&K> {
+        match b {
+            Bound::Included(k) | Bound::Excluded(k) => Some(k),
+            Bound::Unbounded => None,
+        }
+    }
+
+    match (key(&a), key(&b)) {
+        (Some(x), Some(y)) if x > y => (b, a),
+        (Some(x), Some(y)) if x == y => match a {
+            Bound::Excluded(k) => (Bound::Included(k), b),
+            _ => (a, b),
+        },
+        _ => (a, b),
+    }
+}
+
+fn bound<K: Debug + Clone>(
+    keys: impl Strategy<Value = K> + Clone,
+) -> impl Strategy<Value = Bound<K>> {
+    prop_oneof![
+        1 => Just(Bound::Unbounded),
+        3 => keys.clone().prop_map(Bound::Included),
+        3 => keys.prop_map(Bound::Excluded),
+    ]
+}
+
+/// An operation on keys from `keys` and values from `values`, mostly inserts.
+pub fn op<K, V>(
+    keys: impl Strategy<Value = K> + Clone + 'static,
+    values: impl Strategy<Value = V> + 'static,
+) -> impl Strategy<Value = Op<K, V>>
+where
+    K: Debug + Clone + Ord + 'static,
+    V: Debug + Clone + 'static,
+{
+    prop_oneof![
+        4 => (keys.clone(), values).prop_map(|(k, v)| Op::Insert(k, v)),
+        2 => keys.clone().prop_map(Op::Get),
+        2 => keys.clone().prop_map(Op::Delete),
+        1 => (bound(keys.clone()), bound(keys)).prop_map(|(a, b)| {
+            let (start, end) = bounds(a, b);
+            Op::Range(start, end)
+        }),
+    ]
+}
+
+/// Sequences of `len` operations, see `op()`.
+pub fn ops<K, V>(
+    keys: impl Strategy<Value = K> + Clone + 'static,
+    values: impl Strategy<Value = V> + 'static,
+    len: Range<usize>,
+) -> impl Strategy<Value = Vec<Op<K, V>>>
+where
+    K: Debug + Clone + Ord + 'static,
+    V: Debug + Clone + 'static,
+{
+    proptest::collection::vec(op(keys, values), len)
+}
+
+impl<K, V> Subject<K, V> for BTree<K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment,
+    V: Clone + Copy + Debug + Eq,
+{
+    fn insert(&mut self, key: K, value: V) {
+        BTree::insert(self, Slot::new_leaf(key, value));
+    }
+
+    fn get(&mut self, key: K) -> Option<V> {
+        BTree::get(self, key).map(|s| get_left!(s))
+    }
+
+    fn delete(&mut self, key: K) -> bool {
+        BTree::delete(self, key)
+    }
+
+    fn range(&mut self, range: (Bound<K>, Bound<K>)) -> Vec<(K, V)> {
+        BTree::range(self, range).collect()
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        BTree::validate(self)
+    }
+}
+
+impl<K, V> Subject<K, V> for ConcurrentBTree<K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment,
+    V: Clone + Copy + Debug + Eq,
+{
+    fn insert(&mut self, key: K, value: V) {
+        ConcurrentBTree::insert(self, Slot::new_leaf(key, value));
+    }
+
+    fn get(&mut self, key: K) -> Option<V> {
+        ConcurrentBTree::get(self, key).map(|s| get_left!(s))
+    }
+
+    fn delete(&mut self, key: K) -> bool {
+        ConcurrentBTree::delete(self, key)
+    }
+
+    fn range(&mut self, range: (Bound<K>, Bound<K>)) -> Vec<(K, V)> {
+        ConcurrentBTree::range(self, range).collect()
+    }
+}
+
+#[cfg(feature = "fs")]
+impl<K, V> Subject<K, V> for PagedBTree<K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment + Encode,
+    V: Clone + Copy + Debug + Encode,
+{
+    fn insert(&mut self, key: K, value: V) {
+        PagedBTree::insert(self, key, value).unwrap();
+    }
+
+    fn get(&mut self, key: K) -> Option<V> {
+        PagedBTree::get(self, key).unwrap()
+    }
+
+    fn delete(&mut self, key: K) -> bool {
+        PagedBTree::delete(self, key).unwrap().is_some()
+    }
+
+    fn range(&mut self, range: (Bound<K>, Bound<K>)) -> Vec<(K, V)> {
+        PagedBTree::range(self, range).unwrap()
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        PagedBTree::validate(self).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use crate::btree::BTree;
+    #[cfg(feature = "fs")]
+    use crate::buffer::Capacity;
+    use crate::concurrent::ConcurrentBTree;
+    #[cfg(feature = "fs")]
+    use crate::paged::PagedBTree;
+
+    use super::{ops, Op, Oracle, Subject};
+
+    /// A tree that loses every delete of key 7.
+    struct Buggy(BTree<u16, u32>);
+
+    impl Subject<u16, u32> for Buggy {
+        fn insert(&mut self, key: u16, value: u32) {
+            Subject::insert(&mut self.0, key, value)
+        }
+
+        fn get(&mut self, key: u16) -> Option<u32> {
+            Subject::get(&mut self.0, key)
+        }
+
+        fn delete(&mut self, key: u16) -> bool {
+            key == 7 || Subject::delete(&mut self.0, key)
+        }
+
+        fn range(
+            &mut self,
+            range: (std::ops::Bound<u16>, std::ops::Bound<u16>),
+        ) -> Vec<(u16, u32)> {
+            Subject::range(&mut self.0, range)
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn test_oracle(max in 4usize..16, ops in ops(0u16..512, any::<u32>(), 0..400)) {
+            Oracle::new(BTree::new(max)).run(&ops).map_err(|e| TestCaseError::fail(e.to_string()))?;
+            Oracle::new(ConcurrentBTree::new(max)).run(&ops).map_err(|e| TestCaseError::fail(e.to_string()))?;
+        }
+    }
+
+    #[cfg(feature = "fs")]
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(32))]
+
+        #[test]
+        fn test_paged_oracle(max in 4usize..16, ops in ops(0u16..512, any::<u64>(), 0..400)) {
+            let tree = PagedBTree::create_in_memory(max, Capacity::Pages(8)).unwrap();
+            Oracle::new(tree).run(&ops).map_err(|e| TestCaseError::fail(e.to_string()))?;
+        }
+    }
+
+    #[test]
+    fn test_oracle_divergence() {
+        let ops = [
+            Op::Insert(7, 1),
+            Op::Insert(8, 2),
+            Op::Delete(7),
+            Op::Get(7),
+        ];
+        let have = Oracle::new(Buggy(BTree::new(4))).run(&ops).unwrap_err();
+        assert!(have.step == 3 && have.op == "Get(7)", "{}", have);
+        assert!(have.want == "None" && have.have == "Some(1)", "{}", have);
+    }
+}
@@ -0,0 +1,366 @@
+use std::io;
+
+use crate::node::NodeType;
+
+pub const PAGE_SIZE: usize = 4096;
+
+/// Log sequence number, the position of a record in the log. Pages are stamped with the LSN of
+/// the last record that changed them.
+pub type Lsn = u64;
+
+pub type PageBuf = [u8; PAGE_SIZE];
+
+/// Index of a page in the file, page `n` starts at byte `n * PAGE_SIZE`.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Clone, Copy)]
+pub struct PageId(pub u64);
+
+impl PageId {
+    /// Page 0 always holds the tree's metadata, so it doubles as "no page" in node headers.
+    pub const META: PageId = PageId(0);
+}
+
+/// Fixed-size encoding for keys and values stored in pages.
+pub trait Encode: Copy {
+    const SIZE: usize;
+
+    /// `buf` is exactly `SIZE` bytes.
+    fn encode(&self, buf: &mut [u8]);
+    fn decode(buf: &[u8]) -> Self;
+}
+
+macro_rules! impl_encode {
+    ($( $t:ty ),*) => {
+        $(
+        impl Encode for $t {
+            const SIZE: usize = std::mem::size_of::<$t>();
+
+            fn encode(&self, buf: &mut [u8]) {
+                buf.copy_from_slice(&self.to_le_bytes());
+            }
+
+            fn decode(buf: &[u8]) -> Self {
+                Self::from_le_bytes(buf.try_into().unwrap())
+            }
+        }
+        )*
+    };
+}
+
+impl_encode!(i8, i16, i32, i64, u8, u16, u32, u64);
+
+// Every page starts with:
+//
+// | type (1) | unused (1) | count (2) | crc32c (4) | lsn (8) |
+//
+// Node and free pages follow it with:
+//
+// | next (8) |
+const TYPE: usize = 0;
+const COUNT: usize = 2;
+const CHECKSUM: usize = 4;
+const LSN: usize = 8;
+const NEXT: usize = 16;
+pub const HEADER_SIZE: usize = 24;
+
+const LEAF: u8 = 1;
+const INTERNAL: u8 = 2;
+const FREE: u8 = 3;
+const META: u8 = 4;
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// CRC32C of the page, without the checksum field itself.
+pub fn checksum(buf: &PageBuf) -> u32 {
+    let crc = crc32c::crc32c(&buf[..CHECKSUM]);
+    crc32c::crc32c_append(crc, &buf[CHECKSUM + 4..])
+}
+
+/// Stores the page's checksum in its header, done as it is written to disk.
+pub fn set_checksum(buf: &mut PageBuf) {
+    let crc = checksum(buf);
+    buf[CHECKSUM..CHECKSUM + 4].copy_from_slice(&crc.to_le_bytes());
+}
+
+/// Returns `true` if the page matches its checksum or was never written.
+pub fn verify(buf: &PageBuf) -> bool {
+    let crc = u32::from_le_bytes(buf[CHECKSUM..CHECKSUM + 4].try_into().unwrap());
+    crc == checksum(buf) || buf.iter().all(|b| *b == 0)
+}
+
+/// The LSN of the last logged change to the page, 0 if it was never logged.
+pub fn lsn(buf: &PageBuf) -> Lsn {
+    Lsn::from_le_bytes(buf[LSN..LSN + 8].try_into().unwrap())
+}
+
+pub fn set_lsn(buf: &mut PageBuf, lsn: Lsn) {
+    buf[LSN..LSN + 8].copy_from_slice(&lsn.to_le_bytes());
+}
+
+/// Clears a page for the tree's metadata, which is laid out after the common header.
+pub fn encode_meta(buf: &mut PageBuf) {
+    buf.fill(0);
+    buf[TYPE] = META;
+}
+
+pub fn is_meta(buf: &PageBuf) -> bool {
+    buf[TYPE] == META
+}
+
+/// Returns whether a page is the meta page or an internal node, the pages every lookup reads.
+pub fn is_upper(buf: &PageBuf) -> bool {
+    buf[TYPE] == META || buf[TYPE] == INTERNAL
+}
+
+/// Marks a page as free, `next` is the next page on the free list.
+pub fn encode_free(buf: &mut PageBuf, next: Option<PageId>) {
+    buf.fill(0);
+    buf[TYPE] = FREE;
+    buf[NEXT..NEXT + 8].copy_from_slice(&next.unwrap_or(PageId::META).0.to_le_bytes());
+}
+
+/// Returns the next page on the free list, fails if the page isn't free.
+pub fn decode_free(buf: &PageBuf) -> io::Result<Option<PageId>> {
+    if buf[TYPE] != FREE {
+        return Err(invalid(format!("page on the free list has type {}", buf[TYPE])));
+    }
+
+    let next = u64::from_le_bytes(buf[NEXT..NEXT + 8].try_into().unwrap());
+    Ok(Some(PageId(next)).filter(|id| *id != PageId::META))
+}
+
+/// A tree node as stored in a page. Children are referenced by `PageId`, separators follow
+/// `BTree`: each child holds the keys less than its separator.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum PageNode<K, V> {
+    Leaf {
+        entries: Vec<(K, V)>,
+        next: Option<PageId>,
+    },
+    Internal(Vec<(K, PageId)>),
+}
+
+impl<K: Encode, V: Encode> PageNode<K, V> {
+    /// The most entries a leaf page can hold.
+    pub fn leaf_capacity() -> usize {
+        ((PAGE_SIZE - HEADER_SIZE) / (K::SIZE + V::SIZE)).min(u16::MAX as usize)
+    }
+
+    /// The most children an internal page can hold.
+    pub fn internal_capacity() -> usize {
+        ((PAGE_SIZE - HEADER_SIZE) / (K::SIZE + 8)).min(u16::MAX as usize)
+    }
+
+    pub fn t(&self) -> NodeType {
+        match self {
+            PageNode::Leaf { .. } => NodeType::Leaf,
+            PageNode::Internal(_) => NodeType::Internal,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            PageNode::Leaf { entries, .. } => entries.len(),
+            PageNode::Internal(children) => children.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Panics if the node holds more than fits in a page.
+    pub fn encode(&self, buf: &mut PageBuf) {
+        buf.fill(0);
+        buf[COUNT..COUNT + 2].copy_from_slice(&(self.len() as u16).to_le_bytes());
+
+        match self {
+            PageNode::Leaf { entries, next } => {
+                assert!(entries.len() <= Self::leaf_capacity(), "leaf overflows its page");
+
+                buf[TYPE] = LEAF;
+                let next = next.unwrap_or(PageId::META).0;
+                buf[NEXT..NEXT + 8].copy_from_slice(&next.to_le_bytes());
+
+                let size = K::SIZE + V::SIZE;
+                for (i, (k, v)) in entries.iter().enumerate() {
+                    let at = HEADER_SIZE + i * size;
+                    k.encode(&mut buf[at..at + K::SIZE]);
+                    v.encode(&mut buf[at + K::SIZE..at + size]);
+                }
+            }
+            PageNode::Internal(children) => {
+                assert!(children.len() <= Self::internal_capacity(), "node overflows its page");
+
+                buf[TYPE] = INTERNAL;
+
+                let size = K::SIZE + 8;
+                for (i, (k, id)) in children.iter().enumerate() {
+                    let at = HEADER_SIZE + i * size;
+                    k.encode(&mut buf[at..at + K::SIZE]);
+                    buf[at + K::SIZE..at + size].copy_from_slice(&id.0.to_le_bytes());
+                }
+            }
+        }
+    }
+
+    pub fn decode(buf: &PageBuf) -> io::Result<Self> {
+        let count = u16::from_le_bytes([buf[COUNT], buf[COUNT + 1]]) as usize;
+
+        match buf[TYPE] {
+            LEAF => {
+                if count > Self::leaf_capacity() {
+                    return Err(invalid(format!("leaf page holds {count} entries")));
+                }
+
+                let next = u64::from_le_bytes(buf[NEXT..NEXT + 8].try_into().unwrap());
+                let next = Some(PageId(next)).filter(|id| *id != PageId::META);
+
+                let size = K::SIZE + V::SIZE;
+                let entries = (0..count)
+                    .map(|i| {
+                        let at = HEADER_SIZE + i * size;
+                        let k = K::decode(&buf[at..at + K::SIZE]);
+                        let v = V::decode(&buf[at + K::SIZE..at + size]);
+                        (k, v)
+                    })
+                    .collect();
+
+                Ok(PageNode::Leaf { entries, next })
+            }
+            INTERNAL => {
+                if count > Self::internal_capacity() {
+                    return Err(invalid(format!("internal page holds {count} children")));
+                }
+
+                let size = K::SIZE + 8;
+                let children = (0..count)
+                    .map(|i| {
+                        let at = HEADER_SIZE + i * size;
+                        let k = K::decode(&buf[at..at + K::SIZE]);
+                        let id =
+                            u64::from_le_bytes(buf[at + K::SIZE..at + size].try_into().unwrap());
+                        (k, PageId(id))
+                    })
+                    .collect();
+
+                Ok(PageNode::Internal(children))
+            }
+            FREE => Err(invalid("page is on the free list".into())),
+            META => Err(invalid("page holds the tree's metadata".into())),
+            t => Err(invalid(format!("unknown page type {t}"))),
+        }
+    }
+}
+
+impl<K: Encode + Ord, V: Encode> PageNode<K, V> {
+    /// Returns the encoded entries of a leaf page, `K::SIZE + V::SIZE` bytes each, and the next
+    /// leaf, without decoding them.
+    pub fn leaf_entries(buf: &PageBuf) -> io::Result<(&[u8], Option<PageId>)> {
+        if buf[TYPE] != LEAF {
+            return Err(invalid(format!("expected a leaf page, found type {}", buf[TYPE])));
+        }
+        let count = Self::count(buf);
+        if count > Self::leaf_capacity() {
+            return Err(invalid(format!("leaf page holds {count} entries")));
+        }
+
+        let next = u64::from_le_bytes(buf[NEXT..NEXT + 8].try_into().unwrap());
+        let next = Some(PageId(next)).filter(|id| *id != PageId::META);
+        let end = HEADER_SIZE + count * (K::SIZE + V::SIZE);
+        Ok((&buf[HEADER_SIZE..end], next))
+    }
+
+    pub fn is_leaf(buf: &PageBuf) -> bool {
+        buf[TYPE] == LEAF
+    }
+
+    fn count(buf: &PageBuf) -> usize {
+        u16::from_le_bytes([buf[COUNT], buf[COUNT + 1]]) as usize
+    }
+
+    /// Returns the child of an internal page that `key` belongs in, without decoding the rest of
+    /// the page.
+    pub fn find_child(buf: &PageBuf, key: K) -> Option<PageId> {
+        debug_assert!(buf[TYPE] == INTERNAL);
+
+        let size = K::SIZE + 8;
+        let count = Self::count(buf).min(Self::internal_capacity());
+        let at = |i: usize| HEADER_SIZE + i * size;
+
+        // Separators are sorted, find the first one greater than `key`
+        let (mut lo, mut hi) = (0, count);
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            if K::decode(&buf[at(mid)..at(mid) + K::SIZE]) > key {
+                hi = mid;
+            } else {
+                lo = mid + 1;
+            }
+        }
+        if lo == count {
+            return None;
+        }
+
+        let id = &buf[at(lo) + K::SIZE..at(lo) + size];
+        Some(PageId(u64::from_le_bytes(id.try_into().unwrap())))
+    }
+
+    /// Looks `key` up in a leaf page without decoding the rest of the page.
+    pub fn find_entry(buf: &PageBuf, key: K) -> Option<V> {
+        debug_assert!(buf[TYPE] == LEAF);
+
+        let size = K::SIZE + V::SIZE;
+        let count = Self::count(buf).min(Self::leaf_capacity());
+        let at = |i: usize| HEADER_SIZE + i * size;
+
+        let (mut lo, mut hi) = (0, count);
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            match K::decode(&buf[at(mid)..at(mid) + K::SIZE]).cmp(&key) {
+                std::cmp::Ordering::Less => lo = mid + 1,
+                std::cmp::Ordering::Greater => hi = mid,
+                std::cmp::Ordering::Equal => {
+                    return Some(V::decode(&buf[at(mid) + K::SIZE..at(mid) + size]))
+                }
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{PageBuf, PageId, PageNode, PAGE_SIZE};
+
+    #[test]
+    fn test_page_round_trip() {
+        let mut buf: PageBuf = [0; PAGE_SIZE];
+
+        let max = PageNode::<u32, u64>::leaf_capacity();
+        let leaf = PageNode::<u32, u64>::Leaf {
+            entries: (0..max as u32).map(|k| (k, k as u64 * 3)).collect(),
+            next: Some(PageId(7)),
+        };
+        leaf.encode(&mut buf);
+        let have = PageNode::decode(&buf).unwrap();
+        assert!(have == leaf, "Want: {:?}\nHave: {:?}", leaf, have);
+
+        assert!(PageNode::<u32, u64>::find_entry(&buf, 7) == Some(21));
+        assert!(PageNode::<u32, u64>::find_entry(&buf, max as u32).is_none());
+
+        let internal = PageNode::<u32, u64>::Internal(vec![(10, PageId(1)), (20, PageId(2))]);
+        internal.encode(&mut buf);
+        let have = PageNode::decode(&buf).unwrap();
+        assert!(have == internal, "Want: {:?}\nHave: {:?}", internal, have);
+
+        assert!(PageNode::<u32, u64>::find_child(&buf, 9) == Some(PageId(1)));
+        assert!(PageNode::<u32, u64>::find_child(&buf, 10) == Some(PageId(2)));
+        assert!(PageNode::<u32, u64>::find_child(&buf, 20).is_none());
+
+        buf[0] = 9;
+        assert!(PageNode::<u32, u64>::decode(&buf).is_err());
+    }
+}
@@ -0,0 +1,1985 @@
+use std::collections::HashSet;
+use std::fmt::{self, Debug, Display};
+use std::fs;
+use std::io::{self, Write};
+use std::marker::PhantomData;
+use std::ops::{Bound, RangeBounds};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::backup;
+use crate::batch::WriteBatch;
+use crate::btree::{Increment, MergeOperator};
+use crate::buffer::{BufferPool, Capacity, ChecksumPolicy, PageReadGuard, PageWriteGuard};
+use crate::compress::{Codec, CompressionStats};
+use crate::crypt::Key;
+use crate::disk::{self, Backend, DiskManager};
+use crate::display::{self, DisplayOptions, Rendered};
+use crate::dot::{self, DotNode};
+use crate::fault::FaultInjector;
+use crate::format::{self, Legacy, UnsupportedVersion, VERSION};
+use crate::metrics::{self, Counter, Op};
+use crate::observe::{NodeRef, Observer};
+use crate::page::{self, Encode, PageBuf, PageId, PageNode, HEADER_SIZE, PAGE_SIZE};
+use crate::replacer::Policy;
+use crate::run::RunWriter;
+use crate::scan::Scan;
+use crate::stats::TreeStats;
+use crate::store::MemoryStore;
+use crate::tier::Tiering;
+use crate::trace;
+use crate::wal::{Lsn, Record, SyncPolicy, Wal, FILE_HEADER};
+
+// Meta page, after the common page header:
+//
+// | magic (8) | root (8) | len (8) | max (4) | key size (2) | value size (2) | free list (8) |
+// | free pages (8) | version (4) |
+const MAGIC: &[u8; 8] = b"BPTREE\0\0";
+const META: usize = HEADER_SIZE - 8;
+
+/// The log is checkpointed once it grows past this.
+const CHECKPOINT_BYTES: u64 = 64 << 20;
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Returned by a node that split: the new separator for the node and the page holding its greater
+/// half along with that half's separator.
+struct Split<K> {
+    lower: K,
+    upper: K,
+    gt: PageId,
+}
+
+/// The contents of the meta page.
+pub(crate) struct Meta {
+    pub root: Option<PageId>,
+    pub len: u64,
+    pub max: usize,
+    pub free: Option<PageId>,
+    pub free_pages: u64,
+}
+
+impl Meta {
+    /// Fails if `page` isn't the meta page of a tree of `K` and `V`.
+    pub fn decode<K: Encode, V: Encode>(page: &PageBuf) -> io::Result<Self> {
+        let buf = &page[META..];
+        if !page::is_meta(page) || &buf[0..8] != MAGIC {
+            return Err(invalid("not a tree file".into()));
+        }
+
+        let root = u64::from_le_bytes(buf[8..16].try_into().unwrap());
+        let len = u64::from_le_bytes(buf[16..24].try_into().unwrap());
+        let max = u32::from_le_bytes(buf[24..28].try_into().unwrap()) as usize;
+        let k = u16::from_le_bytes([buf[28], buf[29]]) as usize;
+        let v = u16::from_le_bytes([buf[30], buf[31]]) as usize;
+        let free = u64::from_le_bytes(buf[32..40].try_into().unwrap());
+        let free_pages = u64::from_le_bytes(buf[40..48].try_into().unwrap());
+        let version = format::stamped(buf);
+        if version > VERSION {
+            return Err(UnsupportedVersion(version).into());
+        }
+        if k != K::SIZE || v != V::SIZE {
+            return Err(invalid(format!(
+                "tree stores {k} byte keys and {v} byte values, want {} and {}",
+                K::SIZE,
+                V::SIZE
+            )));
+        }
+
+        let capacity = PageNode::<K, V>::leaf_capacity().min(PageNode::<K, V>::internal_capacity());
+        if max < 2 || max > capacity {
+            return Err(invalid(format!("invalid max {max}")));
+        }
+
+        Ok(Self {
+            root: Some(PageId(root)).filter(|id| *id != PageId::META),
+            len,
+            max,
+            free: Some(PageId(free)).filter(|id| *id != PageId::META),
+            free_pages,
+        })
+    }
+
+    pub fn encode<K: Encode, V: Encode>(&self, page: &mut PageBuf) {
+        page::encode_meta(page);
+
+        let buf = &mut page[META..];
+        buf[0..8].copy_from_slice(MAGIC);
+        buf[8..16].copy_from_slice(&self.root.unwrap_or(PageId::META).0.to_le_bytes());
+        buf[16..24].copy_from_slice(&self.len.to_le_bytes());
+        buf[24..28].copy_from_slice(&(self.max as u32).to_le_bytes());
+        buf[28..30].copy_from_slice(&(K::SIZE as u16).to_le_bytes());
+        buf[30..32].copy_from_slice(&(V::SIZE as u16).to_le_bytes());
+        buf[32..40].copy_from_slice(&self.free.unwrap_or(PageId::META).0.to_le_bytes());
+        buf[40..48].copy_from_slice(&self.free_pages.to_le_bytes());
+        buf[48..52].copy_from_slice(&VERSION.to_le_bytes());
+    }
+}
+
+/// What a page write logs.
+enum Change<K, V> {
+    Image,
+    Put(K, V),
+    Remove(K),
+}
+
+/// Settings for a `PagedBTree` created or opened by path.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct Options {
+    pub capacity: Capacity,
+    pub policy: Policy,
+    pub backend: Backend,
+    /// Logs every change to a `Wal` next to the file, synced as the policy says. The pool must
+    /// fit every page one operation changes. Without a log, a crash can lose or corrupt changes
+    /// made since the last `sync()`.
+    pub wal: Option<SyncPolicy>,
+    /// `ChecksumPolicy::Repair` rebuilds a corrupt page from its last image in the log, so it
+    /// only helps for pages changed since the last checkpoint.
+    pub checksum: ChecksumPolicy,
+    /// Compresses pages as they are written back, only when the tree is created. The file then
+    /// ignores `backend`.
+    pub codec: Codec,
+    /// Encrypts pages and the log with the key, which the tree must be opened with from then on.
+    /// The file ignores `backend` like a compressed one.
+    pub key: Option<Key>,
+    /// Keeps the upper levels of the tree and its most used leaves, this many pages in all,
+    /// cached, see `Tiering`.
+    pub hot_pages: Option<usize>,
+}
+
+/// Where the log of the tree at `path` is kept.
+pub fn wal_path(path: &Path) -> PathBuf {
+    let mut wal = path.as_os_str().to_owned();
+    wal.push("-wal");
+    wal.into()
+}
+
+/// How much of a tree's file holds live entries, from `PagedBTree::space()`.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct SpaceReport {
+    pub file_bytes: u64,
+    /// Pages in the file, including the meta page and free pages.
+    pub pages: u64,
+    pub free_pages: u64,
+    /// Bytes taken by the entries themselves.
+    pub live_bytes: u64,
+}
+
+impl SpaceReport {
+    /// The fraction of the file taken by entries.
+    pub fn utilization(&self) -> f64 {
+        match self.file_bytes {
+            0 => 0.0,
+            n => self.live_bytes as f64 / n as f64,
+        }
+    }
+}
+
+impl Display for SpaceReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} bytes in {} pages ({} free), {} bytes live ({:.1}%)",
+            self.file_bytes,
+            self.pages,
+            self.free_pages,
+            self.live_bytes,
+            self.utilization() * 100.0
+        )
+    }
+}
+
+/// A tree stored in a file, one node per page.
+///
+/// Page 0 holds the root's `PageId` and the tree's settings, nodes reference their children and
+/// the next leaf by `PageId`. Pages are read through a `BufferPool`, so only the pages in use
+/// need to be in memory. Modified pages are written back as the pool evicts them, `sync()` writes
+/// the rest and makes every change so far durable.
+///
+/// With a write-ahead log, every change is logged before it is made and pages are stamped with
+/// its LSN. Opening the tree redoes the committed changes the file is missing, whether or not the
+/// log is kept on. The first change to a page after a checkpoint is logged as an image of the whole
+/// page, so a page torn by a crash is rebuilt rather than read.
+///
+/// Like `BTree`, deletes do not rebalance, but a node left empty is unlinked from its parent and
+/// its page is put on a free list kept in the file. New nodes reuse free pages before the file is
+/// grown.
+pub struct PagedBTree<K, V> {
+    pool: BufferPool,
+    root: Option<PageId>,
+    len: u64,
+    max: usize,
+    free: Option<PageId>,
+    free_pages: u64,
+    wal: Option<Arc<Wal>>,
+    // Set by wrappers that wait for group commits after releasing the tree
+    defer_sync: bool,
+    merge: Option<MergeOperator<K, V>>,
+    observer: Option<Arc<dyn Observer<K>>>,
+    _types: PhantomData<(K, V)>,
+}
+
+impl<K, V> PagedBTree<K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment + Encode,
+    V: Clone + Copy + Debug + Encode,
+{
+    /// The largest `max` a node can have and still fit in a page.
+    pub fn capacity() -> usize {
+        PageNode::<K, V>::leaf_capacity().min(PageNode::<K, V>::internal_capacity())
+    }
+
+    /// Creates an empty tree at `path`, truncating any existing file and removing its log.
+    pub fn create<P: AsRef<Path>>(path: P, max: usize) -> io::Result<Self> {
+        Self::create_with_options(path, max, Options::default())
+    }
+
+    /// Like `create`, caching at most `capacity` pages.
+    pub fn create_with_capacity<P: AsRef<Path>>(
+        path: P,
+        max: usize,
+        capacity: Capacity,
+    ) -> io::Result<Self> {
+        let options = Options {
+            capacity,
+            ..Default::default()
+        };
+        Self::create_with_options(path, max, options)
+    }
+
+    pub fn create_with_options<P: AsRef<Path>>(
+        path: P,
+        max: usize,
+        options: Options,
+    ) -> io::Result<Self> {
+        let path = path.as_ref();
+
+        // Before the file is truncated, so the old log is never redone on the new file
+        match fs::remove_file(wal_path(path)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+
+        let disk = match (options.codec, options.key) {
+            (_, Some(key)) => DiskManager::create_encrypted(path, options.codec, key)?,
+            (Codec::None, None) => DiskManager::create_with_backend(path, options.backend)?,
+            (codec, None) => DiskManager::create_compressed(path, codec)?,
+        };
+        let pool = Self::new_pool(disk, &options);
+        let mut tree = Self::create_with_pool(pool, max)?;
+
+        if let Some(policy) = options.wal {
+            // The log only covers changes to a complete file
+            tree.pool.sync()?;
+            let wal = Wal::create_with_key(wal_path(path), 1, policy, options.key)?;
+            tree.attach(Self::shared(wal, &options));
+        }
+
+        Ok(tree)
+    }
+
+    /// Creates a tree kept in a `MemoryStore`, with at most `capacity` pages cached in front of it.
+    pub fn create_in_memory(max: usize, capacity: Capacity) -> io::Result<Self> {
+        Self::create_with_pool(BufferPool::new(MemoryStore::new(), capacity), max)
+    }
+
+    /// Creates a tree in the empty store behind `pool`, for choosing its replacement policy or
+    /// `PageStore`.
+    pub fn create_with_pool(pool: BufferPool, max: usize) -> io::Result<Self> {
+        assert!(max >= 2 && max <= Self::capacity());
+
+        let meta = pool.allocate();
+        assert!(meta == PageId::META);
+
+        let tree = Self {
+            pool,
+            root: None,
+            len: 0,
+            max,
+            free: None,
+            free_pages: 0,
+            wal: None,
+            defer_sync: false,
+            merge: None,
+            observer: None,
+            _types: PhantomData,
+        };
+        tree.write_meta()?;
+
+        Ok(tree)
+    }
+
+    /// Opens the tree at `path`, recovering from its log if it has one. The log is removed once
+    /// recovered.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::open_with_options(path, Options::default())
+    }
+
+    /// Like `open`, caching at most `capacity` pages.
+    pub fn open_with_capacity<P: AsRef<Path>>(path: P, capacity: Capacity) -> io::Result<Self> {
+        let options = Options {
+            capacity,
+            ..Default::default()
+        };
+        Self::open_with_options(path, options)
+    }
+
+    /// Fails with `UnsupportedVersion` if the file is in another format, see `migrate()`.
+    pub fn open_with_options<P: AsRef<Path>>(path: P, options: Options) -> io::Result<Self> {
+        let path = path.as_ref();
+        let wal_path = wal_path(path);
+        format::check(path)?;
+
+        let disk = match options.key {
+            Some(key) => DiskManager::open_encrypted(path, key)?,
+            // A page torn as it was appended is rebuilt from the log
+            None if wal_path.exists() => DiskManager::open_torn(path, options.backend)?,
+            None => DiskManager::open_with_backend(path, options.backend)?,
+        };
+        let pool = Self::new_pool(disk, &options);
+
+        let wal = if wal_path.exists() {
+            let policy = options.wal.unwrap_or(SyncPolicy::Off);
+            let (wal, records) = Wal::open_with_key(&wal_path, policy, options.key)?;
+            // So corrupt pages can be repaired during recovery
+            let wal = Self::shared(wal, &options);
+            pool.set_wal(Some(wal.clone()));
+
+            Self::redo(&pool, records)?;
+            pool.sync()?;
+            wal.checkpoint()?;
+
+            if options.wal.is_none() {
+                pool.set_wal(None);
+                drop(wal);
+                fs::remove_file(&wal_path)?;
+                None
+            } else {
+                Some(wal)
+            }
+        } else {
+            None
+        };
+
+        let mut tree = Self::open_with_pool(pool)?;
+        match (wal, options.wal) {
+            (Some(wal), _) => tree.attach(wal),
+            (None, Some(policy)) => {
+                // Past every LSN on a page, even ones from a log since removed
+                let first = page::lsn(&*tree.pool.fetch(PageId::META)?) + 1;
+                let wal = Wal::create_with_key(&wal_path, first, policy, options.key)?;
+                tree.attach(Self::shared(wal, &options));
+            }
+            (None, None) => {}
+        }
+
+        Ok(tree)
+    }
+
+    /// Rewrites the tree at `path` in the current format if it is in an older one, and returns the
+    /// version it was in. The tree must have been closed with nothing left in its log, a crash
+    /// leaves it in either format.
+    pub fn migrate<P: AsRef<Path>>(path: P) -> io::Result<Option<u32>> {
+        let path = path.as_ref();
+
+        let version = format::version(path)?;
+        if version == VERSION {
+            return Ok(None);
+        }
+        let old = Legacy::<K, V>::open(path, version)?;
+        if fs::metadata(wal_path(path)).is_ok_and(|wal| wal.len() > FILE_HEADER) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "tree has a log to recover with the version that wrote it",
+            ));
+        }
+
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push("-migrate");
+        let tmp = PathBuf::from(tmp);
+
+        // Older pages hold more, so nodes may have to be smaller
+        let mut tree = Self::create(&tmp, old.max.min(Self::capacity()))?;
+        for (k, v) in old.entries()? {
+            tree.insert(k, v)?;
+        }
+        tree.sync()?;
+        drop(tree);
+
+        fs::rename(&tmp, path)?;
+        disk::sync_dir(path)?;
+        match fs::remove_file(wal_path(path)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+
+        Ok(Some(version))
+    }
+
+    /// Appends to `wal` through the same kind of I/O as the pages.
+    fn shared(mut wal: Wal, options: &Options) -> Arc<Wal> {
+        if options.backend == Backend::IoUring {
+            wal.use_io_uring();
+        }
+
+        Arc::new(wal)
+    }
+
+    pub fn open_with_pool(pool: BufferPool) -> io::Result<Self> {
+        if pool.pages() == 0 {
+            return Err(invalid("missing meta page".into()));
+        }
+
+        let meta = Meta::decode::<K, V>(&*pool.fetch(PageId::META)?)?;
+        Ok(Self {
+            pool,
+            root: meta.root,
+            len: meta.len,
+            max: meta.max,
+            free: meta.free,
+            free_pages: meta.free_pages,
+            wal: None,
+            defer_sync: false,
+            merge: None,
+            observer: None,
+            _types: PhantomData,
+        })
+    }
+
+    fn new_pool(disk: DiskManager, options: &Options) -> BufferPool {
+        let pool = BufferPool::with_policy(disk, options.capacity, options.policy);
+        pool.set_checksum_policy(options.checksum);
+        pool.set_repair(Some(Self::repair));
+        pool.set_tiering(options.hot_pages.map(|n| Tiering::new(n, page::is_upper)));
+        pool
+    }
+
+    fn attach(&mut self, wal: Arc<Wal>) {
+        self.pool.set_wal(Some(wal.clone()));
+        self.wal = Some(wal);
+    }
+
+    /// Applies the committed `records` to every page with an older LSN. A page first logged as an
+    /// image is rebuilt from the log without being read, as it may have been torn.
+    fn redo(pool: &BufferPool, records: Vec<(Lsn, Record)>) -> io::Result<()> {
+        let mut seen = HashSet::new();
+        for (lsn, record) in records {
+            let id = match record.page() {
+                Some(id) => id,
+                None => continue,
+            };
+            // Allocated after the file was last synced
+            while pool.pages() <= id.0 {
+                pool.allocate();
+            }
+
+            let mut page = match record {
+                Record::Image { .. } if !seen.contains(&id) => pool.fetch_zeroed(id)?,
+                _ => pool.fetch_mut(id)?,
+            };
+            seen.insert(id);
+
+            if page::lsn(&page) < lsn {
+                Self::apply(&mut page, lsn, record)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Rebuilds page `id` from its last image in the log and the changes after it.
+    fn repair(wal: &Wal, id: PageId, buf: &mut PageBuf) -> io::Result<bool> {
+        let records = wal.records()?;
+        let start = records
+            .iter()
+            .rposition(|(_, r)| matches!(r, Record::Image { page, .. } if *page == id));
+        let start = match start {
+            Some(start) => start,
+            None => return Ok(false),
+        };
+
+        for (lsn, record) in records.into_iter().skip(start) {
+            if record.page() == Some(id) {
+                Self::apply(buf, lsn, record)?;
+            }
+        }
+
+        Ok(true)
+    }
+
+    fn apply(page: &mut PageBuf, lsn: Lsn, record: Record) -> io::Result<()> {
+        match record {
+            Record::Image { data, .. } => {
+                page.fill(0);
+                page[..data.len()].copy_from_slice(&data);
+            }
+            Record::Put { entry, .. } => {
+                let key = K::decode(&entry[..K::SIZE]);
+                let value = V::decode(&entry[K::SIZE..]);
+                Self::redo_leaf(page, |entries| {
+                    match entries.binary_search_by(|e| e.0.cmp(&key)) {
+                        Ok(i) => entries[i].1 = value,
+                        Err(i) => entries.insert(i, (key, value)),
+                    }
+                })?;
+            }
+            Record::Remove { key, .. } => {
+                let key = K::decode(&key);
+                Self::redo_leaf(page, |entries| {
+                    if let Ok(i) = entries.binary_search_by(|e| e.0.cmp(&key)) {
+                        entries.remove(i);
+                    }
+                })?;
+            }
+            Record::Commit => return Ok(()),
+        }
+        page::set_lsn(page, lsn);
+
+        Ok(())
+    }
+
+    fn redo_leaf(page: &mut PageBuf, f: impl FnOnce(&mut Vec<(K, V)>)) -> io::Result<()> {
+        match PageNode::<K, V>::decode(page)? {
+            PageNode::Leaf { mut entries, next } => {
+                f(&mut entries);
+                PageNode::Leaf { entries, next }.encode(page);
+                Ok(())
+            }
+            PageNode::Internal(_) => Err(invalid("leaf change logged for an internal page".into())),
+        }
+    }
+
+    fn write_meta(&self) -> io::Result<()> {
+        let meta = Meta {
+            root: self.root,
+            len: self.len,
+            max: self.max,
+            free: self.free,
+            free_pages: self.free_pages,
+        };
+        let mut page = [0; PAGE_SIZE];
+        meta.encode::<K, V>(&mut page);
+
+        self.put(&mut self.pool.fetch_mut(PageId::META)?, &page, Change::Image);
+        Ok(())
+    }
+
+    /// Overwrites `page` with `buf`, logging `change` first. Without a log the page keeps its
+    /// LSN, so LSNs never go backwards.
+    fn put(&self, page: &mut PageWriteGuard<'_>, buf: &PageBuf, change: Change<K, V>) {
+        let lsn = match &self.wal {
+            Some(wal) => {
+                let id = page.id();
+                let record = match change {
+                    Change::Image => Record::image(id, buf),
+                    // So recovery never has to read a page that could be torn
+                    _ if wal.needs_image(id) => Record::image(id, buf),
+                    Change::Put(k, v) => {
+                        let mut entry = vec![0; K::SIZE + V::SIZE];
+                        k.encode(&mut entry[..K::SIZE]);
+                        v.encode(&mut entry[K::SIZE..]);
+                        Record::Put { page: id, entry }
+                    }
+                    Change::Remove(k) => {
+                        let mut key = vec![0; K::SIZE];
+                        k.encode(&mut key);
+                        Record::Remove { page: id, key }
+                    }
+                };
+                wal.append(&record)
+            }
+            None => page::lsn(page),
+        };
+
+        **page = *buf;
+        page::set_lsn(page, lsn);
+    }
+
+    /// Ends an operation, checkpointing if the log has grown too large.
+    fn commit(&self) -> io::Result<()> {
+        if let Some(wal) = &self.wal {
+            let lsn = wal.commit()?;
+            if !self.defer_sync {
+                wal.wait_durable(lsn)?;
+            }
+            if wal.size() > CHECKPOINT_BYTES {
+                self.checkpoint()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn read(&self, id: PageId) -> io::Result<PageNode<K, V>> {
+        PageNode::decode(&*self.pool.fetch(id)?)
+    }
+
+    fn write(&self, id: PageId, node: &PageNode<K, V>, change: Change<K, V>) -> io::Result<()> {
+        let mut buf = [0; PAGE_SIZE];
+        node.encode(&mut buf);
+        self.put(&mut self.pool.fetch_mut(id)?, &buf, change);
+
+        Ok(())
+    }
+
+    /// Writes `node` to a page from the free list, or a newly allocated one if it is empty.
+    fn write_new(&mut self, node: &PageNode<K, V>) -> io::Result<PageId> {
+        let mut page = match self.free {
+            Some(id) => {
+                let page = self.pool.fetch_mut(id)?;
+                self.free = page::decode_free(&page)?;
+                self.free_pages -= 1;
+                page
+            }
+            None => self.pool.new_page()?,
+        };
+
+        let mut buf = [0; PAGE_SIZE];
+        node.encode(&mut buf);
+        self.put(&mut page, &buf, Change::Image);
+
+        if let Some(observer) = &self.observer {
+            let leaf = matches!(node, PageNode::Leaf { .. });
+            observer.on_node_alloc(Self::node_ref(page.id(), leaf));
+        }
+
+        Ok(page.id())
+    }
+
+    /// Puts page `id` on the free list.
+    fn free(&mut self, id: PageId) -> io::Result<()> {
+        if let Some(observer) = &self.observer {
+            let leaf = PageNode::<K, V>::is_leaf(&*self.pool.fetch(id)?);
+            observer.on_node_free(Self::node_ref(id, leaf));
+        }
+
+        let mut buf = [0; PAGE_SIZE];
+        page::encode_free(&mut buf, self.free);
+        self.put(&mut self.pool.fetch_mut(id)?, &buf, Change::Image);
+
+        self.free = Some(id);
+        self.free_pages += 1;
+
+        Ok(())
+    }
+
+    /// Returns the previous value of `key`.
+    pub fn insert(&mut self, key: K, value: V) -> io::Result<Option<V>> {
+        metrics::count(Counter::Inserts);
+        let _timer = metrics::time(Op::Insert);
+        trace::span!(TRACE, "insert", key = ?key);
+        self.put_with(key, |_| value)
+    }
+
+    /// Sets the function `merge()` combines operands with, such as adding them to a counter.
+    pub fn set_merge_operator<F>(&mut self, merge: F)
+    where
+        F: Fn(K, Option<V>, V) -> V + Send + Sync + 'static,
+    {
+        self.merge = Some(Box::new(merge));
+    }
+
+    /// Combines `operand` with the value of `key` using the merge operator in one pass down the
+    /// tree, and logs the new value, which it returns. Panics unless a merge operator is set.
+    pub fn merge(&mut self, key: K, operand: V) -> io::Result<V> {
+        trace::span!(TRACE, "merge", key = ?key);
+        let merge = self.merge.take().expect("no merge operator set");
+        let mut new = None;
+        let res = self.put_with(key, |old| *new.insert(merge(key, old, operand)));
+        self.merge = Some(merge);
+
+        res.map(|_| new.unwrap())
+    }
+
+    /// Puts the value `value` returns given the old one, returning the old one.
+    fn put_with<F: FnOnce(Option<V>) -> V>(&mut self, key: K, value: F) -> io::Result<Option<V>> {
+        let old = self.put_uncommitted(key, value)?;
+        self.write_meta()?;
+        self.commit()?;
+
+        Ok(old)
+    }
+
+    /// `put_with()` as part of an operation, which the caller commits.
+    fn put_uncommitted<F>(&mut self, key: K, value: F) -> io::Result<Option<V>>
+    where
+        F: FnOnce(Option<V>) -> V,
+    {
+        let root = match self.root {
+            Some(root) => root,
+            None => {
+                let root = self.write_new(&PageNode::Leaf {
+                    entries: Vec::new(),
+                    next: None,
+                })?;
+                self.root = Some(root);
+                if let Some(observer) = &self.observer {
+                    observer.on_root_change(None, Some(Self::node_ref(root, true)));
+                }
+                root
+            }
+        };
+
+        let (old, split) = self._insert(root, key, value)?;
+        if let Some(Split { lower, upper, gt }) = split {
+            let new_root = self.write_new(&PageNode::Internal(vec![(lower, root), (upper, gt)]))?;
+            self.root = Some(new_root);
+            if let Some(observer) = &self.observer {
+                let old = Self::node_ref(root, PageNode::<K, V>::is_leaf(&*self.pool.fetch(root)?));
+                observer.on_root_change(Some(old), Some(Self::node_ref(new_root, false)));
+            }
+        }
+
+        if old.is_none() {
+            self.len += 1;
+        }
+
+        Ok(old)
+    }
+
+    fn _insert<F: FnOnce(Option<V>) -> V>(
+        &mut self,
+        id: PageId,
+        key: K,
+        value: F,
+    ) -> io::Result<(Option<V>, Option<Split<K>>)> {
+        let mut node = self.read(id)?;
+
+        let (old, change) = match &mut node {
+            PageNode::Leaf { entries, .. } => {
+                let (i, old) = match entries.binary_search_by(|e| e.0.cmp(&key)) {
+                    Ok(i) => (i, Some(entries[i].1)),
+                    Err(i) => (i, None),
+                };
+                let value = value(old);
+                match old {
+                    Some(_) => entries[i].1 = value,
+                    None => entries.insert(i, (key, value)),
+                }
+
+                (old, Some(Change::Put(key, value)))
+            }
+            PageNode::Internal(children) => {
+                let mut changed = false;
+                let i = match children.iter().position(|c| key < c.0) {
+                    Some(i) => i,
+                    None => {
+                        let last = children.len() - 1;
+                        children[last].0 = key.next();
+                        changed = true;
+                        last
+                    }
+                };
+
+                let (old, split) = self._insert(children[i].1, key, value)?;
+                if let Some(Split { lower, gt, .. }) = split {
+                    // The greater half keeps the child's old separator
+                    let sep = children[i].0;
+                    children[i].0 = lower;
+                    children.insert(i + 1, (sep, gt));
+                    changed = true;
+                }
+
+                (old, changed.then_some(Change::Image))
+            }
+        };
+
+        if node.len() <= self.max {
+            if let Some(change) = change {
+                self.write(id, &node, change)?;
+            }
+            return Ok((old, None));
+        }
+
+        metrics::count(Counter::Splits);
+        let split = match &mut node {
+            PageNode::Leaf { entries, next } => {
+                let gt_entries = entries.split_off(entries.len() / 2);
+                let first = gt_entries[0].0;
+                let upper = gt_entries.last().unwrap().0.next();
+                let gt = self.write_new(&PageNode::Leaf {
+                    entries: gt_entries,
+                    next: *next,
+                })?;
+                *next = Some(gt);
+
+                // A `next()` that isn't the least key after another may pass the greater half
+                let lower = entries.last().unwrap().0.next().min(first);
+
+                Split { lower, upper, gt }
+            }
+            PageNode::Internal(children) => {
+                let gt_children = children.split_off(children.len() / 2);
+                let upper = gt_children.last().unwrap().0;
+                let gt = self.write_new(&PageNode::Internal(gt_children))?;
+
+                Split {
+                    lower: children.last().unwrap().0,
+                    upper,
+                    gt,
+                }
+            }
+        };
+        if let Some(observer) = &self.observer {
+            let leaf = matches!(node, PageNode::Leaf { .. });
+            let (node, gt) = (Self::node_ref(id, leaf), Self::node_ref(split.gt, leaf));
+            observer.on_split(node, gt, split.lower);
+        }
+        trace::event!(TRACE, page = id.0, gt = split.gt.0, separator = ?split.lower, "split");
+        self.write(id, &node, Change::Image)?;
+
+        Ok((old, Some(split)))
+    }
+
+    /// Returns the pinned leaf `key` belongs in, or `None` if it is greater than every separator.
+    /// Only one page is pinned at a time on the way down.
+    fn find_leaf(&self, key: K) -> io::Result<Option<PageReadGuard<'_>>> {
+        let mut id = match self.root {
+            Some(root) => root,
+            None => return Ok(None),
+        };
+
+        loop {
+            let page = self.pool.fetch(id)?;
+            if PageNode::<K, V>::is_leaf(&page) {
+                return Ok(Some(page));
+            }
+
+            id = match PageNode::<K, V>::find_child(&page, key) {
+                Some(child) => child,
+                None => return Ok(None),
+            };
+        }
+    }
+
+    pub fn get(&self, key: K) -> io::Result<Option<V>> {
+        metrics::count(Counter::Gets);
+        let _timer = metrics::time(Op::Get);
+        trace::span!(TRACE, "get", key = ?key);
+        Ok(self
+            .find_leaf(key)?
+            .and_then(|leaf| PageNode::<K, V>::find_entry(&leaf, key)))
+    }
+
+    pub fn delete(&mut self, key: K) -> io::Result<Option<V>> {
+        metrics::count(Counter::Deletes);
+        let _timer = metrics::time(Op::Delete);
+        trace::span!(TRACE, "delete", key = ?key);
+        let old = self.delete_uncommitted(key)?;
+        if old.is_some() {
+            self.write_meta()?;
+            self.commit()?;
+        }
+
+        Ok(old)
+    }
+
+    /// `delete()` as part of an operation, which the caller commits.
+    fn delete_uncommitted(&mut self, key: K) -> io::Result<Option<V>> {
+        // Check first so nothing is written if `key` isn't there
+        match self.find_leaf(key)? {
+            Some(leaf) if PageNode::<K, V>::find_entry(&leaf, key).is_some() => {}
+            _ => return Ok(None),
+        }
+
+        let root = self.root.unwrap();
+        let (old, emptied) = self._delete(root, key)?;
+        if emptied {
+            if let Some(observer) = &self.observer {
+                let leaf = PageNode::<K, V>::is_leaf(&*self.pool.fetch(root)?);
+                observer.on_root_change(Some(Self::node_ref(root, leaf)), None);
+            }
+            self.free(root)?;
+            self.root = None;
+        }
+
+        self.len -= 1;
+
+        Ok(old)
+    }
+
+    /// Applies every put and delete of `batch` in key order as one operation. With a log they are
+    /// logged and committed together, so a crash leaves the tree with all of them or none. Without
+    /// one, pages are written back in place and a crash can leave any of them.
+    ///
+    /// Like any operation, the pool must fit every page it changes. Nothing is rolled back if one
+    /// fails, such as when the pool runs out of frames: the writes before it stay in the tree and
+    /// are committed with the next operation.
+    pub fn write_batch(&mut self, batch: WriteBatch<K, V>) -> io::Result<()> {
+        trace::span!(TRACE, "write_batch", len = batch.len());
+        for (key, value) in batch.into_ops() {
+            match value {
+                Some(value) => {
+                    metrics::count(Counter::Inserts);
+                    self.put_uncommitted(key, |_| value)?;
+                }
+                None => {
+                    metrics::count(Counter::Deletes);
+                    self.delete_uncommitted(key)?;
+                }
+            }
+        }
+        self.write_meta()?;
+        self.commit()
+    }
+
+    /// Returns `true` if the node was left empty, the caller unlinks and frees it.
+    fn _delete(&mut self, id: PageId, key: K) -> io::Result<(Option<V>, bool)> {
+        let mut node = self.read(id)?;
+
+        let (old, change) = match &mut node {
+            PageNode::Leaf { entries, next } => {
+                let old = match entries.binary_search_by(|e| e.0.cmp(&key)) {
+                    Ok(i) => entries.remove(i).1,
+                    Err(_) => return Ok((None, false)),
+                };
+
+                if entries.is_empty() {
+                    self.unlink_leaf(key, *next)?;
+                    return Ok((Some(old), true));
+                }
+
+                (old, Change::Remove(key))
+            }
+            PageNode::Internal(children) => {
+                let i = match children.iter().position(|c| key < c.0) {
+                    Some(i) => i,
+                    None => return Ok((None, false)),
+                };
+
+                let (old, emptied) = self._delete(children[i].1, key)?;
+                if emptied {
+                    self.free(children[i].1)?;
+                    let (sep, _) = children.remove(i);
+
+                    if children.is_empty() {
+                        return Ok((old, true));
+                    }
+                    // The new last child takes over the keys up to the separator of the old one
+                    if i == children.len() {
+                        children[i - 1].0 = sep;
+                        self.raise_last(children[i - 1].1, sep)?;
+                    }
+                }
+
+                match old {
+                    Some(old) if emptied => (old, Change::Image),
+                    Some(old) => return Ok((Some(old), false)),
+                    None => return Ok((None, false)),
+                }
+            }
+        };
+
+        self.write(id, &node, change)?;
+        Ok((Some(old), false))
+    }
+
+    /// Sets the last separator of every internal node down the right edge of the subtree at `id`
+    /// to `sep`, the separator above it.
+    fn raise_last(&mut self, id: PageId, sep: K) -> io::Result<()> {
+        let mut id = id;
+        while let PageNode::Internal(mut children) = self.read(id)? {
+            let last = children.last_mut().unwrap();
+            last.0 = sep;
+            let child = last.1;
+            self.write(id, &PageNode::Internal(children), Change::Image)?;
+            id = child;
+        }
+
+        Ok(())
+    }
+
+    /// Points the leaf before the one `key` is in at `next`, taking the emptied leaf out of the
+    /// chain.
+    fn unlink_leaf(&mut self, key: K, next: Option<PageId>) -> io::Result<()> {
+        // The previous leaf is the rightmost one under the closest child to the left of the path
+        let mut prev = None;
+        let mut id = self.root.unwrap();
+        loop {
+            match self.read(id)? {
+                PageNode::Leaf { .. } => break,
+                PageNode::Internal(children) => {
+                    let i = children.iter().position(|c| key < c.0).unwrap();
+                    if i > 0 {
+                        prev = Some(children[i - 1].1);
+                    }
+                    id = children[i].1;
+                }
+            }
+        }
+
+        let mut id = match prev {
+            Some(id) => id,
+            None => return Ok(()),
+        };
+        loop {
+            match self.read(id)? {
+                PageNode::Leaf { entries, .. } => {
+                    return self.write(id, &PageNode::Leaf { entries, next }, Change::Image);
+                }
+                PageNode::Internal(children) => id = children.last().unwrap().1,
+            }
+        }
+    }
+
+    /// Merges leaves less than half full into their right sibling where the two fit in one
+    /// node, at most `limit` of them. Each merge is an operation of its own. Returns the number
+    /// of pages freed.
+    pub fn merge_underfull(&mut self, limit: usize) -> io::Result<usize> {
+        match self.root {
+            Some(root) if limit > 0 => self._merge_underfull(root, limit),
+            _ => Ok(0),
+        }
+    }
+
+    fn _merge_underfull(&mut self, id: PageId, limit: usize) -> io::Result<usize> {
+        let mut children = match self.read(id)? {
+            PageNode::Internal(children) => children,
+            PageNode::Leaf { .. } => return Ok(0),
+        };
+
+        let mut merged = 0;
+        // Every child of a node is at the same depth
+        if !PageNode::<K, V>::is_leaf(&*self.pool.fetch(children[0].1)?) {
+            for (_, child) in children {
+                merged += self._merge_underfull(child, limit - merged)?;
+                if merged == limit {
+                    break;
+                }
+            }
+            return Ok(merged);
+        }
+
+        let mut i = 0;
+        while i + 1 < children.len() && merged < limit {
+            let (left, right, next) =
+                match (self.read(children[i].1)?, self.read(children[i + 1].1)?) {
+                    (PageNode::Leaf { entries: l, .. }, PageNode::Leaf { entries: r, next }) => {
+                        (l, r, next)
+                    }
+                    _ => return Err(invalid(format!("page {} has mixed children", id.0))),
+                };
+            if left.len() * 2 >= self.max || left.len() + right.len() > self.max {
+                i += 1;
+                continue;
+            }
+
+            let mut entries = left;
+            entries.extend(right);
+            self.write(children[i].1, &PageNode::Leaf { entries, next }, Change::Image)?;
+            if let Some(observer) = &self.observer {
+                let into = Self::node_ref(children[i].1, true);
+                observer.on_merge(into, Self::node_ref(children[i + 1].1, true));
+            }
+            self.free(children[i + 1].1)?;
+            children[i].0 = children[i + 1].0;
+            children.remove(i + 1);
+            self.write(id, &PageNode::Internal(children.clone()), Change::Image)?;
+            self.write_meta()?;
+            self.commit()?;
+            metrics::count(Counter::Merges);
+            trace::event!(TRACE, page = children[i].1 .0, separator = ?children[i].0, "merge");
+            merged += 1;
+        }
+
+        Ok(merged)
+    }
+
+    /// Returns the entries with keys in `range`, in order, following the leaf chain.
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> io::Result<Vec<(K, V)>> {
+        trace::span!(TRACE, "range", start = ?range.start_bound(), end = ?range.end_bound());
+        let _timer = metrics::time(Op::Scan);
+        let start = match range.start_bound() {
+            Bound::Included(k) | Bound::Excluded(k) => self.find_leaf(*k)?,
+            Bound::Unbounded => self.leftmost_leaf()?,
+        };
+
+        let mut out = Vec::new();
+        let mut leaf = match start {
+            Some(page) => Some((page.id(), PageNode::decode(&page)?)),
+            None => None,
+        };
+        // The last leaf read ahead, the next batch is read on reaching it
+        let mut ahead = None;
+        while let Some((id, PageNode::Leaf { entries, next })) = leaf {
+            // Read ahead while this leaf is scanned, if the range goes on past it
+            match (next, entries.last()) {
+                (Some(_), Some(&(k, _)))
+                    if is_before_end(&range, k) && ahead.is_none_or(|a| a == id) =>
+                {
+                    // Only a hint, a failure is left to the read of the leaf
+                    let ids = leaves_after::<K, V, _>(&self.pool, self.root, k, &range);
+                    let ids = ids.unwrap_or_default();
+                    ahead = ids.last().copied();
+                    self.pool.read_ahead(&ids);
+                }
+                _ => {}
+            }
+            for (k, v) in entries {
+                if !is_before_end(&range, k) {
+                    return Ok(out);
+                }
+                if range.contains(&k) {
+                    out.push((k, v));
+                }
+            }
+
+            leaf = match next {
+                Some(id) => Some((id, self.read(id)?)),
+                None => None,
+            };
+        }
+
+        Ok(out)
+    }
+
+    pub fn iter(&self) -> io::Result<Vec<(K, V)>> {
+        self.range(..)
+    }
+
+    /// Returns a cursor over the entries with keys in `range`, which reads them out of each leaf
+    /// page while it is pinned rather than collecting them, see `Scan`.
+    pub fn scan<R: RangeBounds<K>>(&self, range: R) -> io::Result<Scan<'_, K, V>> {
+        trace::span!(TRACE, "scan", start = ?range.start_bound(), end = ?range.end_bound());
+        let start = match range.start_bound() {
+            Bound::Included(k) | Bound::Excluded(k) => self.find_leaf(*k)?,
+            Bound::Unbounded => self.leftmost_leaf()?,
+        };
+        let range = (range.start_bound().cloned(), range.end_bound().cloned());
+
+        Ok(Scan::new(&self.pool, self.root, start, range))
+    }
+
+    /// Writes every entry to `writer` as a `SortedRun`, a leaf at a time, returning the number
+    /// written.
+    pub fn export_sorted_run<W: Write>(&self, writer: W) -> io::Result<u64> {
+        let mut run = RunWriter::new(writer);
+        let mut leaf = match self.leftmost_leaf()? {
+            Some(page) => Some(PageNode::decode(&page)?),
+            None => None,
+        };
+        while let Some(PageNode::Leaf { entries, next }) = leaf {
+            for (k, v) in entries {
+                run.push(k, v)?;
+            }
+
+            leaf = match next {
+                Some(id) => Some(self.read(id)?),
+                None => None,
+            };
+        }
+
+        run.finish()
+    }
+
+    fn leftmost_leaf(&self) -> io::Result<Option<PageReadGuard<'_>>> {
+        let mut id = match self.root {
+            Some(root) => root,
+            None => return Ok(None),
+        };
+
+        loop {
+            let page = self.pool.fetch(id)?;
+            match PageNode::<K, V>::decode(&page)? {
+                PageNode::Leaf { .. } => return Ok(Some(page)),
+                PageNode::Internal(children) => id = children[0].1,
+            }
+        }
+    }
+
+    /// Writes the nodes of the tree to `writer` as a Graphviz DOT graph, like `BTree::to_dot()`,
+    /// with the page IDs as node names.
+    pub fn to_dot<W: Write>(&self, writer: W) -> io::Result<()> {
+        let mut nodes = Vec::new();
+        let mut level = self.root.into_iter().collect::<Vec<_>>();
+        while !level.is_empty() {
+            let mut below = Vec::new();
+            for id in level {
+                let node = match self.read(id)? {
+                    PageNode::Leaf { entries, next } => DotNode {
+                        id: id.0,
+                        leaf: true,
+                        slots: entries
+                            .iter()
+                            .map(|(k, v)| format!("{:?}: {:?}", k, v))
+                            .collect(),
+                        children: Vec::new(),
+                        next: next.map(|next| next.0),
+                    },
+                    PageNode::Internal(children) => {
+                        below.extend(children.iter().map(|(_, child)| *child));
+                        DotNode {
+                            id: id.0,
+                            leaf: false,
+                            slots: children.iter().map(|(k, _)| format!("{:?}", k)).collect(),
+                            children: children.iter().map(|(_, child)| child.0).collect(),
+                            next: None,
+                        }
+                    }
+                };
+                nodes.push(node);
+            }
+            level = below;
+        }
+
+        dot::write_dot(writer, &nodes)
+    }
+
+    /// Checks the structure of the tree like `BTree::validate()`, and that it holds `len()`
+    /// entries. Fails with `InvalidData` saying what is wrong.
+    pub fn validate(&self) -> io::Result<()> {
+        let root = match self.root {
+            Some(root) => root,
+            None if self.len == 0 => return Ok(()),
+            None => return Err(invalid(format!("empty tree of {} entries", self.len))),
+        };
+
+        let mut leaves = Vec::new();
+        let mut len = 0;
+        self._validate(root, (None, None), 0, &mut leaves, &mut len)?;
+
+        if let Some((_, _, depth)) = leaves.iter().find(|(_, _, d)| *d != leaves[0].2) {
+            return Err(invalid(format!("leaves at depths {} and {depth}", leaves[0].2)));
+        }
+        let chain = leaves
+            .iter()
+            .skip(1)
+            .map(|(id, _, _)| Some(*id))
+            .chain([None]);
+        for ((id, next, _), want) in leaves.iter().zip(chain) {
+            if *next != want {
+                return Err(invalid(format!(
+                    "leaf page {} links to {:?}, not {:?}",
+                    id.0, next, want
+                )));
+            }
+        }
+        if len != self.len {
+            return Err(invalid(format!("tree of {} entries holds {len}", self.len)));
+        }
+
+        Ok(())
+    }
+
+    fn _validate(
+        &self,
+        id: PageId,
+        (lo, hi): (Option<K>, Option<K>),
+        depth: usize,
+        leaves: &mut Vec<(PageId, Option<PageId>, usize)>,
+        len: &mut u64,
+    ) -> io::Result<()> {
+        let node = self.read(id)?;
+        let (keys, leaf) = match &node {
+            PageNode::Leaf { entries, .. } => {
+                (entries.iter().map(|e| e.0).collect::<Vec<_>>(), true)
+            }
+            PageNode::Internal(children) => (children.iter().map(|c| c.0).collect(), false),
+        };
+
+        if keys.windows(2).any(|w| w[0] >= w[1]) {
+            return Err(invalid(format!("page {} has keys out of order: {:?}", id.0, keys)));
+        }
+        // An internal node's last separator is its parent's separator for it
+        let outside = |k: &K| match leaf {
+            true => lo.is_some_and(|lo| *k < lo) || hi.is_some_and(|hi| *k >= hi),
+            false => lo.is_some_and(|lo| *k <= lo) || hi.is_some_and(|hi| *k > hi),
+        };
+        if let Some(k) = keys.iter().find(|k| outside(k)) {
+            return Err(invalid(format!(
+                "page {} has key {:?} outside of {:?}..{:?}",
+                id.0, k, lo, hi
+            )));
+        }
+        if !leaf && hi.is_some() && keys.last() != hi.as_ref() {
+            return Err(invalid(format!(
+                "page {} ends at {:?} rather than its separator {:?}",
+                id.0,
+                keys.last(),
+                hi
+            )));
+        }
+
+        match node {
+            PageNode::Leaf { entries, next } => {
+                *len += entries.len() as u64;
+                leaves.push((id, next, depth));
+            }
+            PageNode::Internal(children) if children.is_empty() => {
+                return Err(invalid(format!("internal page {} has no children", id.0)));
+            }
+            PageNode::Internal(children) => {
+                let mut lo = lo;
+                for (k, child) in children {
+                    self._validate(child, (lo, Some(k)), depth + 1, leaves, len)?;
+                    lo = Some(k);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes the nodes of the tree to `writer` level by level, like `BTree::display()`.
+    pub fn write_tree<W: Write>(&self, mut writer: W, options: DisplayOptions) -> io::Result<()> {
+        let mut levels = Vec::new();
+        let mut level = self.root.into_iter().collect::<Vec<_>>();
+        while !level.is_empty() {
+            let mut below = Vec::new();
+            let mut rendered = Vec::with_capacity(level.len());
+            for id in level {
+                let slots = match self.read(id)? {
+                    PageNode::Leaf { entries, .. } => entries
+                        .iter()
+                        .map(|(k, v)| format!("{:?}: {}", k, options.value(v)))
+                        .collect(),
+                    PageNode::Internal(children) => children
+                        .iter()
+                        .map(|(k, child)| {
+                            below.push(*child);
+                            format!("{:?}", k)
+                        })
+                        .collect(),
+                };
+
+                rendered.push(Rendered {
+                    address: format!("#{}", id.0),
+                    slots,
+                });
+            }
+
+            levels.push(rendered);
+            level = below;
+        }
+
+        let mut out = String::new();
+        display::write_levels(&mut out, &levels, &options).map_err(io::Error::other)?;
+        writer.write_all(out.as_bytes())
+    }
+
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn space(&self) -> SpaceReport {
+        let pages = self.pool.pages();
+
+        SpaceReport {
+            file_bytes: pages * PAGE_SIZE as u64,
+            pages,
+            free_pages: self.free_pages,
+            live_bytes: self.len * (K::SIZE + V::SIZE) as u64,
+        }
+    }
+
+    /// Returns the height of the tree, its node counts and how full each level is against
+    /// `max`.
+    pub fn stats(&self) -> io::Result<TreeStats> {
+        let mut stats = TreeStats::default();
+        let mut level = self.root.into_iter().collect::<Vec<_>>();
+        let mut depth = 0;
+        while !level.is_empty() {
+            let mut below = Vec::new();
+            for id in level {
+                match self.read(id)? {
+                    PageNode::Leaf { entries, .. } => {
+                        stats.add(depth, true, entries.len(), self.max);
+                        *stats.leaf_sizes.entry(self.max).or_default() += 1;
+                    }
+                    PageNode::Internal(children) => {
+                        stats.add(depth, false, children.len(), self.max);
+                        below.extend(children.iter().map(|c| c.1));
+                    }
+                }
+            }
+            level = below;
+            depth += 1;
+        }
+
+        Ok(stats.finish())
+    }
+
+    /// Leaves waiting for group commits to the caller if `defer` is set, who should call
+    /// `Wal::wait_durable()` with `Wal::committed()` after each operation, once other threads can
+    /// use the tree.
+    pub(crate) fn defer_sync(&mut self, defer: bool) -> Option<Arc<Wal>> {
+        self.defer_sync = defer;
+        self.wal.clone()
+    }
+
+    /// Sets the hooks called as the tree changes shape, see `Observer`. Nodes are reported by
+    /// page.
+    pub fn set_observer(&mut self, observer: Option<Arc<dyn Observer<K>>>) {
+        self.observer = observer;
+    }
+
+    fn node_ref(id: PageId, leaf: bool) -> NodeRef {
+        NodeRef { id: id.0, leaf }
+    }
+
+    /// Passes writes to the file and the log through `faults`, see `FaultInjector`.
+    pub fn set_faults(&self, faults: Option<Arc<FaultInjector>>) {
+        if let Some(wal) = &self.wal {
+            wal.set_faults(faults.clone());
+        }
+        self.pool.set_faults(faults);
+    }
+
+    /// Bytes of committed records in the log, 0 without one.
+    pub fn log_size(&self) -> u64 {
+        self.wal.as_ref().map_or(0, |wal| wal.size())
+    }
+
+    /// Writes every modified page back to the file, without waiting for them to reach the disk.
+    pub fn flush(&self) -> io::Result<()> {
+        self.pool.flush_all()
+    }
+
+    /// Waits for every change so far to reach the disk. With a log, only the log is synced.
+    pub fn sync(&self) -> io::Result<()> {
+        match &self.wal {
+            Some(wal) => wal.sync(),
+            None => self.pool.sync(),
+        }
+    }
+
+    /// Writes every modified page back and syncs the file, then empties the log.
+    pub fn checkpoint(&self) -> io::Result<()> {
+        self.pool.sync()?;
+        match &self.wal {
+            Some(wal) => wal.checkpoint(),
+            None => Ok(()),
+        }
+    }
+
+    /// Re-encrypts the tree and its log with `key`, which it must be opened with from then on. A
+    /// crash leaves the tree encrypted with either key. Fails unless it was created with one.
+    pub fn rekey(&mut self, key: Key) -> io::Result<()> {
+        // Empties the log, so none of it is left under the old key
+        self.checkpoint()?;
+        self.pool.rekey(key)?;
+        if let Some(wal) = &self.wal {
+            wal.rekey(key);
+        }
+
+        Ok(())
+    }
+
+    /// Copies the tree to a new tree file at `path`, opened with the same options. Returns the
+    /// LSN the copy is up to, for `backup_since()`. Faults and write backs wait until it is done.
+    pub fn backup_to<P: AsRef<Path>>(&self, path: P) -> io::Result<Lsn> {
+        self.pool.flush_all()?;
+        let lsn = self.wal.as_ref().map_or(0, |wal| wal.committed());
+        self.pool
+            .with_disk(|disk| backup::full(disk, path.as_ref()))?;
+        Ok(lsn)
+    }
+
+    /// Copies the pages changed after `since` to an incremental backup at `path`, which
+    /// `backup::restore()` applies on top of the backup `since` was returned by. Returns the LSN
+    /// it is up to. Changes are only told apart by their LSNs, so the tree must have a log.
+    pub fn backup_since<P: AsRef<Path>>(&self, path: P, since: Lsn) -> io::Result<Lsn> {
+        let wal = self.wal.as_ref().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "incremental backups need a write-ahead log",
+            )
+        })?;
+
+        self.pool.flush_all()?;
+        let upto = wal.committed();
+        self.pool
+            .with_disk(|disk| backup::incremental(disk, path.as_ref(), since, upto))?;
+        Ok(upto)
+    }
+
+    /// How well the tree's pages compress, `None` unless it was created with a `Codec`.
+    pub fn compression(&self) -> Option<CompressionStats> {
+        self.pool.compression()
+    }
+
+    pub fn pool(&self) -> &BufferPool {
+        &self.pool
+    }
+}
+
+pub(crate) fn is_before_end<K: Ord, R: RangeBounds<K>>(range: &R, k: K) -> bool {
+    match range.end_bound() {
+        Bound::Included(end) => k <= *end,
+        Bound::Excluded(end) => k < *end,
+        Bound::Unbounded => true,
+    }
+}
+
+/// The leaves after the one `key` is in that `range` reaches into, for reading them ahead
+/// together: the rest of its parent's children, or the first of the next parent's if it is the
+/// last one. Empty if the root is a leaf.
+pub(crate) fn leaves_after<K, V, R>(
+    pool: &BufferPool,
+    root: Option<PageId>,
+    key: K,
+    range: &R,
+) -> io::Result<Vec<PageId>>
+where
+    K: Copy + Ord + Encode,
+    V: Encode,
+    R: RangeBounds<K>,
+{
+    let Some((children, i, depth)) = parent_of::<K, V>(pool, root, key, None)? else {
+        return Ok(Vec::new());
+    };
+
+    // A leaf starts at the separator of the one before it
+    let mut out = Vec::new();
+    for j in i + 1..children.len() {
+        if !is_before_end(range, children[j - 1].0) {
+            return Ok(out);
+        }
+        out.push(children[j].1);
+    }
+    if !out.is_empty() || !is_before_end(range, children[i].0) {
+        return Ok(out);
+    }
+
+    let start = children[i].0;
+    let Some((children, i, _)) = parent_of::<K, V>(pool, root, start, Some(depth))? else {
+        return Ok(out);
+    };
+    out.push(children[i].1);
+    for j in i + 1..children.len() {
+        if !is_before_end(range, children[j - 1].0) {
+            break;
+        }
+        out.push(children[j].1);
+    }
+
+    Ok(out)
+}
+
+/// The children of a leaf's parent, the index of the leaf and the depth of the parent, counting
+/// the root as 1.
+type Parent<K> = (Vec<(K, PageId)>, usize, usize);
+
+/// The parent of the leaf `key` is in. With its depth given, the leaf isn't read.
+fn parent_of<K, V>(
+    pool: &BufferPool,
+    root: Option<PageId>,
+    key: K,
+    depth: Option<usize>,
+) -> io::Result<Option<Parent<K>>>
+where
+    K: Copy + Ord + Encode,
+    V: Encode,
+{
+    let Some(mut id) = root else {
+        return Ok(None);
+    };
+
+    let mut parent = None;
+    for d in 1.. {
+        if depth.is_some_and(|depth| d > depth) {
+            break;
+        }
+        let page = pool.fetch(id)?;
+        let children = match PageNode::<K, V>::decode(&page)? {
+            PageNode::Internal(children) => children,
+            PageNode::Leaf { .. } => break,
+        };
+        let Some(i) = children.iter().position(|(s, _)| key < *s) else {
+            return Ok(None);
+        };
+
+        id = children[i].1;
+        parent = Some((children, i, d));
+    }
+
+    Ok(parent)
+}
+
+#[cfg(test)]
+mod test {
+    use rand::{seq::SliceRandom, thread_rng};
+
+    use std::os::unix::fs::FileExt;
+
+    use crate::buffer::{BufferPool, Capacity, ChecksumPolicy};
+    use crate::compress::Codec;
+    use crate::crypt::Key;
+    use crate::disk::DiskManager;
+    use crate::page::{PageId, PAGE_SIZE};
+    use crate::replacer::Policy;
+    use crate::wal::{Record, SyncPolicy};
+
+    use super::{wal_path, Options, PagedBTree};
+
+    #[test]
+    fn test_paged_btree() {
+        const MAX: usize = 8;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree");
+
+        let mut keys = (0..2000u32).collect::<Vec<_>>();
+        keys.shuffle(&mut thread_rng());
+
+        let mut tree = PagedBTree::create(&path, MAX).unwrap();
+        for k in &keys {
+            assert!(tree.insert(*k, *k as u64 + 10).unwrap().is_none());
+        }
+        assert!(tree.insert(5, 50).unwrap() == Some(15));
+
+        for k in (0..2000u32).step_by(2) {
+            assert!(tree.delete(k).unwrap().is_some());
+        }
+        assert!(tree.delete(0).unwrap().is_none());
+        assert!(tree.len() == 1000);
+        tree.sync().unwrap();
+        drop(tree);
+
+        let tree = PagedBTree::<u32, u64>::open(&path).unwrap();
+        assert!(tree.len() == 1000);
+        for k in 0..2000u32 {
+            let want = (k % 2 == 1).then_some(if k == 5 { 50 } else { k as u64 + 10 });
+            let have = tree.get(k).unwrap();
+            assert!(have == want, "Key: {k}\nWant: {:?}\nHave: {:?}", want, have);
+        }
+
+        let want = (101..900u32)
+            .step_by(2)
+            .map(|k| (k, k as u64 + 10))
+            .collect::<Vec<_>>();
+        let have = tree.range(100..900).unwrap();
+        assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+        assert!(tree.iter().unwrap().len() == 1000);
+        tree.validate().unwrap();
+
+        assert!(PagedBTree::<u64, u64>::open(&path).is_err());
+    }
+
+    #[test]
+    fn test_delete_last_child() {
+        let mut tree = PagedBTree::<u32, u64>::create_in_memory(4, Capacity::Pages(64)).unwrap();
+        for k in 0..40 {
+            tree.insert(k, k as u64).unwrap();
+        }
+        // Empties the last children of internal nodes below the root
+        let deleted = [
+            0, 7, 14, 21, 28, 35, 2, 9, 16, 23, 30, 37, 4, 11, 18, 25, 32, 39, 6,
+        ];
+        for k in deleted {
+            tree.delete(k).unwrap();
+            tree.validate().unwrap();
+        }
+
+        let want = (6..40)
+            .filter(|k| !deleted.contains(k))
+            .map(|k| (k, k as u64))
+            .collect::<Vec<_>>();
+        let have = tree.range(6..).unwrap();
+        assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+        for (k, v) in want {
+            assert!(tree.get(k).unwrap() == Some(v));
+        }
+    }
+
+    #[test]
+    fn test_free_pages_are_reused() {
+        const MAX: usize = 8;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree");
+
+        let mut tree = PagedBTree::create(&path, MAX).unwrap();
+        for k in 0..2000u32 {
+            tree.insert(k, k).unwrap();
+        }
+        let full = tree.space();
+        assert!(full.free_pages == 0);
+
+        for k in 500..1500u32 {
+            assert!(tree.delete(k).unwrap() == Some(k));
+        }
+        let have = tree.space();
+        assert!(have.free_pages > 100, "{have}");
+        assert!(have.pages == full.pages && have.live_bytes == 1000 * 8, "{have}");
+
+        let want = (0..500u32)
+            .chain(1500..2000)
+            .map(|k| (k, k))
+            .collect::<Vec<_>>();
+        let have = tree.iter().unwrap();
+        assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+
+        tree.sync().unwrap();
+        drop(tree);
+
+        // The free list survives reopening
+        let mut tree = PagedBTree::<u32, u32>::open(&path).unwrap();
+        for k in 500..1500u32 {
+            tree.insert(k, k).unwrap();
+        }
+        let have = tree.space();
+        assert!(have.pages == full.pages, "Want: {full}\nHave: {have}");
+        assert!(tree.iter().unwrap().len() == 2000);
+
+        for k in 0..2000u32 {
+            tree.delete(k).unwrap();
+        }
+        let have = tree.space();
+        assert!(tree.is_empty() && have.free_pages == have.pages - 1, "{have}");
+    }
+
+    #[test]
+    fn test_paged_btree_small_pool() {
+        const MAX: usize = 8;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree");
+
+        // Far fewer frames than pages, most reads miss
+        let disk = DiskManager::create(&path).unwrap();
+        let pool = BufferPool::with_policy(disk, Capacity::Pages(4), Policy::Clock);
+        let mut tree = PagedBTree::create_with_pool(pool, MAX).unwrap();
+        for k in (0..1000u32).rev() {
+            tree.insert(k, k).unwrap();
+        }
+
+        for k in 0..1000u32 {
+            let have = tree.get(k).unwrap();
+            assert!(have == Some(k), "Want: {k}\nHave: {:?}", have);
+        }
+        assert!(tree.iter().unwrap().len() == 1000);
+
+        let stats = tree.pool().stats();
+        assert!(stats.policy == "clock" && stats.evictions > 0, "{:?}", stats);
+    }
+
+    #[test]
+    fn test_compressed_tree() {
+        const MAX: usize = 64;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree");
+
+        let options = Options {
+            capacity: Capacity::Pages(8),
+            wal: Some(SyncPolicy::Off),
+            codec: Codec::Lz4,
+            ..Default::default()
+        };
+        let mut tree = PagedBTree::create_with_options(&path, MAX, options).unwrap();
+        for k in 0..10000u64 {
+            tree.insert(k, k % 16).unwrap();
+        }
+        tree.checkpoint().unwrap();
+
+        let stats = tree.compression().unwrap();
+        assert!(stats.ratio() > 2.0, "{:?}", stats);
+        let file = std::fs::metadata(&path).unwrap().len();
+        assert!(file < tree.space().file_bytes / 2, "{file}");
+        drop(tree);
+
+        // The codec is kept in the file
+        let tree = PagedBTree::<u64, u64>::open(&path).unwrap();
+        assert!(tree.compression().unwrap().codec == Codec::Lz4);
+        let want = (0..10000u64).map(|k| (k, k % 16)).collect::<Vec<_>>();
+        let have = tree.iter().unwrap();
+        assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+    }
+
+    #[test]
+    fn test_encrypted_tree() {
+        const MAX: usize = 8;
+        const SECRET: u64 = 0x5ec7e75ec7e75ec7;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree");
+        let contains = |path: &std::path::Path| {
+            let bytes = std::fs::read(path).unwrap();
+            bytes.windows(8).any(|w| w == SECRET.to_le_bytes())
+        };
+
+        let key = Key([7; 32]);
+        let options = Options {
+            capacity: Capacity::Pages(16),
+            wal: Some(SyncPolicy::PerCommit),
+            codec: Codec::Lz4,
+            key: Some(key),
+            ..Default::default()
+        };
+        let mut tree = PagedBTree::create_with_options(&path, MAX, options).unwrap();
+        for k in 0..1000u32 {
+            tree.insert(k, SECRET).unwrap();
+        }
+        assert!(!contains(&path) && !contains(&wal_path(&path)));
+
+        // Crash, recovery has to decrypt the log
+        std::mem::forget(tree);
+        assert!(PagedBTree::<u32, u64>::open(&path).is_err());
+        let wrong = Options {
+            key: Some(Key([8; 32])),
+            ..options
+        };
+        assert!(PagedBTree::<u32, u64>::open_with_options(&path, wrong).is_err());
+
+        let mut tree = PagedBTree::<u32, u64>::open_with_options(&path, options).unwrap();
+        assert!(tree.len() == 1000 && tree.get(500).unwrap() == Some(SECRET));
+
+        tree.rekey(Key([8; 32])).unwrap();
+        tree.insert(1000, SECRET).unwrap();
+        drop(tree);
+
+        assert!(PagedBTree::<u32, u64>::open_with_options(&path, options).is_err());
+        let tree = PagedBTree::<u32, u64>::open_with_options(&path, wrong).unwrap();
+        let want = (0..=1000u32).map(|k| (k, SECRET)).collect::<Vec<_>>();
+        let have = tree.iter().unwrap();
+        assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+    }
+
+    #[test]
+    fn test_wal_recovery() {
+        const MAX: usize = 8;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree");
+
+        let mut keys = (0..2000u32).collect::<Vec<_>>();
+        keys.shuffle(&mut thread_rng());
+
+        let options = Options {
+            capacity: Capacity::Pages(16),
+            wal: Some(SyncPolicy::PerCommit),
+            ..Default::default()
+        };
+        let mut tree = PagedBTree::create_with_options(&path, MAX, options).unwrap();
+        for k in &keys {
+            tree.insert(*k, *k).unwrap();
+        }
+        for k in (0..2000u32).step_by(3) {
+            tree.delete(k).unwrap();
+        }
+        tree.set_merge_operator(|_, old, n| old.unwrap_or(0) + n);
+        for k in (0..2000u32).step_by(5) {
+            let have = tree.merge(k, 1).unwrap();
+            let want = if k % 3 == 0 { 1 } else { k + 1 };
+            assert!(have == want, "Want: {want}\nHave: {have}");
+        }
+        assert!(tree.pool().stats().evictions > 0);
+
+        // Crash, the pages still cached are never written back, and one being appended is torn
+        std::mem::forget(tree);
+        let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+        let len = file.metadata().unwrap().len();
+        file.write_all_at(&[1; PAGE_SIZE / 2], len).unwrap();
+
+        let tree = PagedBTree::<u32, u32>::open(&path).unwrap();
+        assert!(!wal_path(&path).exists());
+        assert!(tree.len() == 1467, "Have: {}", tree.len());
+
+        let want = (0..2000u32)
+            .filter(|k| k % 3 != 0 || k % 5 == 0)
+            .map(|k| {
+                (
+                    k,
+                    if k % 3 == 0 {
+                        1
+                    } else {
+                        k + (k % 5 == 0) as u32
+                    },
+                )
+            })
+            .collect::<Vec<_>>();
+        let have = tree.iter().unwrap();
+        assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+    }
+
+    #[test]
+    fn test_checksum_repair() {
+        const MAX: usize = 8;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree");
+
+        let options = Options {
+            capacity: Capacity::Pages(8),
+            wal: Some(SyncPolicy::Off),
+            ..Default::default()
+        };
+        let mut tree = PagedBTree::create_with_options(&path, MAX, options).unwrap();
+        for k in 0..300u32 {
+            tree.insert(k, k).unwrap();
+        }
+        tree.flush().unwrap();
+        assert!(!tree.pool().contains(PageId(1)));
+
+        // Corrupt the first leaf, which the log still covers
+        let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.write_all_at(&[0xff; 4], PAGE_SIZE as u64 + 40)
+            .unwrap();
+        assert!(tree.iter().is_err());
+
+        tree.pool().set_checksum_policy(ChecksumPolicy::Repair);
+        assert!(tree.iter().unwrap() == (0..300).map(|k| (k, k)).collect::<Vec<_>>());
+        assert!(tree.pool().stats().repairs == 1 && tree.pool().quarantined().is_empty());
+    }
+
+    #[test]
+    fn test_torn_pages_are_rebuilt() {
+        const MAX: usize = 8;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree");
+
+        let options = Options {
+            wal: Some(SyncPolicy::PerCommit),
+            ..Default::default()
+        };
+        let mut tree = PagedBTree::create_with_options(&path, MAX, options).unwrap();
+        for k in 0..100u32 {
+            tree.insert(k, k).unwrap();
+        }
+        tree.checkpoint().unwrap();
+
+        // The first change to the leaf since the checkpoint logs all of it
+        tree.insert(1000, 1000).unwrap();
+        let wal = tree.wal.clone().unwrap();
+        let have = wal.records().unwrap();
+        assert!(have.iter().any(|(_, r)| matches!(r, Record::Image { .. })));
+        assert!(!have.iter().any(|(_, r)| matches!(r, Record::Put { .. })), "{:?}", have);
+
+        tree.insert(1001, 1001).unwrap();
+        assert!(wal
+            .records()
+            .unwrap()
+            .iter()
+            .any(|(_, r)| matches!(r, Record::Put { .. })));
+
+        // Crash while writing back the leaf, only half of it makes it
+        tree.flush().unwrap();
+        std::mem::forget(tree);
+        let leaf = (1..)
+            .map(PageId)
+            .find(|id| {
+                wal.records()
+                    .unwrap()
+                    .iter()
+                    .any(|(_, r)| r.page() == Some(*id))
+            })
+            .unwrap();
+        let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+        let half = [0; PAGE_SIZE / 2];
+        file.write_all_at(&half, leaf.0 * PAGE_SIZE as u64 + half.len() as u64)
+            .unwrap();
+        drop(wal);
+
+        let tree = PagedBTree::<u32, u32>::open(&path).unwrap();
+        assert!(tree.len() == 102);
+        assert!(tree.get(1001).unwrap() == Some(1001) && tree.get(50).unwrap() == Some(50));
+    }
+}
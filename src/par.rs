@@ -0,0 +1,120 @@
+//! Rayon parallel iterators over a `BTree` and its ranges, with the `rayon` feature. A range is
+//! split in two at the start of the leaf its middle entry is in, found from the entry counts of
+//! the internal nodes, until it is within one leaf.
+
+use std::fmt::Debug;
+
+use rayon::iter::plumbing::{bridge_unindexed, Folder, UnindexedConsumer, UnindexedProducer};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+use crate::btree::{BTree, Increment};
+use crate::iter::Range;
+
+/// A `Range` iterated in parallel.
+pub struct ParRange<'a, K, V>(Range<'a, K, V>);
+
+// The range only reads the tree it borrows
+unsafe impl<K: Send + Sync, V: Sync> Send for ParRange<'_, K, V> {}
+
+impl<'a, K, V> IntoParallelIterator for Range<'a, K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment + Send + Sync,
+    V: Clone + Debug + Eq + Send + Sync,
+{
+    type Iter = ParRange<'a, K, V>;
+    type Item = (K, V);
+
+    fn into_par_iter(self) -> Self::Iter {
+        ParRange(self)
+    }
+}
+
+impl<'a, K, V> IntoParallelIterator for &'a BTree<K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment + Send + Sync,
+    V: Clone + Debug + Eq + Send + Sync,
+{
+    type Iter = ParRange<'a, K, V>;
+    type Item = (K, V);
+
+    fn into_par_iter(self) -> Self::Iter {
+        ParRange(self.iter())
+    }
+}
+
+impl<K, V> ParallelIterator for ParRange<'_, K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment + Send + Sync,
+    V: Clone + Debug + Eq + Send + Sync,
+{
+    type Item = (K, V);
+
+    fn drive_unindexed<C>(self, consumer: C) -> C::Result
+    where
+        C: UnindexedConsumer<Self::Item>,
+    {
+        bridge_unindexed(self, consumer)
+    }
+}
+
+impl<K, V> UnindexedProducer for ParRange<'_, K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment + Send + Sync,
+    V: Clone + Debug + Eq + Send + Sync,
+{
+    type Item = (K, V);
+
+    fn split(self) -> (Self, Option<Self>) {
+        let (left, right) = self.0.split();
+        (ParRange(left), right.map(ParRange))
+    }
+
+    fn fold_with<F: Folder<Self::Item>>(self, folder: F) -> F {
+        folder.consume_iter(self.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Mutex;
+
+    use rayon::prelude::*;
+
+    use crate::btree::BTree;
+
+    #[test]
+    fn test_par_iter() {
+        let mut tree = BTree::bulk_load(8, (0..100_000u64).map(|k| (k, k * 2)));
+        tree.delete_where(|k, _| (20_000..60_000).contains(&k) || k % 7 == 0);
+
+        let want = tree.iter().map(|(_, v)| v).sum::<u64>();
+        let have = tree.par_iter().map(|(_, v)| v).sum::<u64>();
+        assert!(want == have, "Want: {}\nHave: {}", want, have);
+
+        let want = tree.range(10_000..70_000).collect::<Vec<_>>();
+        let have = tree
+            .range(10_000..70_000)
+            .into_par_iter()
+            .collect::<Vec<_>>();
+        assert!(want == have);
+
+        // Every entry is yielded once, from more than one piece
+        let pieces = Mutex::new(0);
+        let count = tree
+            .par_iter()
+            .fold(
+                || 0,
+                |n, _| {
+                    if n == 0 {
+                        *pieces.lock().unwrap() += 1;
+                    }
+                    n + 1
+                },
+            )
+            .sum::<usize>();
+        assert!(count == tree.len());
+        assert!(*pieces.lock().unwrap() > 1);
+
+        assert!(BTree::<u32, u32>::new(8).par_iter().count() == 0);
+    }
+}
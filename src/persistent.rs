@@ -0,0 +1,445 @@
+use std::cmp::Ordering;
+use std::fmt::Debug;
+use std::iter::FusedIterator;
+use std::rc::Rc;
+
+use crate::btree::Increment;
+use crate::diff::Diff;
+
+#[derive(Debug, Clone)]
+enum PNode<K, V> {
+    Leaf(Vec<(K, V)>),
+    // Separators follow `BTree`: each child holds keys less than its separator
+    Internal(Vec<(K, Rc<PNode<K, V>>)>),
+}
+
+impl<K, V> PNode<K, V>
+where
+    K: Copy + Ord + Increment,
+    V: Copy,
+{
+    fn separator(&self) -> K {
+        match self {
+            PNode::Leaf(entries) => entries
+                .last()
+                .expect("split leaf should not be empty")
+                .0
+                .next(),
+            PNode::Internal(children) => {
+                children
+                    .last()
+                    .expect("internal node should not be empty")
+                    .0
+            }
+        }
+    }
+
+    /// The separator of this node, split from `gt`: its own, unless `gt` starts before it, which a
+    /// `next()` that isn't the least key after another allows.
+    fn separator_before(&self, gt: &Self) -> K {
+        match gt {
+            PNode::Leaf(entries) => self.separator().min(entries[0].0),
+            PNode::Internal(_) => self.separator(),
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            PNode::Leaf(entries) => entries.len(),
+            PNode::Internal(children) => children.len(),
+        }
+    }
+
+    /// Returns the new greater sibling if the node had to split.
+    fn insert(node: &mut Rc<Self>, key: K, value: V, max: usize) -> (Option<V>, Option<Rc<Self>>) {
+        // Copies the node if a snapshot still shares it
+        let old = match Rc::make_mut(node) {
+            PNode::Leaf(entries) => match entries.binary_search_by(|e| e.0.cmp(&key)) {
+                Ok(i) => Some(std::mem::replace(&mut entries[i].1, value)),
+                Err(i) => {
+                    entries.insert(i, (key, value));
+                    None
+                }
+            },
+            PNode::Internal(children) => {
+                let i = match children.iter().position(|c| key < c.0) {
+                    Some(i) => i,
+                    None => {
+                        let last = children.len() - 1;
+                        children[last].0 = key.next();
+                        last
+                    }
+                };
+
+                let (old, split) = Self::insert(&mut children[i].1, key, value, max);
+                if let Some(gt) = split {
+                    let sep = children[i].0;
+                    children[i].0 = children[i].1.separator_before(&gt);
+                    children.insert(i + 1, (sep, gt));
+                }
+
+                old
+            }
+        };
+
+        if node.len() <= max {
+            return (old, None);
+        }
+
+        let gt = match Rc::make_mut(node) {
+            PNode::Leaf(entries) => PNode::Leaf(entries.split_off(entries.len() / 2)),
+            PNode::Internal(children) => PNode::Internal(children.split_off(children.len() / 2)),
+        };
+
+        (old, Some(Rc::new(gt)))
+    }
+
+    fn get(&self, key: K) -> Option<V> {
+        match self {
+            PNode::Leaf(entries) => {
+                let i = entries.binary_search_by(|e| e.0.cmp(&key)).ok()?;
+                Some(entries[i].1)
+            }
+            PNode::Internal(children) => children.iter().find(|c| key < c.0)?.1.get(key),
+        }
+    }
+
+    fn delete(node: &mut Rc<Self>, key: K) -> Option<V> {
+        // Find the path first so nothing is copied if `key` isn't there
+        node.get(key)?;
+
+        match Rc::make_mut(node) {
+            PNode::Leaf(entries) => {
+                let i = entries.binary_search_by(|e| e.0.cmp(&key)).ok()?;
+                Some(entries.remove(i).1)
+            }
+            PNode::Internal(children) => {
+                let child = children.iter_mut().find(|c| key < c.0)?;
+                Self::delete(&mut child.1, key)
+            }
+        }
+    }
+}
+
+/// A copy-on-write tree whose nodes are shared between clones.
+///
+/// `clone()` is O(1): it only bumps the root's reference count. A mutation copies just the nodes
+/// on its root-to-leaf path that are still shared with another clone, so keeping old clones
+/// around gives point-in-time snapshots and undo for the cost of the paths changed since.
+///
+/// Like `BTree`, deletes do not rebalance.
+#[derive(Debug, Clone)]
+pub struct PersistentBTree<K, V> {
+    root: Option<Rc<PNode<K, V>>>,
+    max: usize,
+    len: usize,
+}
+
+impl<K, V> PersistentBTree<K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment,
+    V: Clone + Copy + Debug + Eq,
+{
+    pub fn new(max: usize) -> Self {
+        assert!(max >= 2);
+
+        Self {
+            root: None,
+            max,
+            len: 0,
+        }
+    }
+
+    /// Returns the previous value of `key`.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let root = self
+            .root
+            .get_or_insert_with(|| Rc::new(PNode::Leaf(Vec::new())));
+
+        let (old, split) = PNode::insert(root, key, value, self.max);
+        if let Some(gt) = split {
+            let lt = self.root.take().unwrap();
+            let children = vec![(lt.separator_before(&gt), lt), (gt.separator(), gt)];
+            self.root = Some(Rc::new(PNode::Internal(children)));
+        }
+
+        if old.is_none() {
+            self.len += 1;
+        }
+
+        old
+    }
+
+    pub fn get(&self, key: K) -> Option<V> {
+        self.root.as_ref()?.get(key)
+    }
+
+    pub fn delete(&mut self, key: K) -> Option<V> {
+        let old = PNode::delete(self.root.as_mut()?, key);
+        if old.is_some() {
+            self.len -= 1;
+        }
+
+        old
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns a copy of the tree as it is now, sharing all of its nodes.
+    pub fn snapshot(&self) -> Self {
+        self.clone()
+    }
+
+    /// Returns `true` if both trees still share their root, i.e. neither was modified since one
+    /// was cloned from the other.
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        match (&self.root, &other.root) {
+            (Some(a), Some(b)) => Rc::ptr_eq(a, b),
+            (None, None) => true,
+            _ => false,
+        }
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        let mut stack = Vec::new();
+        if let Some(root) = &self.root {
+            stack.push((&**root, 0));
+        }
+
+        Iter { stack }
+    }
+
+    /// Returns the differences from this tree to `new`, in key order. Subtrees the two still share
+    /// are skipped without being read, so diffing a snapshot costs about as much as the paths
+    /// changed since.
+    pub fn diff<'a>(&'a self, new: &'a PersistentBTree<K, V>) -> PersistentDiff<'a, K, V> {
+        PersistentDiff {
+            old: Cursor::new(&self.root),
+            new: Cursor::new(&new.root),
+        }
+    }
+}
+
+/// Iterates over the entries of a `PersistentBTree` in order.
+pub struct Iter<'a, K, V> {
+    stack: Vec<(&'a PNode<K, V>, usize)>,
+}
+
+impl<K: Copy, V: Copy> Iterator for Iter<'_, K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (node, i) = self.stack.last_mut()?;
+            match node {
+                PNode::Leaf(entries) => match entries.get(*i) {
+                    Some(e) => {
+                        *i += 1;
+                        return Some(*e);
+                    }
+                    None => {
+                        self.stack.pop();
+                    }
+                },
+                PNode::Internal(children) => match children.get(*i) {
+                    Some(c) => {
+                        *i += 1;
+                        self.stack.push((&*c.1, 0));
+                    }
+                    None => {
+                        self.stack.pop();
+                    }
+                },
+            }
+        }
+    }
+}
+
+impl<K: Copy, V: Copy> FusedIterator for Iter<'_, K, V> {}
+
+enum Front<'a, K, V> {
+    Node(&'a Rc<PNode<K, V>>),
+    Entry((K, V)),
+    End,
+}
+
+/// A position in a tree that can step over whole subtrees.
+struct Cursor<'a, K, V> {
+    root: Option<&'a Rc<PNode<K, V>>>,
+    stack: Vec<(&'a PNode<K, V>, usize)>,
+}
+
+impl<'a, K: Copy, V: Copy> Cursor<'a, K, V> {
+    fn new(root: &'a Option<Rc<PNode<K, V>>>) -> Self {
+        Self {
+            root: root.as_ref(),
+            stack: Vec::new(),
+        }
+    }
+
+    /// The next subtree or entry.
+    fn front(&mut self) -> Front<'a, K, V> {
+        if let Some(root) = self.root {
+            return Front::Node(root);
+        }
+
+        while let Some((node, i)) = self.stack.last() {
+            match node {
+                PNode::Leaf(entries) if *i < entries.len() => return Front::Entry(entries[*i]),
+                PNode::Internal(children) if *i < children.len() => {
+                    return Front::Node(&children[*i].1)
+                }
+                _ => {
+                    self.stack.pop();
+                }
+            }
+        }
+
+        Front::End
+    }
+
+    /// Steps over the front, which must not be `End`.
+    fn skip(&mut self) {
+        if self.root.take().is_none() {
+            self.stack.last_mut().unwrap().1 += 1;
+        }
+    }
+
+    /// Steps into the front, a subtree.
+    fn descend(&mut self, node: &'a Rc<PNode<K, V>>) {
+        self.skip();
+        self.stack.push((&**node, 0));
+    }
+}
+
+/// See `PersistentBTree::diff`.
+pub struct PersistentDiff<'a, K, V> {
+    old: Cursor<'a, K, V>,
+    new: Cursor<'a, K, V>,
+}
+
+impl<K, V> Iterator for PersistentDiff<'_, K, V>
+where
+    K: Copy + Ord,
+    V: Copy + Eq,
+{
+    type Item = Diff<K, V>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match (self.old.front(), self.new.front()) {
+                // Both start at the same key, so they stay in step
+                (Front::Node(a), Front::Node(b)) if Rc::ptr_eq(a, b) => {
+                    self.old.skip();
+                    self.new.skip();
+                }
+                (Front::Node(a), _) => self.old.descend(a),
+                (_, Front::Node(b)) => self.new.descend(b),
+                (Front::End, Front::End) => return None,
+                (Front::Entry((k, v)), Front::End) => {
+                    self.old.skip();
+                    return Some(Diff::Removed(k, v));
+                }
+                (Front::End, Front::Entry((k, v))) => {
+                    self.new.skip();
+                    return Some(Diff::Added(k, v));
+                }
+                (Front::Entry((key, old)), Front::Entry((k, new))) => match key.cmp(&k) {
+                    Ordering::Less => {
+                        self.old.skip();
+                        return Some(Diff::Removed(key, old));
+                    }
+                    Ordering::Greater => {
+                        self.new.skip();
+                        return Some(Diff::Added(k, new));
+                    }
+                    Ordering::Equal => {
+                        self.old.skip();
+                        self.new.skip();
+                        if old != new {
+                            return Some(Diff::Changed { key, old, new });
+                        }
+                    }
+                },
+            }
+        }
+    }
+}
+
+impl<K: Copy + Ord, V: Copy + Eq> FusedIterator for PersistentDiff<'_, K, V> {}
+
+#[cfg(test)]
+mod test {
+    use std::rc::Rc;
+
+    use super::{PNode, PersistentBTree};
+
+    #[test]
+    fn test_persistent_btree() {
+        const MAX: usize = 8;
+
+        let mut tree = PersistentBTree::new(MAX);
+        for k in (0..200u32).rev() {
+            assert!(tree.insert(k, k).is_none());
+        }
+        assert!(tree.insert(5, 50) == Some(5));
+
+        for k in 0..200u32 {
+            let want = if k == 5 { 50 } else { k };
+            let have = tree.get(k);
+            assert!(have == Some(want), "Want: {want}\nHave: {:?}", have);
+        }
+
+        for k in (0..200u32).step_by(2) {
+            assert!(tree.delete(k).is_some());
+        }
+        assert!(tree.delete(0).is_none());
+        assert!(tree.len() == 100);
+
+        let want = (1..200u32)
+            .step_by(2)
+            .map(|k| (k, if k == 5 { 50 } else { k }))
+            .collect::<Vec<_>>();
+        let have = tree.iter().collect::<Vec<_>>();
+        assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+    }
+
+    #[test]
+    fn test_snapshots_share_nodes() {
+        const MAX: usize = 4;
+
+        let mut tree = PersistentBTree::new(MAX);
+        for k in 0..100u32 {
+            tree.insert(k, k);
+        }
+
+        let snapshot = tree.snapshot();
+        assert!(tree.ptr_eq(&snapshot));
+
+        tree.insert(0, 1000);
+        assert!(!tree.ptr_eq(&snapshot));
+
+        // Only the path to 0 was copied
+        let (a, b) = match (tree.root.as_deref(), snapshot.root.as_deref()) {
+            (Some(PNode::Internal(a)), Some(PNode::Internal(b))) => (a, b),
+            _ => panic!("expected internal roots"),
+        };
+        assert!(!Rc::ptr_eq(&a[0].1, &b[0].1));
+        assert!(Rc::ptr_eq(&a[a.len() - 1].1, &b[b.len() - 1].1));
+
+        tree.delete(99);
+
+        assert!(snapshot.get(0) == Some(0));
+        assert!(snapshot.get(99) == Some(99));
+        assert!(snapshot.len() == 100);
+        assert!(tree.get(0) == Some(1000));
+        assert!(tree.get(99).is_none());
+        assert!(tree.len() == 99);
+    }
+}
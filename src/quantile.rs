@@ -0,0 +1,85 @@
+use std::fmt::Debug;
+
+use crate::btree::{BTree, Increment};
+
+/// One bucket of an equi-depth histogram, the keys from `lo` to `hi` inclusive.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct Bucket<K> {
+    pub lo: K,
+    pub hi: K,
+    pub entries: usize,
+}
+
+impl<K, V> BTree<K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment,
+    V: Clone + Debug + Eq,
+{
+    /// The key `q` of the way through the entries, `q` from 0 to 1, or `None` while the tree is
+    /// empty. Found by the entry counts of the internal nodes, in one descent.
+    pub fn quantile(&self, q: f64) -> Option<K> {
+        assert!((0.0..=1.0).contains(&q), "quantile {} out of 0..=1", q);
+        if self.is_empty() {
+            return None;
+        }
+
+        let rank = (q * (self.len() - 1) as f64).round() as usize;
+        Some(self.select(rank).0)
+    }
+
+    /// Splits the entries into `buckets` ranges of keys holding as many entries as each other,
+    /// give or take one, in key order. Fewer if there are fewer entries. Each boundary is found
+    /// like `quantile()`, without reading the leaves in between.
+    pub fn histogram(&self, buckets: usize) -> Vec<Bucket<K>> {
+        assert!(buckets > 0);
+
+        let len = self.len();
+        let buckets = buckets.min(len);
+        (0..buckets)
+            .map(|i| {
+                let (start, end) = (i * len / buckets, (i + 1) * len / buckets);
+                Bucket {
+                    lo: self.select(start).0,
+                    hi: self.select(end - 1).0,
+                    entries: end - start,
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::btree::BTree;
+
+    use super::Bucket;
+
+    #[test]
+    fn test_histogram() {
+        let mut tree = BTree::bulk_load(8, (0..1000u32).map(|k| (k, k)));
+        // Most of the entries are in the upper half of the key space
+        tree.delete_where(|k, _| k < 500 && k % 10 != 0);
+        assert!(tree.len() == 550);
+
+        assert!(tree.quantile(0.0) == Some(0) && tree.quantile(1.0) == Some(999));
+        let have = tree.quantile(0.5);
+        assert!(have == Some(725), "Have: {:?}", have);
+
+        let have = tree.histogram(10);
+        let want = tree.iter().map(|(k, _)| k).collect::<Vec<_>>();
+        let want = want
+            .chunks(55)
+            .map(|keys| Bucket {
+                lo: keys[0],
+                hi: keys[54],
+                entries: 55,
+            })
+            .collect::<Vec<_>>();
+        assert!(have == want, "Want: {:?}\nHave: {:?}", want, have);
+
+        let have = BTree::bulk_load(8, (0..3u32).map(|k| (k, k))).histogram(5);
+        assert!(have.len() == 3 && have.iter().all(|b| b.lo == b.hi && b.entries == 1));
+        assert!(BTree::<u32, u32>::new(8).quantile(0.5).is_none());
+        assert!(BTree::<u32, u32>::new(8).histogram(4).is_empty());
+    }
+}
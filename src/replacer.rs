@@ -0,0 +1,326 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::page::PageId;
+
+/// Decides which frame of a `BufferPool` to evict when a page has to be loaded and every frame is
+/// in use. Frames are identified by their index in the pool.
+pub trait Replacer: Send {
+    fn name(&self) -> &'static str;
+
+    /// `page` was loaded into `frame`.
+    fn insert(&mut self, frame: usize, page: PageId);
+
+    /// The page in `frame` was used again.
+    fn access(&mut self, frame: usize);
+
+    /// Picks a frame for which `evictable` returns `true` and stops tracking it. Returns `None` if
+    /// there is no such frame.
+    fn victim(&mut self, evictable: &dyn Fn(usize) -> bool) -> Option<usize>;
+}
+
+/// The built-in replacement policies.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum Policy {
+    #[default]
+    Lru,
+    Clock,
+    /// Evicts the page whose `k`th most recent use is the oldest.
+    LruK(usize),
+    TwoQ,
+}
+
+impl Policy {
+    pub fn replacer(&self, frames: usize) -> Box<dyn Replacer> {
+        match *self {
+            Policy::Lru => Box::new(Lru::new()),
+            Policy::Clock => Box::new(Clock::new(frames)),
+            Policy::LruK(k) => Box::new(LruK::new(k)),
+            Policy::TwoQ => Box::new(TwoQ::new(frames)),
+        }
+    }
+}
+
+/// Evicts the least recently used page. A scan larger than the pool evicts everything else.
+#[derive(Default)]
+pub struct Lru {
+    tick: u64,
+    used: HashMap<usize, u64>,
+}
+
+impl Lru {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Replacer for Lru {
+    fn name(&self) -> &'static str {
+        "lru"
+    }
+
+    fn insert(&mut self, frame: usize, _: PageId) {
+        self.access(frame);
+    }
+
+    fn access(&mut self, frame: usize) {
+        self.tick += 1;
+        self.used.insert(frame, self.tick);
+    }
+
+    fn victim(&mut self, evictable: &dyn Fn(usize) -> bool) -> Option<usize> {
+        let (&frame, _) = self
+            .used
+            .iter()
+            .filter(|(f, _)| evictable(**f))
+            .min_by_key(|(_, t)| **t)?;
+        self.used.remove(&frame);
+        Some(frame)
+    }
+}
+
+/// Approximates LRU with one reference bit per frame. A hand sweeps the frames, clearing set bits
+/// and evicting the first frame whose bit is already clear.
+pub struct Clock {
+    referenced: Vec<Option<bool>>,
+    hand: usize,
+}
+
+impl Clock {
+    pub fn new(frames: usize) -> Self {
+        Self {
+            referenced: vec![None; frames],
+            hand: 0,
+        }
+    }
+}
+
+impl Replacer for Clock {
+    fn name(&self) -> &'static str {
+        "clock"
+    }
+
+    fn insert(&mut self, frame: usize, _: PageId) {
+        self.referenced[frame] = Some(true);
+    }
+
+    fn access(&mut self, frame: usize) {
+        self.referenced[frame] = Some(true);
+    }
+
+    fn victim(&mut self, evictable: &dyn Fn(usize) -> bool) -> Option<usize> {
+        if !self
+            .referenced
+            .iter()
+            .enumerate()
+            .any(|(f, r)| r.is_some() && evictable(f))
+        {
+            return None;
+        }
+
+        loop {
+            let frame = self.hand;
+            self.hand = (self.hand + 1) % self.referenced.len();
+
+            if !evictable(frame) {
+                continue;
+            }
+
+            match &mut self.referenced[frame] {
+                Some(r) if *r => *r = false,
+                Some(_) => {
+                    self.referenced[frame] = None;
+                    return Some(frame);
+                }
+                None => {}
+            }
+        }
+    }
+}
+
+/// Evicts the page with the largest backward `k`-distance, how long ago its `k`th most recent use
+/// was. Pages used fewer than `k` times go first, least recently used first, so a scan only
+/// evicts other scanned pages.
+pub struct LruK {
+    k: usize,
+    tick: u64,
+    history: HashMap<usize, VecDeque<u64>>,
+}
+
+impl LruK {
+    pub fn new(k: usize) -> Self {
+        assert!(k > 0);
+
+        Self {
+            k,
+            tick: 0,
+            history: HashMap::new(),
+        }
+    }
+}
+
+impl Replacer for LruK {
+    fn name(&self) -> &'static str {
+        "lru-k"
+    }
+
+    fn insert(&mut self, frame: usize, _: PageId) {
+        self.history.insert(frame, VecDeque::with_capacity(self.k));
+        self.access(frame);
+    }
+
+    fn access(&mut self, frame: usize) {
+        self.tick += 1;
+
+        let history = self.history.entry(frame).or_default();
+        if history.len() == self.k {
+            history.pop_front();
+        }
+        history.push_back(self.tick);
+    }
+
+    fn victim(&mut self, evictable: &dyn Fn(usize) -> bool) -> Option<usize> {
+        // Fewer than `k` uses sorts before everything else, then by the oldest remembered use
+        let (&frame, _) = self
+            .history
+            .iter()
+            .filter(|(f, _)| evictable(**f))
+            .min_by_key(|(_, h)| (h.len() >= self.k, h[0]))?;
+        self.history.remove(&frame);
+        Some(frame)
+    }
+}
+
+/// Simplified 2Q. Pages start in a FIFO queue and are only promoted to the main LRU queue when
+/// used again. Pages evicted from the FIFO are remembered for a while, so one that comes back soon
+/// after goes straight to the main queue.
+pub struct TwoQ {
+    tick: u64,
+    // Frames used once, oldest first
+    a1_in: VecDeque<usize>,
+    // Pages recently evicted from `a1_in`
+    a1_out: VecDeque<PageId>,
+    am: HashMap<usize, u64>,
+    pages: HashMap<usize, PageId>,
+    in_max: usize,
+    out_max: usize,
+}
+
+impl TwoQ {
+    pub fn new(frames: usize) -> Self {
+        Self {
+            tick: 0,
+            a1_in: VecDeque::new(),
+            a1_out: VecDeque::new(),
+            am: HashMap::new(),
+            pages: HashMap::new(),
+            in_max: (frames / 4).max(1),
+            out_max: (frames / 2).max(1),
+        }
+    }
+}
+
+impl Replacer for TwoQ {
+    fn name(&self) -> &'static str {
+        "2q"
+    }
+
+    fn insert(&mut self, frame: usize, page: PageId) {
+        self.pages.insert(frame, page);
+
+        if let Some(i) = self.a1_out.iter().position(|p| *p == page) {
+            self.a1_out.remove(i);
+            self.tick += 1;
+            self.am.insert(frame, self.tick);
+        } else {
+            self.a1_in.push_back(frame);
+        }
+    }
+
+    fn access(&mut self, frame: usize) {
+        self.tick += 1;
+        if let Some(t) = self.am.get_mut(&frame) {
+            *t = self.tick;
+        } else if let Some(i) = self.a1_in.iter().position(|f| *f == frame) {
+            self.a1_in.remove(i);
+            self.am.insert(frame, self.tick);
+        }
+    }
+
+    fn victim(&mut self, evictable: &dyn Fn(usize) -> bool) -> Option<usize> {
+        let a1 = self.a1_in.iter().position(|f| evictable(*f));
+        let am = self
+            .am
+            .iter()
+            .filter(|(f, _)| evictable(**f))
+            .min_by_key(|(_, t)| **t)
+            .map(|(f, _)| *f);
+
+        let frame = match (a1, am) {
+            (Some(i), _) if self.a1_in.len() >= self.in_max || am.is_none() => {
+                let frame = self.a1_in.remove(i).unwrap();
+                if self.a1_out.len() == self.out_max {
+                    self.a1_out.pop_front();
+                }
+                self.a1_out.push_back(self.pages[&frame]);
+                frame
+            }
+            (_, Some(frame)) => {
+                self.am.remove(&frame);
+                frame
+            }
+            (_, None) => return None,
+        };
+
+        self.pages.remove(&frame);
+        Some(frame)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::page::PageId;
+
+    use super::{Policy, Replacer};
+
+    #[test]
+    fn test_replacers() {
+        for policy in [Policy::Lru, Policy::LruK(2), Policy::TwoQ] {
+            let mut replacer: Box<dyn Replacer> = policy.replacer(3);
+            assert!(replacer.victim(&|_| true).is_none());
+
+            for frame in 0..3 {
+                replacer.insert(frame, PageId(frame as u64));
+            }
+            replacer.access(0);
+            replacer.access(2);
+
+            // Frame 1 was only used once, and longest ago
+            let have = replacer.victim(&|_| true);
+            assert!(have == Some(1), "Policy: {}\nHave: {:?}", replacer.name(), have);
+
+            let mut rest = [
+                replacer.victim(&|_| true).unwrap(),
+                replacer.victim(&|_| true).unwrap(),
+            ];
+            rest.sort();
+            assert!(rest == [0, 2], "Policy: {}\nHave: {:?}", replacer.name(), rest);
+            assert!(replacer.victim(&|_| true).is_none());
+        }
+    }
+
+    #[test]
+    fn test_clock() {
+        let mut replacer = Policy::Clock.replacer(3);
+        for frame in 0..3 {
+            replacer.insert(frame, PageId(frame as u64));
+        }
+        assert!(replacer.victim(&|_| false).is_none());
+
+        // Every bit is set, the first sweep clears them all and evicts where it started
+        assert!(replacer.victim(&|_| true) == Some(0));
+        replacer.access(1);
+        assert!(replacer.victim(&|_| true) == Some(2));
+        assert!(replacer.victim(&|_| true) == Some(1));
+        assert!(replacer.victim(&|_| true).is_none());
+    }
+}
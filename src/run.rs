@@ -0,0 +1,323 @@
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::{self, Write};
+use std::marker::PhantomData;
+use std::ops::{Bound, RangeBounds};
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+
+use crate::btree::{BTree, Increment};
+use crate::page::Encode;
+use crate::paged::is_before_end;
+
+const MAGIC: &[u8; 8] = b"BPTRUN\0\0";
+/// Bytes of entries in a block, a block holds at least one entry.
+const BLOCK_SIZE: usize = 4096;
+/// Index offset, blocks, entries, key and value sizes, a CRC32C of the index and the magic.
+const FOOTER: usize = 40;
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn per_block<K: Encode, V: Encode>() -> usize {
+    (BLOCK_SIZE / (K::SIZE + V::SIZE)).max(1)
+}
+
+/// Writes entries in ascending key order to a sorted run, see `SortedRun`.
+pub struct RunWriter<W, K, V> {
+    writer: W,
+    block: Vec<u8>,
+    /// First key and CRC32C of every block written so far.
+    index: Vec<(K, u32)>,
+    last: Option<K>,
+    len: u64,
+    _types: PhantomData<V>,
+}
+
+impl<W: Write, K: Encode + Ord + Debug, V: Encode> RunWriter<W, K, V> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            block: Vec::with_capacity(BLOCK_SIZE),
+            index: Vec::new(),
+            last: None,
+            len: 0,
+            _types: PhantomData,
+        }
+    }
+
+    /// Panics unless `key` is greater than the last one pushed.
+    pub fn push(&mut self, key: K, value: V) -> io::Result<()> {
+        if let Some(last) = self.last {
+            assert!(key > last, "{:?} pushed after {:?}", key, last);
+        }
+
+        let size = K::SIZE + V::SIZE;
+        if self.block.is_empty() {
+            self.index.push((key, 0));
+        }
+        let at = self.block.len();
+        self.block.resize(at + size, 0);
+        key.encode(&mut self.block[at..at + K::SIZE]);
+        value.encode(&mut self.block[at + K::SIZE..at + size]);
+
+        self.last = Some(key);
+        self.len += 1;
+        if self.block.len() / size == per_block::<K, V>() {
+            self.flush_block()?;
+        }
+
+        Ok(())
+    }
+
+    fn flush_block(&mut self) -> io::Result<()> {
+        self.index.last_mut().unwrap().1 = crc32c::crc32c(&self.block);
+        self.writer.write_all(&self.block)?;
+        self.block.clear();
+        Ok(())
+    }
+
+    /// Writes the index after the last block, and returns the number of entries written.
+    pub fn finish(mut self) -> io::Result<u64> {
+        if !self.block.is_empty() {
+            self.flush_block()?;
+        }
+
+        let offset = (self.len as usize * (K::SIZE + V::SIZE)) as u64;
+        let mut index = vec![0; self.index.len() * (K::SIZE + 4)];
+        for (i, (k, crc)) in self.index.iter().enumerate() {
+            let at = i * (K::SIZE + 4);
+            k.encode(&mut index[at..at + K::SIZE]);
+            index[at + K::SIZE..at + K::SIZE + 4].copy_from_slice(&crc.to_le_bytes());
+        }
+        self.writer.write_all(&index)?;
+
+        let mut footer = Vec::with_capacity(FOOTER);
+        footer.extend_from_slice(&offset.to_le_bytes());
+        footer.extend_from_slice(&(self.index.len() as u64).to_le_bytes());
+        footer.extend_from_slice(&self.len.to_le_bytes());
+        footer.extend_from_slice(&(K::SIZE as u16).to_le_bytes());
+        footer.extend_from_slice(&(V::SIZE as u16).to_le_bytes());
+        footer.extend_from_slice(&crc32c::crc32c(&index).to_le_bytes());
+        footer.extend_from_slice(MAGIC);
+        self.writer.write_all(&footer)?;
+        self.writer.flush()?;
+
+        Ok(self.len)
+    }
+}
+
+/// An immutable file of entries in key order, for handing a tree's contents to another system or
+/// archiving them.
+///
+/// Entries are packed into fixed size blocks, followed by an index of the first key of every
+/// block. The index is kept in memory, so a lookup reads a single block.
+pub struct SortedRun<K, V> {
+    file: File,
+    index: Vec<(K, u32)>,
+    len: u64,
+    _types: PhantomData<V>,
+}
+
+impl<K: Encode + Ord, V: Encode> SortedRun<K, V> {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = File::open(path)?;
+
+        let size = file.metadata()?.len();
+        if size < FOOTER as u64 {
+            return Err(invalid("file is too short for a sorted run".into()));
+        }
+        let mut footer = [0; FOOTER];
+        file.read_exact_at(&mut footer, size - FOOTER as u64)?;
+        if &footer[32..] != MAGIC {
+            return Err(invalid("file isn't a sorted run".into()));
+        }
+
+        let u64_at = |at: usize| u64::from_le_bytes(footer[at..at + 8].try_into().unwrap());
+        let (offset, blocks, len) = (u64_at(0), u64_at(8), u64_at(16));
+        let k = u16::from_le_bytes([footer[24], footer[25]]) as usize;
+        let v = u16::from_le_bytes([footer[26], footer[27]]) as usize;
+        if k != K::SIZE || v != V::SIZE {
+            return Err(invalid(format!(
+                "run holds {k} byte keys and {v} byte values, want {} and {}",
+                K::SIZE,
+                V::SIZE
+            )));
+        }
+
+        let entries = len * (K::SIZE + V::SIZE) as u64;
+        if offset != entries || blocks != len.div_ceil(per_block::<K, V>() as u64) {
+            return Err(invalid("sorted run footer is corrupt".into()));
+        }
+        let index_len = blocks * (K::SIZE + 4) as u64;
+        if offset + index_len + FOOTER as u64 != size {
+            return Err(invalid("sorted run is truncated".into()));
+        }
+        let mut index = vec![0; index_len as usize];
+        file.read_exact_at(&mut index, offset)?;
+        if crc32c::crc32c(&index) != u32::from_le_bytes(footer[28..32].try_into().unwrap()) {
+            return Err(invalid("sorted run index is corrupt".into()));
+        }
+
+        let index = index
+            .chunks(K::SIZE + 4)
+            .map(|entry| {
+                let crc = u32::from_le_bytes(entry[K::SIZE..].try_into().unwrap());
+                (K::decode(&entry[..K::SIZE]), crc)
+            })
+            .collect();
+
+        Ok(Self {
+            file,
+            index,
+            len,
+            _types: PhantomData,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Reads and verifies block `i`.
+    fn block(&self, i: usize) -> io::Result<Vec<(K, V)>> {
+        let size = K::SIZE + V::SIZE;
+        let per_block = per_block::<K, V>();
+        let count = per_block.min(self.len as usize - i * per_block);
+
+        let mut buf = vec![0; count * size];
+        self.file
+            .read_exact_at(&mut buf, (i * per_block * size) as u64)?;
+        if crc32c::crc32c(&buf) != self.index[i].1 {
+            return Err(invalid(format!("sorted run block {i} is corrupt")));
+        }
+
+        Ok(buf
+            .chunks(size)
+            .map(|entry| (K::decode(&entry[..K::SIZE]), V::decode(&entry[K::SIZE..])))
+            .collect())
+    }
+
+    /// The block that holds `key` if any block does.
+    fn find_block(&self, key: &K) -> usize {
+        self.index
+            .partition_point(|(first, _)| first <= key)
+            .saturating_sub(1)
+    }
+
+    pub fn get(&self, key: K) -> io::Result<Option<V>> {
+        if self.index.is_empty() {
+            return Ok(None);
+        }
+
+        let block = self.block(self.find_block(&key))?;
+        Ok(block
+            .binary_search_by(|(k, _)| k.cmp(&key))
+            .ok()
+            .map(|i| block[i].1))
+    }
+
+    /// Returns the entries with keys in `range`, in order.
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> io::Result<Vec<(K, V)>> {
+        let start = match range.start_bound() {
+            Bound::Included(k) | Bound::Excluded(k) => self.find_block(k),
+            Bound::Unbounded => 0,
+        };
+
+        let mut out = Vec::new();
+        for i in start..self.index.len() {
+            for (k, v) in self.block(i)? {
+                if !is_before_end(&range, k) {
+                    return Ok(out);
+                }
+                if range.contains(&k) {
+                    out.push((k, v));
+                }
+            }
+        }
+
+        Ok(out)
+    }
+
+    pub fn iter(&self) -> io::Result<Vec<(K, V)>> {
+        self.range(..)
+    }
+}
+
+impl<K, V> BTree<K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment + Encode,
+    V: Clone + Copy + Debug + Eq + Encode,
+{
+    /// Writes every entry to `writer` as a `SortedRun`, returning the number written.
+    pub fn export_sorted_run<W: Write>(&self, writer: W) -> io::Result<u64> {
+        let mut run = RunWriter::new(writer);
+        for (k, v) in self.iter() {
+            run.push(k, v)?;
+        }
+        run.finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::File;
+    use std::io::BufWriter;
+
+    use crate::btree::BTree;
+    use crate::paged::PagedBTree;
+    use crate::slot::Slot;
+
+    use super::SortedRun;
+
+    #[test]
+    fn test_sorted_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("run");
+
+        let mut tree = BTree::new(8);
+        for k in (0..3000u32).map(|k| k * 2) {
+            tree.insert(Slot::new_leaf(k, k as u64 + 1));
+        }
+        let file = BufWriter::new(File::create(&path).unwrap());
+        assert!(tree.export_sorted_run(file).unwrap() == 3000);
+
+        let run = SortedRun::<u32, u64>::open(&path).unwrap();
+        assert!(run.len() == 3000);
+        assert!(run.get(0).unwrap() == Some(1));
+        assert!(run.get(4000).unwrap() == Some(4001));
+        assert!(run.get(4001).unwrap().is_none());
+        assert!(run.get(10_000).unwrap().is_none());
+
+        let want = (501..1500u32)
+            .filter(|k| k % 2 == 0)
+            .map(|k| (k, k as u64 + 1))
+            .collect::<Vec<_>>();
+        let have = run.range(501..1500).unwrap();
+        assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+        assert!(SortedRun::<u64, u64>::open(&path).is_err());
+
+        // A paged tree exports the same run
+        let mut paged = PagedBTree::create(dir.path().join("tree"), 16).unwrap();
+        for k in (0..3000u32).rev() {
+            paged.insert(k * 2, k as u64 * 2 + 1).unwrap();
+        }
+        let paged_path = dir.path().join("paged");
+        let file = BufWriter::new(File::create(&paged_path).unwrap());
+        assert!(paged.export_sorted_run(file).unwrap() == 3000);
+        assert!(std::fs::read(&path).unwrap() == std::fs::read(&paged_path).unwrap());
+
+        let empty = dir.path().join("empty");
+        BTree::<u32, u64>::new(8)
+            .export_sorted_run(File::create(&empty).unwrap())
+            .unwrap();
+        let run = SortedRun::<u32, u64>::open(&empty).unwrap();
+        assert!(run.is_empty() && run.get(1).unwrap().is_none());
+        assert!(run.iter().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,76 @@
+use std::collections::BTreeSet;
+use std::fmt::Debug;
+
+use rand::Rng;
+
+use crate::btree::{BTree, Increment};
+
+impl<K, V> BTree<K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment,
+    V: Clone + Debug + Eq,
+{
+    /// Returns `n` distinct entries chosen uniformly at random, in key order, or every entry if
+    /// there are fewer. Each is found by its rank, descending by the entry counts of the internal
+    /// nodes, so it takes `n` lookups rather than a scan.
+    pub fn sample<R: Rng + ?Sized>(&self, rng: &mut R, n: usize) -> Vec<(K, V)> {
+        let len = self.len();
+        if n >= len {
+            return self.iter().collect();
+        }
+
+        // Floyd's algorithm, `n` distinct ranks in `n` draws
+        let mut ranks = BTreeSet::new();
+        for j in len - n..len {
+            let rank = rng.gen_range(0..=j);
+            if !ranks.insert(rank) {
+                ranks.insert(j);
+            }
+        }
+
+        ranks.into_iter().map(|rank| self.select(rank)).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use crate::btree::BTree;
+
+    #[test]
+    fn test_sample() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut tree = BTree::bulk_load(8, (0..1000u32).map(|k| (k, k * 2)));
+        // Uneven leaves, which sampling a random leaf then a random entry would be skewed by
+        tree.delete_where(|k, _| k < 500 && k % 4 != 0);
+        let len = tree.len();
+
+        let mut seen = vec![0; 1000];
+        for _ in 0..2000 {
+            let have = tree.sample(&mut rng, 50);
+            assert!(have.len() == 50 && have.windows(2).all(|w| w[0].0 < w[1].0));
+            for (k, v) in have {
+                assert!(v == k * 2 && (k >= 500 || k % 4 == 0));
+                seen[k as usize] += 1;
+            }
+        }
+
+        // Every entry is drawn 2000 * 50 / len times on average
+        let want = 2000 * 50 / len;
+        let sparse = seen[..500].iter().step_by(4).sum::<usize>() / 125;
+        let dense = seen[500..].iter().sum::<usize>() / 500;
+        for have in [sparse, dense] {
+            assert!(have.abs_diff(want) < want / 20, "Want: {}\nHave: {}", want, have);
+        }
+        assert!(seen
+            .iter()
+            .enumerate()
+            .all(|(k, n)| (*n > 0) == (k >= 500 || k % 4 == 0)));
+
+        let all = tree.sample(&mut rng, len + 1);
+        assert!(all == tree.iter().collect::<Vec<_>>());
+        assert!(BTree::<u32, u32>::new(8).sample(&mut rng, 3).is_empty());
+    }
+}
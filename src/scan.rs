@@ -0,0 +1,233 @@
+//! A streaming scan over a `PagedBTree`. Unlike `PagedBTree::range()`, which decodes every leaf
+//! into a `Vec`, a `Scan` keeps the leaf it is on pinned and hands out entries that borrow their
+//! bytes from the page. An entry can't outlive the next call to `next()`, which is what lets the
+//! scan unpin a leaf as it moves to the next one, so `Scan` isn't an `Iterator` and is read with
+//! `while let Some(entry) = scan.next()?`.
+
+use std::io;
+use std::marker::PhantomData;
+use std::ops::{Bound, RangeBounds};
+
+use crate::buffer::{BufferPool, PageReadGuard};
+use crate::metrics::{self, Op, Timer};
+use crate::page::{Encode, PageId, PageNode};
+use crate::paged::{is_before_end, leaves_after};
+
+/// An entry in the leaf page a `Scan` has pinned.
+pub struct Entry<'a, K, V> {
+    bytes: &'a [u8],
+    _types: PhantomData<(K, V)>,
+}
+
+impl<'a, K: Encode, V: Encode> Entry<'a, K, V> {
+    pub fn key(&self) -> K {
+        K::decode(self.key_bytes())
+    }
+
+    pub fn value(&self) -> V {
+        V::decode(self.value_bytes())
+    }
+
+    /// The key as encoded in the page.
+    pub fn key_bytes(&self) -> &'a [u8] {
+        &self.bytes[..K::SIZE]
+    }
+
+    /// The value as encoded in the page.
+    pub fn value_bytes(&self) -> &'a [u8] {
+        &self.bytes[K::SIZE..]
+    }
+}
+
+/// The entries of a key range of a `PagedBTree`, in order, see `PagedBTree::scan()`.
+pub struct Scan<'a, K, V> {
+    pool: &'a BufferPool,
+    root: Option<PageId>,
+    // The leaf being read, `None` once the scan is done
+    page: Option<PageReadGuard<'a>>,
+    // The next entry of `page`
+    at: usize,
+    // The last leaf read ahead, the next batch is read on reaching it
+    ahead: Option<PageId>,
+    range: (Bound<K>, Bound<K>),
+    _timer: Timer,
+    _types: PhantomData<V>,
+}
+
+impl<'a, K, V> Scan<'a, K, V>
+where
+    K: Copy + Ord + Encode,
+    V: Encode,
+{
+    /// Scans `range` from `start`, the leaf its start is in.
+    pub(crate) fn new(
+        pool: &'a BufferPool,
+        root: Option<PageId>,
+        start: Option<PageReadGuard<'a>>,
+        range: (Bound<K>, Bound<K>),
+    ) -> Self {
+        let mut scan = Self {
+            pool,
+            root,
+            page: start,
+            at: 0,
+            ahead: None,
+            range,
+            _timer: metrics::time(Op::Scan),
+            _types: PhantomData,
+        };
+        scan.read_ahead();
+
+        scan
+    }
+
+    /// Reads ahead the leaves after the current one if the range goes on past it, a batch at a
+    /// time, see `BufferPool::read_ahead()`.
+    fn read_ahead(&mut self) {
+        let Some(page) = &self.page else {
+            return;
+        };
+        if self.ahead.is_some_and(|a| a != page.id()) {
+            return;
+        }
+        let Ok((entries, Some(_))) = PageNode::<K, V>::leaf_entries(page) else {
+            return;
+        };
+
+        let size = K::SIZE + V::SIZE;
+        if entries.len() >= size {
+            let last = K::decode(&entries[entries.len() - size..][..K::SIZE]);
+            if is_before_end(&self.range, last) {
+                // Only a hint, a failure is left to the read of the leaf
+                let ids = leaves_after::<K, V, _>(self.pool, self.root, last, &self.range);
+                let ids = ids.unwrap_or_default();
+                self.ahead = ids.last().copied();
+                self.pool.read_ahead(&ids);
+            }
+        }
+    }
+
+    /// Returns the next entry, `None` once past the end of the range. The leaf the entry is in
+    /// stays pinned until the scan moves off it or is dropped.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> io::Result<Option<Entry<'_, K, V>>> {
+        let size = K::SIZE + V::SIZE;
+        loop {
+            let Some(page) = &self.page else {
+                return Ok(None);
+            };
+
+            let (entries, next) = PageNode::<K, V>::leaf_entries(page)?;
+            if self.at < entries.len() / size {
+                let key = K::decode(&entries[self.at * size..self.at * size + K::SIZE]);
+                if !is_before_end(&self.range, key) {
+                    self.page = None;
+                    return Ok(None);
+                }
+                if self.range.contains(&key) {
+                    break;
+                }
+
+                self.at += 1;
+                continue;
+            }
+
+            // Unpin the leaf before pinning the next one
+            self.page = None;
+            self.at = 0;
+            if let Some(id) = next {
+                self.page = Some(self.pool.fetch(id)?);
+                self.read_ahead();
+            }
+        }
+
+        let at = self.at;
+        self.at += 1;
+        let (entries, _) = PageNode::<K, V>::leaf_entries(self.page.as_ref().unwrap())?;
+        Ok(Some(Entry {
+            bytes: &entries[at * size..(at + 1) * size],
+            _types: PhantomData,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::buffer::Capacity;
+    use crate::disk::Backend;
+    use crate::paged::{Options, PagedBTree};
+
+    #[test]
+    fn test_scan() {
+        // Too few frames to pin more than a couple of leaves at once
+        let mut tree = PagedBTree::create_in_memory(8, Capacity::Pages(4)).unwrap();
+        for k in (0..2000u32).rev() {
+            tree.insert(k, k as u64 * 2).unwrap();
+        }
+
+        for range in [(10, 20), (0, 2000), (1990, 3000), (500, 500)] {
+            let mut have = Vec::new();
+            let mut scan = tree.scan(range.0..range.1).unwrap();
+            while let Some(entry) = scan.next().unwrap() {
+                assert!(entry.value_bytes() == (entry.key() as u64 * 2).to_le_bytes());
+                have.push((entry.key(), entry.value()));
+            }
+            assert!(scan.next().unwrap().is_none());
+
+            let want = tree.range(range.0..range.1).unwrap();
+            assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+        }
+
+        // Leaves are read ahead only when the range goes on past them
+        let prefetches = || tree.pool().stats().prefetches;
+        let before = prefetches();
+        tree.scan(10..11).unwrap().next().unwrap();
+        tree.range(10..11).unwrap();
+        assert!(prefetches() == before, "Have: {}", prefetches() - before);
+        tree.range(..).unwrap();
+        let mut scan = tree.scan(..).unwrap();
+        while scan.next().unwrap().is_some() {}
+        assert!(prefetches() - before > 100, "Have: {}", prefetches() - before);
+
+        let mut scan = tree.scan(..=3).unwrap();
+        let first = scan.next().unwrap().unwrap();
+        assert!(first.key() == 0 && first.key_bytes() == 0u32.to_le_bytes());
+        let mut scan = tree.scan(1999..).unwrap();
+        assert!(scan.next().unwrap().unwrap().value() == 3998);
+        assert!(scan.next().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_scan_reads_ahead() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree");
+        let options = Options {
+            backend: Backend::IoUring,
+            ..Default::default()
+        };
+        let mut tree = PagedBTree::create_with_options(&path, 8, options).unwrap();
+        for k in 0..4000u32 {
+            tree.insert(k, k as u64).unwrap();
+        }
+        drop(tree);
+
+        let options = Options {
+            capacity: Capacity::Pages(64),
+            ..options
+        };
+        let tree = PagedBTree::<u32, u64>::open_with_options(&path, options).unwrap();
+        let mut have = Vec::new();
+        let mut scan = tree.scan(..).unwrap();
+        while let Some(entry) = scan.next().unwrap() {
+            have.push(entry.key());
+        }
+        assert!(have == (0..4000).collect::<Vec<_>>());
+
+        // Leaves are read a parent's worth at a time and found in the pool by the scan
+        let stats = tree.pool().stats();
+        if tree.pool().with_disk(|disk| disk.batches_reads()) {
+            assert!(stats.misses * 4 < stats.hits, "Have: {:?}", stats);
+        }
+        assert!(stats.prefetches > 500, "Have: {:?}", stats);
+    }
+}
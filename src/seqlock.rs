@@ -0,0 +1,127 @@
+use crate::sync::{self, fence, AtomicU64, Ordering};
+
+/// A sequence lock for a single writer and any number of optimistic readers.
+///
+/// The sequence is odd while a write is in progress. Readers note the sequence before copying
+/// the protected data and retry if it changed by the time they are done, so they never block the
+/// writer and never write to shared memory themselves.
+#[derive(Debug, Default)]
+pub struct SeqLock {
+    seq: AtomicU64,
+}
+
+pub struct SeqLockWriteGuard<'a> {
+    lock: &'a SeqLock,
+}
+
+impl Drop for SeqLockWriteGuard<'_> {
+    fn drop(&mut self) {
+        self.lock.seq.fetch_add(1, Ordering::Release);
+    }
+}
+
+impl Clone for SeqLock {
+    // A copy is a different node, it starts with no readers or writer
+    fn clone(&self) -> Self {
+        Self::new()
+    }
+}
+
+impl SeqLock {
+    pub fn new() -> Self {
+        Self {
+            seq: AtomicU64::new(0),
+        }
+    }
+
+    /// Waits out an in-progress write and returns the sequence to validate against.
+    pub fn read_begin(&self) -> u64 {
+        loop {
+            let seq = self.seq.load(Ordering::Acquire);
+            if seq & 1 == 0 {
+                return seq;
+            }
+
+            sync::spin_loop();
+        }
+    }
+
+    /// Returns `true` if nothing was written since `read_begin` returned `seq`.
+    pub fn read_validate(&self, seq: u64) -> bool {
+        fence(Ordering::Acquire);
+        self.seq.load(Ordering::Relaxed) == seq
+    }
+
+    /// Callers must already exclude other writers, the lock only fences readers.
+    pub fn write(&self) -> SeqLockWriteGuard<'_> {
+        let seq = self.seq.fetch_add(1, Ordering::Relaxed);
+        assert!(seq & 1 == 0, "concurrent writers on a seqlock");
+        fence(Ordering::Release);
+
+        SeqLockWriteGuard { lock: self }
+    }
+
+    pub fn sequence(&self) -> u64 {
+        self.seq.load(Ordering::Acquire)
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod test {
+    use super::SeqLock;
+
+    #[test]
+    fn test_seqlock() {
+        let lock = SeqLock::new();
+
+        let seq = lock.read_begin();
+        assert!(lock.read_validate(seq));
+
+        {
+            let _w = lock.write();
+            let have = lock.sequence();
+            assert!(have & 1 == 1, "Have: {have}");
+        }
+
+        assert!(!lock.read_validate(seq));
+
+        let seq = lock.read_begin();
+        assert!(seq == 2, "Want: 2\nHave: {seq}");
+        assert!(lock.read_validate(seq));
+    }
+}
+
+#[cfg(all(test, loom))]
+mod loom_test {
+    use loom::sync::atomic::{AtomicU64, Ordering};
+    use loom::sync::Arc;
+    use loom::thread;
+
+    use super::SeqLock;
+
+    #[test]
+    fn loom_seqlock_read_is_consistent() {
+        loom::model(|| {
+            let lock = Arc::new(SeqLock::new());
+            let data = Arc::new((AtomicU64::new(0), AtomicU64::new(0)));
+
+            let writer = {
+                let (lock, data) = (lock.clone(), data.clone());
+                thread::spawn(move || {
+                    let _w = lock.write();
+                    data.0.store(1, Ordering::Relaxed);
+                    data.1.store(1, Ordering::Relaxed);
+                })
+            };
+
+            let seq = lock.read_begin();
+            let a = data.0.load(Ordering::Relaxed);
+            let b = data.1.load(Ordering::Relaxed);
+            if lock.read_validate(seq) {
+                assert!(a == b, "Torn read: {a} {b}");
+            }
+
+            writer.join().unwrap();
+        });
+    }
+}
@@ -0,0 +1,91 @@
+use std::fmt::Debug;
+
+use serde::de::Error;
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::btree::{BTree, Increment, DEFAULT_MAX};
+
+/// The entries of a tree in order, serialized as a sequence of `(key, value)` pairs.
+struct Entries<'a, K, V>(&'a BTree<K, V>);
+
+impl<K, V> Serialize for Entries<'_, K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment + Serialize,
+    V: Clone + Debug + Eq + Serialize,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.0.iter())
+    }
+}
+
+/// A tree is serialized as its fanout, `max`, and its entries in order, not its nodes.
+impl<K, V> Serialize for BTree<K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment + Serialize,
+    V: Clone + Debug + Eq + Serialize,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut tree = serializer.serialize_struct("BTree", 2)?;
+        tree.serialize_field("max", &self.max())?;
+        tree.serialize_field("entries", &Entries(self))?;
+        tree.end()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "BTree")]
+struct Loaded<K, V> {
+    max: Option<usize>,
+    entries: Vec<(K, V)>,
+}
+
+/// Rebuilds a tree with `BTree::bulk_load()`. `max` may be left out, and the entries must be in
+/// ascending key order.
+impl<'de, K, V> Deserialize<'de> for BTree<K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment + Deserialize<'de>,
+    V: Clone + Debug + Eq + Deserialize<'de>,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let loaded = Loaded::<K, V>::deserialize(deserializer)?;
+
+        let max = loaded.max.unwrap_or(DEFAULT_MAX);
+        if max < 4 {
+            return Err(D::Error::custom(format!("fanout {max} is less than 4")));
+        }
+        if let Some(w) = loaded.entries.windows(2).find(|w| w[0].0 >= w[1].0) {
+            return Err(D::Error::custom(format!(
+                "key {:?} out of order after {:?}",
+                w[1].0, w[0].0
+            )));
+        }
+
+        Ok(BTree::bulk_load(max, loaded.entries))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::btree::{BTree, DEFAULT_MAX};
+    use crate::slot::Slot;
+
+    #[test]
+    fn test_serde() {
+        let mut tree = BTree::new(8);
+        for k in (0..500u32).rev() {
+            tree.insert(Slot::new_leaf(k, k as u64 * 2));
+        }
+
+        let json = serde_json::to_string(&tree).unwrap();
+        let have = serde_json::from_str::<BTree<u32, u64>>(&json).unwrap();
+        assert!(have.max() == 8, "Have: {}", have.max());
+        let (want, have) = (tree.iter().collect::<Vec<_>>(), have.iter().collect::<Vec<_>>());
+        assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+
+        let have = serde_json::from_str::<BTree<u32, u64>>(r#"{"entries":[[1,2],[3,4]]}"#).unwrap();
+        assert!(have.max() == DEFAULT_MAX && have.get(3).is_some());
+        assert!(serde_json::from_str::<BTree<u32, u64>>(r#"{"entries":[[3,4],[1,2]]}"#).is_err());
+        assert!(serde_json::from_str::<BTree<u32, u64>>(r#"{"entries":[[1,2],[1,2]]}"#).is_err());
+    }
+}
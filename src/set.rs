@@ -0,0 +1,132 @@
+use std::fmt::Debug;
+use std::iter::FusedIterator;
+
+use crate::btree::{BTree, Increment};
+use crate::iter::{Duplicates, Merge, Range};
+
+impl<K, V> BTree<K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment,
+    V: Clone + Copy + Debug + Eq,
+{
+    /// Returns the entries of either tree in order, with this tree's value for keys in both.
+    pub fn union<'a>(&'a self, other: &'a BTree<K, V>) -> Merge<Range<'a, K, V>, K, V> {
+        Merge::new([self.iter(), other.iter()], Duplicates::First)
+    }
+
+    /// Returns the entries of this tree whose keys are in `other`, in order.
+    pub fn intersection<'a>(&'a self, other: &'a BTree<K, V>) -> Intersection<'a, K, V> {
+        Intersection {
+            a: self.iter(),
+            b: other.iter(),
+        }
+    }
+
+    /// Returns the entries of this tree whose keys aren't in `other`, in order.
+    pub fn difference<'a>(&'a self, other: &'a BTree<K, V>) -> Difference<'a, K, V> {
+        Difference {
+            a: self.iter(),
+            b: other.iter(),
+        }
+    }
+}
+
+/// Walks the leaves of both trees together, each skipping ahead to the other's next key a leaf at
+/// a time. See `BTree::intersection`.
+pub struct Intersection<'a, K, V> {
+    a: Range<'a, K, V>,
+    b: Range<'a, K, V>,
+}
+
+impl<K, V> Iterator for Intersection<'_, K, V>
+where
+    K: Copy + Ord,
+    V: Copy,
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (mut k, mut v) = self.a.next()?;
+        loop {
+            self.b.seek(k);
+            let (other, _) = self.b.peek()?;
+            if other == k {
+                self.b.next();
+                return Some((k, v));
+            }
+
+            self.a.seek(other);
+            (k, v) = self.a.next()?;
+        }
+    }
+}
+
+impl<K: Copy + Ord, V: Copy> FusedIterator for Intersection<'_, K, V> {}
+
+/// Walks the leaves of both trees together, see `BTree::difference`.
+pub struct Difference<'a, K, V> {
+    a: Range<'a, K, V>,
+    b: Range<'a, K, V>,
+}
+
+impl<K, V> Iterator for Difference<'_, K, V>
+where
+    K: Copy + Ord,
+    V: Copy,
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (k, v) = self.a.next()?;
+            self.b.seek(k);
+            if self.b.peek().is_none_or(|(other, _)| other != k) {
+                return Some((k, v));
+            }
+        }
+    }
+}
+
+impl<K: Copy + Ord, V: Copy> FusedIterator for Difference<'_, K, V> {}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+
+    use crate::btree::BTree;
+
+    #[test]
+    fn test_set_operations() {
+        // Document IDs of two search terms
+        let a = (0..2000u32)
+            .filter(|k| k.is_multiple_of(3))
+            .collect::<BTreeSet<_>>();
+        let b = (1000..1400u32)
+            .filter(|k| k.is_multiple_of(5))
+            .collect::<BTreeSet<_>>();
+        let tree_a = BTree::bulk_load(8, a.iter().map(|k| (*k, ())));
+        let tree_b = BTree::bulk_load(8, b.iter().map(|k| (*k, ())));
+
+        let want = a.union(&b).copied().collect::<Vec<_>>();
+        let have = tree_a.union(&tree_b).map(|e| e.0).collect::<Vec<_>>();
+        assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+
+        for (x, y, tx, ty) in [(&a, &b, &tree_a, &tree_b), (&b, &a, &tree_b, &tree_a)] {
+            let want = x.intersection(y).copied().collect::<Vec<_>>();
+            let have = tx.intersection(ty).map(|e| e.0).collect::<Vec<_>>();
+            assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+
+            let want = x.difference(y).copied().collect::<Vec<_>>();
+            let have = tx.difference(ty).map(|e| e.0).collect::<Vec<_>>();
+            assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+        }
+
+        // Into a new tree
+        let both = BTree::bulk_load(8, tree_a.intersection(&tree_b));
+        assert!(both.iter().count() == 27);
+        let empty = BTree::new(8);
+        assert!(tree_a.intersection(&empty).next().is_none());
+        assert!(tree_a.difference(&empty).count() == a.len());
+        assert!(empty.union(&tree_b).count() == b.len());
+    }
+}
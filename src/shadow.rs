@@ -0,0 +1,125 @@
+//! Shadow verification for `BTree`, with the `shadow` feature. Each tree keeps a `BTreeMap` of
+//! what it should hold and checks every operation against it, panicking with both and a dump of
+//! the tree at the first disagreement. Every operation costs at least what it does on the map,
+//! and ranges are read in full when created, so this is for debugging and tests.
+//!
+//! Without the feature the hooks are empty and the map isn't there.
+
+#[cfg(feature = "shadow")]
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::ops::Bound;
+
+use crate::btree::{BTree, Increment};
+
+/// The model a shadowed tree is checked against.
+#[cfg(feature = "shadow")]
+pub(crate) type Shadow<K, V> = BTreeMap<K, V>;
+
+#[cfg_attr(not(feature = "shadow"), allow(unused_variables))]
+impl<K, V> BTree<K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment,
+    V: Clone + Debug + Eq,
+{
+    /// `key` was written with `value`.
+    #[inline(always)]
+    pub(crate) fn shadow_insert(&mut self, key: K, value: &V) {
+        #[cfg(feature = "shadow")]
+        self.shadow.insert(key, value.clone());
+    }
+
+    /// `get(key)` returned `have`.
+    #[inline(always)]
+    pub(crate) fn shadow_get(&self, key: K, have: Option<&V>) {
+        #[cfg(feature = "shadow")]
+        self.diverged(&format!("get({:?})", key), self.shadow.get(&key), have);
+    }
+
+    /// `key` was deleted if `have`.
+    #[inline(always)]
+    pub(crate) fn shadow_delete(&mut self, key: K, have: bool) {
+        #[cfg(feature = "shadow")]
+        {
+            let want = self.shadow.remove(&key).is_some();
+            self.diverged(&format!("delete({:?})", key), want, have);
+        }
+    }
+
+    /// A range from `start` to `end` was created, which yields `have`.
+    #[inline(always)]
+    pub(crate) fn shadow_range<I: Iterator<Item = (K, V)>>(
+        &self,
+        start: Bound<K>,
+        end: Bound<K>,
+        have: impl FnOnce() -> I,
+    ) {
+        #[cfg(feature = "shadow")]
+        {
+            let want = self
+                .shadow
+                .range((start, end))
+                .map(|(k, v)| (*k, v.clone()));
+            let op = format!("range({:?}, {:?})", start, end);
+            self.diverged(&op, want.collect::<Vec<_>>(), have().collect::<Vec<_>>());
+        }
+    }
+
+    /// Many entries changed at once, by `update_range()` or `delete_where()`. `want` brings the
+    /// model up to date, then the whole tree is compared with it.
+    #[cfg(feature = "shadow")]
+    pub(crate) fn shadow_bulk<F: FnOnce(&mut Shadow<K, V>)>(&mut self, op: &str, want: F) {
+        want(&mut self.shadow);
+        self.shadow_range(Bound::Unbounded, Bound::Unbounded, || self._range(..));
+        if let Err(e) = self.validate() {
+            self.diverged(op, "a valid tree".to_string(), e);
+        }
+    }
+
+    #[cfg(feature = "shadow")]
+    fn diverged<T: PartialEq + Debug>(&self, op: &str, want: T, have: T) {
+        if want != have {
+            panic!(
+                "shadowed tree diverged at {}\nWant: {:?}\nHave: {:?}\n{}",
+                op,
+                want,
+                have,
+                self.display(Default::default())
+            );
+        }
+    }
+}
+
+#[cfg(all(test, feature = "shadow"))]
+mod test {
+    use std::panic::{self, AssertUnwindSafe};
+    use std::ptr;
+
+    use crate::btree::BTree;
+    use crate::slot::Slot;
+
+    #[test]
+    fn test_shadow() {
+        let mut tree = BTree::new(4);
+        for k in 0..200u32 {
+            tree.insert(Slot::new_leaf(k, k));
+        }
+        for k in (0..200).step_by(3) {
+            assert!(tree.delete(k));
+        }
+        assert!(tree.range(10..50).count() == 27);
+        tree.update_range(..100, |_, v| *v += 1);
+        tree.delete_where(|k, _| k % 5 == 0);
+        tree.set_merge_operator(|_, old, v| old.unwrap_or(0) + v);
+        tree.merge(1, 10);
+        tree.merge(1000, 10);
+        assert!(tree.shadow.len() == tree.iter().count());
+
+        // Losing an entry behind the tree's back panics on the next read of it
+        let test = Slot::new_internal(4, ptr::null_mut());
+        assert!(BTree::_delete(tree.root(), test));
+        let have = panic::catch_unwind(AssertUnwindSafe(|| tree.get(4))).unwrap_err();
+        let have = have.downcast_ref::<String>().unwrap();
+        assert!(have.starts_with("shadowed tree diverged at get(4)\nWant: Some(5)\nHave: None"));
+    }
+}
@@ -0,0 +1,189 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fmt::Debug;
+use std::hash::{Hash, Hasher};
+use std::ops::{Bound, RangeBounds};
+use std::sync::RwLock;
+
+use crate::btree::{BTree, Increment};
+use crate::iter::{Duplicates, Merge};
+use crate::slot::Slot;
+
+pub enum Partition<K> {
+    /// Shard `i` holds the keys less than `bounds[i]` and not less than `bounds[i - 1]`, the last
+    /// shard holds everything from the last bound up.
+    Range(Vec<K>),
+    /// Keys are spread by hash, so a range scan has to visit and merge every shard.
+    Hash(fn(&K) -> u64),
+}
+
+fn hash_key<K: Hash>(key: &K) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Partitions keys across independent `BTree`s, each behind its own lock, so operations on
+/// different shards don't contend.
+pub struct ShardedBTree<K, V> {
+    shards: Vec<RwLock<BTree<K, V>>>,
+    partition: Partition<K>,
+}
+
+impl<K, V> ShardedBTree<K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment,
+    V: Clone + Copy + Debug + Eq,
+{
+    /// Creates `bounds.len() + 1` range-partitioned shards, `bounds` must be sorted.
+    pub fn with_ranges(max: usize, bounds: Vec<K>) -> Self {
+        assert!(bounds.windows(2).all(|w| w[0] < w[1]), "bounds must be sorted");
+
+        Self {
+            shards: (0..=bounds.len())
+                .map(|_| RwLock::new(BTree::new(max)))
+                .collect(),
+            partition: Partition::Range(bounds),
+        }
+    }
+
+    pub fn with_hash(max: usize, shards: usize) -> Self
+    where
+        K: Hash,
+    {
+        assert!(shards > 0);
+
+        Self {
+            shards: (0..shards).map(|_| RwLock::new(BTree::new(max))).collect(),
+            partition: Partition::Hash(hash_key::<K>),
+        }
+    }
+
+    pub fn shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Returns the shard `key` belongs to.
+    pub fn shard(&self, key: K) -> usize {
+        match &self.partition {
+            Partition::Range(bounds) => bounds.partition_point(|b| *b <= key),
+            Partition::Hash(hash) => (hash(&key) % self.shards.len() as u64) as usize,
+        }
+    }
+
+    pub fn insert(&self, entry: Slot<K, V>) {
+        self.shards[self.shard(entry.0)]
+            .write()
+            .unwrap()
+            .insert(entry);
+    }
+
+    pub fn get(&self, key: K) -> Option<Slot<K, V>> {
+        self.shards[self.shard(key)].read().unwrap().get(key)
+    }
+
+    pub fn delete(&self, key: K) -> bool {
+        self.shards[self.shard(key)].write().unwrap().delete(key)
+    }
+
+    /// Returns the entries with keys in `range` from every shard, in order. Each shard is locked
+    /// only while its part of the range is copied out.
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> Vec<(K, V)> {
+        let range = (range.start_bound().cloned(), range.end_bound().cloned());
+
+        let shards = match (&self.partition, range.0, range.1) {
+            (Partition::Range(_), start, end) => {
+                let first = match start {
+                    Bound::Included(k) | Bound::Excluded(k) => self.shard(k),
+                    Bound::Unbounded => 0,
+                };
+                let last = match end {
+                    Bound::Included(k) | Bound::Excluded(k) => self.shard(k),
+                    Bound::Unbounded => self.shards.len() - 1,
+                };
+
+                first..(last + 1).max(first)
+            }
+            (Partition::Hash(_), _, _) => 0..self.shards.len(),
+        };
+
+        let parts = self.shards[shards]
+            .iter()
+            .map(|shard| shard.read().unwrap().range(range).collect::<Vec<_>>());
+
+        // Range shards are already in order, hash shards each hold a sorted subset
+        match self.partition {
+            Partition::Range(_) => parts.flatten().collect(),
+            Partition::Hash(_) => {
+                let parts = parts.map(Vec::into_iter).collect::<Vec<_>>();
+                Merge::new(parts, Duplicates::First).collect()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+
+    use crate::get_left;
+    use crate::slot::{Either, Slot};
+
+    use super::ShardedBTree;
+
+    fn check(tree: &ShardedBTree<u32, u32>) {
+        thread::scope(|s| {
+            for t in 0..4u32 {
+                s.spawn(move || {
+                    for k in (t * 250)..((t + 1) * 250) {
+                        tree.insert(Slot::new_leaf(k, k + 10));
+                    }
+                });
+            }
+        });
+
+        for k in 0..1000u32 {
+            let test = match tree.get(k) {
+                Some(t) => t,
+                None => panic!("Could not find {k}"),
+            };
+
+            let have = get_left!(test);
+            assert!(have == k + 10, "Want: {}\nHave: {have}", k + 10);
+        }
+
+        for k in (0..1000u32).step_by(2) {
+            assert!(tree.delete(k));
+        }
+
+        let want = (101..900u32)
+            .step_by(2)
+            .map(|k| (k, k + 10))
+            .collect::<Vec<_>>();
+        let have = tree.range(100..900);
+        assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+
+        let have = tree.range(..).len();
+        assert!(have == 500, "Want: 500\nHave: {have}");
+    }
+
+    #[test]
+    fn test_range_sharded() {
+        const MAX: usize = 8;
+
+        let tree = ShardedBTree::with_ranges(MAX, vec![250, 500, 750]);
+        assert!(tree.shards() == 4);
+        assert!(tree.shard(0) == 0);
+        assert!(tree.shard(250) == 1);
+        assert!(tree.shard(999) == 3);
+
+        check(&tree);
+    }
+
+    #[test]
+    fn test_hash_sharded() {
+        const MAX: usize = 8;
+
+        let tree = ShardedBTree::with_hash(MAX, 4);
+        check(&tree);
+    }
+}
@@ -0,0 +1,93 @@
+//! Adaptive leaf sizes for `BTree`. With a `Sizing` set, the tree counts the reads and writes of
+//! each leaf, and when a leaf splits it picks the fanout of both halves from what the leaf was
+//! used for since it was made: leaves in regions taking inserts faster than they are read get
+//! smaller, so they are cheaper to split, and leaves read far more than written, as in regions
+//! that are mostly scanned, get larger, so scans cross fewer of them.
+//!
+//! Only leaves adapt, internal nodes keep the fanout the tree was created with. The sizes leaves
+//! ended up with are in `TreeStats::leaf_sizes`.
+
+use std::fmt::Debug;
+use std::sync::atomic::Ordering;
+
+use crate::btree::{BTree, Increment};
+use crate::node::Node;
+
+/// Reads per write at or above which a leaf is considered scanned rather than written.
+const READ_HEAVY: u32 = 8;
+
+/// The bounds leaf fanouts are kept within.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct Sizing {
+    pub min: usize,
+    pub max: usize,
+}
+
+impl Default for Sizing {
+    fn default() -> Self {
+        Self { min: 16, max: 256 }
+    }
+}
+
+impl Sizing {
+    /// The fanout for the halves of `leaf`, which is splitting.
+    pub(crate) fn resize<K, V>(&self, leaf: &Node<K, V>) -> usize {
+        let reads = leaf.heat.reads.load(Ordering::Relaxed);
+        let writes = leaf.heat.writes;
+
+        let max = match () {
+            _ if writes >= reads => leaf.max / 2,
+            _ if reads >= writes.saturating_mul(READ_HEAVY) => leaf.max * 2,
+            _ => leaf.max,
+        };
+        max.clamp(self.min.max(4), self.max.max(self.min).max(4))
+    }
+}
+
+impl<K, V> BTree<K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment,
+    V: Clone + Debug + Eq,
+{
+    /// Lets leaves change fanout as they split, within `sizing`, see `Sizing`. `None` keeps new
+    /// leaves at `max()`, leaves already resized stay as they are.
+    pub fn set_sizing(&mut self, sizing: Option<Sizing>) {
+        self.sizing = sizing;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::btree::BTree;
+    use crate::slot::Slot;
+
+    use super::Sizing;
+
+    #[test]
+    fn test_sizing() {
+        // Keys below 20000 are scanned over and over while a few inserts trickle in, keys above
+        // are inserted without being read
+        let mut tree = BTree::bulk_load(32, (0..10_000u32).map(|k| (k * 2, k)));
+        tree.set_sizing(Some(Sizing { min: 8, max: 128 }));
+        for round in 0..400u32 {
+            assert!(tree.range(..20_000).count() == 10_000 + round as usize);
+            tree.insert(Slot::new_leaf((round * 97 % 10_000) * 2 + 1, 0));
+        }
+        for k in 0..20_000u32 {
+            tree.insert(Slot::new_leaf(20_000 + k.reverse_bits() % 100_000, k));
+        }
+        tree.validate().unwrap();
+
+        let sizes = tree.stats().leaf_sizes;
+        let (smallest, largest) = (sizes.keys().next(), sizes.keys().last());
+        assert!(smallest == Some(&8) && largest >= Some(&64), "Have: {:?}", sizes);
+
+        // Without it, leaves keep the tree's fanout
+        let mut tree = BTree::new(32);
+        for k in 0..10_000u32 {
+            tree.insert(Slot::new_leaf(k, k));
+        }
+        let have = tree.stats().leaf_sizes.into_iter().collect::<Vec<_>>();
+        assert!(have.len() == 1 && have[0].0 == 32, "Have: {:?}", have);
+    }
+}
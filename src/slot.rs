@@ -1,3 +1,8 @@
+use std::fmt::{self, Debug};
+use std::ops::{Bound, RangeBounds};
+use std::ptr;
+use std::slice;
+
 use crate::node::Node;
 
 #[derive(PartialEq, Eq, Debug, Clone, Copy)]
@@ -44,15 +49,211 @@ impl<A, B> Slot<A, B> {
             Either::Right(_) => false,
         }
     }
+
+    /// The value of a leaf slot.
+    pub fn value(&self) -> &B {
+        match &self.1 {
+            Either::Left(value) => value,
+            Either::Right(_) => unreachable!(),
+        }
+    }
+}
+
+/// The slots of a node, kept sorted by key in a single buffer.
+///
+/// A node reserves its capacity up front so the buffer is never reallocated while the node is
+/// reachable, which is what lets `copy_racy` read it without holding a lock.
+pub struct Slots<A, B>(Vec<Slot<A, B>>);
+
+impl<A: Clone, B: Clone> Clone for Slots<A, B> {
+    // Keep the reserved capacity, copies made by writers are published too
+    fn clone(&self) -> Self {
+        let mut slots = Vec::with_capacity(self.0.capacity());
+        slots.extend_from_slice(&self.0);
+
+        Self(slots)
+    }
+}
+
+impl<A, B> Default for Slots<A, B> {
+    fn default() -> Self {
+        Self(Vec::new())
+    }
+}
+
+impl<A: Debug, B: Debug> Debug for Slots<A, B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.0.iter()).finish()
+    }
+}
+
+impl<'a, A, B> IntoIterator for &'a Slots<A, B> {
+    type Item = &'a Slot<A, B>;
+    type IntoIter = slice::Iter<'a, Slot<A, B>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+impl<A: Ord, B> Slots<A, B> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self(Vec::with_capacity(capacity))
+    }
+
+    fn search(&self, slot: &Slot<A, B>) -> Result<usize, usize> {
+        self.0.binary_search_by(|s| s.0.cmp(&slot.0))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.0.capacity()
+    }
+
+    pub fn as_ptr(&self) -> *const Slot<A, B> {
+        self.0.as_ptr()
+    }
+
+    /// Adds `slot`, replacing and returning the slot with the same key.
+    pub fn replace(&mut self, slot: Slot<A, B>) -> Option<Slot<A, B>> {
+        match self.search(&slot) {
+            Ok(i) => Some(std::mem::replace(&mut self.0[i], slot)),
+            Err(i) => {
+                self.0.insert(i, slot);
+                None
+            }
+        }
+    }
+
+    /// Adds `slot` if there isn't one with the same key already.
+    pub fn insert(&mut self, slot: Slot<A, B>) -> bool {
+        match self.search(&slot) {
+            Ok(_) => false,
+            Err(i) => {
+                self.0.insert(i, slot);
+                true
+            }
+        }
+    }
+
+    pub fn get(&self, slot: &Slot<A, B>) -> Option<&Slot<A, B>> {
+        self.search(slot).ok().map(|i| &self.0[i])
+    }
+
+    pub fn remove(&mut self, slot: &Slot<A, B>) -> bool {
+        match self.search(slot) {
+            Ok(i) => {
+                self.0.remove(i);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    pub fn first(&self) -> Option<&Slot<A, B>> {
+        self.0.first()
+    }
+
+    pub fn last(&self) -> Option<&Slot<A, B>> {
+        self.0.last()
+    }
+
+    pub fn pop_last(&mut self) -> Option<Slot<A, B>> {
+        self.0.pop()
+    }
+
+    /// Keeps the slots `f` returns `true` for, visiting them in order.
+    pub fn retain<F: FnMut(&Slot<A, B>) -> bool>(&mut self, f: F) {
+        self.0.retain(f);
+    }
+
+    /// Moves every slot with a key greater than or equal to `slot`'s into the returned set.
+    pub fn split_off(&mut self, slot: &Slot<A, B>) -> Self {
+        let at = match self.search(slot) {
+            Ok(i) | Err(i) => i,
+        };
+        let mut gt = Vec::with_capacity(self.0.capacity());
+        gt.extend(self.0.drain(at..));
+
+        Self(gt)
+    }
+
+    pub fn iter(&self) -> slice::Iter<'_, Slot<A, B>> {
+        self.0.iter()
+    }
+
+    /// Calls `f` with every leaf slot with a key in `range` to change its value, returning how
+    /// many there were. Keys can't be changed, so the slots stay sorted.
+    pub fn update_range<R, F>(&mut self, range: &R, mut f: F) -> usize
+    where
+        R: RangeBounds<A>,
+        F: FnMut(&A, &mut B),
+    {
+        let start = self.0.partition_point(|s| match range.start_bound() {
+            Bound::Included(k) => s.0 < *k,
+            Bound::Excluded(k) => s.0 <= *k,
+            Bound::Unbounded => false,
+        });
+
+        let mut n = 0;
+        for slot in &mut self.0[start..] {
+            if !range.contains(&slot.0) {
+                break;
+            }
+
+            match &mut slot.1 {
+                Either::Left(value) => f(&slot.0, value),
+                Either::Right(_) => unreachable!(),
+            }
+            n += 1;
+        }
+
+        n
+    }
+
+    /// Copies the slots into `out` without synchronising with writers.
+    ///
+    /// # Safety
+    ///
+    /// `this` must point to live slots whose buffer is not reallocated during the copy. The copy
+    /// may be torn if a writer is active, callers must validate it (e.g. with the node's
+    /// `SeqLock`) before using it.
+    pub unsafe fn copy_racy(this: *const Self, out: &mut Vec<Slot<A, B>>)
+    where
+        A: Copy,
+        B: Copy,
+    {
+        let vec = ptr::addr_of!((*this).0);
+        let len = (*vec).len().min((*vec).capacity());
+        let src = (*vec).as_ptr();
+
+        out.clear();
+        out.reserve(len);
+        for i in 0..len {
+            out.push(ptr::read_volatile(src.add(i)));
+        }
+    }
 }
 
 #[cfg(test)]
 mod test {
     use std::collections::BTreeSet;
 
-    use super::Slot;
+    use super::{Slot, Slots};
 
     #[test]
+    #[allow(clippy::map_clone)]
     fn test_set() {
         let mut slots = BTreeSet::new();
         for (a, b) in (0..10).zip((100..200).step_by(10)) {
@@ -74,4 +275,29 @@ mod test {
         let have = slots.iter().map(|s| *s).collect::<Vec<Slot<i32, i32>>>();
         assert!(want == have, "\nWant: {:?}\nHave: {:?}\n", want, have);
     }
+
+    #[test]
+    fn test_slots() {
+        let mut slots = Slots::new();
+        for k in [5, 1, 4, 2, 3] {
+            assert!(slots.insert(Slot::new_leaf(k, k * 10)));
+        }
+        assert!(!slots.insert(Slot::new_leaf(3, 0)));
+
+        let old = slots.replace(Slot::new_leaf(3, 300));
+        assert!(old == Some(Slot::new_leaf(3, 30)), "Have: {:?}", old);
+
+        assert!(slots.remove(&Slot::new_leaf(1, 0)));
+        assert!(!slots.remove(&Slot::new_leaf(1, 0)));
+
+        let gt = slots.split_off(&Slot::new_leaf(4, 0));
+
+        let want = vec![Slot::new_leaf(2, 20), Slot::new_leaf(3, 300)];
+        let have = slots.iter().copied().collect::<Vec<_>>();
+        assert!(want == have, "\nWant: {:?}\nHave: {:?}\n", want, have);
+
+        let want = vec![Slot::new_leaf(4, 40), Slot::new_leaf(5, 50)];
+        let have = gt.iter().copied().collect::<Vec<_>>();
+        assert!(want == have, "\nWant: {:?}\nHave: {:?}\n", want, have);
+    }
 }
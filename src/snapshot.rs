@@ -0,0 +1,171 @@
+use std::fmt::Debug;
+use std::io::{self, Read, Write};
+
+use crate::btree::{BTree, Increment};
+use crate::page::Encode;
+
+const MAGIC: &[u8; 8] = b"BPTSNAP\0";
+/// Magic, entries, fanout, key and value sizes and a CRC32C of the entries.
+const HEADER: usize = 32;
+/// Entries encoded or decoded at a time.
+const BATCH: usize = 1024;
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+impl<K, V> BTree<K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment + Encode,
+    V: Clone + Copy + Debug + Eq + Encode,
+{
+    /// Writes the entries to `writer` as a snapshot, returning the number written. A snapshot is
+    /// the encoded entries back to back, after a header with their count and checksum.
+    pub fn save<W: Write>(&self, mut writer: W) -> io::Result<u64> {
+        // The header goes first, so the entries are walked once to count and checksum them
+        let (mut len, mut crc) = (0, 0);
+        self.encode_batches(|batch, n| {
+            len += n;
+            crc = crc32c::crc32c_append(crc, batch);
+            Ok(())
+        })?;
+
+        let mut header = Vec::with_capacity(HEADER);
+        header.extend_from_slice(MAGIC);
+        header.extend_from_slice(&len.to_le_bytes());
+        header.extend_from_slice(&(self.max() as u32).to_le_bytes());
+        header.extend_from_slice(&(K::SIZE as u16).to_le_bytes());
+        header.extend_from_slice(&(V::SIZE as u16).to_le_bytes());
+        header.extend_from_slice(&crc.to_le_bytes());
+        header.resize(HEADER, 0);
+        writer.write_all(&header)?;
+
+        self.encode_batches(|batch, _| writer.write_all(batch))?;
+        writer.flush()?;
+
+        Ok(len)
+    }
+
+    /// Calls `f` with every `BATCH` entries encoded and the number of them.
+    fn encode_batches(&self, mut f: impl FnMut(&[u8], u64) -> io::Result<()>) -> io::Result<()> {
+        let size = K::SIZE + V::SIZE;
+        let mut buf = Vec::with_capacity(BATCH * size);
+        for (k, v) in self.iter() {
+            let at = buf.len();
+            buf.resize(at + size, 0);
+            k.encode(&mut buf[at..at + K::SIZE]);
+            v.encode(&mut buf[at + K::SIZE..at + size]);
+
+            if buf.len() == BATCH * size {
+                f(&buf, BATCH as u64)?;
+                buf.clear();
+            }
+        }
+        if !buf.is_empty() {
+            f(&buf, (buf.len() / size) as u64)?;
+        }
+
+        Ok(())
+    }
+
+    /// Reads a snapshot written by `save()` and rebuilds the tree with `bulk_load()`.
+    pub fn load<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut header = [0; HEADER];
+        reader.read_exact(&mut header)?;
+        if &header[..8] != MAGIC {
+            return Err(invalid("not a tree snapshot".into()));
+        }
+
+        let len = u64::from_le_bytes(header[8..16].try_into().unwrap());
+        let max = u32::from_le_bytes(header[16..20].try_into().unwrap()) as usize;
+        let k = u16::from_le_bytes([header[20], header[21]]) as usize;
+        let v = u16::from_le_bytes([header[22], header[23]]) as usize;
+        let want = u32::from_le_bytes(header[24..28].try_into().unwrap());
+        if k != K::SIZE || v != V::SIZE {
+            return Err(invalid(format!(
+                "snapshot holds {k} byte keys and {v} byte values, want {} and {}",
+                K::SIZE,
+                V::SIZE
+            )));
+        }
+        if max < 4 {
+            return Err(invalid(format!("snapshot fanout {max} is less than 4")));
+        }
+
+        // Read in batches, a corrupt count runs into the end of the snapshot instead of
+        // allocating it all up front
+        let size = K::SIZE + V::SIZE;
+        let mut buf = vec![0; BATCH * size];
+        let mut entries = Vec::new();
+        let mut crc = 0;
+        let mut left = len;
+        while left > 0 {
+            let n = left.min(BATCH as u64) as usize;
+            let batch = &mut buf[..n * size];
+            reader.read_exact(batch)?;
+            crc = crc32c::crc32c_append(crc, batch);
+
+            entries.extend(
+                batch
+                    .chunks(size)
+                    .map(|entry| (K::decode(&entry[..K::SIZE]), V::decode(&entry[K::SIZE..]))),
+            );
+            left -= n as u64;
+        }
+
+        if crc != want {
+            return Err(invalid("snapshot is corrupt".into()));
+        }
+        if let Some(w) = entries.windows(2).find(|w| w[0].0 >= w[1].0) {
+            return Err(invalid(format!(
+                "snapshot key {:?} out of order after {:?}",
+                w[1].0, w[0].0
+            )));
+        }
+
+        Ok(Self::bulk_load(max, entries))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+
+    use crate::btree::BTree;
+    use crate::slot::Slot;
+
+    #[test]
+    fn test_snapshot() {
+        let mut tree = BTree::new(16);
+        for k in (0..5000u32).rev() {
+            tree.insert(Slot::new_leaf(k * 3, k as u64));
+        }
+
+        let mut buf = Vec::new();
+        assert!(tree.save(&mut buf).unwrap() == 5000);
+        assert!(buf.len() == 32 + 5000 * 12, "Have: {}", buf.len());
+
+        let have = BTree::<u32, u64>::load(&buf[..]).unwrap();
+        assert!(have.max() == 16, "Have: {}", have.max());
+        let (want, have) = (tree.iter().collect::<Vec<_>>(), have.iter().collect::<Vec<_>>());
+        assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+
+        let mut corrupt = buf.clone();
+        corrupt[1000] ^= 1;
+        let err = BTree::<u32, u64>::load(&corrupt[..]).err().unwrap();
+        assert!(err.kind() == io::ErrorKind::InvalidData, "Have: {:?}", err);
+        let err = BTree::<u32, u64>::load(&buf[..buf.len() - 1])
+            .err()
+            .unwrap();
+        assert!(err.kind() == io::ErrorKind::UnexpectedEof, "Have: {:?}", err);
+        assert!(BTree::<u64, u64>::load(&buf[..]).is_err());
+
+        let mut buf = Vec::new();
+        BTree::<u32, u64>::new(8).save(&mut buf).unwrap();
+        assert!(BTree::<u32, u64>::load(&buf[..])
+            .unwrap()
+            .iter()
+            .next()
+            .is_none());
+    }
+}
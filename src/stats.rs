@@ -0,0 +1,223 @@
+use std::collections::BTreeMap;
+use std::fmt::{self, Debug, Display};
+use std::ptr;
+
+use crate::btree::{BTree, Increment};
+use crate::node::{Node, NodeType};
+use crate::slot::{Either, Slot};
+
+/// The nodes of one level of a tree.
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub struct LevelStats {
+    pub nodes: usize,
+    /// Entries in leaves, children in internal nodes.
+    pub slots: usize,
+    /// The fraction of the slots a node can hold that it does.
+    pub min_fill: f64,
+    pub avg_fill: f64,
+    pub max_fill: f64,
+}
+
+/// The shape of a tree, from `stats()`.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct TreeStats {
+    pub height: usize,
+    pub internal_nodes: usize,
+    pub leaf_nodes: usize,
+    pub entries: usize,
+    /// From the root down to the leaves.
+    pub levels: Vec<LevelStats>,
+    /// Leaves by their fanout, which differs between leaves of a `BTree` with a `Sizing`.
+    pub leaf_sizes: BTreeMap<usize, usize>,
+}
+
+impl TreeStats {
+    /// Counts a node at `depth` holding `len` of `capacity` slots. `finish()` must be called once
+    /// every node is.
+    pub(crate) fn add(&mut self, depth: usize, leaf: bool, len: usize, capacity: usize) {
+        if self.levels.len() <= depth {
+            self.levels.resize(depth + 1, LevelStats::default());
+        }
+
+        let fill = len as f64 / capacity as f64;
+        let level = &mut self.levels[depth];
+        if level.nodes == 0 {
+            level.min_fill = fill;
+        }
+        level.nodes += 1;
+        level.slots += len;
+        level.min_fill = level.min_fill.min(fill);
+        level.max_fill = level.max_fill.max(fill);
+        // Summed until `finish()`
+        level.avg_fill += fill;
+
+        match leaf {
+            true => {
+                self.leaf_nodes += 1;
+                self.entries += len;
+            }
+            false => self.internal_nodes += 1,
+        }
+    }
+
+    pub(crate) fn finish(mut self) -> Self {
+        self.height = self.levels.len();
+        for level in &mut self.levels {
+            level.avg_fill /= level.nodes as f64;
+        }
+
+        self
+    }
+}
+
+impl Display for TreeStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "height {}, {} internal and {} leaf nodes, {} entries",
+            self.height, self.internal_nodes, self.leaf_nodes, self.entries
+        )?;
+        for (depth, level) in self.levels.iter().enumerate() {
+            writeln!(
+                f,
+                "level {depth}: {} nodes, {} slots, fill {:.2}/{:.2}/{:.2}",
+                level.nodes, level.slots, level.min_fill, level.avg_fill, level.max_fill
+            )?;
+        }
+        if self.leaf_sizes.len() > 1 {
+            writeln!(f, "leaf fanouts {:?}", self.leaf_sizes)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<K, V> BTree<K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment,
+    V: Clone + Debug + Eq,
+{
+    /// Returns the height of the tree, its node counts and how full each level is. Nodes split
+    /// once they hold half of their fanout, `max()` unless a `Sizing` changed it, so that is what
+    /// they are full at.
+    pub fn stats(&self) -> TreeStats {
+        fn walk<K: Ord, V>(node: *mut Node<K, V>, depth: usize, stats: &mut TreeStats) {
+            let node = unsafe { &*node };
+            let leaf = node.t == NodeType::Leaf;
+            stats.add(depth, leaf, node.values.len(), (node.max / 2).max(1));
+            if leaf {
+                *stats.leaf_sizes.entry(node.max).or_default() += 1;
+            }
+
+            for slot in &node.values {
+                if let Either::Right(child) = slot.1 {
+                    walk(child, depth + 1, stats);
+                }
+            }
+        }
+
+        let mut stats = TreeStats::default();
+        if !self.root().is_null() {
+            walk(self.root(), 0, &mut stats);
+        }
+
+        stats.finish()
+    }
+
+    /// The levels from the root down to the leftmost leaf, 0 while the tree is empty. Every leaf
+    /// is that deep in a valid tree.
+    pub fn height(&self) -> usize {
+        let mut height = 0;
+        let mut node = self.root();
+        while !node.is_null() {
+            height += 1;
+            node = match unsafe { (*node).first() } {
+                Some(Slot(_, Either::Right(child))) => *child,
+                _ => ptr::null_mut(),
+            };
+        }
+
+        height
+    }
+
+    /// The nodes a lookup of `key` reads, from the root down. Less than `height()` for keys past
+    /// the last separator, where the lookup stops without reaching a leaf.
+    pub fn depth(&self, key: K) -> usize {
+        let mut depth = 0;
+        let mut node = self.root();
+        while !node.is_null() {
+            depth += 1;
+            node = unsafe { (*node).find_child(key) }.unwrap_or(ptr::null_mut());
+        }
+
+        depth
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::btree::BTree;
+    #[cfg(feature = "fs")]
+    use crate::buffer::Capacity;
+    #[cfg(feature = "fs")]
+    use crate::paged::PagedBTree;
+    use crate::slot::Slot;
+
+    #[test]
+    fn test_stats() {
+        assert!(BTree::<u32, u32>::new(8).stats().height == 0);
+
+        let tree = BTree::bulk_load(8, (0..1000u32).map(|k| (k, k)));
+        let stats = tree.stats();
+        assert!(stats.entries == 1000 && stats.leaf_nodes == 250, "{}", stats);
+        assert!(stats.height == 5 && stats.levels[0].nodes == 1, "{}", stats);
+        let leaves = stats.levels.last().unwrap();
+        assert!(leaves.min_fill == 1.0 && leaves.avg_fill == 1.0, "{}", stats);
+        let nodes = stats.levels.iter().map(|l| l.nodes).sum::<usize>();
+        assert!(nodes == stats.internal_nodes + stats.leaf_nodes);
+        // Every child is counted by its parent
+        for (above, below) in stats.levels.iter().zip(&stats.levels[1..]) {
+            assert!(above.slots == below.nodes, "{}", stats);
+        }
+
+        #[cfg(feature = "fs")]
+        {
+            let mut tree = PagedBTree::create_in_memory(16, Capacity::Pages(16)).unwrap();
+            for k in 0..1000u32 {
+                tree.insert(k, k as u64).unwrap();
+            }
+            let stats = tree.stats().unwrap();
+            assert!(stats.entries == 1000 && stats.height == stats.levels.len(), "{}", stats);
+            let leaves = stats.levels.last().unwrap();
+            assert!(leaves.min_fill >= 0.5 && leaves.max_fill <= 1.0, "{}", stats);
+        }
+    }
+
+    #[test]
+    fn test_height() {
+        let mut tree = BTree::new(8);
+        assert!(tree.height() == 0 && tree.depth(0) == 0);
+
+        tree.insert(Slot::new_leaf(0u32, 0u32));
+        assert!(tree.height() == 1 && tree.depth(0) == 1);
+
+        // Bulk loading packs leaves as full as inserting in order does
+        let loaded = BTree::bulk_load(8, (0..1000u32).map(|k| (k, k)));
+        for k in 1..1000 {
+            tree.insert(Slot::new_leaf(k, k));
+        }
+        for tree in [&tree, &loaded] {
+            let height = tree.height();
+            assert!(height == tree.stats().height, "{}", tree.stats());
+            assert!((0..1000).all(|k| tree.depth(k) == height));
+            // Past the last separator the lookup stops at the root
+            assert!(tree.depth(1000) == 1);
+        }
+        assert!(loaded.height() <= tree.height());
+
+        // Deleting leaves the tree as high as it was, with a path down to one entry
+        let height = tree.height();
+        tree.delete_where(|k, _| k > 0);
+        assert!(tree.height() == height && tree.depth(0) == height);
+    }
+}
@@ -0,0 +1,252 @@
+use std::io;
+use std::sync::Arc;
+
+use crate::compress::CompressionStats;
+use crate::crypt::Key;
+use crate::disk::DiskManager;
+use crate::fault::FaultInjector;
+use crate::page::{self, PageBuf, PageId, PAGE_SIZE};
+
+fn unsupported(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, format!("page store doesn't support {what}"))
+}
+
+/// Where a `BufferPool` reads and writes pages. `DiskManager` keeps them in a file and
+/// `MemoryStore` on the heap, other stores can be plugged in, such as a cache in front of object
+/// storage.
+///
+/// Pages are numbered from 0 in the order they are allocated. A page allocated but never written
+/// reads as zeroed.
+pub trait PageStore: Send {
+    /// The number of pages allocated so far.
+    fn pages(&self) -> u64;
+
+    /// Reserves a new page after the last one.
+    fn allocate(&mut self) -> PageId;
+
+    /// Fails with a `ChecksumMismatch` if the page was found corrupt.
+    fn read_page(&self, id: PageId, buf: &mut PageBuf) -> io::Result<()>;
+
+    /// Writes every page, checksummed, see `page::set_checksum()`.
+    fn write_pages(&mut self, pages: &[(PageId, &PageBuf)]) -> io::Result<()>;
+
+    fn write_page(&mut self, id: PageId, buf: &PageBuf) -> io::Result<()> {
+        self.write_pages(&[(id, buf)])
+    }
+
+    /// Reads every page, failing if any read does.
+    fn read_pages(&self, pages: &mut [(PageId, &mut PageBuf)]) -> io::Result<()> {
+        pages
+            .iter_mut()
+            .try_for_each(|(id, buf)| self.read_page(*id, buf))
+    }
+
+    /// Returns `true` if `read_pages()` has its reads in flight together, so reading pages ahead
+    /// as a batch costs about as much as reading one.
+    fn batches_reads(&self) -> bool {
+        false
+    }
+
+    /// Hints that page `id` will be read soon, so a store that can read ahead may start.
+    fn prefetch(&self, _id: PageId) {}
+
+    /// Waits for every written page to be durable.
+    fn sync(&self) -> io::Result<()>;
+
+    fn compression(&self) -> Option<CompressionStats> {
+        None
+    }
+
+    /// Re-encrypts every page with `key`.
+    fn rekey(&mut self, _key: Key) -> io::Result<()> {
+        Err(unsupported("encryption"))
+    }
+
+    fn set_faults(&mut self, _faults: Option<Arc<FaultInjector>>) {}
+
+    /// What a copy of the pages as they are stored must start with, for backups.
+    fn raw_header(&self) -> io::Result<Vec<u8>> {
+        Ok(Vec::new())
+    }
+
+    /// Page `id` as it is stored, for backups. `None` if it was never written.
+    fn read_raw(&self, id: PageId) -> io::Result<Option<Vec<u8>>> {
+        let mut buf = [0; PAGE_SIZE];
+        self.read_page(id, &mut buf)?;
+        Ok(Some(buf.to_vec()))
+    }
+}
+
+impl PageStore for DiskManager {
+    fn pages(&self) -> u64 {
+        self.pages()
+    }
+
+    fn allocate(&mut self) -> PageId {
+        self.allocate()
+    }
+
+    fn read_page(&self, id: PageId, buf: &mut PageBuf) -> io::Result<()> {
+        self.read_page(id, buf)
+    }
+
+    fn write_pages(&mut self, pages: &[(PageId, &PageBuf)]) -> io::Result<()> {
+        self.write_pages(pages)
+    }
+
+    fn read_pages(&self, pages: &mut [(PageId, &mut PageBuf)]) -> io::Result<()> {
+        self.read_pages(pages)
+    }
+
+    fn batches_reads(&self) -> bool {
+        self.batches_reads()
+    }
+
+    fn prefetch(&self, id: PageId) {
+        self.prefetch(id)
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        self.sync()
+    }
+
+    fn compression(&self) -> Option<CompressionStats> {
+        self.compression()
+    }
+
+    fn rekey(&mut self, key: Key) -> io::Result<()> {
+        self.rekey(key)
+    }
+
+    fn set_faults(&mut self, faults: Option<Arc<FaultInjector>>) {
+        self.set_faults(faults)
+    }
+
+    fn raw_header(&self) -> io::Result<Vec<u8>> {
+        self.header()
+    }
+
+    fn read_raw(&self, id: PageId) -> io::Result<Option<Vec<u8>>> {
+        self.read_raw(id)
+    }
+}
+
+/// Keeps pages on the heap, so a `PagedBTree` can run without a file. Nothing survives it being
+/// dropped.
+#[derive(Default)]
+pub struct MemoryStore {
+    pages: Vec<Option<Box<PageBuf>>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl PageStore for MemoryStore {
+    fn pages(&self) -> u64 {
+        self.pages.len() as u64
+    }
+
+    fn allocate(&mut self) -> PageId {
+        self.pages.push(None);
+        PageId(self.pages.len() as u64 - 1)
+    }
+
+    fn read_page(&self, id: PageId, buf: &mut PageBuf) -> io::Result<()> {
+        match &self.pages[id.0 as usize] {
+            Some(page) => *buf = **page,
+            None => buf.fill(0),
+        }
+
+        Ok(())
+    }
+
+    fn write_pages(&mut self, pages: &[(PageId, &PageBuf)]) -> io::Result<()> {
+        for (id, buf) in pages {
+            let mut page = Box::new(**buf);
+            page::set_checksum(&mut page);
+            self.pages[id.0 as usize] = Some(page);
+        }
+
+        Ok(())
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn read_raw(&self, id: PageId) -> io::Result<Option<Vec<u8>>> {
+        Ok(self.pages[id.0 as usize].as_ref().map(|page| page.to_vec()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    use crate::buffer::{BufferPool, Capacity};
+    use crate::page::{PageBuf, PageId};
+    use crate::paged::PagedBTree;
+
+    use super::{MemoryStore, PageStore};
+
+    /// A store of its own, counting the pages written through it.
+    struct Counting(MemoryStore, Arc<AtomicU64>);
+
+    impl PageStore for Counting {
+        fn pages(&self) -> u64 {
+            self.0.pages()
+        }
+
+        fn allocate(&mut self) -> PageId {
+            self.0.allocate()
+        }
+
+        fn read_page(&self, id: PageId, buf: &mut PageBuf) -> io::Result<()> {
+            self.0.read_page(id, buf)
+        }
+
+        fn write_pages(&mut self, pages: &[(PageId, &PageBuf)]) -> io::Result<()> {
+            self.1.fetch_add(pages.len() as u64, Ordering::Relaxed);
+            self.0.write_pages(pages)
+        }
+
+        fn sync(&self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_page_stores() {
+        let dir = tempfile::tempdir().unwrap();
+        let writes = Arc::new(AtomicU64::new(0));
+        let want = (0..3000u32).map(|k| (k, k as u64 * 3)).collect::<Vec<_>>();
+
+        let mut memory = PagedBTree::create_in_memory(16, Capacity::Pages(8)).unwrap();
+        let store = Counting(MemoryStore::new(), writes.clone());
+        let pool = BufferPool::new(store, Capacity::Pages(8));
+        let mut counting = PagedBTree::create_with_pool(pool, 16).unwrap();
+        for (k, v) in want.iter().rev() {
+            memory.insert(*k, *v).unwrap();
+            counting.insert(*k, *v).unwrap();
+        }
+
+        // Evicted pages go through the store
+        assert!(writes.load(Ordering::Relaxed) > 0);
+        for tree in [&memory, &counting] {
+            let have = tree.iter().unwrap();
+            assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+        }
+
+        // A tree in memory backs up to a file
+        let path = dir.path().join("tree");
+        memory.backup_to(&path).unwrap();
+        let tree = PagedBTree::<u32, u64>::open(&path).unwrap();
+        let have = tree.iter().unwrap();
+        assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+    }
+}
@@ -0,0 +1,28 @@
+//! Synchronisation primitives used by the concurrent paths. Built with `--cfg loom` they are
+//! replaced by loom's instrumented versions so the model tests can explore every interleaving.
+//! Every node's `SeqLock` then needs a loom model to run in, so only the loom tests work in such
+//! a build, run them with `RUSTFLAGS="--cfg loom" cargo test --release loom`.
+
+#[cfg(loom)]
+pub(crate) use loom::sync::atomic::{fence, AtomicPtr, AtomicU64, Ordering};
+#[cfg(loom)]
+pub(crate) use loom::sync::{Condvar, Mutex};
+#[cfg(loom)]
+pub(crate) use loom::thread;
+
+#[cfg(not(loom))]
+pub(crate) use std::sync::atomic::{fence, AtomicPtr, AtomicU64, Ordering};
+#[cfg(not(loom))]
+pub(crate) use std::sync::{Condvar, Mutex};
+#[cfg(not(loom))]
+pub(crate) use std::thread;
+
+/// Hints that the caller is spinning. Under loom this yields so the model schedules the thread
+/// being waited on.
+pub(crate) fn spin_loop() {
+    #[cfg(loom)]
+    loom::thread::yield_now();
+
+    #[cfg(not(loom))]
+    std::hint::spin_loop();
+}
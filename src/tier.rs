@@ -0,0 +1,195 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::page::{PageBuf, PageId};
+
+/// Returns whether a page belongs to the upper levels of a tree, which are always hot.
+pub type Upper = fn(&PageBuf) -> bool;
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct TierCounts {
+    /// Pages cached in the tier.
+    pub pages: usize,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl TierCounts {
+    pub fn hit_ratio(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            n => self.hits as f64 / n as f64,
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct TierStats {
+    pub hot: TierCounts,
+    pub cold: TierCounts,
+    /// Leaves moved into the hot tier, and out of it.
+    pub promotions: u64,
+    pub demotions: u64,
+}
+
+/// Splits the pages cached by a `BufferPool` into a hot tier, which is never evicted, and a cold
+/// tier left to the pool's `Replacer`. Set with `BufferPool::set_tiering()`.
+///
+/// The hot tier holds at most `hot_pages` pages: every page of the upper levels of the tree, then
+/// the most used leaves. Uses are counted per page and halved every `16 * hot_pages` accesses, so
+/// the hot leaves follow the workload. A leaf is promoted when it is used more than the least used
+/// hot leaf, which is demoted to the cold tier.
+pub struct Tiering {
+    hot_pages: usize,
+    upper: Upper,
+    uses: HashMap<PageId, u32>,
+    accesses: u64,
+    uppers: HashSet<PageId>,
+    hot: HashSet<PageId>,
+    stats: TierStats,
+}
+
+impl Tiering {
+    pub fn new(hot_pages: usize, upper: Upper) -> Self {
+        assert!(hot_pages > 0);
+
+        Self {
+            hot_pages,
+            upper,
+            uses: HashMap::new(),
+            accesses: 0,
+            uppers: HashSet::new(),
+            hot: HashSet::new(),
+            stats: TierStats::default(),
+        }
+    }
+
+    pub fn hot_pages(&self) -> usize {
+        self.hot_pages
+    }
+
+    pub(crate) fn is_hot(&self, id: PageId) -> bool {
+        self.hot.contains(&id)
+    }
+
+    /// Page `id` was used, and was cached already if `hit`. It is cached now.
+    pub(crate) fn access(&mut self, id: PageId, hit: bool) {
+        let tier = match self.hot.contains(&id) {
+            true => &mut self.stats.hot,
+            false => &mut self.stats.cold,
+        };
+        match hit {
+            true => tier.hits += 1,
+            false => tier.misses += 1,
+        }
+
+        self.accesses += 1;
+        if self.accesses.is_multiple_of(16 * self.hot_pages as u64) {
+            self.uses.retain(|_, n| {
+                *n /= 2;
+                *n > 0
+            });
+        }
+        let uses = self.uses.entry(id).or_default();
+        *uses = uses.saturating_add(1);
+
+        if !self.hot.contains(&id) {
+            self.promote(id);
+        }
+    }
+
+    /// Page `id` is cached and holds `buf`, which may have changed.
+    pub(crate) fn classify(&mut self, id: PageId, buf: &PageBuf) {
+        if (self.upper)(buf) {
+            if self.uppers.insert(id) && !self.hot.contains(&id) {
+                self.promote(id);
+            }
+        } else if self.uppers.remove(&id) && self.hot.remove(&id) {
+            // Freed or reused as a leaf, it has to earn its place again
+            self.stats.demotions += 1;
+        }
+    }
+
+    /// Moves `id` into the hot tier if there is room or it is used more than the least used hot
+    /// leaf. Upper pages take the place of any leaf.
+    fn promote(&mut self, id: PageId) {
+        if self.hot.len() >= self.hot_pages {
+            let uses = |id: &PageId| self.uses.get(id).copied().unwrap_or(0);
+            let coldest = self
+                .hot
+                .iter()
+                .filter(|p| !self.uppers.contains(p))
+                .min_by_key(|p| uses(p))
+                .copied();
+
+            match coldest {
+                Some(p) if self.uppers.contains(&id) || uses(&p) < uses(&id) => {
+                    self.hot.remove(&p);
+                    self.stats.demotions += 1;
+                }
+                _ => return,
+            }
+        }
+
+        self.hot.insert(id);
+        self.stats.promotions += 1;
+    }
+
+    /// Stats with `cached` pages in the pool.
+    pub(crate) fn stats(&self, cached: usize) -> TierStats {
+        let mut stats = self.stats;
+        stats.hot.pages = self.hot.len();
+        stats.cold.pages = cached - self.hot.len();
+        stats
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::Rng;
+
+    use crate::buffer::{BufferPool, Capacity};
+    use crate::page::{self, PageId};
+    use crate::paged::PagedBTree;
+    use crate::store::MemoryStore;
+
+    use super::Tiering;
+
+    #[test]
+    fn test_tiering() {
+        let pool = BufferPool::new(MemoryStore::new(), Capacity::Pages(24));
+        pool.set_tiering(Some(Tiering::new(12, page::is_upper)));
+        let mut tree = PagedBTree::create_with_pool(pool, 64).unwrap();
+        for k in 0..3000u32 {
+            tree.insert(k, k as u64).unwrap();
+        }
+
+        // Most reads go to a few leaves
+        let mut rng = rand::thread_rng();
+        for _ in 0..5000 {
+            let k = match rng.gen_bool(0.9) {
+                true => rng.gen_range(1000..1050),
+                false => rng.gen_range(0..3000),
+            };
+            assert!(tree.get(k).unwrap() == Some(k as u64));
+        }
+
+        // A scan evicts every cold page but keeps the hot ones
+        let stats = tree.pool().tier_stats().unwrap();
+        let before = (0..tree.pool().pages())
+            .map(PageId)
+            .filter(|id| tree.pool().contains(*id))
+            .count();
+        assert!(tree.iter().unwrap().len() == 3000);
+        assert!(stats.hot.pages == 12 && before == 24, "{:?}", stats);
+        assert!(stats.promotions > stats.demotions && stats.demotions > 0, "{:?}", stats);
+
+        let misses = tree.pool().tier_stats().unwrap().cold.misses;
+        for k in 1000..1050 {
+            tree.get(k).unwrap();
+        }
+        let stats = tree.pool().tier_stats().unwrap();
+        assert!(stats.cold.misses == misses, "Want: {}\nHave: {:?}", misses, stats);
+        assert!(stats.hot.hit_ratio() == 1.0 && stats.cold.hit_ratio() < 0.9, "{:?}", stats);
+        tree.validate().unwrap();
+    }
+}
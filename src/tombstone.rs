@@ -0,0 +1,238 @@
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::io;
+use std::ops::RangeBounds;
+
+use crate::btree::Increment;
+use crate::mvcc::Timestamp;
+use crate::page::Encode;
+use crate::paged::PagedBTree;
+
+/// A value in a `TombstoneBTree`, or the tombstone left by a delete committed at a timestamp.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Stored<V> {
+    Live(V),
+    Deleted(Timestamp),
+}
+
+impl<V> Stored<V> {
+    pub fn live(self) -> Option<V> {
+        match self {
+            Stored::Live(v) => Some(v),
+            Stored::Deleted(_) => None,
+        }
+    }
+}
+
+impl<V: Encode> Encode for Stored<V> {
+    /// A tag byte, then the value or the timestamp.
+    const SIZE: usize = 1 + if V::SIZE > 8 { V::SIZE } else { 8 };
+
+    fn encode(&self, buf: &mut [u8]) {
+        buf.fill(0);
+        match self {
+            Stored::Live(v) => v.encode(&mut buf[1..1 + V::SIZE]),
+            Stored::Deleted(ts) => {
+                buf[0] = 1;
+                buf[1..9].copy_from_slice(&ts.to_le_bytes());
+            }
+        }
+    }
+
+    fn decode(buf: &[u8]) -> Self {
+        match buf[0] {
+            0 => Stored::Live(V::decode(&buf[1..1 + V::SIZE])),
+            _ => Stored::Deleted(u64::from_le_bytes(buf[1..9].try_into().unwrap())),
+        }
+    }
+}
+
+/// A `PagedBTree` whose deletes overwrite the entry with a tombstone, so a delete is a single
+/// leaf write and never unlinks or frees pages. Reads and scans skip tombstones.
+///
+/// Tombstones are stamped with a logical timestamp and kept while a reader registered with
+/// `begin_read` before the delete may still need to learn of it through `deleted_since`.
+/// `vacuum` removes the rest and merges the leaves they leave underfull.
+pub struct TombstoneBTree<K, V> {
+    tree: PagedBTree<K, Stored<V>>,
+    clock: Timestamp,
+    readers: BTreeMap<Timestamp, usize>,
+}
+
+impl<K, V> TombstoneBTree<K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment + Encode,
+    V: Clone + Copy + Debug + Encode,
+{
+    /// Takes over `tree`, scanning it for the newest tombstone to carry on its clock.
+    pub fn new(tree: PagedBTree<K, Stored<V>>) -> io::Result<Self> {
+        let clock = tree
+            .iter()?
+            .into_iter()
+            .filter_map(|(_, s)| match s {
+                Stored::Deleted(ts) => Some(ts),
+                Stored::Live(_) => None,
+            })
+            .max()
+            .unwrap_or(0);
+
+        Ok(Self {
+            tree,
+            clock,
+            readers: BTreeMap::new(),
+        })
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> io::Result<Option<V>> {
+        Ok(self
+            .tree
+            .insert(key, Stored::Live(value))?
+            .and_then(Stored::live))
+    }
+
+    pub fn get(&self, key: K) -> io::Result<Option<V>> {
+        Ok(self.tree.get(key)?.and_then(Stored::live))
+    }
+
+    /// Replaces the entry of `key` with a tombstone, returning its value. Nothing is written if
+    /// `key` isn't live.
+    pub fn delete(&mut self, key: K) -> io::Result<Option<V>> {
+        let old = match self.get(key)? {
+            Some(old) => old,
+            None => return Ok(None),
+        };
+
+        self.clock += 1;
+        self.tree.insert(key, Stored::Deleted(self.clock))?;
+
+        Ok(Some(old))
+    }
+
+    /// Returns the live entries with keys in `range`, in order.
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> io::Result<Vec<(K, V)>> {
+        Ok(self
+            .tree
+            .range(range)?
+            .into_iter()
+            .filter_map(|(k, s)| Some((k, s.live()?)))
+            .collect())
+    }
+
+    pub fn iter(&self) -> io::Result<Vec<(K, V)>> {
+        self.range(..)
+    }
+
+    /// Returns the keys deleted after `ts` whose tombstones are still kept, in order.
+    pub fn deleted_since(&self, ts: Timestamp) -> io::Result<Vec<K>> {
+        Ok(self
+            .tree
+            .iter()?
+            .into_iter()
+            .filter(|(_, s)| matches!(s, Stored::Deleted(t) if *t > ts))
+            .map(|(k, _)| k)
+            .collect())
+    }
+
+    /// Returns the timestamp of the last delete.
+    pub fn now(&self) -> Timestamp {
+        self.clock
+    }
+
+    /// Registers a reader at the current timestamp, tombstones of later deletes are kept by
+    /// `vacuum` until `end_read` is called with the returned timestamp.
+    pub fn begin_read(&mut self) -> Timestamp {
+        *self.readers.entry(self.clock).or_insert(0) += 1;
+        self.clock
+    }
+
+    pub fn end_read(&mut self, ts: Timestamp) {
+        match self.readers.get_mut(&ts) {
+            Some(1) => {
+                self.readers.remove(&ts);
+            }
+            Some(n) => *n -= 1,
+            None => panic!("no reader registered at {ts}"),
+        }
+    }
+
+    /// Removes the tombstones older than the oldest registered reader, then merges the leaves
+    /// left underfull. Returns the number removed.
+    pub fn vacuum(&mut self) -> io::Result<usize> {
+        let oldest = self.readers.keys().next().copied().unwrap_or(self.clock);
+        let purged = self
+            .tree
+            .iter()?
+            .into_iter()
+            .filter(|(_, s)| matches!(s, Stored::Deleted(ts) if *ts <= oldest))
+            .map(|(k, _)| k)
+            .collect::<Vec<_>>();
+        for k in &purged {
+            self.tree.delete(*k)?;
+        }
+        self.tree.merge_underfull(usize::MAX)?;
+
+        Ok(purged.len())
+    }
+
+    pub fn inner(&self) -> &PagedBTree<K, Stored<V>> {
+        &self.tree
+    }
+
+    pub fn into_inner(self) -> PagedBTree<K, Stored<V>> {
+        self.tree
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::buffer::Capacity;
+    use crate::paged::PagedBTree;
+
+    use super::TombstoneBTree;
+
+    #[test]
+    fn test_tombstones() {
+        let pool = PagedBTree::create_in_memory(8, Capacity::Pages(16)).unwrap();
+        let mut tree = TombstoneBTree::<u32, u64>::new(pool).unwrap();
+        for k in 0..1000u32 {
+            tree.insert(k, k as u64).unwrap();
+        }
+
+        for k in (0..500).filter(|k| k % 3 != 0) {
+            assert!(tree.delete(k).unwrap() == Some(k as u64));
+        }
+        let reader = tree.begin_read();
+        for k in (500..1000).filter(|k| k % 3 != 0) {
+            tree.delete(k).unwrap();
+        }
+        assert!(tree.delete(1).unwrap().is_none() && tree.get(2).unwrap().is_none());
+
+        // Deletes only overwrite entries
+        assert!(tree.inner().len() == 1000 && tree.inner().space().free_pages == 0);
+        let want = (0..1000)
+            .step_by(3)
+            .map(|k| (k, k as u64))
+            .collect::<Vec<_>>();
+        let have = tree.iter().unwrap();
+        assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+
+        // The reader keeps the tombstones of the deletes after it
+        let have = tree.vacuum().unwrap();
+        assert!(have == 333, "Have: {}", have);
+        let want = (500..1000).filter(|k| k % 3 != 0).collect::<Vec<_>>();
+        let have = tree.deleted_since(reader).unwrap();
+        assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+
+        tree.end_read(reader);
+        let have = tree.vacuum().unwrap();
+        assert!(have == 333, "Have: {}", have);
+        assert!(tree.inner().len() == 334 && tree.inner().space().free_pages > 0);
+        tree.inner().validate().unwrap();
+
+        // The clock carries on from the newest tombstone
+        tree.delete(999).unwrap();
+        let now = tree.now();
+        let tree = TombstoneBTree::new(tree.into_inner()).unwrap();
+        assert!(tree.now() == now && tree.deleted_since(0).unwrap() == [999]);
+    }
+}
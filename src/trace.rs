@@ -0,0 +1,127 @@
+//! Spans and events for the `tracing` crate, with the `tracing` feature. Without it the macros
+//! expand to nothing and their fields aren't evaluated.
+//!
+//! Operations on a tree are spans at `TRACE` level with the key or range they cover, splits,
+//! merges and page I/O are events within them, and WAL syncs are spans at `DEBUG` level.
+
+/// Enters a span at `$level` until the end of the enclosing block.
+macro_rules! span {
+    ($level:ident, $($args:tt)*) => {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::span!(tracing::Level::$level, $($args)*).entered();
+    };
+}
+
+macro_rules! event {
+    ($level:ident, $($args:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::event!(tracing::Level::$level, $($args)*);
+    };
+}
+
+pub(crate) use {event, span};
+
+#[cfg(all(test, feature = "tracing"))]
+mod test {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    use crate::btree::BTree;
+    #[cfg(feature = "fs")]
+    use crate::buffer::Capacity;
+    #[cfg(feature = "fs")]
+    use crate::paged::{Options, PagedBTree};
+    use crate::slot::Slot;
+    #[cfg(feature = "fs")]
+    use crate::wal::SyncPolicy;
+
+    /// Records the names of spans and events.
+    #[derive(Clone, Default)]
+    struct Recorder {
+        next: Arc<AtomicU64>,
+        names: Arc<Mutex<Vec<&'static str>>>,
+    }
+
+    impl Recorder {
+        fn count(&self, name: &str) -> usize {
+            self.names
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|n| **n == name)
+                .count()
+        }
+    }
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            self.names.lock().unwrap().push(span.metadata().name());
+            Id::from_u64(self.next.fetch_add(1, Ordering::Relaxed) + 1)
+        }
+
+        fn record(&self, _: &Id, _: &Record<'_>) {}
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+        fn event(&self, event: &Event<'_>) {
+            let mut name = "";
+            event.record(&mut |field: &tracing::field::Field, value: &dyn std::fmt::Debug| {
+                if field.name() == "message" {
+                    name = Box::leak(format!("{:?}", value).into_boxed_str());
+                }
+            });
+            self.names.lock().unwrap().push(name);
+        }
+
+        fn enter(&self, _: &Id) {}
+
+        fn exit(&self, _: &Id) {}
+    }
+
+    #[test]
+    fn test_tracing() {
+        let recorder = Recorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut tree = BTree::new(4);
+            for k in 0..20u32 {
+                tree.insert(Slot::new_leaf(k, k));
+            }
+            tree.get(3);
+            tree.delete(3);
+        });
+        assert!(recorder.count("insert") == 20 && recorder.count("get") == 1);
+        assert!(recorder.count("delete") == 1 && recorder.count("split") > 0);
+
+        #[cfg(feature = "fs")]
+        {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("tree.db");
+
+            let recorder = Recorder::default();
+            tracing::subscriber::with_default(recorder.clone(), || {
+                let options = Options {
+                    capacity: Capacity::Pages(8),
+                    wal: Some(SyncPolicy::Off),
+                    ..Default::default()
+                };
+                let mut tree = PagedBTree::create_with_options(&path, 8, options).unwrap();
+                for k in 0..200u32 {
+                    tree.insert(k, k as u64).unwrap();
+                }
+                tree.range(10..20).unwrap();
+                tree.sync().unwrap();
+            });
+            assert!(recorder.count("insert") == 200 && recorder.count("range") == 1);
+            assert!(recorder.count("split") > 0 && recorder.count("write pages") > 0);
+            assert!(recorder.count("read page") > 0 && recorder.count("wal commit") == 200);
+            assert!(recorder.count("wal sync") > 0);
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use std::error::Error;
+use std::fmt::{self, Debug, Display};
+
+use crate::btree::{BTree, Increment};
+use crate::watch::Event;
+
+/// Returned by a before trigger to stop a write.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Vetoed(pub String);
+
+impl Display for Vetoed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "write vetoed: {}", self.0)
+    }
+}
+
+impl Error for Vetoed {}
+
+/// Called before a write with the change it makes. It may change the value written, but not the
+/// key or the kind of change, or veto it.
+pub type BeforeTrigger<K, V> = Box<dyn Fn(&mut Event<K, V>) -> Result<(), Vetoed> + Send + Sync>;
+
+/// Called after a write with the change it made.
+pub type AfterTrigger<K, V> = Box<dyn Fn(&Event<K, V>) + Send + Sync>;
+
+pub(crate) struct Triggers<K, V> {
+    before: Vec<BeforeTrigger<K, V>>,
+    after: Vec<AfterTrigger<K, V>>,
+}
+
+impl<K, V> BTree<K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment,
+    V: Clone + Debug + Eq,
+{
+    /// Adds a trigger run before every insert, update and delete, in the order added. Writes
+    /// only look up the old value of their key while there are triggers or subscriptions.
+    pub fn before_write<F>(&mut self, trigger: F)
+    where
+        F: Fn(&mut Event<K, V>) -> Result<(), Vetoed> + Send + Sync + 'static,
+    {
+        self.triggers_mut().before.push(Box::new(trigger));
+    }
+
+    /// Adds a trigger run after every insert, update and delete, in the order added.
+    pub fn after_write<F>(&mut self, trigger: F)
+    where
+        F: Fn(&Event<K, V>) + Send + Sync + 'static,
+    {
+        self.triggers_mut().after.push(Box::new(trigger));
+    }
+
+    pub fn clear_triggers(&mut self) {
+        self.triggers = None;
+    }
+
+    fn triggers_mut(&mut self) -> &mut Triggers<K, V> {
+        self.triggers.get_or_insert_with(|| {
+            Box::new(Triggers {
+                before: Vec::new(),
+                after: Vec::new(),
+            })
+        })
+    }
+
+    pub(crate) fn run_before(&self, event: &mut Event<K, V>) -> Result<(), Vetoed> {
+        let triggers = match &self.triggers {
+            Some(triggers) => triggers,
+            None => return Ok(()),
+        };
+
+        let key = event.key();
+        for trigger in &triggers.before {
+            let kind = std::mem::discriminant(event);
+            trigger(event)?;
+            assert!(
+                event.key() == key && std::mem::discriminant(event) == kind,
+                "a trigger changed the key or kind of a write"
+            );
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn run_after(&self, event: &Event<K, V>) {
+        if let Some(triggers) = &self.triggers {
+            for trigger in &triggers.after {
+                trigger(event);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    use crate::btree::BTree;
+    use crate::slot::Slot;
+    use crate::watch::Event;
+
+    use super::Vetoed;
+
+    #[test]
+    fn test_triggers() {
+        let mut tree = BTree::new(4);
+
+        // Values are capped at 100, and key 0 can't be deleted
+        tree.before_write(|event: &mut Event<u32, u64>| match event {
+            Event::Insert(_, v) | Event::Update { new: v, .. } if *v > 100 => {
+                *v = 100;
+                Ok(())
+            }
+            Event::Delete(0, _) => Err(Vetoed("0 is permanent".into())),
+            _ => Ok(()),
+        });
+
+        // A running total of the values
+        let total = Arc::new(AtomicU64::new(0));
+        let sum = total.clone();
+        tree.after_write(move |event| match event {
+            Event::Insert(_, v) => _ = sum.fetch_add(*v, Ordering::Relaxed),
+            Event::Update { old, new, .. } => {
+                sum.fetch_add(*new, Ordering::Relaxed);
+                sum.fetch_sub(*old, Ordering::Relaxed);
+            }
+            Event::Delete(_, v) => _ = sum.fetch_sub(*v, Ordering::Relaxed),
+        });
+
+        for k in 0..50u32 {
+            tree.insert(Slot::new_leaf(k, k as u64 * 5));
+        }
+        assert!(tree.get(30).unwrap() == Slot::new_leaf(30, 100));
+
+        let have = tree.delete_checked(0);
+        assert!(have == Err(Vetoed("0 is permanent".into())), "Have: {:?}", have);
+        assert!(tree.delete(1) && tree.get(0).is_some());
+
+        tree.set_merge_operator(|_, old, v| old.unwrap_or(0) + v);
+        tree.merge(2, 1000);
+        tree.merge(60, 7);
+
+        let want = tree.iter().map(|e| e.1).sum::<u64>();
+        let have = total.load(Ordering::Relaxed);
+        assert!(want == have, "Want: {}\nHave: {}", want, have);
+        assert!(tree.get(2).unwrap() == Slot::new_leaf(2, 100));
+
+        tree.clear_triggers();
+        assert!(tree.delete(0));
+    }
+}
@@ -0,0 +1,264 @@
+use std::fmt::Debug;
+#[cfg(feature = "fs")]
+use std::io;
+use std::ops::RangeBounds;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::btree::{BTree, Increment};
+use crate::page::Encode;
+#[cfg(feature = "fs")]
+use crate::paged::PagedBTree;
+use crate::slot::{Either, Slot};
+
+/// Milliseconds since the Unix epoch.
+pub type Clock = fn() -> u64;
+
+/// The system clock. Targets without one, such as wasm32-unknown-unknown, need a clock of their
+/// own.
+pub fn system_clock() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("the clock is before the Unix epoch")
+        .as_millis() as u64
+}
+
+/// A value that expires at `expires`, in milliseconds since the Unix epoch. Stored in a tree so
+/// expired entries can be skipped and swept.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct Expiring<V> {
+    pub value: V,
+    pub expires: u64,
+}
+
+impl<V> Expiring<V> {
+    pub fn never(value: V) -> Self {
+        Self {
+            value,
+            expires: u64::MAX,
+        }
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires <= now
+    }
+}
+
+impl<V: Encode> Encode for Expiring<V> {
+    const SIZE: usize = V::SIZE + 8;
+
+    fn encode(&self, buf: &mut [u8]) {
+        self.value.encode(&mut buf[..V::SIZE]);
+        buf[V::SIZE..].copy_from_slice(&self.expires.to_le_bytes());
+    }
+
+    fn decode(buf: &[u8]) -> Self {
+        Self {
+            value: V::decode(&buf[..V::SIZE]),
+            expires: u64::from_le_bytes(buf[V::SIZE..].try_into().unwrap()),
+        }
+    }
+}
+
+/// A `BTree` whose entries may expire. Expired entries are skipped by reads and stay in the tree
+/// until `sweep_expired()`.
+pub struct TtlBTree<K, V> {
+    tree: BTree<K, Expiring<V>>,
+    clock: Clock,
+}
+
+impl<K, V> TtlBTree<K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment,
+    V: Clone + Copy + Debug + Eq,
+{
+    pub fn new(max: usize) -> Self {
+        Self::with_clock(max, system_clock)
+    }
+
+    pub fn with_clock(max: usize, clock: Clock) -> Self {
+        Self {
+            tree: BTree::new(max),
+            clock,
+        }
+    }
+
+    /// Inserts an entry that never expires.
+    pub fn insert(&mut self, key: K, value: V) {
+        self.tree
+            .insert(Slot::new_leaf(key, Expiring::never(value)));
+    }
+
+    /// Inserts an entry that expires after `ttl`.
+    pub fn insert_with_ttl(&mut self, key: K, value: V, ttl: Duration) {
+        let expires = (self.clock)().saturating_add(ttl.as_millis() as u64);
+        self.tree
+            .insert(Slot::new_leaf(key, Expiring { value, expires }));
+    }
+
+    /// `None` if `key` has expired.
+    pub fn get(&self, key: K) -> Option<V> {
+        match self.tree.get(key)?.1 {
+            Either::Left(e) if !e.is_expired((self.clock)()) => Some(e.value),
+            Either::Left(_) => None,
+            Either::Right(_) => unreachable!(),
+        }
+    }
+
+    /// When `key` expires, `None` if it has or never will.
+    pub fn expires(&self, key: K) -> Option<u64> {
+        self.get_expiring(key)
+            .filter(|e| e.expires != u64::MAX)
+            .map(|e| e.expires)
+    }
+
+    fn get_expiring(&self, key: K) -> Option<Expiring<V>> {
+        match self.tree.get(key)?.1 {
+            Either::Left(e) if !e.is_expired((self.clock)()) => Some(e),
+            _ => None,
+        }
+    }
+
+    /// Returns whether `key` was there and hadn't expired.
+    pub fn delete(&mut self, key: K) -> bool {
+        let live = self.get_expiring(key).is_some();
+        self.tree.delete(key) && live
+    }
+
+    /// Returns an iterator over the entries with keys in `range` that haven't expired, in order.
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> impl Iterator<Item = (K, V)> + '_ {
+        let now = (self.clock)();
+        self.tree
+            .range(range)
+            .filter(move |(_, e)| !e.is_expired(now))
+            .map(|(k, e)| (k, e.value))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (K, V)> + '_ {
+        self.range(..)
+    }
+
+    /// Removes the expired entries, returning how many. The tree is rebuilt if they were most of
+    /// it, so it doesn't keep the empty leaves.
+    pub fn sweep_expired(&mut self) -> usize {
+        let now = (self.clock)();
+        let (expired, live): (Vec<_>, Vec<_>) =
+            self.tree.iter().partition(|(_, e)| e.is_expired(now));
+
+        if expired.len() > live.len() {
+            self.tree = BTree::bulk_load(self.tree.max(), live);
+        } else {
+            for (k, _) in &expired {
+                self.tree.delete(*k);
+            }
+        }
+
+        expired.len()
+    }
+
+    pub fn inner(&self) -> &BTree<K, Expiring<V>> {
+        &self.tree
+    }
+}
+
+#[cfg(feature = "fs")]
+impl<K, V> PagedBTree<K, Expiring<V>>
+where
+    K: Clone + Copy + Debug + Ord + Increment + Encode,
+    V: Clone + Copy + Debug + Encode,
+{
+    /// Deletes the entries expired by `now`, then merges the leaves left underfull. Returns the
+    /// number deleted.
+    pub fn sweep_expired(&mut self, now: u64) -> io::Result<usize> {
+        let expired = self
+            .iter()?
+            .into_iter()
+            .filter(|(_, e)| e.is_expired(now))
+            .collect::<Vec<_>>();
+        for (k, _) in &expired {
+            self.delete(*k)?;
+        }
+        self.merge_underfull(usize::MAX)?;
+
+        Ok(expired.len())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::time::Duration;
+
+    #[cfg(feature = "fs")]
+    use crate::buffer::Capacity;
+    #[cfg(feature = "fs")]
+    use crate::paged::PagedBTree;
+
+    #[cfg(feature = "fs")]
+    use super::Expiring;
+    use super::TtlBTree;
+
+    static NOW: AtomicU64 = AtomicU64::new(0);
+
+    fn clock() -> u64 {
+        NOW.load(Ordering::Relaxed)
+    }
+
+    #[test]
+    fn test_ttl() {
+        NOW.store(1000, Ordering::Relaxed);
+        let mut tree = TtlBTree::with_clock(8, clock);
+        for k in 0..1000u32 {
+            match k % 4 {
+                0 => tree.insert(k, k),
+                n => tree.insert_with_ttl(k, k, Duration::from_millis(n as u64 * 100)),
+            }
+        }
+        assert!(tree.expires(3) == Some(1300) && tree.expires(4).is_none());
+
+        NOW.store(1200, Ordering::Relaxed);
+        let want = (0..1000)
+            .filter(|k| k % 4 == 0 || k % 4 == 3)
+            .collect::<Vec<_>>();
+        let have = tree.iter().map(|e| e.0).collect::<Vec<_>>();
+        assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+        assert!(tree.get(1).is_none() && tree.get(2).is_none() && tree.get(3) == Some(3));
+        assert!(!tree.delete(1) && tree.delete(3));
+
+        // Deleting the expired 1 removed it too
+        let have = tree.sweep_expired();
+        assert!(have == 499, "Have: {}", have);
+        assert!(tree.inner().iter().count() == 499);
+
+        NOW.store(2000, Ordering::Relaxed);
+        let have = tree.sweep_expired();
+        assert!(have == 249, "Have: {}", have);
+        let want = (0..1000).step_by(4).map(|k| (k, k)).collect::<Vec<_>>();
+        let have = tree.iter().collect::<Vec<_>>();
+        assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+        tree.inner().validate().unwrap();
+
+        #[cfg(feature = "fs")]
+        {
+            let mut tree = PagedBTree::create_in_memory(8, Capacity::Pages(16)).unwrap();
+            for k in 0..1000u32 {
+                tree.insert(
+                    k,
+                    Expiring {
+                        value: k,
+                        expires: k as u64,
+                    },
+                )
+                .unwrap();
+            }
+            assert!(tree.sweep_expired(900).unwrap() == 901);
+            let have = tree
+                .iter()
+                .unwrap()
+                .into_iter()
+                .map(|e| e.0)
+                .collect::<Vec<_>>();
+            assert!(have == (901..1000).collect::<Vec<_>>(), "Have: {:?}", have);
+            tree.validate().unwrap();
+        }
+    }
+}
@@ -0,0 +1,184 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::{self, Debug, Display};
+
+use crate::btree::Increment;
+use crate::mvcc::{MvccBTree, Timestamp};
+
+/// Returned by `Transaction::commit` when another transaction committed a write to `key` after
+/// this one started. None of the transaction's writes are applied.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct Conflict<K> {
+    pub key: K,
+}
+
+impl<K: Debug> Display for Conflict<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "write conflict on key {:?}", self.key)
+    }
+}
+
+impl<K: Debug> Error for Conflict<K> {}
+
+/// Buffers inserts and deletes and applies them to an `MvccBTree` all at once.
+///
+/// Reads see the transaction's own writes on top of the tree as of `begin`. On commit, the
+/// first committer wins: if any written key got a newer version since the transaction started,
+/// the commit fails and nothing is applied. Dropping the transaction rolls it back.
+pub struct Transaction<'a, K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment,
+    V: Clone + Copy + Debug + Eq,
+{
+    tree: &'a MvccBTree<K, V>,
+    start: Timestamp,
+    writes: BTreeMap<K, Option<V>>,
+}
+
+impl<K, V> MvccBTree<K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment,
+    V: Clone + Copy + Debug + Eq,
+{
+    pub fn begin(&self) -> Transaction<'_, K, V> {
+        Transaction {
+            tree: self,
+            start: self.begin_read(),
+            writes: BTreeMap::new(),
+        }
+    }
+}
+
+impl<K, V> Transaction<'_, K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment,
+    V: Clone + Copy + Debug + Eq,
+{
+    pub fn start_ts(&self) -> Timestamp {
+        self.start
+    }
+
+    pub fn get(&self, key: K) -> Option<V> {
+        match self.writes.get(&key) {
+            Some(value) => *value,
+            None => self.tree.get_at(key, self.start),
+        }
+    }
+
+    pub fn insert(&mut self, key: K, value: V) {
+        self.writes.insert(key, Some(value));
+    }
+
+    pub fn delete(&mut self, key: K) {
+        self.writes.insert(key, None);
+    }
+
+    /// Returns the number of buffered writes.
+    pub fn len(&self) -> usize {
+        self.writes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty()
+    }
+
+    /// Applies every buffered write under one commit timestamp, which is returned.
+    pub fn commit(self) -> Result<Timestamp, Conflict<K>> {
+        self.tree
+            .commit_writes(self.start, &self.writes)
+            .map_err(|key| Conflict { key })
+    }
+
+    /// Discards every buffered write.
+    pub fn rollback(self) {}
+}
+
+impl<K, V> Drop for Transaction<'_, K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment,
+    V: Clone + Copy + Debug + Eq,
+{
+    fn drop(&mut self) {
+        self.tree.end_read(self.start);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::mvcc::MvccBTree;
+
+    use super::Conflict;
+
+    #[test]
+    fn test_commit() {
+        const MAX: usize = 8;
+
+        let tree = MvccBTree::new(MAX);
+        for k in 0..10u32 {
+            tree.insert(k, k);
+        }
+
+        let mut txn = tree.begin();
+        for k in 0..5u32 {
+            txn.insert(k, k + 100);
+        }
+        txn.delete(9);
+        txn.delete(50);
+
+        // Own writes are visible, others aren't until commit
+        assert!(txn.get(0) == Some(100));
+        assert!(txn.get(9).is_none());
+        assert!(tree.get(0) == Some(0));
+        assert!(tree.get(9) == Some(9));
+
+        let ts = txn.commit().unwrap();
+        for k in 0..5u32 {
+            let have = tree.get(k);
+            assert!(have == Some(k + 100), "Want: {:?}\nHave: {:?}", Some(k + 100), have);
+            assert!(tree.versions(k).last().unwrap().ts == ts);
+        }
+        assert!(tree.get(9).is_none());
+        assert!(tree.versions(50).is_empty());
+        assert!(tree.oldest_reader().is_none());
+    }
+
+    #[test]
+    fn test_rollback() {
+        const MAX: usize = 8;
+
+        let tree = MvccBTree::new(MAX);
+        tree.insert(1u32, 1u32);
+
+        let mut txn = tree.begin();
+        txn.insert(1, 2);
+        txn.insert(2, 2);
+        txn.rollback();
+
+        assert!(tree.get(1) == Some(1));
+        assert!(tree.get(2).is_none());
+        assert!(tree.oldest_reader().is_none());
+    }
+
+    #[test]
+    fn test_first_committer_wins() {
+        const MAX: usize = 8;
+
+        let tree = MvccBTree::new(MAX);
+        tree.insert(1u32, 1u32);
+
+        let mut a = tree.begin();
+        let mut b = tree.begin();
+        a.insert(1, 10);
+        a.insert(2, 20);
+        b.insert(1, 100);
+        b.insert(3, 300);
+
+        a.commit().unwrap();
+        let have = b.commit();
+        assert!(have == Err(Conflict { key: 1 }), "Have: {:?}", have);
+
+        assert!(tree.get(1) == Some(10));
+        assert!(tree.get(2) == Some(20));
+        assert!(tree.get(3).is_none());
+    }
+}
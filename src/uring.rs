@@ -0,0 +1,202 @@
+use std::fs::File;
+use std::io;
+use std::os::fd::AsRawFd;
+use std::sync::Mutex;
+
+use io_uring::{opcode, squeue, types, IoUring};
+
+const ENTRIES: u32 = 64;
+
+/// One I/O for `Ring::run()`.
+pub enum Op<'a> {
+    /// A read that reaches the end of the file zeroes the rest of `buf`.
+    Read {
+        file: &'a File,
+        buf: &'a mut [u8],
+        offset: u64,
+    },
+    Write {
+        file: &'a File,
+        buf: &'a [u8],
+        offset: u64,
+    },
+    /// Waits for every earlier op of the same `run()`, then syncs the file's data.
+    Sync { file: &'a File },
+}
+
+/// An io_uring shared by a `DiskManager` or `Wal`.
+pub struct Ring {
+    ring: Mutex<IoUring>,
+}
+
+impl Ring {
+    pub fn new() -> io::Result<Self> {
+        Ok(Self {
+            ring: Mutex::new(IoUring::new(ENTRIES)?),
+        })
+    }
+
+    /// Submits `ops` and waits for all of them, so they are in flight together. A read or write
+    /// cut short is submitted again for the rest. Returns the first error.
+    pub fn run(&self, ops: &mut [Op<'_>]) -> io::Result<()> {
+        let mut ring = self.ring.lock().unwrap();
+
+        for chunk in ops.chunks_mut(ENTRIES as usize) {
+            let mut left = (0..chunk.len()).collect::<Vec<_>>();
+            while !left.is_empty() {
+                for &i in &left {
+                    let entry = match &mut chunk[i] {
+                        Op::Read { file, buf, offset } => {
+                            let fd = types::Fd(file.as_raw_fd());
+                            opcode::Read::new(fd, buf.as_mut_ptr(), buf.len() as u32)
+                                .offset(*offset)
+                                .build()
+                        }
+                        Op::Write { file, buf, offset } => {
+                            let fd = types::Fd(file.as_raw_fd());
+                            opcode::Write::new(fd, buf.as_ptr(), buf.len() as u32)
+                                .offset(*offset)
+                                .build()
+                        }
+                        Op::Sync { file } => opcode::Fsync::new(types::Fd(file.as_raw_fd()))
+                            .flags(types::FsyncFlags::DATASYNC)
+                            .build()
+                            .flags(squeue::Flags::IO_DRAIN),
+                    };
+
+                    // SAFETY: every buffer outlives the wait below
+                    unsafe {
+                        ring.submission()
+                            .push(&entry.user_data(i as u64))
+                            .expect("submission queue is sized for a chunk");
+                    }
+                }
+
+                ring.submit_and_wait(left.len())?;
+
+                let mut err = None;
+                let results = ring
+                    .completion()
+                    .map(|cqe| (cqe.user_data() as usize, cqe.result()))
+                    .collect::<Vec<_>>();
+                left.clear();
+                for (i, res) in results {
+                    if res < 0 {
+                        err.get_or_insert(io::Error::from_raw_os_error(-res));
+                        continue;
+                    }
+
+                    let n = res as usize;
+                    match &mut chunk[i] {
+                        // Only a read that gets nothing is at the end of the file
+                        Op::Read { buf, .. } if n == 0 => buf.fill(0),
+                        Op::Write { buf, .. } if n == 0 && !buf.is_empty() => {
+                            err.get_or_insert(io::Error::from(io::ErrorKind::WriteZero));
+                        }
+                        Op::Read { buf, offset, .. } if n < buf.len() => {
+                            *buf = &mut std::mem::take(buf)[n..];
+                            *offset += n as u64;
+                            left.push(i);
+                        }
+                        Op::Write { buf, offset, .. } if n < buf.len() => {
+                            let rest: &[u8] = buf;
+                            *buf = &rest[n..];
+                            *offset += n as u64;
+                            left.push(i);
+                        }
+                        _ => {}
+                    }
+                }
+                if let Some(e) = err {
+                    return Err(e);
+                }
+
+                // A sync only covers the writes before it, so it goes again after theirs
+                if !left.is_empty() {
+                    left.extend((0..chunk.len()).filter(|&i| matches!(chunk[i], Op::Sync { .. })));
+                    left.sort();
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::{File, OpenOptions};
+    use std::io::{self, Write};
+    use std::os::fd::OwnedFd;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::{Op, Ring};
+
+    #[test]
+    fn test_ring() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(dir.path().join("file"))
+            .unwrap();
+
+        let ring = Ring::new().unwrap();
+        let bufs = (0..100u8).map(|i| [i; 512]).collect::<Vec<_>>();
+        let mut ops = bufs
+            .iter()
+            .enumerate()
+            .map(|(i, buf)| Op::Write {
+                file: &file,
+                buf,
+                offset: i as u64 * 512,
+            })
+            .collect::<Vec<_>>();
+        ops.push(Op::Sync { file: &file });
+        ring.run(&mut ops).unwrap();
+
+        // The last read is past the end of the file
+        let mut have = vec![[0xff; 512]; 101];
+        let mut ops = have
+            .iter_mut()
+            .enumerate()
+            .map(|(i, buf)| Op::Read {
+                file: &file,
+                buf,
+                offset: i as u64 * 512,
+            })
+            .collect::<Vec<_>>();
+        ring.run(&mut ops).unwrap();
+
+        assert!(have[..100] == bufs[..]);
+        assert!(have[100] == [0; 512]);
+    }
+
+    #[test]
+    fn test_ring_short_read() {
+        // A pipe hands over what it has, so the first read comes back with half the buffer
+        let (reader, mut writer) = io::pipe().unwrap();
+        let reader = File::from(OwnedFd::from(reader));
+        writer.write_all(&[1; 256]).unwrap();
+
+        let ring = Ring::new().unwrap();
+        let mut have = [0xff; 512];
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(20));
+                writer.write_all(&[2; 256]).unwrap();
+            });
+            let mut ops = [Op::Read {
+                file: &reader,
+                buf: &mut have,
+                offset: 0,
+            }];
+            ring.run(&mut ops).unwrap();
+        });
+
+        assert!(have[..256] == [1; 256] && have[256..] == [2; 256], "Have: {:?}", have);
+    }
+}
@@ -0,0 +1,740 @@
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read};
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::crypt::{Cipher, Key};
+use crate::fault::FaultInjector;
+pub use crate::page::Lsn;
+use crate::page::{PageBuf, PageId};
+use crate::trace;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+use crate::uring::{Op, Ring};
+
+// Log file header:
+//
+// | magic (8) | first lsn (8) |
+//
+// followed by records:
+//
+// | length (4) | crc32c (4) | lsn (8) | kind (1) | page (8) | data |
+//
+// The length covers everything after the checksum, the checksum covers everything after itself.
+// In an encrypted log the data is sealed along with the LSN, kind and page.
+const MAGIC: &[u8; 8] = b"BPTWAL\0\0";
+const SEALED: &[u8; 8] = b"BPTWALS\0";
+pub(crate) const FILE_HEADER: u64 = 16;
+const RECORD_HEADER: usize = 25;
+
+const PUT: u8 = 1;
+const REMOVE: u8 = 2;
+const IMAGE: u8 = 3;
+const COMMIT: u8 = 4;
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// When committed records are synced to disk.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum SyncPolicy {
+    /// Every commit waits for the log to reach the disk.
+    #[default]
+    PerCommit,
+    /// A commit syncs the log if it wasn't synced for this long, so at most about this much is
+    /// lost on power failure.
+    Periodic(Duration),
+    /// The log is written at every commit but never synced, it survives the process crashing but
+    /// not the machine.
+    Off,
+    /// Commits are written at once and synced by `wait_durable()`, where commits from other
+    /// threads share a sync. The first to wait holds its sync back for up to `delay`, or until
+    /// `batch` commits are waiting, trading its latency for fewer syncs.
+    Group { delay: Duration, batch: usize },
+}
+
+/// A change to one page. Keys and values are kept encoded, the tree decodes them on recovery.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Record {
+    /// Sets an entry of a leaf, `entry` is the key followed by the value.
+    Put { page: PageId, entry: Vec<u8> },
+    /// Removes the entry with `key` from a leaf.
+    Remove { page: PageId, key: Vec<u8> },
+    /// Overwrites a page, `data` is its start and the rest is zeroed.
+    Image { page: PageId, data: Vec<u8> },
+    /// Ends the records of one operation, only committed operations are recovered.
+    Commit,
+}
+
+impl Record {
+    /// An image of `buf` without its trailing zeroes.
+    pub fn image(page: PageId, buf: &PageBuf) -> Self {
+        let end = buf.iter().rposition(|b| *b != 0).map_or(0, |i| i + 1);
+
+        Record::Image {
+            page,
+            data: buf[..end].to_vec(),
+        }
+    }
+
+    /// The page the record changes.
+    pub fn page(&self) -> Option<PageId> {
+        match self {
+            Record::Put { page, .. } | Record::Remove { page, .. } | Record::Image { page, .. } => {
+                Some(*page)
+            }
+            Record::Commit => None,
+        }
+    }
+
+    fn encode(&self, lsn: Lsn, cipher: Option<&Cipher>, out: &mut Vec<u8>) {
+        let (kind, page, data) = match self {
+            Record::Put { page, entry } => (PUT, *page, &entry[..]),
+            Record::Remove { page, key } => (REMOVE, *page, &key[..]),
+            Record::Image { page, data } => (IMAGE, *page, &data[..]),
+            Record::Commit => (COMMIT, PageId::META, &[][..]),
+        };
+
+        let start = out.len();
+        out.extend_from_slice(&[0; 8]);
+        out.extend_from_slice(&lsn.to_le_bytes());
+        out.push(kind);
+        out.extend_from_slice(&page.0.to_le_bytes());
+        match cipher {
+            Some(cipher) => {
+                let sealed = cipher.seal(&out[start + 8..], data);
+                out.extend_from_slice(&sealed);
+            }
+            None => out.extend_from_slice(data),
+        }
+
+        let len = (out.len() - start - 8) as u32;
+        out[start..start + 4].copy_from_slice(&len.to_le_bytes());
+
+        let crc = crc32c::crc32c(&out[start + 8..]);
+        out[start + 4..start + 8].copy_from_slice(&crc.to_le_bytes());
+    }
+
+    /// Decodes the record at the start of `buf` and returns its encoded length. Returns `None` if
+    /// it is cut short or corrupt, and fails if it is intact but doesn't decrypt.
+    fn decode(buf: &[u8], cipher: Option<&Cipher>) -> io::Result<Option<(Lsn, Record, usize)>> {
+        if buf.len() < RECORD_HEADER {
+            return Ok(None);
+        }
+
+        let len = u32::from_le_bytes(buf[0..4].try_into().unwrap()) as usize + 8;
+        if len < RECORD_HEADER || len > buf.len() {
+            return Ok(None);
+        }
+        let crc = u32::from_le_bytes(buf[4..8].try_into().unwrap());
+        if crc32c::crc32c(&buf[8..len]) != crc {
+            return Ok(None);
+        }
+
+        let lsn = Lsn::from_le_bytes(buf[8..16].try_into().unwrap());
+        let page = PageId(u64::from_le_bytes(buf[17..25].try_into().unwrap()));
+        let data = match cipher {
+            Some(cipher) => cipher
+                .open(&buf[8..RECORD_HEADER], &buf[RECORD_HEADER..len])
+                .ok_or_else(|| invalid(format!("record {lsn} doesn't decrypt with the key")))?,
+            None => buf[RECORD_HEADER..len].to_vec(),
+        };
+        let record = match buf[16] {
+            PUT => Record::Put { page, entry: data },
+            REMOVE => Record::Remove { page, key: data },
+            IMAGE => Record::Image { page, data },
+            COMMIT => Record::Commit,
+            _ => return Ok(None),
+        };
+
+        Ok(Some((lsn, record, len)))
+    }
+}
+
+struct Inner {
+    file: File,
+    // Bytes of the file holding committed records
+    len: u64,
+    // Records of the operation in progress
+    pending: Vec<u8>,
+    next: Lsn,
+    committed: Lsn,
+    synced: Lsn,
+    synced_at: Instant,
+    // Pages with an image in the log
+    imaged: HashSet<PageId>,
+    cipher: Option<Cipher>,
+    // A group sync is in progress, and how many commits wait on it
+    syncing: bool,
+    waiting: usize,
+    syncs: u64,
+    // A commit failed to be written, and whatever of it reached the file
+    failed: bool,
+    faults: Option<Arc<FaultInjector>>,
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    ring: Option<Ring>,
+}
+
+/// A write-ahead log of page changes.
+///
+/// Records are buffered until their operation commits, then written together. A page changed by
+/// an operation must not be written back before it commits, and then only once the log is synced
+/// up to the page's LSN, see `sync_to()`. Recovery redoes the committed records on every page with
+/// an older LSN.
+///
+/// A page can be torn by a crash while it is being written. So the first change to a page after a
+/// checkpoint should be logged as an `Image`, see `needs_image()`, then recovery rebuilds the page
+/// from the log without reading it.
+///
+/// A log created with a key has its records encrypted, and can only be opened with the same key.
+pub struct Wal {
+    policy: SyncPolicy,
+    inner: Mutex<Inner>,
+    // Signals group commits joining and finishing
+    group: Condvar,
+}
+
+impl Wal {
+    /// Creates an empty log at `path`, truncating any existing one. Records are numbered from
+    /// `first`, which must be greater than the LSN of every page.
+    pub fn create<P: AsRef<Path>>(path: P, first: Lsn, policy: SyncPolicy) -> io::Result<Self> {
+        Self::create_with_key(path, first, policy, None)
+    }
+
+    /// Like `create`, encrypting records with `key` if there is one.
+    pub fn create_with_key<P: AsRef<Path>>(
+        path: P,
+        first: Lsn,
+        policy: SyncPolicy,
+        key: Option<Key>,
+    ) -> io::Result<Self> {
+        assert!(first > 0);
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        let cipher = key.as_ref().map(Cipher::new);
+        write_header(&file, first, cipher.is_some())?;
+        file.sync_data()?;
+
+        Ok(Self::new(file, FILE_HEADER, first, policy, cipher))
+    }
+
+    /// Opens the log at `path` and returns the records of every committed operation in it, in
+    /// order. A torn or corrupt tail is discarded.
+    pub fn open<P: AsRef<Path>>(
+        path: P,
+        policy: SyncPolicy,
+    ) -> io::Result<(Self, Vec<(Lsn, Record)>)> {
+        Self::open_with_key(path, policy, None)
+    }
+
+    /// Like `open`, for a log created with `key`. Fails if a committed record doesn't decrypt,
+    /// without discarding anything.
+    pub fn open_with_key<P: AsRef<Path>>(
+        path: P,
+        policy: SyncPolicy,
+        key: Option<Key>,
+    ) -> io::Result<(Self, Vec<(Lsn, Record)>)> {
+        let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf)?;
+        if buf.len() < FILE_HEADER as usize {
+            return Err(invalid("not a log file".into()));
+        }
+        let cipher = match (&buf[0..8], key) {
+            (magic, None) if magic == MAGIC => None,
+            (magic, Some(key)) if magic == SEALED => Some(Cipher::new(&key)),
+            (magic, _) if magic == MAGIC || magic == SEALED => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "log is encrypted only if a key is given",
+                ))
+            }
+            _ => return Err(invalid("not a log file".into())),
+        };
+        let first = Lsn::from_le_bytes(buf[8..16].try_into().unwrap());
+        let (records, len, next) = parse(&buf[FILE_HEADER as usize..], first, cipher.as_ref())?;
+        let len = FILE_HEADER + len as u64;
+
+        // Drop the tail so later records aren't followed by stale ones
+        file.set_len(len)?;
+        file.sync_data()?;
+
+        Ok((Self::new(file, len, next, policy, cipher), records))
+    }
+
+    /// Reads back the records of every committed operation still in the log.
+    pub fn records(&self) -> io::Result<Vec<(Lsn, Record)>> {
+        let inner = self.inner.lock().unwrap();
+
+        let mut buf = vec![0; (inner.len - FILE_HEADER) as usize];
+        inner.file.read_exact_at(&mut buf, FILE_HEADER)?;
+
+        Ok(parse(&buf, inner.next, inner.cipher.as_ref())?.0)
+    }
+
+    fn new(file: File, len: u64, next: Lsn, policy: SyncPolicy, cipher: Option<Cipher>) -> Self {
+        Self {
+            policy,
+            inner: Mutex::new(Inner {
+                file,
+                len,
+                pending: Vec::new(),
+                next,
+                committed: next - 1,
+                synced: next - 1,
+                synced_at: Instant::now(),
+                imaged: HashSet::new(),
+                cipher,
+                syncing: false,
+                waiting: 0,
+                syncs: 0,
+                failed: false,
+                faults: None,
+                #[cfg(all(feature = "io-uring", target_os = "linux"))]
+                ring: None,
+            }),
+            group: Condvar::new(),
+        }
+    }
+
+    pub fn policy(&self) -> SyncPolicy {
+        self.policy
+    }
+
+    /// Adds `record` to the operation in progress.
+    pub fn append(&self, record: &Record) -> Lsn {
+        let inner = &mut *self.inner.lock().unwrap();
+
+        let lsn = inner.next;
+        inner.next += 1;
+        record.encode(lsn, inner.cipher.as_ref(), &mut inner.pending);
+        if let Record::Image { page, .. } = record {
+            inner.imaged.insert(*page);
+        }
+
+        lsn
+    }
+
+    /// Returns `true` if `page` wasn't logged as an `Image` since the last checkpoint.
+    pub fn needs_image(&self, page: PageId) -> bool {
+        !self.inner.lock().unwrap().imaged.contains(&page)
+    }
+
+    /// Ends the operation in progress and writes its records, syncing them as the policy says.
+    ///
+    /// If that fails the log takes no more commits or checkpoints, the operation's changes are
+    /// only in memory and whatever of its records reached the file is dropped when the log is
+    /// opened again.
+    pub fn commit(&self) -> io::Result<Lsn> {
+        let inner = &mut *self.inner.lock().unwrap();
+        inner.check_failed()?;
+
+        let lsn = inner.next;
+        Record::Commit.encode(lsn, inner.cipher.as_ref(), &mut inner.pending);
+
+        let sync = match self.policy {
+            SyncPolicy::PerCommit => true,
+            SyncPolicy::Periodic(every) => inner.synced_at.elapsed() >= every,
+            SyncPolicy::Off | SyncPolicy::Group { .. } => false,
+        };
+        trace::span!(TRACE, "wal commit", lsn, sync);
+        if let Err(e) = inner.write_pending(sync) {
+            inner.failed = true;
+            return Err(e);
+        }
+
+        inner.next += 1;
+        inner.len += inner.pending.len() as u64;
+        inner.pending.clear();
+        inner.committed = lsn;
+        if sync {
+            inner.synced = lsn;
+            inner.synced_at = Instant::now();
+            inner.syncs += 1;
+        }
+
+        Ok(lsn)
+    }
+
+    /// Waits for the commit at `lsn` to reach the disk under `SyncPolicy::Group`, sharing a sync
+    /// with every other commit waiting. Returns at once under other policies, their commits are
+    /// already synced as far as they will be.
+    pub fn wait_durable(&self, lsn: Lsn) -> io::Result<()> {
+        let (delay, batch) = match self.policy {
+            SyncPolicy::Group { delay, batch } => (delay, batch),
+            _ => return Ok(()),
+        };
+
+        let mut inner = self.inner.lock().unwrap();
+        loop {
+            if inner.synced >= lsn {
+                return Ok(());
+            }
+            if !inner.syncing {
+                break;
+            }
+
+            // Join the sync in progress, or the next one if it started without this commit
+            inner.waiting += 1;
+            self.group.notify_all();
+            inner = self.group.wait(inner).unwrap();
+            inner.waiting -= 1;
+        }
+
+        // Lead a sync, holding it back for others to join
+        inner.syncing = true;
+        let deadline = Instant::now() + delay;
+        while inner.waiting + 1 < batch {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            inner = self.group.wait_timeout(inner, deadline - now).unwrap().0;
+        }
+
+        // Commits go on being written while the file syncs
+        let upto = inner.committed;
+        let file = match &inner.faults {
+            Some(faults) => faults.check().and_then(|_| inner.file.try_clone()),
+            None => inner.file.try_clone(),
+        };
+        drop(inner);
+        trace::span!(DEBUG, "wal sync", upto, group = true);
+        let synced = file.and_then(|file| file.sync_data());
+
+        let mut inner = self.inner.lock().unwrap();
+        inner.syncing = false;
+        if synced.is_ok() {
+            inner.synced = inner.synced.max(upto);
+            inner.synced_at = Instant::now();
+            inner.syncs += 1;
+        }
+        self.group.notify_all();
+
+        synced
+    }
+
+    /// Passes appends and syncs through `faults`, see `FaultInjector`.
+    pub fn set_faults(&self, faults: Option<Arc<FaultInjector>>) {
+        self.inner.lock().unwrap().faults = faults;
+    }
+
+    /// How many times commits have been synced, by themselves or in groups.
+    pub fn syncs(&self) -> u64 {
+        self.inner.lock().unwrap().syncs
+    }
+
+    /// Writes and syncs the log through an io_uring from now on. Returns `false` if it can't,
+    /// without the `io-uring` feature, off Linux, or if the kernel refuses to set up a ring.
+    pub fn use_io_uring(&mut self) -> bool {
+        #[cfg(all(feature = "io-uring", target_os = "linux"))]
+        {
+            let inner = self.inner.get_mut().unwrap();
+            inner.ring = Ring::new().ok();
+            inner.ring.is_some()
+        }
+
+        #[cfg(not(all(feature = "io-uring", target_os = "linux")))]
+        false
+    }
+
+    /// The LSN of the last commit. Pages with a greater LSN must not be written back.
+    pub fn committed(&self) -> Lsn {
+        self.inner.lock().unwrap().committed
+    }
+
+    /// Waits for every committed record to reach the disk.
+    pub fn sync(&self) -> io::Result<()> {
+        self.inner.lock().unwrap().sync()
+    }
+
+    /// Makes sure the records up to `lsn` are on disk, syncing if they aren't yet.
+    pub fn sync_to(&self, lsn: Lsn) -> io::Result<()> {
+        let inner = &mut *self.inner.lock().unwrap();
+        debug_assert!(lsn <= inner.committed, "page changed by an uncommitted operation");
+
+        if lsn > inner.synced {
+            inner.sync()?;
+        }
+
+        Ok(())
+    }
+
+    /// Bytes of committed records in the log.
+    pub fn size(&self) -> u64 {
+        self.inner.lock().unwrap().len - FILE_HEADER
+    }
+
+    /// Empties the log. Every page it covers must have been written back and synced.
+    pub fn checkpoint(&self) -> io::Result<()> {
+        let inner = &mut *self.inner.lock().unwrap();
+        inner.check_failed()?;
+        assert!(inner.pending.is_empty(), "checkpoint during an operation");
+        if let Some(faults) = &inner.faults {
+            faults.check()?;
+        }
+
+        // The new first LSN goes in before the records go, so a crash in between only redoes
+        // records that are already applied
+        write_header(&inner.file, inner.next, inner.cipher.is_some())?;
+        inner.file.set_len(FILE_HEADER)?;
+        inner.len = FILE_HEADER;
+        inner.imaged.clear();
+        inner.sync()
+    }
+
+    /// Encrypts records with `key` from now on. The log must be empty, after a checkpoint, and
+    /// have been created with a key.
+    pub fn rekey(&self, key: Key) {
+        let inner = &mut *self.inner.lock().unwrap();
+        assert!(inner.cipher.is_some(), "log isn't encrypted");
+        assert!(
+            inner.len == FILE_HEADER && inner.pending.is_empty(),
+            "rekey of a log with records"
+        );
+
+        inner.cipher = Some(Cipher::new(&key));
+    }
+}
+
+impl Inner {
+    /// Writes the pending records after the committed ones, then syncs if `sync` is set. With a
+    /// ring both are submitted together.
+    fn check_failed(&self) -> io::Result<()> {
+        match self.failed {
+            true => Err(io::Error::other("a commit failed, the log must be opened again")),
+            false => Ok(()),
+        }
+    }
+
+    fn write_pending(&self, sync: bool) -> io::Result<()> {
+        if let Some(faults) = &self.faults {
+            faults.write_at(&self.file, &self.pending, self.len)?;
+            faults.check()?;
+            return match sync {
+                true => self.file.sync_data(),
+                false => Ok(()),
+            };
+        }
+
+        #[cfg(all(feature = "io-uring", target_os = "linux"))]
+        if let Some(ring) = &self.ring {
+            let file = &self.file;
+            let mut ops = vec![Op::Write {
+                file,
+                buf: &self.pending,
+                offset: self.len,
+            }];
+            if sync {
+                ops.push(Op::Sync { file });
+            }
+            return ring.run(&mut ops);
+        }
+
+        self.file.write_all_at(&self.pending, self.len)?;
+        if sync {
+            self.file.sync_data()?;
+        }
+
+        Ok(())
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        trace::span!(DEBUG, "wal sync", upto = self.committed, group = false);
+        if let Some(faults) = &self.faults {
+            faults.check()?;
+        }
+        self.file.sync_data()?;
+        self.synced = self.committed;
+        self.synced_at = Instant::now();
+        self.syncs += 1;
+
+        Ok(())
+    }
+}
+
+/// Committed records, the length they take up and the LSN to carry on from.
+type Parsed = (Vec<(Lsn, Record)>, usize, Lsn);
+
+/// Parses the records in `buf`, stopping at the first one cut short or corrupt.
+fn parse(buf: &[u8], first: Lsn, cipher: Option<&Cipher>) -> io::Result<Parsed> {
+    let mut records = Vec::new();
+    let mut pending = Vec::new();
+    let mut next = first;
+    let mut at = 0;
+    let mut len = 0;
+    while let Some((lsn, record, n)) = Record::decode(&buf[at..], cipher)? {
+        at += n;
+        next = next.max(lsn + 1);
+
+        match record {
+            Record::Commit => {
+                records.append(&mut pending);
+                len = at;
+            }
+            record => pending.push((lsn, record)),
+        }
+    }
+
+    Ok((records, len, next))
+}
+
+fn write_header(file: &File, first: Lsn, sealed: bool) -> io::Result<()> {
+    let mut header = [0; FILE_HEADER as usize];
+    header[0..8].copy_from_slice(if sealed { SEALED } else { MAGIC });
+    header[8..16].copy_from_slice(&first.to_le_bytes());
+    file.write_all_at(&header, 0)
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::OpenOptions;
+    use std::sync::Mutex;
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::fault::FaultInjector;
+    use crate::page::PageId;
+
+    use super::{Record, SyncPolicy, Wal};
+
+    #[test]
+    fn test_wal_recovers_committed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal");
+
+        let put = |page, b| Record::Put {
+            page: PageId(page),
+            entry: vec![b; 12],
+        };
+
+        let mut wal = Wal::create(&path, 10, SyncPolicy::PerCommit).unwrap();
+        // Either way the log reads back the same
+        assert!(wal.use_io_uring() == cfg!(all(feature = "io-uring", target_os = "linux")));
+        assert!(wal.append(&put(1, 1)) == 10);
+        wal.append(&Record::Remove {
+            page: PageId(2),
+            key: vec![2; 4],
+        });
+        assert!(wal.commit().unwrap() == 12);
+        wal.append(&put(3, 3));
+        wal.commit().unwrap();
+        // Never committed
+        wal.append(&put(4, 4));
+        let size = wal.size();
+        drop(wal);
+
+        let (wal, have) = Wal::open(&path, SyncPolicy::Off).unwrap();
+        let want = vec![
+            (10, put(1, 1)),
+            (
+                11,
+                Record::Remove {
+                    page: PageId(2),
+                    key: vec![2; 4],
+                },
+            ),
+            (13, put(3, 3)),
+        ];
+        assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+        assert!(wal.committed() == 14 && wal.size() == size);
+
+        // A torn last operation is dropped
+        drop(wal);
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(file.metadata().unwrap().len() - 3).unwrap();
+        let (wal, have) = Wal::open(&path, SyncPolicy::Off).unwrap();
+        assert!(have == want[..2], "Have: {:?}", have);
+
+        // Numbering carries on past a checkpoint
+        wal.checkpoint().unwrap();
+        assert!(wal.size() == 0);
+        wal.append(&put(5, 5));
+        assert!(wal.commit().unwrap() == 15);
+        drop(wal);
+
+        let (_, have) = Wal::open(&path, SyncPolicy::Off).unwrap();
+        assert!(have == [(14, put(5, 5))], "Have: {:?}", have);
+    }
+
+    #[test]
+    fn test_wal_failed_commit() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal");
+
+        let put = |page, b| Record::Put {
+            page: PageId(page),
+            entry: vec![b; 12],
+        };
+
+        let wal = Wal::create(&path, 10, SyncPolicy::PerCommit).unwrap();
+        wal.append(&put(1, 1));
+        wal.commit().unwrap();
+
+        // Once a commit fails, later ones do too, even with the disk back, rather than taking the
+        // failed operation with them
+        let faults = FaultInjector::new();
+        wal.set_faults(Some(faults.clone()));
+        faults.crash_after(1, true);
+        wal.append(&put(2, 2));
+        assert!(wal.commit().is_err());
+        faults.reset();
+        wal.append(&put(3, 3));
+        assert!(wal.commit().is_err() && wal.checkpoint().is_err());
+        assert!(wal.committed() == 11);
+        drop(wal);
+
+        let (wal, have) = Wal::open(&path, SyncPolicy::PerCommit).unwrap();
+        assert!(have == [(10, put(1, 1))], "Have: {:?}", have);
+        wal.append(&put(4, 4));
+        assert!(wal.commit().unwrap() == 13);
+    }
+
+    #[test]
+    fn test_group_commit() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal");
+
+        let policy = SyncPolicy::Group {
+            delay: Duration::from_millis(2),
+            batch: 4,
+        };
+        let wal = Wal::create(&path, 1, policy).unwrap();
+        // Operations take turns, like writers of a tree
+        let op = Mutex::new(());
+        thread::scope(|s| {
+            for t in 0..4u8 {
+                let (wal, op) = (&wal, &op);
+                s.spawn(move || {
+                    for _ in 0..50 {
+                        let guard = op.lock().unwrap();
+                        wal.append(&Record::Put {
+                            page: PageId(t as u64),
+                            entry: vec![t; 8],
+                        });
+                        let lsn = wal.commit().unwrap();
+                        drop(guard);
+
+                        wal.wait_durable(lsn).unwrap();
+                        assert!(wal.inner.lock().unwrap().synced >= lsn);
+                    }
+                });
+            }
+        });
+
+        // Committing doesn't sync by itself
+        assert!(wal.syncs() > 0 && wal.syncs() < 200, "Syncs: {}", wal.syncs());
+        drop(wal);
+        let (_, records) = Wal::open(&path, policy).unwrap();
+        assert!(records.len() == 200, "Have: {}", records.len());
+    }
+}
@@ -0,0 +1,167 @@
+use std::fmt::Debug;
+use std::ops::{Bound, RangeBounds};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvError, SyncSender, TrySendError};
+use std::sync::Arc;
+
+use crate::btree::{BTree, Increment};
+
+/// A change to an entry, as sent to subscriptions.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Event<K, V> {
+    Insert(K, V),
+    Update { key: K, old: V, new: V },
+    Delete(K, V),
+}
+
+impl<K: Copy, V> Event<K, V> {
+    pub fn key(&self) -> K {
+        match self {
+            Event::Insert(key, _) | Event::Update { key, .. } | Event::Delete(key, _) => *key,
+        }
+    }
+}
+
+/// The receiving end of `BTree::subscribe`.
+///
+/// Events are buffered up to the capacity the subscription was made with. The tree never waits
+/// for a subscriber, events that don't fit are dropped and counted by `missed`, so a subscriber
+/// that falls behind should resync from the tree.
+pub struct Subscription<K, V> {
+    rx: Receiver<Event<K, V>>,
+    missed: Arc<AtomicUsize>,
+}
+
+impl<K, V> Subscription<K, V> {
+    /// Waits for the next event, `Err` once the tree is dropped.
+    pub fn recv(&self) -> Result<Event<K, V>, RecvError> {
+        self.rx.recv()
+    }
+
+    pub fn try_recv(&self) -> Option<Event<K, V>> {
+        self.rx.try_recv().ok()
+    }
+
+    /// Returns the buffered events without waiting.
+    pub fn drain(&self) -> impl Iterator<Item = Event<K, V>> + '_ {
+        self.rx.try_iter()
+    }
+
+    /// Returns the number of events dropped because the buffer was full, and resets it.
+    pub fn missed(&self) -> usize {
+        self.missed.swap(0, Ordering::Relaxed)
+    }
+}
+
+/// The tree's end of a subscription.
+pub(crate) struct Watcher<K, V> {
+    range: (Bound<K>, Bound<K>),
+    tx: SyncSender<Event<K, V>>,
+    missed: Arc<AtomicUsize>,
+}
+
+impl<K, V> Watcher<K, V>
+where
+    K: Copy + Ord,
+{
+    /// Returns `false` once the subscription has been dropped.
+    fn send(&self, event: Event<K, V>) -> bool {
+        if !self.range.contains(&event.key()) {
+            return true;
+        }
+
+        match self.tx.try_send(event) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                self.missed.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            Err(TrySendError::Disconnected(_)) => false,
+        }
+    }
+}
+
+impl<K, V> BTree<K, V>
+where
+    K: Clone + Copy + Debug + Ord + Increment,
+    V: Clone + Debug + Eq,
+{
+    /// Returns a subscription to the inserts, updates and deletes of keys in `range`, buffering
+    /// up to `capacity` events. Dropping it unsubscribes.
+    pub fn subscribe<R: RangeBounds<K>>(
+        &mut self,
+        range: R,
+        capacity: usize,
+    ) -> Subscription<K, V> {
+        let (tx, rx) = mpsc::sync_channel(capacity);
+        let missed = Arc::new(AtomicUsize::new(0));
+        self.watchers.push(Watcher {
+            range: (range.start_bound().cloned(), range.end_bound().cloned()),
+            tx,
+            missed: missed.clone(),
+        });
+
+        Subscription { rx, missed }
+    }
+
+    pub(crate) fn notify(&mut self, event: Event<K, V>) {
+        self.watchers.retain(|w| w.send(event.clone()));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+
+    use crate::btree::BTree;
+    use crate::slot::Slot;
+
+    use super::Event;
+
+    #[test]
+    fn test_subscribe() {
+        let mut tree = BTree::new(4);
+        let sub = tree.subscribe(10..20, 64);
+        let small = tree.subscribe(.., 2);
+
+        for k in 0..30u32 {
+            tree.insert(Slot::new_leaf(k, k));
+        }
+        tree.insert(Slot::new_leaf(15, 150));
+        tree.delete(12);
+        tree.delete(12);
+        tree.delete(25);
+
+        let mut want = (10..20).map(|k| Event::Insert(k, k)).collect::<Vec<_>>();
+        want.push(Event::Update {
+            key: 15,
+            old: 15,
+            new: 150,
+        });
+        want.push(Event::Delete(12, 12));
+        let have = sub.drain().collect::<Vec<_>>();
+        assert!(want == have, "Want: {:?}\nHave: {:?}", want, have);
+
+        // 33 changes, 2 buffered
+        let have = small.drain().collect::<Vec<_>>();
+        assert!(have == [Event::Insert(0, 0), Event::Insert(1, 1)], "Have: {:?}", have);
+        assert!(small.missed() == 31 && small.missed() == 0);
+
+        // Dropped subscriptions are unsubscribed on the next change
+        drop(small);
+        tree.insert(Slot::new_leaf(40, 40));
+        assert!(tree.watchers.len() == 1);
+
+        let sub = thread::scope(|s| {
+            let reader = s.spawn(move || {
+                let have = sub.recv().unwrap();
+                assert!(have == Event::Insert(12, 12), "Have: {:?}", have);
+                sub
+            });
+            tree.insert(Slot::new_leaf(12, 12));
+            reader.join().unwrap()
+        });
+        drop(tree);
+        assert!(sub.recv().is_err());
+    }
+}
@@ -0,0 +1,279 @@
+//! Deterministic streams of operations for benchmarks and tests. A `Workload` generates the same
+//! operations for the same settings, with its own generator rather than `rand`'s, so a seed is
+//! enough to reproduce a run.
+
+use std::iter::FusedIterator;
+use std::ops::Bound;
+
+use crate::oracle::Op;
+
+/// How keys are drawn from `0..keys`.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Distribution {
+    Uniform,
+    /// Skewed with exponent `theta` between 0 and 1, 0.99 in YCSB. The smaller the key the more
+    /// often it is drawn, key 0 the most.
+    Zipfian(f64),
+    /// Every key in order, starting over after the last.
+    Sequential,
+}
+
+/// The relative weights of each kind of operation.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct Mix {
+    pub insert: u32,
+    pub get: u32,
+    pub delete: u32,
+    pub range: u32,
+}
+
+impl Default for Mix {
+    /// Half reads and half writes, YCSB's workload A.
+    fn default() -> Self {
+        Self {
+            insert: 50,
+            get: 50,
+            delete: 0,
+            range: 0,
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Workload {
+    pub seed: u64,
+    /// The size of the key space.
+    pub keys: u64,
+    pub distribution: Distribution,
+    pub mix: Mix,
+    /// The keys each range covers, from a key drawn like any other.
+    pub scan_len: u64,
+}
+
+impl Default for Workload {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            keys: 100_000,
+            distribution: Distribution::Uniform,
+            mix: Mix::default(),
+            scan_len: 100,
+        }
+    }
+}
+
+impl Workload {
+    /// Returns an endless iterator over the operations of the workload.
+    pub fn ops(&self) -> Ops {
+        assert!(self.keys > 0, "a workload needs keys");
+        let mix = self.mix;
+        assert!(mix.insert + mix.get + mix.delete + mix.range > 0, "a workload needs operations");
+
+        let zipf = match self.distribution {
+            Distribution::Zipfian(theta) => Some(Zipf::new(self.keys, theta)),
+            _ => None,
+        };
+
+        Ops {
+            workload: *self,
+            rng: SplitMix64(self.seed),
+            zipf,
+            next: 0,
+        }
+    }
+
+    /// The first `n` operations.
+    pub fn take(&self, n: usize) -> Vec<Op<u64, u64>> {
+        self.ops().take(n).collect()
+    }
+}
+
+/// The operations of a `Workload`.
+pub struct Ops {
+    workload: Workload,
+    rng: SplitMix64,
+    zipf: Option<Zipf>,
+    // The next key of `Distribution::Sequential`
+    next: u64,
+}
+
+impl Ops {
+    fn key(&mut self) -> u64 {
+        match self.workload.distribution {
+            Distribution::Uniform => self.rng.below(self.workload.keys),
+            Distribution::Zipfian(_) => {
+                let u = self.rng.unit();
+                self.zipf.as_ref().unwrap().rank(u)
+            }
+            Distribution::Sequential => {
+                let key = self.next;
+                self.next = (self.next + 1) % self.workload.keys;
+                key
+            }
+        }
+    }
+}
+
+impl Iterator for Ops {
+    type Item = Op<u64, u64>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Mix {
+            insert,
+            get,
+            delete,
+            range,
+        } = self.workload.mix;
+        let pick = self.rng.below((insert + get + delete + range) as u64) as u32;
+        let key = self.key();
+
+        let op = if pick < insert {
+            Op::Insert(key, self.rng.next_u64())
+        } else if pick < insert + get {
+            Op::Get(key)
+        } else if pick < insert + get + delete {
+            Op::Delete(key)
+        } else {
+            let end = key.saturating_add(self.workload.scan_len);
+            Op::Range(Bound::Included(key), Bound::Excluded(end))
+        };
+
+        Some(op)
+    }
+}
+
+// Never ends
+impl FusedIterator for Ops {}
+
+/// A small generator whose output is fixed by its seed, unlike `rand`'s, which may change
+/// between versions.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `0..n`.
+    fn below(&mut self, n: u64) -> u64 {
+        ((self.next_u64() as u128 * n as u128) >> 64) as u64
+    }
+
+    /// Uniform in `[0, 1)`.
+    fn unit(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Zipfian ranks in `0..n`, by the method of Gray et al., "Quickly Generating Billion-Record
+/// Synthetic Databases", as in YCSB.
+struct Zipf {
+    n: u64,
+    theta: f64,
+    alpha: f64,
+    zetan: f64,
+    eta: f64,
+}
+
+impl Zipf {
+    fn new(n: u64, theta: f64) -> Self {
+        assert!(theta > 0.0 && theta < 1.0, "theta must be between 0 and 1");
+
+        let zeta = |n: u64| (1..=n).map(|i| 1.0 / (i as f64).powf(theta)).sum::<f64>();
+        let zetan = zeta(n);
+        let eta = (1.0 - (2.0 / n as f64).powf(1.0 - theta)) / (1.0 - zeta(2) / zetan);
+
+        Self {
+            n,
+            theta,
+            alpha: 1.0 / (1.0 - theta),
+            zetan,
+            eta,
+        }
+    }
+
+    fn rank(&self, u: f64) -> u64 {
+        let uz = u * self.zetan;
+        if uz < 1.0 {
+            return 0;
+        }
+        if uz < 1.0 + 0.5f64.powf(self.theta) {
+            return 1.min(self.n - 1);
+        }
+
+        let rank = self.n as f64 * (self.eta * u - self.eta + 1.0).powf(self.alpha);
+        (rank as u64).min(self.n - 1)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::ops::Bound;
+
+    use crate::btree::BTree;
+    use crate::oracle::{Op, Oracle};
+
+    use super::{Distribution, Mix, Workload};
+
+    #[test]
+    fn test_workload() {
+        let workload = Workload {
+            seed: 42,
+            keys: 1000,
+            distribution: Distribution::Zipfian(0.99),
+            mix: Mix {
+                insert: 40,
+                get: 40,
+                delete: 10,
+                range: 10,
+            },
+            scan_len: 20,
+        };
+        let ops = workload.take(20_000);
+        assert!(ops == workload.take(20_000));
+        assert!(
+            ops != Workload {
+                seed: 43,
+                ..workload
+            }
+            .take(20_000)
+        );
+
+        let key = |op: &Op<u64, u64>| match *op {
+            Op::Insert(k, _) | Op::Get(k) | Op::Delete(k) => k,
+            Op::Range(Bound::Included(k), _) => k,
+            _ => unreachable!(),
+        };
+        let hot = ops.iter().filter(|op| key(op) < 10).count();
+        assert!(hot > ops.len() / 4, "Have: {hot}");
+        assert!(ops.iter().all(|op| key(op) < 1000));
+        let ranges = ops.iter().filter(|op| matches!(op, Op::Range(..))).count();
+        assert!((1500..2500).contains(&ranges), "Have: {ranges}");
+
+        // Uniform keys are spread out
+        let uniform = Workload {
+            distribution: Distribution::Uniform,
+            ..workload
+        };
+        let hot = uniform
+            .take(20_000)
+            .iter()
+            .filter(|op| key(op) < 10)
+            .count();
+        assert!(hot < 500, "Have: {hot}");
+
+        let sequential = Workload {
+            keys: 5,
+            distribution: Distribution::Sequential,
+            ..workload
+        };
+        let have = sequential.take(7).iter().map(key).collect::<Vec<_>>();
+        assert!(have == [0, 1, 2, 3, 4, 0, 1], "Have: {:?}", have);
+
+        Oracle::new(BTree::new(8)).run(&ops).unwrap();
+    }
+}